@@ -0,0 +1,15 @@
+//! HeartRate-For-VRChat 心率变换插件示例：原样返回心率。
+//!
+//! 构建后把生成的 identity_plugin.dll / libidentity_plugin.so / libidentity_plugin.dylib
+//! 填入 config.toml 的 `plugin_path` 即可加载。在此基础上修改 `hr_transform` 即可实现
+//! 自己的平滑或心率区间算法。
+
+/// 主程序在每次发送 OSC 前调用（心率为 0 即未佩戴时不调用）。
+///
+/// `history` 指向最近的原始心率（按时间先后，最多 60 个），仅在本次调用期间有效，
+/// 需要时用 `std::slice::from_raw_parts(history, history_len)` 读取（`history_len` 为 0 时不要读取）；
+/// 不要保存该指针，也不要让 panic 跨出本函数。
+#[no_mangle]
+pub extern "C" fn hr_transform(bpm: u8, _history: *const u8, _history_len: usize) -> u8 {
+    bpm
+}
@@ -0,0 +1,139 @@
+//! `hr_percent` 自动增益（`max_heart_rate_for_percent = 0`）：记录最近 10 分钟的心率，
+//! 以其中最高心率的 110% 作为分母，固定的 200 对最高只到 175 的用户永远到不了 1.0。
+//! 分母限制在 140–220 BPM 之间，单个异常读数不会把它拉得过高；还没有读数时按 200 计算。
+//!
+//! 心率区间（`hr_zone`、运行总结、心跳震动）与训练负荷使用同一个分母。
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// 统计最高心率的时间窗口。
+pub const WINDOW: Duration = Duration::from_secs(10 * 60);
+/// 分母 = 窗口内最高心率 × 该系数
+const HEADROOM: f32 = 1.1;
+const MIN_MAX_HR: f32 = 140.0;
+const MAX_MAX_HR: f32 = 220.0;
+/// 还没有读数时的分母（与 max_heart_rate_for_percent 的默认值相同）
+const FALLBACK_MAX_HR: f32 = 200.0;
+
+/// 最近 `WINDOW` 内的心率，只保留可能成为窗口最大值的读数（按时间先后、心率递减），
+/// 取最大值为 O(1)。心率 0（未佩戴）不计入。
+#[derive(Debug, Default)]
+pub struct HistoryBuffer {
+    samples: VecDeque<(Instant, u8)>,
+}
+
+impl HistoryBuffer {
+    pub const fn new() -> Self {
+        HistoryBuffer {
+            samples: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, bpm: u8, now: Instant) {
+        if bpm == 0 {
+            return;
+        }
+        // 比新读数小的旧读数在窗口内不可能再成为最大值
+        while self.samples.back().is_some_and(|&(_, old)| old <= bpm) {
+            self.samples.pop_back();
+        }
+        self.samples.push_back((now, bpm));
+        while self
+            .samples
+            .front()
+            .is_some_and(|&(at, _)| now.duration_since(at) > WINDOW)
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    /// 窗口内的最高心率。
+    pub fn max(&self) -> Option<u8> {
+        self.samples.front().map(|&(_, bpm)| bpm)
+    }
+}
+
+/// 自动增益的 `hr_percent` 计算。
+pub struct AutoGainHrPercent;
+
+impl AutoGainHrPercent {
+    /// 当前的分母（BPM）。
+    pub fn max_hr(history: &HistoryBuffer) -> f32 {
+        match history.max() {
+            Some(max) => (f32::from(max) * HEADROOM).clamp(MIN_MAX_HR, MAX_MAX_HR),
+            None => FALLBACK_MAX_HR,
+        }
+    }
+
+    /// `current` 占自动分母的比例，0–1。
+    pub fn compute(history: &HistoryBuffer, current: u8) -> f32 {
+        let max_hr = Self::max_hr(history);
+        f32::from(current).min(max_hr) / max_hr
+    }
+}
+
+static HISTORY: Mutex<HistoryBuffer> = Mutex::new(HistoryBuffer::new());
+
+/// 记录一次读数（每次收到心率时调用）。
+pub fn record(bpm: u8, now: Instant) {
+    HISTORY.lock().unwrap().push(bpm, now);
+}
+
+/// 自动增益下的 `hr_percent`。
+pub fn hr_percent(current: u8) -> f32 {
+    AutoGainHrPercent::compute(&HISTORY.lock().unwrap(), current)
+}
+
+/// 配置的 `max_heart_rate_for_percent`：0 表示自动，返回当前的自动分母，否则原样返回。
+pub fn resolve(configured: f32) -> f32 {
+    if configured == 0.0 {
+        AutoGainHrPercent::max_hr(&HISTORY.lock().unwrap())
+    } else {
+        configured
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn denominator_tracks_the_rolling_maximum_with_headroom() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut history = HistoryBuffer::new();
+        assert_eq!(AutoGainHrPercent::max_hr(&history), 200.0);
+
+        history.push(150, at(0));
+        history.push(0, at(1));
+        history.push(160, at(60));
+        history.push(120, at(120));
+        assert_eq!(history.max(), Some(160));
+        assert_eq!(AutoGainHrPercent::max_hr(&history), 176.0);
+        assert_eq!(AutoGainHrPercent::compute(&history, 88), 0.5);
+        assert_eq!(AutoGainHrPercent::compute(&history, 190), 1.0);
+
+        // 160 滑出 10 分钟窗口后由之后的最大值接替
+        history.push(110, at(61 + 600));
+        assert_eq!(history.max(), Some(120));
+    }
+
+    #[test]
+    fn denominator_is_clamped() {
+        let now = Instant::now();
+        let mut resting = HistoryBuffer::new();
+        resting.push(70, now);
+        assert_eq!(AutoGainHrPercent::max_hr(&resting), 140.0);
+
+        let mut glitch = HistoryBuffer::new();
+        glitch.push(255, now);
+        assert_eq!(AutoGainHrPercent::max_hr(&glitch), 220.0);
+    }
+
+    #[test]
+    fn configured_denominator_is_used_as_is() {
+        assert_eq!(resolve(185.0), 185.0);
+    }
+}
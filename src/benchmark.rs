@@ -0,0 +1,239 @@
+//! `--benchmark [--duration-secs N] [--rate-hz N]`：不使用蓝牙，连续编码并发送
+//! `duration_secs * rate_hz` 个 OSC Bundle，测量本机能维持的最大发送速率。
+//! 编码与发送走与正常运行相同的 `encode_hr_bundle` / `send_raw_osc`，
+//! 每次发送的耗时包括编码；结束后发送一次清零状态。
+
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::{
+    encode_hr_bundle, osc_socket, send_raw_osc, AppError, Config, OscExtras, Result, ResultExt,
+};
+
+/// 未指定时的测试时长（秒）与目标速率（Hz）。
+pub const DEFAULT_DURATION_SECS: u64 = 10;
+pub const DEFAULT_RATE_HZ: u32 = 100;
+
+/// 发送总数上限，避免参数写错时长时间占满网络。
+const MAX_SENDS: u64 = 10_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Options {
+    pub duration_secs: u64,
+    pub rate_hz: u32,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            duration_secs: DEFAULT_DURATION_SECS,
+            rate_hz: DEFAULT_RATE_HZ,
+        }
+    }
+}
+
+impl Options {
+    /// 发送总数；乘积溢出 u64 时为 None。
+    fn total_sends(&self) -> Option<u64> {
+        self.duration_secs.checked_mul(u64::from(self.rate_hz))
+    }
+}
+
+/// 解析 `--benchmark` 之后的参数，两个选项都可省略、顺序任意。
+pub fn parse_options(args: &[String]) -> std::result::Result<Options, String> {
+    let mut options = Options::default();
+    let mut rest = args.iter();
+    while let Some(flag) = rest.next() {
+        let value = rest.next();
+        match (flag.as_str(), value) {
+            ("--duration-secs", Some(value)) => match value.parse::<u64>() {
+                Ok(secs) if secs > 0 => options.duration_secs = secs,
+                _ => return Err(format!("无效的测试时长: {}（需为正整数秒）", value)),
+            },
+            ("--rate-hz", Some(value)) => match value.parse::<u32>() {
+                Ok(hz) if hz > 0 => options.rate_hz = hz,
+                _ => return Err(format!("无效的发送速率: {}（需为正整数 Hz）", value)),
+            },
+            ("--duration-secs" | "--rate-hz", None) => {
+                return Err(format!("{} 需要指定数值。", flag))
+            }
+            _ => return Err(format!("无法识别的参数: {}", flag)),
+        }
+    }
+    match options.total_sends() {
+        Some(total) if total <= MAX_SENDS => Ok(options),
+        Some(total) => Err(format!(
+            "发送总数 {} 过大（duration_secs × rate_hz 不能超过 {}）。",
+            total, MAX_SENDS
+        )),
+        None => Err(format!(
+            "发送总数过大（duration_secs × rate_hz 不能超过 {}）。",
+            MAX_SENDS
+        )),
+    }
+}
+
+/// 发送缓冲区已满：非阻塞套接字返回 WouldBlock，部分系统返回 ENOBUFS / WSAENOBUFS。
+fn is_buffer_overflow(e: &io::Error) -> bool {
+    const ENOBUFS: i32 = if cfg!(windows) { 10055 } else { 105 };
+    e.kind() == io::ErrorKind::WouldBlock
+        || (cfg!(any(windows, target_os = "linux")) && e.raw_os_error() == Some(ENOBUFS))
+}
+
+/// 已排序样本的第 p 百分位（最近秩法）。
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[derive(Debug, Default)]
+struct Report {
+    sent: u64,
+    overflowed: u64,
+    elapsed: Duration,
+    latencies: Vec<Duration>,
+}
+
+impl Report {
+    fn print(&mut self, options: Options, osc_addr: SocketAddr, bundle_len: usize) {
+        self.latencies.sort_unstable();
+        let attempts = self.sent + self.overflowed;
+        let secs = self.elapsed.as_secs_f64().max(f64::EPSILON);
+        let throughput = self.sent as f64 / secs;
+        let micros = |d: Duration| d.as_secs_f64() * 1_000_000.0;
+
+        println!();
+        println!("OSC 发送性能测试结果（目标 {}）", osc_addr);
+        println!(
+            "  {:<16}{:>14}",
+            "Bundle 大小",
+            format!("{} 字节", bundle_len)
+        );
+        println!("  {:<16}{:>14}", "尝试发送", attempts);
+        println!("  {:<16}{:>14}", "成功发送", self.sent);
+        println!("  {:<16}{:>14.3}", "总耗时 (s)", secs);
+        println!("  {:<16}{:>14.0}", "吞吐量 (个/s)", throughput);
+        println!(
+            "  {:<16}{:>14.1}",
+            "p50 延迟 (µs)",
+            micros(percentile(&self.latencies, 50.0))
+        );
+        println!(
+            "  {:<16}{:>14.1}",
+            "p99 延迟 (µs)",
+            micros(percentile(&self.latencies, 99.0))
+        );
+        println!(
+            "  {:<16}{:>14.1}",
+            "最大延迟 (µs)",
+            micros(self.latencies.last().copied().unwrap_or_default())
+        );
+        println!(
+            "  {:<16}{:>13.2}%",
+            "缓冲区溢出率",
+            if attempts == 0 {
+                0.0
+            } else {
+                self.overflowed as f64 * 100.0 / attempts as f64
+            }
+        );
+        println!();
+        if throughput >= f64::from(options.rate_hz) && self.overflowed == 0 {
+            println!(
+                "本机可以维持 {} Hz 的发送速率（最大约 {:.0} 个/s）。",
+                options.rate_hz, throughput
+            );
+        } else {
+            println!(
+                "本机无法稳定维持 {} Hz 的发送速率，请降低发送频率。",
+                options.rate_hz
+            );
+        }
+    }
+}
+
+/// 尽快发送 `duration_secs * rate_hz` 个 Bundle 并打印汇总表。
+pub fn run(options: Options, config: &Config, osc_addr: SocketAddr) -> Result<()> {
+    let socket = osc_socket(config, osc_addr).context("创建 OSC 套接字")?;
+    // 非阻塞发送：缓冲区满时立即返回 WouldBlock 计为溢出，而不是阻塞在 send_to 里
+    socket.set_nonblocking(true)?;
+    // parse_options 已拒绝超过上限的组合，这里的限制只防直接构造的 Options
+    let total = options.total_sends().unwrap_or(MAX_SENDS).min(MAX_SENDS);
+    println!(
+        "正在向 {} 发送 {} 个 OSC Bundle（{} 秒 × {} Hz），不经过蓝牙...",
+        osc_addr, total, options.duration_secs, options.rate_hz
+    );
+
+    let bundle_len = encode_hr_bundle(120, OscExtras::default(), config)?.len();
+    let mut report = Report {
+        latencies: Vec::with_capacity(total as usize),
+        ..Report::default()
+    };
+    let start = Instant::now();
+    for i in 0..total {
+        // 心率在 60–200 之间变化，避免每次编码完全相同的数据
+        let heart_rate = 60 + (i % 141) as u8;
+        let send_start = Instant::now();
+        let result = encode_hr_bundle(heart_rate, OscExtras::default(), config)
+            .and_then(|data| send_raw_osc(&socket, osc_addr, &data));
+        let latency = send_start.elapsed();
+        match result {
+            Ok(()) => {
+                report.sent += 1;
+                report.latencies.push(latency);
+            }
+            Err(AppError::Io(e)) if is_buffer_overflow(&e) => report.overflowed += 1,
+            Err(e) => return Err(e),
+        }
+    }
+    report.elapsed = start.elapsed();
+    report.print(options, osc_addr, bundle_len);
+
+    // 测试期间发送的是假心率，结束后把 avatar 恢复为未连接状态
+    socket.set_nonblocking(false)?;
+    let cleared = encode_hr_bundle(0, OscExtras::default(), config)?;
+    send_raw_osc(&socket, osc_addr, &cleared)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn options_default_and_accept_either_order() {
+        assert_eq!(parse_options(&[]), Ok(Options::default()));
+        assert_eq!(
+            parse_options(&args(&["--rate-hz", "500", "--duration-secs", "3"])),
+            Ok(Options {
+                duration_secs: 3,
+                rate_hz: 500
+            })
+        );
+        assert!(parse_options(&args(&["--rate-hz", "0"])).is_err());
+        assert!(parse_options(&args(&["--duration-secs"])).is_err());
+        assert!(
+            parse_options(&args(&["--duration-secs", "1000000", "--rate-hz", "1000"])).is_err()
+        );
+        // 乘积溢出时同样拒绝，而不是回绕成一个小数值
+        let overflow = (u64::MAX / 2).to_string();
+        assert!(parse_options(&args(&["--duration-secs", &overflow, "--rate-hz", "4"])).is_err());
+        assert!(parse_options(&args(&["--bogus", "1"])).is_err());
+    }
+
+    #[test]
+    fn percentile_uses_nearest_rank() {
+        let samples: Vec<Duration> = (1..=100).map(Duration::from_micros).collect();
+        assert_eq!(percentile(&samples, 50.0), Duration::from_micros(50));
+        assert_eq!(percentile(&samples, 99.0), Duration::from_micros(99));
+        assert_eq!(percentile(&samples[..1], 99.0), Duration::from_micros(1));
+        assert_eq!(percentile(&[], 50.0), Duration::ZERO);
+    }
+}
@@ -0,0 +1,208 @@
+//! 仅广播模式（`broadcast_mode = true`）：不连接设备，持续扫描并从广播数据中读取心率。
+//!
+//! Garmin 手表的"广播心率"和部分心率带会在广播包的 0x180D 服务数据里携带心率测量值，
+//! 这样手表可以保持与手机的连接，也省去了连接/订阅过程。个别设备把心率放在厂商数据里，
+//! 可通过 `broadcast_manufacturer_id` / `broadcast_hr_offset` 指定。
+//!
+//! 第一个发出有效心率的广播者会被锁定（按设备 ID），房间里有多块正在广播的手表时
+//! 也不会交替读取；锁定设备的广播中断超过 `heartbeat_timeout_secs` 后解除锁定并清零状态。
+//! 注意 BlueZ 只在广播内容变化时上报，心率长时间不变的设备请适当调大心跳超时。
+
+use std::collections::HashMap;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use btleplug::api::{Central, CentralEvent, Peripheral as _, ScanFilter};
+use btleplug::platform::{Adapter, Manager, PeripheralId};
+use futures_util::StreamExt;
+use tokio::time;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    ble_timeout, clear_state, hr_service_uuids, is_scan_hang, parse_heart_rate_measurement,
+    powered_adapter, reset_ble_stack, serial_source, wait_for_adapter, Config,
+    HeartRateMeasurement, HeartRateSink, Result,
+};
+
+/// 持续接收广播心率，出错或适配器断开后自动恢复，不会返回 Ok。
+pub async fn run(
+    mut manager: Manager,
+    mut central: Adapter,
+    socket: &UdpSocket,
+    osc_addr: SocketAddr,
+    config: &Config,
+    hr_file: &Path,
+) -> Result<()> {
+    loop {
+        let result = listen(&central, socket, osc_addr, config, hr_file).await;
+        clear_state(socket, osc_addr, config, hr_file);
+        match result {
+            Err(e) if is_scan_hang(&e) => {
+                (manager, central) = reset_ble_stack(manager, central).await?;
+                continue;
+            }
+            Err(e) => error!("接收心率广播时发生错误: {}", e),
+            Ok(()) => {}
+        }
+
+        if powered_adapter(&manager).await.is_none() {
+            (manager, central) = wait_for_adapter().await;
+        } else {
+            info!("将在 {} 秒后重新开始接收广播...", config.retry_delay_secs);
+            time::sleep(Duration::from_secs(config.retry_delay_secs)).await;
+        }
+    }
+}
+
+/// 从 0x180D（及配置的额外心率服务）的服务数据中解析心率测量值。
+fn heart_rate_from_service_data(
+    service_data: &HashMap<Uuid, Vec<u8>>,
+    service_uuids: &[Uuid],
+) -> Option<HeartRateMeasurement> {
+    service_uuids
+        .iter()
+        .find_map(|uuid| service_data.get(uuid))
+        .and_then(|value| parse_heart_rate_measurement(value))
+}
+
+/// 按配置的厂商 ID 和字节偏移从厂商数据中读取心率（单字节 BPM）。
+fn heart_rate_from_manufacturer_data(
+    manufacturer_data: &HashMap<u16, Vec<u8>>,
+    config: &Config,
+) -> Option<HeartRateMeasurement> {
+    let data = manufacturer_data.get(&config.broadcast_manufacturer_id?)?;
+    let bpm = *data.get(config.broadcast_hr_offset)?;
+    Some(HeartRateMeasurement {
+        heart_rate: u16::from(bpm),
+        rr_intervals: Vec::new(),
+        energy_expended: None,
+    })
+}
+
+async fn listen(
+    central: &Adapter,
+    socket: &UdpSocket,
+    osc_addr: SocketAddr,
+    config: &Config,
+    hr_file: &Path,
+) -> Result<()> {
+    let mut events = central.events().await?;
+    // 广播心率的设备不一定在服务 UUID 列表里列出 0x180D（只带服务数据或厂商数据），不按服务过滤
+    ble_timeout(
+        "start_scan",
+        config.scan_timeout_secs,
+        central.start_scan(ScanFilter::default()),
+    )
+    .await?;
+    info!("仅广播模式：正在监听心率广播（不连接设备）...");
+
+    let service_uuids = hr_service_uuids(config);
+    let heartbeat = Duration::from_secs(config.heartbeat_timeout_secs);
+    let mut locked: Option<(PeripheralId, HeartRateSink)> = None;
+    let mut last_beat = Instant::now();
+
+    let result = loop {
+        let event =
+            match time::timeout(heartbeat.saturating_sub(last_beat.elapsed()), events.next()).await
+            {
+                Ok(Some(event)) => event,
+                Ok(None) => {
+                    info!("蓝牙事件流已关闭。");
+                    break Ok(());
+                }
+                Err(_) => {
+                    if locked.take().is_some() {
+                        warn!(
+                            "未在 {} 秒内收到锁定设备的心率广播，解除锁定并清零状态。",
+                            config.heartbeat_timeout_secs
+                        );
+                        clear_state(socket, osc_addr, config, hr_file);
+                    }
+                    last_beat = Instant::now();
+                    continue;
+                }
+            };
+
+        let (id, measurement) = match event {
+            CentralEvent::ServiceDataAdvertisement { id, service_data } => {
+                match heart_rate_from_service_data(&service_data, &service_uuids) {
+                    Some(measurement) => (id, measurement),
+                    None => continue,
+                }
+            }
+            CentralEvent::ManufacturerDataAdvertisement {
+                id,
+                manufacturer_data,
+            } => match heart_rate_from_manufacturer_data(&manufacturer_data, config) {
+                Some(measurement) => (id, measurement),
+                None => continue,
+            },
+            _ => continue,
+        };
+
+        // 锁定第一个发出有效心率的广播者，忽略其他设备
+        match &locked {
+            Some((locked_id, _)) if *locked_id != id => continue,
+            Some(_) => {}
+            None => {
+                let address = match central.peripheral(&id).await {
+                    Ok(p) => p.address().to_string(),
+                    Err(_) => id.to_string(),
+                };
+                info!("已锁定心率广播设备 {}", address);
+                let mut sink = HeartRateSink::new(socket, osc_addr, config, hr_file);
+                sink.status.device_address = Some(address);
+                locked = Some((id, sink));
+            }
+        }
+        let Some((_, sink)) = locked.as_mut() else {
+            continue;
+        };
+
+        let now = Instant::now();
+        last_beat = now;
+        serial_source::ble_reading(now);
+        sink.handle(&measurement, now);
+    };
+
+    let _ = central.stop_scan().await;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HEART_RATE_SERVICE_UUID;
+
+    #[test]
+    fn service_data_uses_standard_measurement_format() {
+        let service_data = HashMap::from([(HEART_RATE_SERVICE_UUID, vec![0x00, 88])]);
+        assert_eq!(
+            heart_rate_from_service_data(&service_data, &[HEART_RATE_SERVICE_UUID])
+                .map(|m| m.heart_rate),
+            Some(88)
+        );
+        assert!(
+            heart_rate_from_service_data(&HashMap::new(), &[HEART_RATE_SERVICE_UUID]).is_none()
+        );
+    }
+
+    #[test]
+    fn manufacturer_data_reads_configured_offset() {
+        let config = Config {
+            broadcast_manufacturer_id: Some(0x0087),
+            broadcast_hr_offset: 2,
+            ..Config::default()
+        };
+        let manufacturer_data = HashMap::from([(0x0087, vec![0x01, 0x02, 76])]);
+        assert_eq!(
+            heart_rate_from_manufacturer_data(&manufacturer_data, &config).map(|m| m.heart_rate),
+            Some(76)
+        );
+
+        let unconfigured = Config::default();
+        assert!(heart_rate_from_manufacturer_data(&manufacturer_data, &unconfigured).is_none());
+    }
+}
@@ -0,0 +1,109 @@
+//! 控制台心率显示（`console_display`）：
+//!
+//! - `"simple"`（默认）：原有的文字状态行（OSC 数值、RSSI 等）；
+//! - `"health-bar"`：血条式的一行，如 `♥ [████████░░░░…] 82 BPM`，填充长度与心率百分比成正比，
+//!   按心率区间着色（0–2 绿、3–4 黄、5 红）；
+//! - `"none"`：不显示状态行，只保留日志。
+//!
+//! 两种状态行都经 `logging::status_line` 用 `\r` 原地刷新，日志会先换行再输出。
+//! 只用 ANSI 转义序列着色；`TERM=dumb` 时不输出颜色。
+
+use std::env;
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+use crate::logging;
+
+/// 血条的格数。
+const BAR_WIDTH: usize = 40;
+
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const RED: &str = "\x1b[31m";
+const RESET: &str = "\x1b[0m";
+
+/// 控制台状态行的显示方式。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConsoleDisplay {
+    /// 文字状态行
+    #[default]
+    Simple,
+    /// 随心率伸缩的血条
+    HealthBar,
+    /// 不显示
+    None,
+}
+
+/// 终端是否支持颜色：只在 `TERM=dumb` 时关闭。
+fn colour_enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| env::var("TERM").map_or(true, |term| term != "dumb"))
+}
+
+/// 显示非心率的状态（如空闲）；`none` 时不显示。
+pub fn status(mode: ConsoleDisplay, line: &str) {
+    if mode != ConsoleDisplay::None {
+        logging::status_line(line);
+    }
+}
+
+/// 显示一次心率：`simple` 时显示 `simple_line` 生成的文字，`health-bar` 时显示血条。
+pub fn heart_rate(
+    mode: ConsoleDisplay,
+    bpm: u8,
+    percent: f32,
+    zone: u8,
+    simple_line: impl FnOnce() -> String,
+) {
+    match mode {
+        ConsoleDisplay::Simple => logging::status_line(&simple_line()),
+        ConsoleDisplay::HealthBar => {
+            logging::status_line(&health_bar(bpm, percent, zone, colour_enabled()))
+        }
+        ConsoleDisplay::None => {}
+    }
+}
+
+/// 血条文字；`percent` 为 0–1，超出范围时截断。
+fn health_bar(bpm: u8, percent: f32, zone: u8, colour: bool) -> String {
+    let filled = ((percent.clamp(0.0, 1.0) * BAR_WIDTH as f32).round() as usize).min(BAR_WIDTH);
+    let bar = format!("{}{}", "█".repeat(filled), "░".repeat(BAR_WIDTH - filled));
+    if colour {
+        let colour = match zone {
+            0..=2 => GREEN,
+            3..=4 => YELLOW,
+            _ => RED,
+        };
+        format!("♥ [{}{}{}] {} BPM", colour, bar, RESET, bpm)
+    } else {
+        format!("♥ [{}] {} BPM", bar, bpm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bar_fill_follows_percent_and_colour_follows_zone() {
+        assert_eq!(
+            health_bar(82, 0.2, 0, false),
+            format!("♥ [{}{}] 82 BPM", "█".repeat(8), "░".repeat(32))
+        );
+        assert_eq!(
+            health_bar(0, 0.0, 0, false),
+            format!("♥ [{}] 0 BPM", "░".repeat(40))
+        );
+        assert_eq!(
+            health_bar(250, 1.3, 5, false),
+            format!("♥ [{}] 250 BPM", "█".repeat(40))
+        );
+
+        assert!(health_bar(82, 0.2, 1, true).starts_with("♥ [\x1b[32m█"));
+        assert!(health_bar(150, 0.75, 3, true).contains("\x1b[33m"));
+        assert!(health_bar(190, 0.95, 5, true).contains("\x1b[31m"));
+        assert!(health_bar(190, 0.95, 5, true).ends_with("\x1b[0m] 190 BPM"));
+    }
+}
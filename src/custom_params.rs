@@ -0,0 +1,293 @@
+//! 自定义 OSC 参数（`[[custom_osc_params]]`）：每项由地址、类型和一个表达式组成，
+//! 发送心率时用当前数值求值，结果按类型附加到 Bundle 末尾，无需重新编译即可发送 `(bpm - 60) / 140.0` 等派生值。
+//! 表达式在加载配置时编译（检查语法与变量名），发送时只对编译结果求值，
+//! 每次发送的所有参数共用一份变量上下文。
+//!
+//! 表达式由 evalexpr 求值，可用的变量：
+//!
+//! - `bpm` / `hr`：心率（整数，断开时为 0）；
+//! - `percent`：与 `hr_percent` 相同的 0–1 浮点数；
+//! - `active`：是否有心率（布尔）；
+//! - `zone`：心率区间 0–5（整数）；
+//! - `avg`：本次连接的平均心率（浮点数）；
+//! - `rr`：本次通知中最后一个 RR 间期（毫秒，浮点数）；
+//! - `sdnn`：RR 间期的标准差（毫秒，浮点数）；
+//! - `battery`：连接时读到的电量 0–100（整数）。
+//!
+//! `avg`、`rr`、`sdnn`、`battery` 没有数据时未定义，用到它们的参数此时不发送。
+//! 除 evalexpr 的内置函数（`min`、`max`、`floor`、`round` 等）外还提供 `clamp(x, 最小值, 最大值)`。
+//! 注意 evalexpr 中两个整数相除为整数除法，需要小数时写成 `bpm / 2.0`。
+
+use std::fmt;
+
+use evalexpr::{
+    ContextWithMutableFunctions, ContextWithMutableVariables, EvalexprError, EvalexprResult,
+    Function, HashMapContext, Node, Value,
+};
+use serde::{Deserialize, Serialize};
+
+/// 自定义参数的 OSC 类型。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OscParamType {
+    #[default]
+    Float,
+    Int,
+    Bool,
+}
+
+/// 一个自定义参数（config.toml 中的一个 `[[custom_osc_params]]`）。
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct CustomParam {
+    /// OSC 地址；不以 `/` 开头时视为参数名，加上参数地址前缀
+    #[serde(alias = "addr")]
+    pub address: String,
+    /// 不设置时为 float
+    #[serde(rename = "type", default)]
+    pub kind: OscParamType,
+    #[serde(alias = "expr")]
+    pub expression: String,
+    /// `compile` 的结果；未编译时每次求值都重新解析
+    #[serde(skip)]
+    compiled: Option<Node>,
+}
+
+/// 表达式可用的变量名。
+const VARIABLE_NAMES: [&str; 9] = [
+    "bpm", "hr", "percent", "active", "zone", "avg", "rr", "sdnn", "battery",
+];
+
+/// 表达式可用的变量。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Variables {
+    pub bpm: u8,
+    pub percent: f32,
+    pub active: bool,
+    pub zone: u8,
+    pub avg: Option<f32>,
+    pub rr: Option<f32>,
+    pub sdnn: Option<f32>,
+    pub battery: Option<u8>,
+}
+
+/// 由 `Variables::context` 建立的求值上下文，一次发送中的所有参数共用。
+pub struct Context(HashMapContext);
+
+impl Variables {
+    pub fn context(&self) -> Context {
+        let mut context = HashMapContext::new();
+        let mut set = |name: &str, value| {
+            // 变量名均合法，HashMapContext 设置变量不会失败
+            let _ = context.set_value(name.to_string(), value);
+        };
+        set("bpm", Value::Int(i64::from(self.bpm)));
+        set("hr", Value::Int(i64::from(self.bpm)));
+        set("percent", Value::Float(f64::from(self.percent)));
+        set("active", Value::Boolean(self.active));
+        set("zone", Value::Int(i64::from(self.zone)));
+        let optional = [("avg", self.avg), ("rr", self.rr), ("sdnn", self.sdnn)];
+        for (name, value) in optional {
+            if let Some(value) = value {
+                set(name, Value::Float(f64::from(value)));
+            }
+        }
+        if let Some(battery) = self.battery {
+            set("battery", Value::Int(i64::from(battery)));
+        }
+        let _ = context.set_function("clamp".to_string(), Function::new(clamp));
+        Context(context)
+    }
+}
+
+/// `clamp(x, 最小值, 最大值)`，结果为浮点数。
+fn clamp(argument: &Value) -> EvalexprResult<Value> {
+    let args = argument.as_fixed_len_tuple(3)?;
+    let (x, min, max) = (
+        args[0].as_number()?,
+        args[1].as_number()?,
+        args[2].as_number()?,
+    );
+    if min > max {
+        return Err(EvalexprError::CustomMessage(format!(
+            "clamp 的最小值 {} 大于最大值 {}",
+            min, max
+        )));
+    }
+    Ok(Value::Float(x.clamp(min, max)))
+}
+
+/// 表达式求值失败，或结果无法转换为参数类型。
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalError(String);
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl CustomParam {
+    #[cfg(test)]
+    pub fn new(address: &str, kind: OscParamType, expression: &str) -> Self {
+        CustomParam {
+            address: address.to_string(),
+            kind,
+            expression: expression.to_string(),
+            compiled: None,
+        }
+    }
+
+    /// 在 `context` 中求值并转换为参数类型：数值可互相转换（Int 四舍五入），
+    /// Bool 参数也接受数值（非 0 为 true）。
+    pub fn eval(&self, context: &Context) -> Result<rosc::OscType, EvalError> {
+        let context = &context.0;
+        let value = match &self.compiled {
+            Some(node) => node.eval_with_context(context),
+            None => evalexpr::eval_with_context(&self.expression, context),
+        }
+        .map_err(|e| EvalError(e.to_string()))?;
+        let mismatch = || EvalError(format!("结果 {:?} 无法转换为 {:?}", value, self.kind));
+        Ok(match (self.kind, &value) {
+            (OscParamType::Bool, Value::Boolean(b)) => rosc::OscType::Bool(*b),
+            (OscParamType::Bool, _) => {
+                rosc::OscType::Bool(value.as_number().map_err(|_| mismatch())? != 0.0)
+            }
+            (OscParamType::Int, Value::Int(i)) => {
+                rosc::OscType::Int(i32::try_from(*i).map_err(|_| mismatch())?)
+            }
+            (OscParamType::Int, _) => {
+                let number = value.as_number().map_err(|_| mismatch())?.round();
+                if !(f64::from(i32::MIN)..=f64::from(i32::MAX)).contains(&number) {
+                    return Err(mismatch());
+                }
+                rosc::OscType::Int(number as i32)
+            }
+            (OscParamType::Float, _) => {
+                rosc::OscType::Float(value.as_number().map_err(|_| mismatch())? as f32)
+            }
+        })
+    }
+
+    /// 加载配置时调用：编译表达式，语法错误或用到未知变量时返回错误。
+    /// 不试算：结果取决于发送时的数值（例如除以 `bpm - 80`），运行时出错只跳过那一次发送。
+    pub fn compile(&mut self) -> Result<(), EvalError> {
+        let node = evalexpr::build_operator_tree(&self.expression)
+            .map_err(|e| EvalError(e.to_string()))?;
+        if let Some(unknown) = node
+            .iter_variable_identifiers()
+            .find(|name| !VARIABLE_NAMES.contains(name))
+        {
+            return Err(EvalError(format!("未知变量 {}", unknown)));
+        }
+        self.compiled = Some(node);
+        Ok(())
+    }
+
+    /// 实际发送的地址。
+    pub fn full_address(&self, prefix: &str) -> String {
+        if self.address.starts_with('/') {
+            self.address.clone()
+        } else {
+            format!("{}{}", prefix, self.address)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn param(kind: OscParamType, expression: &str) -> CustomParam {
+        CustomParam::new("hr_custom", kind, expression)
+    }
+
+    #[test]
+    fn expressions_are_evaluated_and_converted_to_the_parameter_type() {
+        let variables = Variables {
+            bpm: 130,
+            percent: 0.65,
+            active: true,
+            zone: 2,
+            avg: Some(120.0),
+            rr: Some(461.9),
+            sdnn: None,
+            battery: None,
+        };
+        let context = variables.context();
+        let eval = |kind, expression| param(kind, expression).eval(&context);
+
+        assert_eq!(
+            eval(OscParamType::Float, "(bpm - 60) / 140.0"),
+            Ok(rosc::OscType::Float(0.5))
+        );
+        assert_eq!(
+            eval(OscParamType::Int, "bpm * 2"),
+            Ok(rosc::OscType::Int(260))
+        );
+        assert_eq!(
+            eval(OscParamType::Int, "percent * 100"),
+            Ok(rosc::OscType::Int(65))
+        );
+        assert_eq!(
+            eval(OscParamType::Bool, "active && zone >= 2"),
+            Ok(rosc::OscType::Bool(true))
+        );
+        assert_eq!(
+            eval(OscParamType::Bool, "zone - 2"),
+            Ok(rosc::OscType::Bool(false))
+        );
+        assert_eq!(
+            eval(OscParamType::Float, "zone"),
+            Ok(rosc::OscType::Float(2.0))
+        );
+
+        assert_eq!(
+            eval(OscParamType::Float, "clamp((hr - 70) / 120.0, 0, 1)"),
+            Ok(rosc::OscType::Float(0.5))
+        );
+        assert_eq!(
+            eval(OscParamType::Float, "clamp(hr - avg, 0, 5)"),
+            Ok(rosc::OscType::Float(5.0))
+        );
+        assert_eq!(eval(OscParamType::Int, "rr"), Ok(rosc::OscType::Int(462)));
+
+        // 没有数据的变量未定义
+        assert!(eval(OscParamType::Float, "sdnn / 100.0").is_err());
+        assert!(eval(OscParamType::Int, "battery").is_err());
+        assert!(eval(OscParamType::Float, "clamp(hr, 1, 0)").is_err());
+        assert!(eval(OscParamType::Int, "active").is_err());
+        assert!(eval(OscParamType::Float, "bpm +").is_err());
+    }
+
+    #[test]
+    fn compile_rejects_syntax_errors_and_unknown_variables() {
+        let mut valid = param(OscParamType::Float, "sdnn / 100.0 + battery + rr + avg");
+        assert_eq!(valid.compile(), Ok(()));
+        assert!(valid.compiled.is_some());
+        assert!(param(OscParamType::Float, "hrv * 2").compile().is_err());
+        assert!(param(OscParamType::Float, "(bpm - 60").compile().is_err());
+        // 只在某些数值下出错的表达式可以编译，出错时只跳过那一次发送
+        assert_eq!(
+            param(OscParamType::Float, "1 / (bpm - 80)").compile(),
+            Ok(())
+        );
+        assert_eq!(
+            param(OscParamType::Float, "clamp(bpm, avg, 200)").compile(),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn relative_addresses_get_the_parameter_prefix() {
+        let prefix = "/avatar/parameters/";
+        assert_eq!(
+            param(OscParamType::Int, "bpm").full_address(prefix),
+            "/avatar/parameters/hr_custom"
+        );
+        let absolute = CustomParam {
+            address: "/chatbox/custom".to_string(),
+            ..param(OscParamType::Int, "bpm")
+        };
+        assert_eq!(absolute.full_address(prefix), "/chatbox/custom");
+    }
+}
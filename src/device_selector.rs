@@ -0,0 +1,159 @@
+//! 扫描结束后从候选设备中选出要连接的一个（`selection_mode`）。
+//!
+//! 选择器只看扫描时取得的广播属性，不做任何蓝牙操作，因此可以脱离适配器单独测试；
+//! "上次使用的设备优先" 不属于选择模式，由调用方在选择器之外处理。
+
+use btleplug::api::PeripheralProperties;
+
+use crate::{sanitize_device_name, Config};
+
+pub trait DeviceSelector: Send + Sync {
+    /// 打印在设备列表之后的选择模式说明。
+    fn describe(&self) -> String;
+
+    /// 返回选中候选的下标（按扫描结果顺序）；没有合适的设备时返回 None。
+    fn select(&self, candidates: &[PeripheralProperties]) -> Option<usize>;
+}
+
+/// 按 `target_device_names` 包含匹配，取第一个匹配的设备。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ByNameSelector {
+    pub names: Vec<String>,
+}
+
+impl DeviceSelector for ByNameSelector {
+    fn describe(&self) -> String {
+        format!("按名称匹配, 关键字: {:?}", self.names)
+    }
+
+    fn select(&self, candidates: &[PeripheralProperties]) -> Option<usize> {
+        candidates.iter().position(|props| {
+            props.local_name.as_deref().is_some_and(|name| {
+                let name = sanitize_device_name(name);
+                self.names
+                    .iter()
+                    .any(|target| name.contains(target.as_str()))
+            })
+        })
+    }
+}
+
+/// 取 RSSI 最强的设备；RSSI 相同时取先扫描到的，没有 RSSI 的设备不参与。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StrongestSignalSelector;
+
+impl DeviceSelector for StrongestSignalSelector {
+    fn describe(&self) -> String {
+        "选择信号最强的设备".to_string()
+    }
+
+    fn select(&self, candidates: &[PeripheralProperties]) -> Option<usize> {
+        let mut best: Option<(usize, i16)> = None;
+        for (index, props) in candidates.iter().enumerate() {
+            if let Some(rssi) = props.rssi {
+                if best.is_none_or(|(_, best_rssi)| rssi > best_rssi) {
+                    best = Some((index, rssi));
+                }
+            }
+        }
+        best.map(|(index, _)| index)
+    }
+}
+
+/// 取第一个扫描到的心率设备。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FirstFoundSelector;
+
+impl DeviceSelector for FirstFoundSelector {
+    fn describe(&self) -> String {
+        "选择第一个扫描到的设备".to_string()
+    }
+
+    fn select(&self, candidates: &[PeripheralProperties]) -> Option<usize> {
+        (!candidates.is_empty()).then_some(0)
+    }
+}
+
+/// 依次尝试各选择器，取第一个有结果的（`auto` = 名称优先，无匹配时信号最强）。
+pub struct FallbackSelector {
+    pub description: String,
+    pub selectors: Vec<Box<dyn DeviceSelector>>,
+}
+
+impl DeviceSelector for FallbackSelector {
+    fn describe(&self) -> String {
+        self.description.clone()
+    }
+
+    fn select(&self, candidates: &[PeripheralProperties]) -> Option<usize> {
+        self.selectors.iter().find_map(|s| s.select(candidates))
+    }
+}
+
+/// 按 `selection_mode` 创建选择器（load_config 已把非法值替换为 auto）。
+pub fn from_config(config: &Config) -> Box<dyn DeviceSelector> {
+    let by_name = || ByNameSelector {
+        names: config.target_device_names.clone(),
+    };
+    match config.selection_mode.as_str() {
+        "name" => Box::new(by_name()),
+        "strongest" => Box::new(StrongestSignalSelector),
+        "first" => Box::new(FirstFoundSelector),
+        _ => Box::new(FallbackSelector {
+            description: format!(
+                "自动（优先匹配名称 {:?}，无匹配时选择信号最强）",
+                config.target_device_names
+            ),
+            selectors: vec![Box::new(by_name()), Box::new(StrongestSignalSelector)],
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(name: Option<&str>, rssi: Option<i16>) -> PeripheralProperties {
+        PeripheralProperties {
+            local_name: name.map(str::to_string),
+            rssi,
+            ..PeripheralProperties::default()
+        }
+    }
+
+    fn selector(mode: &str) -> Box<dyn DeviceSelector> {
+        from_config(&Config {
+            selection_mode: mode.to_string(),
+            target_device_names: vec!["Polar".to_string()],
+            ..Config::default()
+        })
+    }
+
+    #[test]
+    fn each_mode_picks_the_expected_candidate() {
+        let candidates = [
+            device(None, None),
+            device(Some("Band"), Some(-80)),
+            device(Some("Polar H10\u{0}"), Some(-70)),
+            device(Some("Watch"), Some(-50)),
+        ];
+        assert_eq!(selector("name").select(&candidates), Some(2));
+        assert_eq!(selector("strongest").select(&candidates), Some(3));
+        assert_eq!(selector("first").select(&candidates), Some(0));
+        assert_eq!(selector("auto").select(&candidates), Some(2));
+    }
+
+    #[test]
+    fn auto_falls_back_to_strongest_and_empty_scan_selects_nothing() {
+        let candidates = [
+            device(Some("Band"), Some(-80)),
+            device(Some("Watch"), Some(-80)),
+        ];
+        assert_eq!(selector("name").select(&candidates), None);
+        // RSSI 相同时取先扫描到的
+        assert_eq!(selector("auto").select(&candidates), Some(0));
+        for mode in ["auto", "name", "strongest", "first"] {
+            assert_eq!(selector(mode).select(&[]), None);
+        }
+    }
+}
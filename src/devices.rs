@@ -0,0 +1,385 @@
+//! 已知设备库（程序目录下的 `devices.toml`）与 `devices` 子命令：
+//!
+//! - `devices list`：列出保存的设备；
+//! - `devices add --name <别名> --mac <地址>`：保存设备（别名已存在时更新地址）；
+//! - `devices remove <别名>`：删除设备；
+//! - `devices use <别名>`：设为首选设备。
+//!
+//! 扫描时首选设备优先于上次使用的设备和 `selection_mode`：先是 `devices use` 设置的设备，
+//! 随后是 config.toml 的 `preferred_device_order`（别名或 MAC 地址），取第一个在附近的。
+//! 日志中的设备地址后会附上别名。
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::pair;
+
+/// 设备库的文件名（位于程序目录）。
+pub const FILE: &str = "devices.toml";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct KnownDevices {
+    /// `devices use` 设置的首选设备别名
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preferred: Option<String>,
+    pub devices: Vec<KnownDevice>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct KnownDevice {
+    pub alias: String,
+    /// MAC 地址（macOS 上为系统分配的设备 ID），与 last_device.txt 中的格式相同
+    pub mac: String,
+}
+
+/// `devices` 子命令。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    List,
+    Add { alias: String, mac: String },
+    Remove(String),
+    Use(String),
+}
+
+/// 把用户输入的地址规范为设备标识：MAC 地址转为大写冒号格式，macOS 的设备 ID（UUID）转为小写带连字符格式。
+pub fn normalize_key(mac: &str) -> Option<String> {
+    pair::normalize_mac(mac).or_else(|| Uuid::parse_str(mac.trim()).ok().map(|id| id.to_string()))
+}
+
+/// 解析 `devices` 之后的参数；没有子命令时为 `list`。
+pub fn parse_args(args: &[String]) -> Result<Action, String> {
+    let Some((command, rest)) = args.split_first() else {
+        return Ok(Action::List);
+    };
+    match (command.as_str(), rest) {
+        ("list", []) => Ok(Action::List),
+        ("add", options) => parse_add(options),
+        ("remove", [alias]) => Ok(Action::Remove(alias.clone())),
+        ("use", [alias]) => Ok(Action::Use(alias.clone())),
+        ("remove" | "use", []) => Err(format!("devices {} 需要指定设备别名。", command)),
+        ("list" | "remove" | "use", [_, extra, ..]) | ("list", [extra]) => {
+            Err(format!("无法识别的参数: {}", extra))
+        }
+        _ => Err(format!(
+            "无法识别的 devices 子命令: {}（可用 list、add、remove、use）",
+            command
+        )),
+    }
+}
+
+fn parse_add(options: &[String]) -> Result<Action, String> {
+    let (mut alias, mut mac) = (None, None);
+    let mut rest = options.iter();
+    while let Some(flag) = rest.next() {
+        let value = rest.next();
+        match (flag.as_str(), value) {
+            ("--name", Some(value)) if !value.trim().is_empty() => {
+                alias = Some(value.trim().to_string())
+            }
+            ("--name", Some(_)) => return Err("设备别名不能为空。".to_string()),
+            ("--mac", Some(value)) => match normalize_key(value) {
+                Some(key) => mac = Some(key),
+                None => {
+                    return Err(format!(
+                        "无效的 MAC 地址: {}（应形如 AA:BB:CC:DD:EE:FF，macOS 上为设备 ID）",
+                        value
+                    ))
+                }
+            },
+            ("--name" | "--mac", None) => return Err(format!("{} 需要指定值。", flag)),
+            _ => return Err(format!("无法识别的参数: {}", flag)),
+        }
+    }
+    match (alias, mac) {
+        (Some(alias), Some(mac)) => Ok(Action::Add { alias, mac }),
+        _ => Err("devices add 需要同时指定 --name <别名> 和 --mac <地址>。".to_string()),
+    }
+}
+
+impl KnownDevices {
+    /// 读取设备库；文件不存在时为空。
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                toml::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(KnownDevices::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let text = toml::to_string_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(path, text)
+    }
+
+    fn find(&self, alias: &str) -> Option<&KnownDevice> {
+        self.devices.iter().find(|device| device.alias == alias)
+    }
+
+    /// 保存设备；别名已存在时更新地址并返回原地址。
+    pub fn add(&mut self, alias: &str, mac: &str) -> Option<String> {
+        match self.devices.iter_mut().find(|device| device.alias == alias) {
+            Some(device) => Some(std::mem::replace(&mut device.mac, mac.to_string())),
+            None => {
+                self.devices.push(KnownDevice {
+                    alias: alias.to_string(),
+                    mac: mac.to_string(),
+                });
+                None
+            }
+        }
+    }
+
+    /// 删除设备（是首选设备时同时取消首选）；返回是否确实删除了。
+    pub fn remove(&mut self, alias: &str) -> bool {
+        let before = self.devices.len();
+        self.devices.retain(|device| device.alias != alias);
+        if self.preferred.as_deref() == Some(alias) {
+            self.preferred = None;
+        }
+        self.devices.len() != before
+    }
+
+    /// 设为首选设备；别名不存在时返回 false。
+    pub fn set_preferred(&mut self, alias: &str) -> bool {
+        if self.find(alias).is_none() {
+            return false;
+        }
+        self.preferred = Some(alias.to_string());
+        true
+    }
+
+    /// 首选设备的标识，按优先级排列：`devices use` 的首选设备在前，随后是 `order`
+    /// （preferred_device_order，别名或地址）。第二项为无法识别的条目。
+    fn preferred_keys(&self, order: &[String]) -> (Vec<String>, Vec<String>) {
+        let mut keys: Vec<String> = Vec::new();
+        let mut unknown = Vec::new();
+        let entries = self.preferred.iter().chain(order);
+        for entry in entries {
+            let key = match self.find(entry) {
+                Some(device) => Some(device.mac.clone()),
+                None => normalize_key(entry),
+            };
+            match key {
+                Some(key) if !keys.contains(&key) => keys.push(key),
+                Some(_) => {}
+                None => unknown.push(entry.clone()),
+            }
+        }
+        (keys, unknown)
+    }
+}
+
+/// 执行 `devices` 子命令，结果输出到标准输出。
+pub fn run(action: &Action, path: &Path) -> io::Result<()> {
+    let mut known = KnownDevices::load(path)?;
+    match action {
+        Action::List => {
+            if known.devices.is_empty() {
+                println!(
+                    "设备库 {} 中还没有设备，可用 devices add --name <别名> --mac <地址> 添加。",
+                    path.display()
+                );
+            }
+            for device in &known.devices {
+                let preferred = known.preferred.as_deref() == Some(device.alias.as_str());
+                println!(
+                    "{} {}  {}",
+                    if preferred { "*" } else { " " },
+                    device.alias,
+                    device.mac
+                );
+            }
+            if known.preferred.is_some() {
+                println!("（* 为首选设备）");
+            }
+            return Ok(());
+        }
+        Action::Add { alias, mac } => match known.add(alias, mac) {
+            Some(old) if old == *mac => println!("设备 {} 已保存，地址未变（{}）。", alias, mac),
+            Some(old) => println!("已将设备 {} 的地址由 {} 改为 {}。", alias, old, mac),
+            None => println!("已保存设备 {}（{}）。", alias, mac),
+        },
+        Action::Remove(alias) => {
+            if !known.remove(alias) {
+                println!("设备库中没有设备 {}。", alias);
+                return Ok(());
+            }
+            println!("已删除设备 {}。", alias);
+        }
+        Action::Use(alias) => {
+            if !known.set_preferred(alias) {
+                println!(
+                    "设备库中没有设备 {}，请先用 devices add --name {} --mac <地址> 添加。",
+                    alias, alias
+                );
+                return Ok(());
+            }
+            println!("已将 {} 设为首选设备，下次扫描时在附近即优先连接。", alias);
+        }
+    }
+    known.save(path)
+}
+
+/// 运行期间使用的设备库：别名与首选顺序。
+#[derive(Debug, Default)]
+struct Registry {
+    devices: Vec<KnownDevice>,
+    preferred: Vec<String>,
+}
+
+static REGISTRY: OnceLock<Registry> = OnceLock::new();
+
+/// 启动时调用一次：读取设备库并解析首选顺序，无法识别的条目只警告。
+pub fn init(path: &Path, order: &[String]) {
+    let known = KnownDevices::load(path).unwrap_or_else(|e| {
+        warn!(
+            "读取设备库 {} 失败（{}），将不使用别名和首选设备。",
+            path.display(),
+            e
+        );
+        KnownDevices::default()
+    });
+    let (preferred, unknown) = known.preferred_keys(order);
+    for entry in unknown {
+        warn!(
+            "preferred_device_order 中的 {} 既不是设备库中的别名，也不是有效的地址，已忽略。",
+            entry
+        );
+    }
+    let _ = REGISTRY.set(Registry {
+        devices: known.devices,
+        preferred,
+    });
+}
+
+/// 设备标识对应的别名。
+pub fn alias(key: &str) -> Option<&'static str> {
+    REGISTRY
+        .get()?
+        .devices
+        .iter()
+        .find_map(|device| (device.mac.eq_ignore_ascii_case(key)).then_some(device.alias.as_str()))
+}
+
+/// 设备标识在首选顺序中的位置；不是首选设备时为 None。
+pub fn preference_rank(key: &str) -> Option<usize> {
+    REGISTRY
+        .get()?
+        .preferred
+        .iter()
+        .position(|preferred| preferred.eq_ignore_ascii_case(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_subcommands() {
+        assert_eq!(parse_args(&[]), Ok(Action::List));
+        assert_eq!(parse_args(&args(&["list"])), Ok(Action::List));
+        assert_eq!(
+            parse_args(&args(&[
+                "add",
+                "--name",
+                "chest",
+                "--mac",
+                "aa:bb:cc:dd:ee:ff"
+            ])),
+            Ok(Action::Add {
+                alias: "chest".to_string(),
+                mac: "AA:BB:CC:DD:EE:FF".to_string()
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&[
+                "add",
+                "--mac",
+                "8F1C6E2A-0B7D-4E43-9A3B-2C5D7E9F1A20",
+                "--name",
+                "band"
+            ])),
+            Ok(Action::Add {
+                alias: "band".to_string(),
+                mac: "8f1c6e2a-0b7d-4e43-9a3b-2c5d7e9f1a20".to_string()
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&["remove", "band"])),
+            Ok(Action::Remove("band".to_string()))
+        );
+        assert_eq!(
+            parse_args(&args(&["use", "chest"])),
+            Ok(Action::Use("chest".to_string()))
+        );
+
+        assert!(parse_args(&args(&["add", "--name", "chest"])).is_err());
+        assert!(parse_args(&args(&["add", "--name", "chest", "--mac", "nope"])).is_err());
+        assert!(parse_args(&args(&["add", "--name", " ", "--mac", "AA:BB:CC:DD:EE:FF"])).is_err());
+        assert!(parse_args(&args(&["use"])).is_err());
+        assert!(parse_args(&args(&["use", "a", "b"])).is_err());
+        assert!(parse_args(&args(&["list", "all"])).is_err());
+        assert!(parse_args(&args(&["rename"])).is_err());
+    }
+
+    #[test]
+    fn add_remove_and_prefer_devices() {
+        let mut known = KnownDevices::default();
+        assert_eq!(known.add("band", "AA:BB:CC:DD:EE:01"), None);
+        assert_eq!(known.add("chest", "AA:BB:CC:DD:EE:02"), None);
+        assert_eq!(
+            known.add("band", "AA:BB:CC:DD:EE:03").as_deref(),
+            Some("AA:BB:CC:DD:EE:01")
+        );
+        assert_eq!(known.devices.len(), 2);
+
+        assert!(!known.set_preferred("watch"));
+        assert!(known.set_preferred("chest"));
+        assert_eq!(known.preferred.as_deref(), Some("chest"));
+
+        let order = args(&["band", "chest", "11:22:33:44:55:66", "watch"]);
+        let (keys, unknown) = known.preferred_keys(&order);
+        assert_eq!(
+            keys,
+            [
+                "AA:BB:CC:DD:EE:02",
+                "AA:BB:CC:DD:EE:03",
+                "11:22:33:44:55:66"
+            ]
+        );
+        assert_eq!(unknown, ["watch"]);
+
+        assert!(known.remove("chest"));
+        assert!(!known.remove("chest"));
+        assert_eq!(known.preferred, None);
+    }
+
+    #[test]
+    fn database_round_trips_through_toml() {
+        let dir = std::env::temp_dir().join(format!("hr-devices-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(FILE);
+        assert_eq!(KnownDevices::load(&path).unwrap(), KnownDevices::default());
+
+        let mut known = KnownDevices::default();
+        known.add("band", "AA:BB:CC:DD:EE:01");
+        known.set_preferred("band");
+        known.save(&path).unwrap();
+        assert_eq!(KnownDevices::load(&path).unwrap(), known);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,415 @@
+//! Discord 通知（`discord_enabled = true`）：通过 `discord_webhook_url`（频道设置 → 整合 → Webhook）
+//! 发送带颜色的嵌入消息，在 VR 中看不到控制台时也能从手机/手表上收到提醒：
+//!
+//! - 设备断开后 `discord_disconnect_alert_secs` 秒仍未恢复连接（每次断开只提醒一次，0 表示关闭）；
+//! - 连接时读到的电量低于 `discord_battery_threshold`%（充电到阈值以上后才会再次提醒，0 表示关闭）；
+//! - 退出时发送本次运行总结（`discord_session_summary`）。
+//!
+//! 消息正文由 `discord_*_message` 模板生成，可用占位符 `{device}`（设备地址）、`{secs}`、`{battery}`、`{summary}`。
+//! 消息经后台任务逐条发送，两条之间至少间隔 `MIN_INTERVAL`，收到 429 时按 `Retry-After` 等待后重发，
+//! 不会触发 Discord 的限流；心率处理路径上只有一次通道写入。退出总结在退出清理中同步发送。
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio::time;
+use tracing::{debug, warn};
+
+use crate::http_client::{self, Post, StatusError, Target};
+use crate::recorder::{self, Event, Reading, Recorder};
+use crate::template::fill;
+use crate::Config;
+
+/// 两条消息之间的最短间隔（Discord 对每个 Webhook 约限制每分钟 30 条）。
+const MIN_INTERVAL: Duration = Duration::from_secs(2);
+/// 每条消息最多尝试发送的次数；429 未给出 Retry-After 时的等待时间。
+const DELIVERY_ATTEMPTS: u32 = 3;
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(5);
+/// 等待处理的事件数上限，超过时丢弃新事件。
+const QUEUE_CAPACITY: usize = 16;
+
+/// 嵌入消息左侧色条的颜色。
+const COLOR_DISCONNECT: u32 = 0xE74C3C;
+const COLOR_BATTERY: u32 = 0xF39C12;
+const COLOR_SUMMARY: u32 = 0x3498DB;
+
+/// 消息模板及阈值（来自配置）。
+#[derive(Debug, Clone)]
+struct Settings {
+    target: Target,
+    disconnect_alert: Option<Duration>,
+    battery_threshold: u8,
+    session_summary: bool,
+    disconnect_message: String,
+    battery_message: String,
+    summary_message: String,
+}
+
+impl Settings {
+    fn new(config: &Config) -> Result<Self, String> {
+        Ok(Settings {
+            target: http_client::parse_url(&config.discord_webhook_url)?,
+            disconnect_alert: (config.discord_disconnect_alert_secs > 0)
+                .then(|| Duration::from_secs(config.discord_disconnect_alert_secs)),
+            battery_threshold: config.discord_battery_threshold,
+            session_summary: config.discord_session_summary,
+            disconnect_message: config.discord_disconnect_message.clone(),
+            battery_message: config.discord_battery_message.clone(),
+            summary_message: config.discord_summary_message.clone(),
+        })
+    }
+}
+
+/// 一条待发送的嵌入消息。
+#[derive(Debug, Clone, PartialEq)]
+struct Message {
+    title: &'static str,
+    description: String,
+    color: u32,
+    at: DateTime<Local>,
+}
+
+#[derive(Serialize)]
+struct Embed<'a> {
+    title: &'a str,
+    description: &'a str,
+    color: u32,
+    timestamp: String,
+}
+
+#[derive(Serialize)]
+struct Body<'a> {
+    embeds: [Embed<'a>; 1],
+}
+
+impl Message {
+    fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(&Body {
+            embeds: [Embed {
+                title: self.title,
+                description: &self.description,
+                color: self.color,
+                timestamp: recorder::timestamp(self.at),
+            }],
+        })
+    }
+
+    fn post<'a>(&'a self, target: &'a Target, body: &'a str) -> Post<'a> {
+        Post {
+            target,
+            content_type: "application/json",
+            headers: &[],
+            body,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Input {
+    Connected(String),
+    Disconnected,
+    Battery { device: String, level: u8 },
+}
+
+/// 根据连接事件和电量决定何时发送提醒。
+#[derive(Debug)]
+struct Watcher {
+    settings: Settings,
+    device: Option<String>,
+    /// 尚未恢复的断开：提醒时间、断开时间
+    outage: Option<(Instant, DateTime<Local>)>,
+    /// 已提醒过低电量，充电到阈值以上前不再提醒
+    battery_alerted: bool,
+}
+
+impl Watcher {
+    fn new(settings: Settings) -> Self {
+        Watcher {
+            settings,
+            device: None,
+            outage: None,
+            battery_alerted: false,
+        }
+    }
+
+    fn input(&mut self, input: Input, now: Instant, at: DateTime<Local>) -> Option<Message> {
+        match input {
+            Input::Connected(device) => {
+                self.device = Some(device);
+                self.outage = None;
+                None
+            }
+            Input::Disconnected => {
+                if let (None, Some(delay)) = (&self.outage, self.settings.disconnect_alert) {
+                    self.outage = Some((now + delay, at));
+                }
+                None
+            }
+            Input::Battery { device, level } => {
+                let threshold = self.settings.battery_threshold;
+                if level >= threshold {
+                    self.battery_alerted = false;
+                    return None;
+                }
+                if self.battery_alerted {
+                    return None;
+                }
+                self.battery_alerted = true;
+                Some(Message {
+                    title: "心率带电量低",
+                    description: fill(
+                        &self.settings.battery_message,
+                        &[("device", &device), ("battery", &level.to_string())],
+                    ),
+                    color: COLOR_BATTERY,
+                    at,
+                })
+            }
+        }
+    }
+
+    /// 断开已超过提醒时间且仍未恢复时返回提醒。
+    fn due(&mut self, now: Instant) -> Option<Message> {
+        let (deadline, since) = self.outage?;
+        if now < deadline {
+            return None;
+        }
+        self.outage = None;
+        let secs = self
+            .settings
+            .disconnect_alert
+            .unwrap_or_default()
+            .as_secs()
+            .to_string();
+        Some(Message {
+            title: "心率带已断开",
+            description: fill(
+                &self.settings.disconnect_message,
+                &[
+                    ("device", self.device.as_deref().unwrap_or("未知设备")),
+                    ("secs", &secs),
+                ],
+            ),
+            color: COLOR_DISCONNECT,
+            at: since,
+        })
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.outage.map(|(deadline, _)| deadline)
+    }
+}
+
+struct Shared {
+    sender: mpsc::Sender<Input>,
+    settings: Settings,
+}
+
+static SHARED: Mutex<Option<Shared>> = Mutex::new(None);
+
+/// 启动时调用（需在 tokio 运行时内）：启动后台任务并接收连接事件。地址无效时返回说明。
+pub fn start(config: &Config) -> Result<(), String> {
+    let settings = Settings::new(config)?;
+    let (sender, inputs) = mpsc::channel(QUEUE_CAPACITY);
+    let (outgoing, messages) = mpsc::channel(QUEUE_CAPACITY);
+    tokio::spawn(watch(inputs, Watcher::new(settings.clone()), outgoing));
+    tokio::spawn(deliver(messages, settings.target.clone()));
+    *SHARED.lock().unwrap() = Some(Shared { sender, settings });
+    recorder::register(Box::new(Tracker));
+    Ok(())
+}
+
+/// 是否已启用（`start` 已调用）。
+pub fn is_enabled() -> bool {
+    SHARED.lock().unwrap().is_some()
+}
+
+fn send(input: Input) {
+    if let Some(shared) = SHARED.lock().unwrap().as_ref() {
+        if shared.sender.try_send(input).is_err() {
+            debug!("Discord 通知队列已满，丢弃事件");
+        }
+    }
+}
+
+/// 连接时读到设备电量后调用。
+pub fn battery(device: &str, level: u8) {
+    send(Input::Battery {
+        device: device.to_string(),
+        level,
+    });
+}
+
+/// 退出清理中调用：同步发送本次运行总结（未启用或已关闭时不做任何事）。
+pub fn session_summary(summary: &str) {
+    let Some(shared) = SHARED.lock().unwrap().take() else {
+        return;
+    };
+    let settings = shared.settings;
+    if !settings.session_summary {
+        return;
+    }
+    let message = Message {
+        title: "本次运行总结",
+        description: fill(&settings.summary_message, &[("summary", summary)]),
+        color: COLOR_SUMMARY,
+        at: Local::now(),
+    };
+    let result = message
+        .to_json()
+        .map_err(|e| e.to_string())
+        .and_then(|body| {
+            message
+                .post(&settings.target, &body)
+                .send_blocking()
+                .map_err(|e| e.to_string())
+        });
+    if let Err(e) = result {
+        warn!("发送 Discord 运行总结失败: {}", e);
+    }
+}
+
+/// 把连接事件转交给后台任务。
+struct Tracker;
+
+impl Recorder for Tracker {
+    fn reading(&mut self, _at: DateTime<Local>, _reading: &Reading) {}
+
+    fn event(&mut self, _at: DateTime<Local>, event: &Event) {
+        match event {
+            Event::Connected(device) => send(Input::Connected(device.clone())),
+            Event::Disconnected | Event::Rescan => send(Input::Disconnected),
+            Event::Start | Event::Idle | Event::Active | Event::Stop => {}
+        }
+    }
+
+    fn finish(&mut self) {}
+}
+
+/// 处理事件并在断开超时时生成提醒，直到发送端被丢弃。
+async fn watch(
+    mut inputs: mpsc::Receiver<Input>,
+    mut watcher: Watcher,
+    outgoing: mpsc::Sender<Message>,
+) {
+    loop {
+        let message = match watcher.deadline() {
+            Some(deadline) => tokio::select! {
+                input = inputs.recv() => match input {
+                    Some(input) => watcher.input(input, Instant::now(), Local::now()),
+                    None => return,
+                },
+                () = time::sleep_until(deadline.into()) => watcher.due(Instant::now()),
+            },
+            None => match inputs.recv().await {
+                Some(input) => watcher.input(input, Instant::now(), Local::now()),
+                None => return,
+            },
+        };
+        if let Some(message) = message {
+            if outgoing.try_send(message).is_err() {
+                debug!("Discord 发送队列已满，丢弃消息");
+            }
+        }
+    }
+}
+
+/// 逐条发送消息，遵守最短间隔和 429 的 Retry-After。
+async fn deliver(mut messages: mpsc::Receiver<Message>, target: Target) {
+    while let Some(message) = messages.recv().await {
+        let body = match message.to_json() {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("生成 Discord 消息时出错: {}", e);
+                continue;
+            }
+        };
+        for attempt in 1..=DELIVERY_ATTEMPTS {
+            let e = match message.post(&target, &body).send().await {
+                Ok(()) => break,
+                Err(e) => e,
+            };
+            let wait = match StatusError::from_io(&e) {
+                Some(status) if status.code == 429 => {
+                    status.retry_after.unwrap_or(DEFAULT_RETRY_AFTER)
+                }
+                _ => MIN_INTERVAL,
+            };
+            if attempt == DELIVERY_ATTEMPTS {
+                warn!(
+                    "发送 Discord 通知「{}」失败（已尝试 {} 次）: {}",
+                    message.title, DELIVERY_ATTEMPTS, e
+                );
+            } else {
+                debug!("发送 Discord 通知失败（第 {} 次）: {}", attempt, e);
+                time::sleep(wait).await;
+            }
+        }
+        time::sleep(MIN_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watcher() -> Watcher {
+        let config = Config {
+            discord_webhook_url: "https://discord.com/api/webhooks/1/token".to_string(),
+            ..Config::default()
+        };
+        Watcher::new(Settings::new(&config).unwrap())
+    }
+
+    #[test]
+    fn disconnect_alert_fires_once_unless_reconnected() {
+        let mut watcher = watcher();
+        let t0 = Instant::now();
+        let secs = |s| t0 + Duration::from_secs(s);
+        let at = Local::now();
+
+        watcher.input(Input::Connected("A0:9E".to_string()), secs(0), at);
+        // 30 秒内恢复：不提醒
+        watcher.input(Input::Disconnected, secs(10), at);
+        watcher.input(Input::Connected("A0:9E".to_string()), secs(40), at);
+        assert_eq!(watcher.due(secs(100)), None);
+
+        watcher.input(Input::Disconnected, secs(200), at);
+        // 重连失败又记一次断开，不推迟提醒时间
+        watcher.input(Input::Disconnected, secs(230), at);
+        assert_eq!(watcher.due(secs(259)), None);
+        let message = watcher.due(secs(260)).unwrap();
+        assert_eq!(
+            message.description,
+            "心率带 A0:9E 已断开 60 秒，仍未恢复连接。"
+        );
+        assert_eq!(watcher.due(secs(400)), None);
+    }
+
+    #[test]
+    fn low_battery_alerts_again_only_after_charging() {
+        let mut watcher = watcher();
+        let now = Instant::now();
+        let at = Local::now();
+        let mut battery = |level| {
+            watcher
+                .input(
+                    Input::Battery {
+                        device: "A0:9E".to_string(),
+                        level,
+                    },
+                    now,
+                    at,
+                )
+                .is_some()
+        };
+        assert!(!battery(50));
+        assert!(battery(15));
+        assert!(!battery(12));
+        assert!(!battery(80));
+        assert!(battery(19));
+    }
+}
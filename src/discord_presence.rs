@@ -0,0 +1,244 @@
+//! Discord 动态（Rich Presence，`discord_presence_enabled = true`）：通过本机 Discord 客户端的 IPC，
+//! 在个人资料中显示"正在玩 <应用名称>"及实时心率，如 `❤ 96 BPM`。
+//!
+//! 需要先在 Discord 开发者后台创建一个应用，把应用 ID 填入 `discord_presence_client_id`；
+//! 应用名称就是"正在玩"后面显示的名称（例如命名为 VRChat）。
+//! 两行文字由 `discord_presence_details` / `discord_presence_state` 模板生成，占位符与 `heart_rate_file_format`
+//! 相同（见 template 模块）；未连接或未佩戴时第一行显示 `discord_presence_offline`，不显示第二行。
+//!
+//! Discord 限制动态约每 15 秒更新一次，这里最多每 `UPDATE_INTERVAL` 发送一次最新状态。
+//! IPC 是阻塞调用，放在单独的线程中；Discord 未运行或中途退出时每 `RETRY_INTERVAL` 重试一次，只警告一次。
+//! 退出时清除动态。
+
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use discord_rich_presence::{activity, DiscordIpc, DiscordIpcClient};
+use tracing::{debug, info, warn};
+
+use crate::template::{self, Values};
+use crate::Config;
+
+/// 两次更新动态的最短间隔（Discord 的限流）。
+const UPDATE_INTERVAL: Duration = Duration::from_secs(15);
+/// 连接不上 Discord 时的重试间隔。
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+/// 退出时等待清除动态的最长时间。
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// 动态的文字模板。
+#[derive(Debug, Clone)]
+struct Settings {
+    client_id: String,
+    details: String,
+    state: String,
+    offline: String,
+}
+
+impl Settings {
+    /// 动态的两行文字；`None`（未连接/未佩戴）时只有第一行。空行不显示。
+    fn lines(&self, values: Option<&Values>) -> (String, Option<String>) {
+        match values {
+            Some(values) => {
+                let state = template::render(&self.state, values);
+                (
+                    template::render(&self.details, values),
+                    (!state.trim().is_empty()).then_some(state),
+                )
+            }
+            None => (self.offline.clone(), None),
+        }
+    }
+}
+
+enum Update {
+    /// 最新心率的模板数值，未连接或未佩戴时为 None
+    Values(Option<Values>),
+    /// 清除动态后回复
+    Shutdown(mpsc::SyncSender<()>),
+}
+
+static SENDER: Mutex<Option<mpsc::Sender<Update>>> = Mutex::new(None);
+
+/// 启动时调用：在后台线程中连接 Discord，之后 `publish` 才会生效。
+pub fn start(config: &Config) {
+    let settings = Settings {
+        client_id: config.discord_presence_client_id.clone(),
+        details: config.discord_presence_details.clone(),
+        state: config.discord_presence_state.clone(),
+        offline: config.discord_presence_offline.clone(),
+    };
+    let (sender, updates) = mpsc::channel();
+    *SENDER.lock().unwrap() = Some(sender);
+    thread::spawn(move || run(settings, updates));
+}
+
+/// 是否已启用（`start` 已调用）。
+pub fn is_enabled() -> bool {
+    SENDER.lock().unwrap().is_some()
+}
+
+/// 更新要显示的心率；后台线程按限流间隔发送最新的一次。未启用时不做任何事。
+pub fn publish(values: Option<Values>) {
+    if let Some(sender) = SENDER.lock().unwrap().as_ref() {
+        let _ = sender.send(Update::Values(values));
+    }
+}
+
+/// 退出清理中调用：清除动态并断开，最多等待 `SHUTDOWN_TIMEOUT`。
+pub fn shutdown() {
+    let Some(sender) = SENDER.lock().unwrap().take() else {
+        return;
+    };
+    let (done, wait) = mpsc::sync_channel(1);
+    if sender.send(Update::Shutdown(done)).is_ok() {
+        let _ = wait.recv_timeout(SHUTDOWN_TIMEOUT);
+    }
+}
+
+/// 与 Discord 客户端的连接。
+struct Presence {
+    settings: Settings,
+    client: Option<DiscordIpcClient>,
+    next_connect: Instant,
+    /// 程序启动时间（Unix 秒），显示为"已进行 xx:xx"
+    started: i64,
+    warned: bool,
+}
+
+impl Presence {
+    fn connect(&mut self) -> Option<&mut DiscordIpcClient> {
+        if self.client.is_none() && Instant::now() >= self.next_connect {
+            let result = DiscordIpcClient::new(&self.settings.client_id).and_then(|mut client| {
+                client.connect()?;
+                Ok(client)
+            });
+            match result {
+                Ok(client) => {
+                    info!("已连接 Discord，将在动态中显示心率。");
+                    self.client = Some(client);
+                    self.warned = false;
+                }
+                Err(e) => {
+                    if self.warned {
+                        debug!("连接 Discord 失败: {}", e);
+                    } else {
+                        warn!(
+                            "无法连接 Discord（{}），请确认 Discord 客户端已启动；将每 {} 秒重试。",
+                            e,
+                            RETRY_INTERVAL.as_secs()
+                        );
+                        self.warned = true;
+                    }
+                    self.next_connect = Instant::now() + RETRY_INTERVAL;
+                }
+            }
+        }
+        self.client.as_mut()
+    }
+
+    /// 发送动态，返回是否成功；失败时断开，稍后重连。
+    fn set(&mut self, values: Option<&Values>) -> bool {
+        let (details, state) = self.settings.lines(values);
+        let started = self.started;
+        let Some(client) = self.connect() else {
+            return false;
+        };
+        let mut activity = activity::Activity::new()
+            .details(&details)
+            .timestamps(activity::Timestamps::new().start(started));
+        if let Some(state) = &state {
+            activity = activity.state(state);
+        }
+        match client.set_activity(activity) {
+            Ok(()) => true,
+            Err(e) => {
+                info!("与 Discord 的连接已断开（{}），将自动重连。", e);
+                self.client = None;
+                self.next_connect = Instant::now() + RETRY_INTERVAL;
+                false
+            }
+        }
+    }
+
+    fn close(&mut self) {
+        if let Some(mut client) = self.client.take() {
+            let _ = client.clear_activity();
+            let _ = client.close();
+        }
+    }
+}
+
+/// 后台线程：合并更新，最多每 `UPDATE_INTERVAL` 发送一次。
+fn run(settings: Settings, updates: mpsc::Receiver<Update>) {
+    let mut presence = Presence {
+        settings,
+        client: None,
+        next_connect: Instant::now(),
+        started: chrono::Local::now().timestamp(),
+        warned: false,
+    };
+    let mut latest: Option<Values> = None;
+    let mut dirty = true;
+    let mut next_send = Instant::now();
+    loop {
+        match updates.recv_timeout(next_send.saturating_duration_since(Instant::now())) {
+            Ok(Update::Values(values)) => {
+                if values != latest {
+                    latest = values;
+                    dirty = true;
+                }
+                continue;
+            }
+            Ok(Update::Shutdown(done)) => {
+                presence.close();
+                let _ = done.send(());
+                return;
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                presence.close();
+                return;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+        if dirty && presence.set(latest.as_ref()) {
+            dirty = false;
+        }
+        next_send = Instant::now() + UPDATE_INTERVAL;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_use_templates_and_offline_text() {
+        let config = Config::default();
+        let settings = Settings {
+            client_id: String::new(),
+            details: config.discord_presence_details,
+            state: "区间 {zone}".to_string(),
+            offline: config.discord_presence_offline,
+        };
+        let values = Values {
+            hr: 96,
+            percent: 48,
+            zone: 0,
+            avg: 94,
+        };
+        assert_eq!(
+            settings.lines(Some(&values)),
+            ("❤ 96 BPM".to_string(), Some("区间 0".to_string()))
+        );
+        assert_eq!(settings.lines(None), ("心率带未连接".to_string(), None));
+
+        let settings = Settings {
+            state: String::new(),
+            ..settings
+        };
+        assert_eq!(settings.lines(Some(&values)).1, None);
+    }
+}
@@ -0,0 +1,311 @@
+//! `--discover-uuids <MAC>`：为使用私有 UUID 的非标准心率设备探测正确的服务/特征。
+//!
+//! 连接指定设备后列出全部服务与特征，逐个订阅带 Notify/Indicate 的特征并试听几秒，
+//! 按心率测量格式（首字节 flags + 心率值）或"首字节即心率"的私有格式判断哪个特征在发送心率，
+//! 最后输出可直接粘贴进 config.toml 的配置片段。
+
+use std::time::Duration;
+
+use btleplug::api::{Central, CharPropFlags, Characteristic, Peripheral as _, ScanFilter};
+use btleplug::platform::{Adapter, Peripheral};
+use futures_util::StreamExt;
+use tokio::time;
+
+use crate::{
+    acquire_adapter, ble_timeout, parse_heart_rate_measurement, AppError, Config, ConnectionGuard,
+    PayloadFormat, Result, HEART_RATE_CHAR_UUID, HEART_RATE_SERVICE_UUID,
+};
+
+/// 每个特征订阅后收集数据的时长（秒）。
+const LISTEN_SECS: u64 = 3;
+
+/// 单个特征的试听结果。
+struct Probe {
+    characteristic: Characteristic,
+    samples: Vec<Vec<u8>>,
+}
+
+impl Probe {
+    /// 能按心率测量格式解析出合理心率的样本数。
+    fn heart_rate_like_samples(&self) -> usize {
+        self.samples
+            .iter()
+            .filter(|value| looks_like_heart_rate(value))
+            .count()
+    }
+
+    /// 猜测数据格式：有样本符合标准心率测量格式时为 standard；
+    /// 否则所有样本首字节都像心率（30–220）时为 raw-u8；都不像时返回 None。
+    fn guessed_format(&self) -> Option<PayloadFormat> {
+        if self.heart_rate_like_samples() > 0 {
+            Some(PayloadFormat::Standard)
+        } else if !self.samples.is_empty()
+            && self
+                .samples
+                .iter()
+                .all(|value| value.first().is_some_and(|bpm| (30..=220).contains(bpm)))
+        {
+            Some(PayloadFormat::RawU8)
+        } else {
+            None
+        }
+    }
+}
+
+/// 首字节是合法的心率 flags（保留位 5–7 为 0），且按规范解析出的心率在 25–250 BPM 之间。
+fn looks_like_heart_rate(value: &[u8]) -> bool {
+    value.first().is_some_and(|flags| flags & 0xE0 == 0)
+        && parse_heart_rate_measurement(value).is_some_and(|m| (25..=250).contains(&m.heart_rate))
+}
+
+/// 连接 `target`（MAC 地址，macOS 上为设备 ID）并打印探测报告。
+pub async fn run(config: &Config, target: &str) -> Result<()> {
+    let (_manager, central) = acquire_adapter().await?;
+    let device = find_by_address(&central, config, target).await?;
+
+    println!("正在连接 {} ...", target);
+    ble_timeout("connect", config.connect_timeout_secs, device.connect()).await?;
+    let guard = ConnectionGuard::new(&device);
+    let probes = probe_device(&device, config).await;
+    guard.teardown().await;
+
+    print_report(&probes?);
+    Ok(())
+}
+
+async fn find_by_address(central: &Adapter, config: &Config, target: &str) -> Result<Peripheral> {
+    println!(
+        "正在扫描 {} 秒，查找 {} ...",
+        config.scan_duration_secs, target
+    );
+    // 非标准设备往往不广播 0x180D，这里不按服务过滤
+    ble_timeout(
+        "start_scan",
+        config.scan_timeout_secs,
+        central.start_scan(ScanFilter::default()),
+    )
+    .await?;
+    time::sleep(Duration::from_secs(config.scan_duration_secs)).await;
+    let peripherals = central.peripherals().await?;
+    let _ = central.stop_scan().await;
+
+    peripherals
+        .into_iter()
+        .find(|p| {
+            p.address().to_string().eq_ignore_ascii_case(target)
+                || p.id().to_string().eq_ignore_ascii_case(target)
+        })
+        .ok_or(AppError::DeviceNotFound)
+}
+
+async fn probe_device(device: &Peripheral, config: &Config) -> Result<Vec<Probe>> {
+    ble_timeout(
+        "discover_services",
+        config.service_timeout_secs,
+        device.discover_services(),
+    )
+    .await?;
+
+    let characteristics = device.characteristics();
+    println!("\n共发现 {} 个特征:", characteristics.len());
+    for c in &characteristics {
+        println!(
+            "服务 {} | 特征 {} | {:?}",
+            c.service_uuid, c.uuid, c.properties
+        );
+    }
+
+    let mut notifications = device.notifications().await?;
+    let mut probes = Vec::new();
+    for characteristic in characteristics.into_iter().filter(|c| {
+        c.properties
+            .intersects(CharPropFlags::NOTIFY | CharPropFlags::INDICATE)
+    }) {
+        println!(
+            "\n试听特征 {}（{} 秒）...",
+            characteristic.uuid, LISTEN_SECS
+        );
+        if let Err(e) = ble_timeout(
+            "subscribe",
+            config.service_timeout_secs,
+            device.subscribe(&characteristic),
+        )
+        .await
+        {
+            eprintln!("订阅失败: {}", e);
+            continue;
+        }
+
+        let mut samples = Vec::new();
+        let deadline = time::Instant::now() + Duration::from_secs(LISTEN_SECS);
+        while let Ok(Some(notification)) = time::timeout_at(deadline, notifications.next()).await {
+            if notification.uuid == characteristic.uuid {
+                samples.push(notification.value);
+            }
+        }
+
+        if let Err(e) = ble_timeout(
+            "unsubscribe",
+            config.service_timeout_secs,
+            device.unsubscribe(&characteristic),
+        )
+        .await
+        {
+            eprintln!("退订失败: {}", e);
+        }
+        probes.push(Probe {
+            characteristic,
+            samples,
+        });
+    }
+    Ok(probes)
+}
+
+fn hex(value: &[u8]) -> String {
+    value
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn print_report(probes: &[Probe]) {
+    println!("\n===== 试听结果 =====");
+    for probe in probes {
+        println!(
+            "服务 {} | 特征 {} | 收到 {} 条，疑似心率 {} 条 | 首条数据: {}",
+            probe.characteristic.service_uuid,
+            probe.characteristic.uuid,
+            probe.samples.len(),
+            probe.heart_rate_like_samples(),
+            probe.samples.first().map_or("-".to_string(), |v| hex(v))
+        );
+    }
+
+    // 标准格式优先于 raw-u8 猜测；其次疑似心率样本最多者；再次优先标准 0x2A37
+    let best = probes
+        .iter()
+        .filter_map(|p| p.guessed_format().map(|format| (p, format)))
+        .max_by_key(|(p, format)| {
+            (
+                *format == PayloadFormat::Standard,
+                p.heart_rate_like_samples(),
+                p.characteristic.uuid == HEART_RATE_CHAR_UUID,
+            )
+        });
+    match best {
+        None => println!(
+            "\n没有特征发出疑似心率的数据。请确认设备已开启心率广播（或正在测量心率）后重试。"
+        ),
+        Some((probe, format)) => match config_snippet(&probe.characteristic, format) {
+            None => println!("\n该设备使用标准心率服务与特征，无需修改配置。"),
+            Some(snippet) => {
+                println!(
+                    "\n最可能的心率特征为上面的 {}（数据格式 {}）。",
+                    probe.characteristic.uuid, format
+                );
+                println!("将以下内容粘贴到 config.toml（替换同名配置项）：\n");
+                print!("{}", snippet);
+            }
+        },
+    }
+}
+
+/// 生成让主程序识别该特征所需的配置片段；标准服务 + 标准特征时无需配置，返回 None。
+fn config_snippet(characteristic: &Characteristic, format: PayloadFormat) -> Option<String> {
+    let mut snippet = String::new();
+    if characteristic.service_uuid != HEART_RATE_SERVICE_UUID {
+        snippet.push_str(&format!(
+            "additional_hr_service_uuids = [\"{}\"]\n",
+            characteristic.service_uuid
+        ));
+    }
+    if characteristic.uuid != HEART_RATE_CHAR_UUID {
+        snippet.push_str(&format!(
+            "additional_hr_char_uuids = [\"{}\"]\n",
+            characteristic.uuid
+        ));
+    }
+    if format != PayloadFormat::Standard {
+        snippet.push_str(&format!(
+            "hr_char_formats = {{ \"{}\" = \"{}\" }}\n",
+            characteristic.uuid, format
+        ));
+    }
+    (!snippet.is_empty()).then_some(snippet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+    use uuid::Uuid;
+
+    fn characteristic(service_uuid: Uuid, uuid: Uuid) -> Characteristic {
+        Characteristic {
+            uuid,
+            service_uuid,
+            properties: CharPropFlags::NOTIFY,
+            descriptors: BTreeSet::new(),
+        }
+    }
+
+    #[test]
+    fn looks_like_heart_rate_checks_flags_and_range() {
+        assert!(looks_like_heart_rate(&[0x00, 72]));
+        assert!(looks_like_heart_rate(&[0x10, 72, 0x00, 0x04]));
+        assert!(
+            !looks_like_heart_rate(&[0x80, 72]),
+            "reserved flag bits set"
+        );
+        assert!(!looks_like_heart_rate(&[0x00, 3]), "implausible heart rate");
+        assert!(!looks_like_heart_rate(&[0x00]));
+    }
+
+    #[test]
+    fn guessed_format_falls_back_to_raw_u8() {
+        let probe = |samples: Vec<Vec<u8>>| Probe {
+            characteristic: characteristic(HEART_RATE_SERVICE_UUID, HEART_RATE_CHAR_UUID),
+            samples,
+        };
+
+        assert_eq!(
+            probe(vec![vec![0x00, 72]]).guessed_format(),
+            Some(PayloadFormat::Standard)
+        );
+        assert_eq!(
+            probe(vec![vec![72, 0xFF], vec![75, 0xFF]]).guessed_format(),
+            Some(PayloadFormat::RawU8)
+        );
+        assert_eq!(probe(vec![vec![0xFF, 0xFF]]).guessed_format(), None);
+        assert_eq!(probe(Vec::new()).guessed_format(), None);
+    }
+
+    #[test]
+    fn config_snippet_only_lists_non_standard_uuids() {
+        let custom = Uuid::from_u128(0x6e40_0001_b5a3_f393_e0a9_e50e_24dc_ca9e);
+
+        assert_eq!(
+            config_snippet(
+                &characteristic(HEART_RATE_SERVICE_UUID, HEART_RATE_CHAR_UUID),
+                PayloadFormat::Standard
+            ),
+            None
+        );
+        assert_eq!(
+            config_snippet(
+                &characteristic(custom, HEART_RATE_CHAR_UUID),
+                PayloadFormat::Standard
+            )
+            .as_deref(),
+            Some(format!("additional_hr_service_uuids = [\"{}\"]\n", custom).as_str())
+        );
+        let snippet =
+            config_snippet(&characteristic(custom, custom), PayloadFormat::RawU8).unwrap();
+        assert!(snippet.contains("additional_hr_char_uuids"));
+        assert!(snippet.contains(&format!(
+            "hr_char_formats = {{ \"{}\" = \"raw-u8\" }}",
+            custom
+        )));
+    }
+}
@@ -0,0 +1,145 @@
+//! 后台写文件：心率文件、单值文件和 status.json 由专门的线程写入，接收循环只提交最新内容，
+//! 磁盘缓慢或文件被锁定（OneDrive 等同步目录很常见）时不会拖慢 OSC 发送。
+//!
+//! 同一文件在写入前收到的多次提交只写最后一次；写入失败时保留内容、稍后重试（期间有新内容则改写新内容），
+//! 持续失败每分钟只提示一次。
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Condvar, Mutex, MutexGuard, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+use crate::replace_file;
+
+/// 写入失败后等待多久再重试。
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// 同一文件持续写入失败时两次提示的最小间隔。
+const ERROR_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Default)]
+struct Queue {
+    /// 待写入的内容，每个文件只保留最新的一份，按首次提交的顺序写入
+    pending: Vec<(PathBuf, String)>,
+    /// 写入线程正在写一批内容
+    busy: bool,
+}
+
+impl Queue {
+    fn is_idle(&self) -> bool {
+        self.pending.is_empty() && !self.busy
+    }
+}
+
+static QUEUE: Mutex<Queue> = Mutex::new(Queue {
+    pending: Vec::new(),
+    busy: false,
+});
+/// 有新内容时唤醒写入线程
+static SUBMITTED: Condvar = Condvar::new();
+/// 写完一批后唤醒 `flush`
+static WRITTEN: Condvar = Condvar::new();
+static WRITER: OnceLock<()> = OnceLock::new();
+
+fn queue() -> MutexGuard<'static, Queue> {
+    QUEUE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// 提交文件的最新内容，由写入线程稍后写入；不会阻塞。第一次调用时启动写入线程。
+pub fn submit(path: PathBuf, content: String) {
+    WRITER.get_or_init(|| {
+        thread::spawn(run);
+    });
+    let mut queue = queue();
+    match queue.pending.iter_mut().find(|(p, _)| *p == path) {
+        Some((_, pending)) => *pending = content,
+        None => queue.pending.push((path, content)),
+    }
+    SUBMITTED.notify_one();
+}
+
+/// 等待已提交的内容写完（最多 `timeout`），返回是否全部写完。退出前调用，避免离线内容还没落盘进程就结束了。
+pub fn flush(timeout: Duration) -> bool {
+    let (queue, _) = WRITTEN
+        .wait_timeout_while(queue(), timeout, |queue| !queue.is_idle())
+        .unwrap_or_else(|e| e.into_inner());
+    queue.is_idle()
+}
+
+fn run() {
+    // 每个持续写入失败的文件上次提示的时间
+    let mut failing: HashMap<PathBuf, Instant> = HashMap::new();
+    loop {
+        let batch = {
+            let mut queue = SUBMITTED
+                .wait_while(queue(), |queue| queue.pending.is_empty())
+                .unwrap_or_else(|e| e.into_inner());
+            queue.busy = true;
+            std::mem::take(&mut queue.pending)
+        };
+
+        let mut failed = Vec::new();
+        for (path, content) in batch {
+            match replace_file(&path, &content) {
+                Ok(()) => {
+                    if failing.remove(&path).is_some() {
+                        info!("已恢复写入文件 {}", path.display());
+                    }
+                }
+                Err(e) => {
+                    let now = Instant::now();
+                    let report = failing
+                        .get(&path)
+                        .is_none_or(|last| now.duration_since(*last) >= ERROR_REPORT_INTERVAL);
+                    if report {
+                        warn!(
+                            "写入文件 {} 时出错: {}（将继续重试，每分钟最多提示一次）",
+                            path.display(),
+                            e
+                        );
+                        failing.insert(path.clone(), now);
+                    }
+                    failed.push((path, content));
+                }
+            }
+        }
+
+        let retry = !failed.is_empty();
+        {
+            let mut queue = queue();
+            queue.busy = false;
+            // 等待期间提交了新内容的文件直接写新内容
+            for (path, content) in failed {
+                if !queue.pending.iter().any(|(p, _)| *p == path) {
+                    queue.pending.push((path, content));
+                }
+            }
+            WRITTEN.notify_all();
+        }
+        if retry {
+            thread::sleep(RETRY_DELAY);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
+
+    #[test]
+    fn latest_submission_wins_and_flush_waits_for_disk() {
+        let dir = env::temp_dir().join(format!("hr-vrc-writer-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("HeartRate.txt");
+        for bpm in 60..=80 {
+            submit(path.clone(), bpm.to_string());
+        }
+        assert!(flush(Duration::from_secs(5)));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "80");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
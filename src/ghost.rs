@@ -0,0 +1,244 @@
+//! 断线保持（`ghost_mode_secs`）：通知流关闭或心跳超时后，继续以 1 Hz 重发最后一次有效心率
+//! （isHRActive = true），持续 `ghost_mode_secs` 秒后才清零，避免短暂断线时 avatar 上的心率闪成 0。
+//!
+//! 重发在独立的 tokio 任务中进行，不阻塞重连；收到新的真实读数、重置请求或退出时立即取消。
+//! 定时发送（`send_mode = "scheduled"`）在保持开始时暂停，收到新读数后恢复。
+
+use std::net::{SocketAddr, UdpSocket};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+use tokio::time;
+use tracing::info;
+
+use crate::scheduler::{self, Schedule};
+use crate::{clear_state, send_osc, Config, OscExtras};
+
+/// 断线保持的状态：最近一次发往 VRChat 的有效心率（未佩戴的 0 会清除它）和正在运行的保持任务。
+///
+/// 退出清理（Windows 上在控制台事件处理例程内）也会调用 `cancel`，锁中毒时直接取回数据，不 panic。
+struct Ghost {
+    last_hr: Mutex<Option<u8>>,
+    task: Mutex<Option<JoinHandle<()>>>,
+    /// 保持期间暂停的定时发送，避免它与保持任务交替发送不同的数值
+    schedule: &'static Schedule,
+}
+
+static GHOST: Ghost = Ghost::new(&scheduler::SCHEDULE);
+
+impl Ghost {
+    const fn new(schedule: &'static Schedule) -> Self {
+        Ghost {
+            last_hr: Mutex::new(None),
+            task: Mutex::new(None),
+            schedule,
+        }
+    }
+
+    fn record(&self, heart_rate: u8) {
+        *self.last_hr.lock().unwrap_or_else(|e| e.into_inner()) =
+            (heart_rate > 0).then_some(heart_rate);
+    }
+
+    /// 取出最近一次有效心率；取出后清空，同一读数只会被保持一次。
+    fn take_last(&self) -> Option<u8> {
+        self.last_hr
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+    }
+
+    fn start(
+        &self,
+        socket: &UdpSocket,
+        osc_addr: SocketAddr,
+        config: &Config,
+        hr_file: PathBuf,
+    ) -> bool {
+        self.cancel();
+        if config.ghost_mode_secs == 0 {
+            return false;
+        }
+        let Some(heart_rate) = self.take_last() else {
+            return false;
+        };
+        let Ok(socket) = socket.try_clone() else {
+            return false;
+        };
+        self.schedule.clear();
+        let config = config.clone();
+        let task = tokio::spawn(async move {
+            let mut ticker = time::interval(Duration::from_secs(1));
+            for remaining in (1..=config.ghost_mode_secs).rev() {
+                ticker.tick().await;
+                let _ = send_osc(&socket, osc_addr, heart_rate, OscExtras::default(), &config);
+                info!(
+                    "ghost mode active: {}s remaining（断线保持，继续发送心率 {}）",
+                    remaining, heart_rate
+                );
+            }
+            ticker.tick().await;
+            clear_state(&socket, osc_addr, &config, &hr_file);
+            info!("断线保持结束，已清零心率。");
+        });
+        *self.task.lock().unwrap_or_else(|e| e.into_inner()) = Some(task);
+        true
+    }
+
+    fn cancel(&self) {
+        if let Some(task) = self.task.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            task.abort();
+        }
+    }
+}
+
+/// 记录发往 VRChat 的心率，供断线后重发。
+pub fn record(heart_rate: u8) {
+    GHOST.record(heart_rate);
+}
+
+/// 断线后调用：有可保持的心率时启动保持任务并返回 true，结束后由任务负责清零；
+/// 否则返回 false，由调用方立即清零。
+pub fn start(socket: &UdpSocket, osc_addr: SocketAddr, config: &Config, hr_file: PathBuf) -> bool {
+    GHOST.start(socket, osc_addr, config, hr_file)
+}
+
+/// 立即停止断线保持（收到新的真实读数、重置请求或退出时）。
+pub fn cancel() {
+    GHOST.cancel();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_osc::{assert_param_bool, assert_param_int, TestOscReceiver};
+    use crate::SendMode;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn disabled_ghost_mode_leaves_clearing_to_the_caller() {
+        let ghost = Ghost::new(private_schedule());
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let config = Config {
+            ghost_mode_secs: 0,
+            ..Config::default()
+        };
+        ghost.record(72);
+        let target = SocketAddr::from((Ipv4Addr::LOCALHOST, 9000));
+        assert!(!ghost.start(&socket, target, &config, PathBuf::from("HeartRate.txt")));
+    }
+
+    /// 每个测试独立的定时发送状态，不受其他测试的读数影响。
+    fn private_schedule() -> &'static Schedule {
+        Box::leak(Box::new(Schedule::new()))
+    }
+
+    fn ghost_config(secs: u64) -> Config {
+        Config {
+            ghost_mode_secs: secs,
+            write_heart_rate_file: false,
+            ..Config::default()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn last_heart_rate_is_resent_every_second_then_cleared() {
+        let ghost = Ghost::new(private_schedule());
+        let receiver = TestOscReceiver::bind();
+        let sender = UdpSocket::bind("127.0.0.1:0").expect("bind OSC sender");
+        ghost.record(72);
+        assert!(ghost.start(
+            &sender,
+            receiver.addr(),
+            &ghost_config(3),
+            PathBuf::from("unused-heart-rate.txt")
+        ));
+        // 启动时已取出该读数，同一读数只会被保持一次
+        assert_eq!(ghost.take_last(), None);
+
+        // 每秒恰好重发一次，共 ghost_mode_secs 次
+        time::sleep(Duration::from_millis(500)).await;
+        for _ in 0..3 {
+            let held = receiver.recv_bundle();
+            assert_param_int(&held, "HR", 72);
+            assert_param_bool(&held, "hr_connected", true);
+            assert!(receiver.try_recv_bundle().is_none());
+            time::sleep(Duration::from_secs(1)).await;
+        }
+
+        let cleared = receiver.recv_bundle();
+        assert_param_int(&cleared, "HR", 0);
+        assert_param_bool(&cleared, "hr_connected", false);
+        time::sleep(Duration::from_secs(5)).await;
+        assert!(receiver.try_recv_bundle().is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn new_reading_cancels_the_hold_without_clearing() {
+        let ghost = Ghost::new(private_schedule());
+        let receiver = TestOscReceiver::bind();
+        let sender = UdpSocket::bind("127.0.0.1:0").expect("bind OSC sender");
+        ghost.record(90);
+        assert!(ghost.start(
+            &sender,
+            receiver.addr(),
+            &ghost_config(5),
+            PathBuf::from("unused-heart-rate.txt")
+        ));
+
+        time::sleep(Duration::from_millis(1500)).await;
+        assert_param_int(&receiver.recv_bundle(), "HR", 90);
+        assert_param_int(&receiver.recv_bundle(), "HR", 90);
+
+        // HeartRateSink 收到新的真实读数时先 cancel 再 record
+        ghost.cancel();
+        ghost.record(95);
+        time::sleep(Duration::from_secs(10)).await;
+        assert!(receiver.try_recv_bundle().is_none());
+        assert_eq!(ghost.take_last(), Some(95));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn scheduled_sending_pauses_while_the_hold_is_active() {
+        let schedule = private_schedule();
+        let ghost = Ghost::new(schedule);
+        let receiver = TestOscReceiver::bind();
+        let sender = UdpSocket::bind("127.0.0.1:0").expect("bind OSC sender");
+        let config = Config {
+            send_mode: SendMode::Scheduled,
+            hr_alarm_high: Some(70),
+            ..ghost_config(3)
+        };
+        schedule.start(&sender, receiver.addr(), &config);
+        let alarm = OscExtras {
+            alarm: true,
+            ..OscExtras::default()
+        };
+        schedule.update(72, alarm, &config);
+        ghost.record(72);
+
+        // 定时发送的第一帧带着读数时的报警状态
+        time::sleep(Duration::from_millis(100)).await;
+        let scheduled = receiver.recv_bundle();
+        assert_param_int(&scheduled, "HR", 72);
+        assert_param_bool(&scheduled, "hr_alarm", true);
+        assert!(receiver.try_recv_bundle().is_none());
+
+        assert!(ghost.start(
+            &sender,
+            receiver.addr(),
+            &config,
+            PathBuf::from("unused-heart-rate.txt")
+        ));
+        // 保持期间只有保持任务每秒一帧，定时发送不再以 send_rate_hz 插入旧状态
+        time::sleep(Duration::from_millis(2500)).await;
+        for _ in 0..3 {
+            let held = receiver.recv_bundle();
+            assert_param_int(&held, "HR", 72);
+            assert_param_bool(&held, "hr_alarm", false);
+        }
+        assert!(receiver.try_recv_bundle().is_none());
+    }
+}
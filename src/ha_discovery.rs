@@ -0,0 +1,208 @@
+//! Home Assistant MQTT 自动发现（`mqtt_ha_discovery = true`，需同时开启 mqtt_enabled）：
+//! 设备连接后向 `<mqtt_ha_discovery_prefix>/<组件>/<节点>/<对象>/config` 发布保留的配置消息，
+//! Home Assistant 会在同一个设备条目下自动创建三个实体：
+//!
+//! - `sensor` 心率（bpm，读取状态 JSON 的 `bpm`）；
+//! - `sensor` 电量（%，device_class battery，读取 `battery`）；
+//! - `binary_sensor` 连接状态（device_class connectivity，读取可用性主题的 online / offline）。
+//!
+//! 节点名和 unique_id 由设备地址生成，重新配对或重装程序不会产生重复实体；换一个设备则是另一个设备条目。
+//! `mqtt_ha_discovery_remove = true` 时改为向同样的主题发布空的保留消息，Home Assistant 随之删除这些实体。
+//! 发布时机（设备地址变化时、与代理重连后）由 mqtt 模块负责。
+
+use serde::Serialize;
+
+use crate::Config;
+
+/// 发现消息用到的配置。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settings {
+    prefix: String,
+    state_topic: String,
+    availability_topic: String,
+    remove: bool,
+}
+
+impl Settings {
+    /// 未开启 mqtt_ha_discovery（且不是在清除实体）时返回 None。
+    pub fn new(config: &Config) -> Option<Self> {
+        if !config.mqtt_ha_discovery && !config.mqtt_ha_discovery_remove {
+            return None;
+        }
+        Some(Settings {
+            prefix: config.mqtt_ha_discovery_prefix.clone(),
+            state_topic: config.mqtt_state_topic.clone(),
+            availability_topic: config.mqtt_availability_topic.clone(),
+            remove: config.mqtt_ha_discovery_remove,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct Device<'a> {
+    identifiers: [&'a str; 1],
+    /// [["bluetooth", 地址]]，让 Home Assistant 能与其蓝牙集成里的同一设备关联
+    connections: [[&'a str; 2]; 1],
+    name: &'a str,
+}
+
+#[derive(Serialize)]
+struct Entity<'a> {
+    name: &'static str,
+    unique_id: String,
+    state_topic: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value_template: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit_of_measurement: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device_class: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state_class: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    entity_category: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    icon: Option<&'static str>,
+    /// 心率和电量在设备断开时显示为"不可用"；连接状态实体本身读取该主题，不设置
+    #[serde(skip_serializing_if = "Option::is_none")]
+    availability_topic: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload_on: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload_off: Option<&'static str>,
+    device: &'a Device<'a>,
+}
+
+/// 由设备地址生成节点名：只保留字母数字并转为小写（`AA:BB:..` → `heartrate_aabb..`），
+/// 满足 Home Assistant 对节点名和 unique_id 的字符要求。
+fn node_id(address: &str) -> String {
+    let id: String = address
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    format!("heartrate_{}", id)
+}
+
+/// 某个设备的全部发现消息（主题, 内容）。清除模式下内容为空。
+pub fn messages(
+    settings: &Settings,
+    address: &str,
+    device_name: Option<&str>,
+) -> serde_json::Result<Vec<(String, String)>> {
+    let node = node_id(address);
+    let device = Device {
+        identifiers: [node.as_str()],
+        connections: [["bluetooth", address]],
+        name: device_name.unwrap_or("Heart Rate Band"),
+    };
+    let entity = |name, object| Entity {
+        name,
+        unique_id: format!("{}_{}", node, object),
+        state_topic: &settings.state_topic,
+        value_template: None,
+        unit_of_measurement: None,
+        device_class: None,
+        state_class: None,
+        entity_category: None,
+        icon: None,
+        availability_topic: Some(&settings.availability_topic),
+        payload_on: None,
+        payload_off: None,
+        device: &device,
+    };
+    let entities = [
+        (
+            "sensor",
+            "heart_rate",
+            Entity {
+                value_template: Some("{{ value_json.bpm }}"),
+                unit_of_measurement: Some("bpm"),
+                state_class: Some("measurement"),
+                // Home Assistant 没有心率的 device_class
+                icon: Some("mdi:heart-pulse"),
+                ..entity("Heart Rate", "heart_rate")
+            },
+        ),
+        (
+            "sensor",
+            "battery",
+            Entity {
+                value_template: Some("{{ value_json.battery }}"),
+                unit_of_measurement: Some("%"),
+                device_class: Some("battery"),
+                state_class: Some("measurement"),
+                entity_category: Some("diagnostic"),
+                ..entity("Battery", "battery")
+            },
+        ),
+        (
+            "binary_sensor",
+            "connectivity",
+            Entity {
+                state_topic: &settings.availability_topic,
+                device_class: Some("connectivity"),
+                availability_topic: None,
+                payload_on: Some("online"),
+                payload_off: Some("offline"),
+                ..entity("Connected", "connectivity")
+            },
+        ),
+    ];
+    entities
+        .iter()
+        .map(|(component, object, entity)| {
+            let topic = format!(
+                "{}/{}/{}/{}/config",
+                settings.prefix, component, node, object
+            );
+            let payload = if settings.remove {
+                String::new()
+            } else {
+                serde_json::to_string(entity)?
+            };
+            Ok((topic, payload))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(remove: bool) -> Settings {
+        Settings {
+            prefix: "homeassistant".to_string(),
+            state_topic: "heartrate/state".to_string(),
+            availability_topic: "heartrate/availability".to_string(),
+            remove,
+        }
+    }
+
+    #[test]
+    fn node_id_is_derived_from_address() {
+        assert_eq!(node_id("A0:9E:1A:00:BC:12"), "heartrate_a09e1a00bc12");
+        // macOS 上为设备 UUID
+        assert_eq!(node_id("5C1A-32F0-ab"), "heartrate_5c1a32f0ab");
+    }
+
+    #[test]
+    fn topics_cover_three_entities_and_removal_sends_empty_payloads() {
+        let topics: Vec<String> = messages(&settings(true), "A0:9E:1A:00:BC:12", None)
+            .unwrap()
+            .into_iter()
+            .map(|(topic, payload)| {
+                assert!(payload.is_empty());
+                topic
+            })
+            .collect();
+        assert_eq!(
+            topics,
+            [
+                "homeassistant/sensor/heartrate_a09e1a00bc12/heart_rate/config",
+                "homeassistant/sensor/heartrate_a09e1a00bc12/battery/config",
+                "homeassistant/binary_sensor/heartrate_a09e1a00bc12/connectivity/config",
+            ]
+        );
+    }
+}
@@ -0,0 +1,340 @@
+//! 心跳震动（`haptics_enabled = true`）：每次心跳向 `haptics_osc_addresses` 发送一个短脉冲，
+//! 让触觉背心等设备随心跳震动。可以是 bHaptics 的 OSC 接收端，也可以是背心映射到的 avatar 参数。
+//!
+//! 心跳时刻由 `BeatClock` 推算：设备提供 RR 间期时以最近一次间期为周期（与真实心跳同步），
+//! 否则由 BPM 合成。每个脉冲先发送开（Float 为强度、Bool 为 true），`haptics_pulse_ms` 毫秒后发送关（0.0 / false）。
+//! 强度按心率区间（见 template 模块）在 `haptics_intensity_min` 与 `haptics_intensity_max` 之间线性变化。
+//!
+//! 脉冲在独立的 tokio 任务中调度，发往 `osc_ip` 的 `haptics_osc_port`（不设置则与心率相同的端口）。
+//! 断开、未佩戴或超过 `STALE_AFTER` 没有新读数时停止，正在进行的脉冲立即发送关。
+
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio::time;
+use tracing::debug;
+
+use crate::{auto_gain, osc_socket, send_raw_osc, template, Config, OscTimetag};
+
+/// 超过该时间没有新读数时停止震动（不按旧心率一直震下去）。
+const STALE_AFTER: Duration = Duration::from_secs(3);
+/// 可信的 RR 间期范围（毫秒），对应 30–240 BPM；超出的间期改用 BPM 合成。
+const RR_RANGE_MS: std::ops::RangeInclusive<u32> = 250..=2000;
+/// 等待调度任务处理的读数上限，超过时丢弃。
+const QUEUE_CAPACITY: usize = 32;
+
+/// 脉冲的取值类型。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HapticValue {
+    /// 开为强度 0–1，关为 0.0（bHaptics 及多数 avatar 参数）
+    #[default]
+    Float,
+    /// 开为 true，关为 false（不支持强度）
+    Bool,
+}
+
+/// 由心率读数推算下一次心跳的时刻。
+#[derive(Debug, Default)]
+pub struct BeatClock {
+    period: Option<Duration>,
+    next: Option<Instant>,
+    updated: Option<Instant>,
+}
+
+impl BeatClock {
+    /// 收到新读数。RR 间期单位为 1/1024 秒，有可信的间期时以最后一个为周期；心率 0 时停止。
+    pub fn update(&mut self, bpm: u8, rr_intervals: &[u16], now: Instant) {
+        if bpm == 0 {
+            self.stop();
+            return;
+        }
+        let rr_ms = rr_intervals
+            .last()
+            .map(|&rr| u32::from(rr) * 1000 / 1024)
+            .filter(|ms| RR_RANGE_MS.contains(ms));
+        let period = match rr_ms {
+            Some(ms) => Duration::from_millis(u64::from(ms)),
+            None => Duration::from_secs(60) / u32::from(bpm),
+        };
+        self.period = Some(period);
+        self.updated = Some(now);
+        // 保持相位，只在心率变快时把下一拍提前，避免连续读数让节奏忽快忽慢
+        let latest = now + period;
+        self.next = Some(self.next.map_or(now, |next| next.min(latest)));
+    }
+
+    /// 下一次心跳的时刻；已停止或读数过期时为 None。
+    pub fn next_beat(&self) -> Option<Instant> {
+        let next = self.next?;
+        let updated = self.updated?;
+        (next.saturating_duration_since(updated) <= STALE_AFTER).then_some(next)
+    }
+
+    /// 心跳已发出，推进到下一拍。
+    pub fn advance(&mut self, now: Instant) {
+        if let (Some(next), Some(period)) = (self.next, self.period) {
+            // 任务被延迟时不补发错过的拍子
+            self.next = Some((next + period).max(now));
+        }
+    }
+
+    pub fn stop(&mut self) {
+        *self = BeatClock::default();
+    }
+}
+
+/// 脉冲设置（来自配置）。
+#[derive(Debug, Clone)]
+struct Settings {
+    addresses: Vec<String>,
+    value: HapticValue,
+    pulse: Duration,
+    intensity_min: f32,
+    intensity_max: f32,
+    /// max_heart_rate_for_percent（0 为自动）
+    max_hr: f32,
+}
+
+impl Settings {
+    /// 心率区间 0–5 线性映射到强度范围。
+    fn intensity(&self, bpm: u8) -> f32 {
+        let zone = f32::from(template::zone(bpm, auto_gain::resolve(self.max_hr)));
+        self.intensity_min + (self.intensity_max - self.intensity_min) * zone / 5.0
+    }
+
+    /// 开（`Some(强度)`）或关（`None`）的 OSC 数据包。
+    fn encode(&self, intensity: Option<f32>) -> Result<Vec<u8>, rosc::OscError> {
+        let arg = match self.value {
+            HapticValue::Float => rosc::OscType::Float(intensity.unwrap_or(0.0)),
+            HapticValue::Bool => rosc::OscType::Bool(intensity.is_some()),
+        };
+        let content = self
+            .addresses
+            .iter()
+            .map(|addr| {
+                rosc::OscPacket::Message(rosc::OscMessage {
+                    addr: addr.clone(),
+                    args: vec![arg.clone()],
+                })
+            })
+            .collect();
+        rosc::encoder::encode(&rosc::OscPacket::Bundle(rosc::OscBundle {
+            timetag: OscTimetag::IMMEDIATELY,
+            content,
+        }))
+    }
+}
+
+enum Input {
+    Reading {
+        bpm: u8,
+        rr_intervals: Vec<u16>,
+        at: Instant,
+    },
+    Stop,
+}
+
+struct Shared {
+    sender: mpsc::Sender<Input>,
+    socket: UdpSocket,
+    target: SocketAddr,
+    settings: Settings,
+}
+
+static SHARED: Mutex<Option<Shared>> = Mutex::new(None);
+
+/// 启动时调用（需在 tokio 运行时内）：创建套接字并启动调度任务，返回脉冲发往的地址。
+pub fn start(config: &Config, osc_addr: SocketAddr) -> std::io::Result<SocketAddr> {
+    let target = SocketAddr::new(
+        osc_addr.ip(),
+        config.haptics_osc_port.unwrap_or(osc_addr.port()),
+    );
+    let socket = osc_socket(config, target)?;
+    let settings = Settings {
+        addresses: config.haptics_osc_addresses.clone(),
+        value: config.haptics_value,
+        pulse: Duration::from_millis(config.haptics_pulse_ms),
+        intensity_min: config.haptics_intensity_min,
+        intensity_max: config.haptics_intensity_max,
+        max_hr: config.max_heart_rate_for_percent,
+    };
+    let (sender, inputs) = mpsc::channel(QUEUE_CAPACITY);
+    tokio::spawn(run(inputs, socket.try_clone()?, target, settings.clone()));
+    *SHARED.lock().unwrap() = Some(Shared {
+        sender,
+        socket,
+        target,
+        settings,
+    });
+    Ok(target)
+}
+
+fn send(input: Input) {
+    if let Some(shared) = SHARED.lock().unwrap().as_ref() {
+        if shared.sender.try_send(input).is_err() {
+            debug!("心跳震动队列已满，丢弃读数");
+        }
+    }
+}
+
+/// 每次心率读数后调用（未启用时不做任何事）。
+pub fn reading(bpm: u8, rr_intervals: &[u16]) {
+    send(Input::Reading {
+        bpm,
+        rr_intervals: rr_intervals.to_vec(),
+        at: Instant::now(),
+    });
+}
+
+/// 断开或清零时调用：停止震动。
+pub fn stop() {
+    send(Input::Stop);
+}
+
+/// 退出清理中调用：停止调度任务，并直接发送一次关，确保背心不会停在震动状态。
+pub fn shutdown() {
+    let Some(shared) = SHARED.lock().unwrap().take() else {
+        return;
+    };
+    if let Ok(data) = shared.settings.encode(None) {
+        let _ = send_raw_osc(&shared.socket, shared.target, &data);
+    }
+}
+
+/// 调度任务被唤醒的原因。
+enum Wake {
+    Input(Option<Input>),
+    Timer,
+}
+
+/// 接收读数并按心跳发送脉冲，直到发送端被丢弃（退出）。
+async fn run(
+    mut inputs: mpsc::Receiver<Input>,
+    socket: UdpSocket,
+    target: SocketAddr,
+    settings: Settings,
+) {
+    let pulse = |intensity: Option<f32>| match settings.encode(intensity) {
+        Ok(data) => {
+            if let Err(e) = send_raw_osc(&socket, target, &data) {
+                debug!("发送心跳震动失败: {}", e);
+            }
+        }
+        Err(e) => debug!("编码心跳震动失败: {}", e),
+    };
+    let mut clock = BeatClock::default();
+    let mut intensity = settings.intensity_min;
+    let mut pulse_end: Option<Instant> = None;
+    loop {
+        let deadline = match (pulse_end, clock.next_beat()) {
+            (Some(end), Some(beat)) => Some(end.min(beat)),
+            (end, beat) => end.or(beat),
+        };
+        let wake = match deadline {
+            Some(deadline) => tokio::select! {
+                input = inputs.recv() => Wake::Input(input),
+                () = time::sleep_until(deadline.into()) => Wake::Timer,
+            },
+            None => Wake::Input(inputs.recv().await),
+        };
+        match wake {
+            Wake::Input(Some(Input::Reading {
+                bpm,
+                rr_intervals,
+                at,
+            })) => {
+                clock.update(bpm, &rr_intervals, at);
+                intensity = settings.intensity(bpm);
+                if bpm == 0 && pulse_end.take().is_some() {
+                    pulse(None);
+                }
+            }
+            Wake::Input(Some(Input::Stop)) => {
+                clock.stop();
+                if pulse_end.take().is_some() {
+                    pulse(None);
+                }
+            }
+            Wake::Input(None) => return,
+            Wake::Timer => {
+                let now = Instant::now();
+                if pulse_end.is_some_and(|end| end <= now) {
+                    pulse_end = None;
+                    pulse(None);
+                }
+                if clock.next_beat().is_some_and(|beat| beat <= now) {
+                    clock.advance(now);
+                    pulse(Some(intensity));
+                    pulse_end = Some(now + settings.pulse);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_osc::{assert_param_bool, assert_param_float, decode_bundle};
+
+    #[test]
+    fn beat_clock_prefers_rr_intervals_and_stops() {
+        let start = Instant::now();
+        let mut clock = BeatClock::default();
+        assert_eq!(clock.next_beat(), None);
+
+        clock.update(60, &[], start);
+        assert_eq!(clock.next_beat(), Some(start), "first beat is immediate");
+        clock.advance(start);
+        assert_eq!(clock.next_beat(), Some(start + Duration::from_secs(1)));
+
+        // 768/1024 秒 = 750 ms，比 60 BPM 合成的周期短，下一拍提前
+        clock.update(80, &[800, 768], start);
+        assert_eq!(clock.next_beat(), Some(start + Duration::from_millis(750)));
+        // 不可信的 RR 间期改用 BPM
+        clock.update(120, &[100], start);
+        assert_eq!(clock.next_beat(), Some(start + Duration::from_millis(500)));
+
+        // 读数过期后不再震动
+        clock.advance(start + Duration::from_secs(4));
+        assert_eq!(clock.next_beat(), None);
+
+        clock.update(70, &[], start);
+        clock.update(0, &[], start);
+        assert_eq!(clock.next_beat(), None);
+    }
+
+    #[test]
+    fn pulses_scale_with_zone_and_match_value_type() {
+        let mut settings = Settings {
+            addresses: vec![
+                "/avatar/parameters/VestFront".to_string(),
+                "/avatar/parameters/VestBack".to_string(),
+            ],
+            value: HapticValue::Float,
+            pulse: Duration::from_millis(80),
+            intensity_min: 0.3,
+            intensity_max: 0.8,
+            max_hr: 200.0,
+        };
+        assert!((settings.intensity(60) - 0.3).abs() < 1e-6);
+        assert!((settings.intensity(190) - 0.8).abs() < 1e-6);
+        assert!((settings.intensity(130) - 0.5).abs() < 1e-6);
+
+        let on = decode_bundle(&settings.encode(Some(0.5)).unwrap());
+        assert_param_float(&on, "VestFront", 0.5, 1e-6);
+        assert_param_float(&on, "VestBack", 0.5, 1e-6);
+        let off = decode_bundle(&settings.encode(None).unwrap());
+        assert_param_float(&off, "VestFront", 0.0, 1e-6);
+
+        settings.value = HapticValue::Bool;
+        let on = decode_bundle(&settings.encode(Some(0.5)).unwrap());
+        assert_param_bool(&on, "VestBack", true);
+        let off = decode_bundle(&settings.encode(None).unwrap());
+        assert_param_bool(&off, "VestBack", false);
+    }
+}
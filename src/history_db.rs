@@ -0,0 +1,291 @@
+//! SQLite 历史库（`write_history_db = true`）：所有会话记录在同一个 `heartrate.db` 中。
+//!
+//! ```sql
+//! sessions (id, start, end, device, min, max, avg)  -- 一次连接一行；min/max/avg 不含 0，断开时填写
+//! readings (session_id, timestamp, bpm, rr)         -- rr 为毫秒，多个用 ';' 分隔，没有时为 NULL
+//! ```
+//!
+//! 时间均为 RFC 3339 字符串（带本地时区）。读数先缓存在内存中，每 `history_db_commit_secs` 秒
+//! 在一个事务中批量写入；断开或退出时写入剩余读数并关闭会话。异常退出时未关闭的会话
+//! 在下次启动时以最后一条读数的时间补上结束时间。
+//!
+//! `--export-session <id>` 把某个会话导出为 CSV。
+
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use tracing::warn;
+
+use crate::recorder::{self, Event, Reading, Recorder};
+use crate::Result;
+
+// end 是 SQL 关键字，作为列名时加引号
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS sessions (
+    id INTEGER PRIMARY KEY,
+    start TEXT NOT NULL,
+    "end" TEXT,
+    device TEXT,
+    min INTEGER,
+    max INTEGER,
+    avg REAL
+);
+CREATE TABLE IF NOT EXISTS readings (
+    session_id INTEGER NOT NULL REFERENCES sessions(id),
+    timestamp TEXT NOT NULL,
+    bpm INTEGER NOT NULL,
+    rr TEXT
+);
+CREATE INDEX IF NOT EXISTS readings_session ON readings(session_id);
+"#;
+
+#[derive(Debug)]
+struct PendingReading {
+    session_id: i64,
+    timestamp: String,
+    bpm: u8,
+    rr: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct HistoryDb {
+    conn: Connection,
+    commit_interval: Duration,
+    /// 当前会话的 id；连接前（或广播模式收到第一条读数前）为 None
+    session: Option<i64>,
+    pending: Vec<PendingReading>,
+    last_commit: Instant,
+    error_shown: bool,
+}
+
+impl HistoryDb {
+    /// 打开（不存在时创建）数据库，并补上次异常退出时未关闭的会话。
+    pub fn open(path: &Path, commit_interval: Duration) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        conn.execute(
+            r#"UPDATE sessions SET "end" = (SELECT MAX(timestamp) FROM readings WHERE session_id = sessions.id)
+               WHERE "end" IS NULL"#,
+            params![],
+        )?;
+        Ok(Self {
+            conn,
+            commit_interval,
+            session: None,
+            pending: Vec::new(),
+            last_commit: Instant::now(),
+            error_shown: false,
+        })
+    }
+
+    fn begin_session(&mut self, at: DateTime<Local>, device: Option<&str>) -> Result<i64> {
+        self.end_session(at)?;
+        self.conn.execute(
+            "INSERT INTO sessions (start, device) VALUES (?1, ?2)",
+            params![recorder::timestamp(at), device],
+        )?;
+        let id = self.conn.last_insert_rowid();
+        self.session = Some(id);
+        Ok(id)
+    }
+
+    /// 写入剩余读数并填写结束时间与统计；没有任何读数的会话（连接后订阅失败等）直接删除。
+    fn end_session(&mut self, at: DateTime<Local>) -> Result<()> {
+        self.commit()?;
+        let Some(id) = self.session.take() else {
+            return Ok(());
+        };
+        self.conn.execute(
+            "DELETE FROM sessions WHERE id = ?1
+             AND NOT EXISTS (SELECT 1 FROM readings WHERE session_id = ?1)",
+            params![id],
+        )?;
+        self.conn.execute(
+            r#"UPDATE sessions SET "end" = ?2,
+                   min = (SELECT MIN(bpm) FROM readings WHERE session_id = ?1 AND bpm > 0),
+                   max = (SELECT MAX(bpm) FROM readings WHERE session_id = ?1 AND bpm > 0),
+                   avg = (SELECT AVG(bpm) FROM readings WHERE session_id = ?1 AND bpm > 0)
+               WHERE id = ?1"#,
+            params![id, recorder::timestamp(at)],
+        )?;
+        Ok(())
+    }
+
+    /// 在一个事务中写入缓存的读数。
+    fn commit(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let tx = self.conn.transaction()?;
+        {
+            let mut insert = tx.prepare_cached(
+                "INSERT INTO readings (session_id, timestamp, bpm, rr) VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for reading in &self.pending {
+                insert.execute(params![
+                    reading.session_id,
+                    reading.timestamp,
+                    reading.bpm,
+                    reading.rr
+                ])?;
+            }
+        }
+        tx.commit()?;
+        self.pending.clear();
+        self.last_commit = Instant::now();
+        Ok(())
+    }
+
+    fn try_reading(&mut self, at: DateTime<Local>, reading: &Reading) -> Result<()> {
+        let session_id = match self.session {
+            Some(id) => id,
+            // 广播模式没有连接事件，第一条读数开始一个会话
+            None => self.begin_session(at, None)?,
+        };
+        let rr = recorder::rr_ms(reading.rr_intervals);
+        self.pending.push(PendingReading {
+            session_id,
+            timestamp: recorder::timestamp(at),
+            bpm: reading.bpm,
+            rr: (!rr.is_empty()).then_some(rr),
+        });
+        if self.last_commit.elapsed() >= self.commit_interval {
+            self.commit()?;
+        }
+        Ok(())
+    }
+
+    fn report(&mut self, result: Result<()>) {
+        match result {
+            Ok(()) => self.error_shown = false,
+            Err(e) => {
+                if !self.error_shown {
+                    warn!("写入历史库时出错: {}（恢复前不再重复提示）", e);
+                    self.error_shown = true;
+                }
+            }
+        }
+    }
+}
+
+impl Recorder for HistoryDb {
+    fn reading(&mut self, at: DateTime<Local>, reading: &Reading) {
+        let result = self.try_reading(at, reading);
+        self.report(result);
+    }
+
+    fn event(&mut self, at: DateTime<Local>, event: &Event) {
+        let result = match event {
+            Event::Connected(device) => self.begin_session(at, Some(device)).map(|_| ()),
+            Event::Disconnected | Event::Rescan | Event::Stop => self.end_session(at),
+            Event::Start | Event::Idle | Event::Active => Ok(()),
+        };
+        self.report(result);
+    }
+
+    fn finish(&mut self) {
+        let result = self.end_session(Local::now());
+        self.report(result);
+    }
+}
+
+/// `--export-session <id>`：把会话的读数按时间顺序写成 CSV（`timestamp,bpm,rr_ms`），
+/// 返回导出的行数；会话不存在时返回 None。以只读方式打开，数据库不存在时报错而不是新建。
+pub fn export_csv(path: &Path, session_id: i64, out: &mut impl Write) -> Result<Option<usize>> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let exists = conn
+        .query_row(
+            "SELECT 1 FROM sessions WHERE id = ?1",
+            params![session_id],
+            |row| row.get::<_, i64>(0),
+        )
+        .optional()?;
+    if exists.is_none() {
+        return Ok(None);
+    }
+    let mut select = conn
+        .prepare("SELECT timestamp, bpm, rr FROM readings WHERE session_id = ?1 ORDER BY rowid")?;
+    let rows = select.query_map(params![session_id], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, u8>(1)?,
+            row.get::<_, Option<String>>(2)?,
+        ))
+    })?;
+    writeln!(out, "timestamp,bpm,rr_ms")?;
+    let mut count = 0;
+    for row in rows {
+        let (timestamp, bpm, rr) = row?;
+        writeln!(out, "{},{},{}", timestamp, bpm, rr.unwrap_or_default())?;
+        count += 1;
+    }
+    Ok(Some(count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::{env, fs};
+
+    fn at(secs: i64) -> DateTime<Local> {
+        Local.timestamp_opt(secs, 0).unwrap()
+    }
+
+    fn bpm(bpm: u8) -> Reading<'static> {
+        Reading {
+            bpm,
+            rr_intervals: &[],
+            rssi: None,
+            energy_kj: None,
+        }
+    }
+
+    #[test]
+    fn sessions_are_closed_with_stats_and_exported_as_csv() {
+        let path = env::temp_dir().join(format!("hr-vrc-history-test-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut db = HistoryDb::open(&path, Duration::from_secs(60)).unwrap();
+
+        db.event(at(0), &Event::Connected("A0:9E:1A:00:00:01".to_string()));
+        db.reading(
+            at(1),
+            &Reading {
+                bpm: 80,
+                rr_intervals: &[768],
+                rssi: None,
+                energy_kj: None,
+            },
+        );
+        db.reading(at(2), &bpm(0));
+        db.reading(at(3), &bpm(90));
+        db.event(at(4), &Event::Disconnected);
+        // 没有读数的会话不保留
+        db.event(at(5), &Event::Connected("A0:9E:1A:00:00:01".to_string()));
+        db.finish();
+
+        let (count, min, max, avg): (i64, u8, u8, f64) = db
+            .conn
+            .query_row(
+                "SELECT COUNT(*), MIN(min), MAX(max), MAX(avg) FROM sessions",
+                params![],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .unwrap();
+        assert_eq!((count, min, max, avg), (1, 80, 90, 85.0));
+
+        let mut csv = Vec::new();
+        assert_eq!(export_csv(&path, 1, &mut csv).unwrap(), Some(3));
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "timestamp,bpm,rr_ms");
+        assert!(lines[1].ends_with(",80,750"));
+        assert!(lines[2].ends_with(",0,"));
+        assert_eq!(export_csv(&path, 2, &mut Vec::new()).unwrap(), None);
+        drop(db);
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,135 @@
+//! 短时心率变异性（HRV）与压力指数。
+//!
+//! 心率设备在 Heart Rate Measurement 中附带的 RR 间期（相邻两次心跳的间隔）
+//! 被缓存在一个短窗口内，据此计算：
+//!
+//! - `mean_rr`：窗口内 RR 间期均值；
+//! - `SDNN`：窗口内 RR 间期的标准差；
+//! - 压力指数 `SI = 1024 / (mean_rr² × SDNN)`，其中 mean_rr 以秒、SDNN 以毫秒计。
+//!   交感神经兴奋时心率升高（mean_rr 变小）、变异性下降（SDNN 变小），SI 随之增大；
+//!   静息状态（mean_rr ≈ 1 s、SDNN ≈ 100 ms）约为 10。
+//!
+//! 临床局限（请勿据此做任何健康判断）：
+//!
+//! - 这是娱乐/可视化用途的粗略指标，并非医学诊断依据，也不等同于 Baevsky 压力指数；
+//! - 手环的 RR 间期多由 PPG 光学信号推算，精度远低于心电胸带，运动、佩戴松动都会引入伪差；
+//! - 呼吸节律、体位、咖啡因、睡眠等都会显著影响短时 HRV，不同人、不同时段的绝对值没有可比性；
+//! - 窗口很短，估计值本身方差较大，因此样本不足或预热期内不给出结果。
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// 参与计算的最近 RR 间期数量。
+const WINDOW_LEN: usize = 64;
+
+/// 少于该数量的 RR 间期时不计算压力指数。
+const MIN_SAMPLES: usize = 10;
+
+/// 首个 RR 间期之后的预热时长：刚连上时的估计噪声很大。
+const WARMUP: Duration = Duration::from_secs(30);
+
+/// 生理上不可能的 RR 间期（对应约 200 BPM 以上或 30 BPM 以下）视为伪差丢弃。
+const MIN_RR_MS: f32 = 300.0;
+const MAX_RR_MS: f32 = 2000.0;
+
+/// 缓存最近的 RR 间期并计算短时 HRV 指标。每次连接重新创建。
+#[derive(Debug, Default)]
+pub struct HrvCalculator {
+    rr_ms: VecDeque<f32>,
+    first_sample: Option<Instant>,
+    last_sample: Option<Instant>,
+}
+
+impl HrvCalculator {
+    /// 加入一个 RR 间期（单位 1/1024 秒，即 GATT 规范中的原始值）。
+    pub fn push(&mut self, rr_1024: u16, now: Instant) {
+        let rr_ms = f32::from(rr_1024) * 1000.0 / 1024.0;
+        if !(MIN_RR_MS..=MAX_RR_MS).contains(&rr_ms) {
+            return;
+        }
+        if self.rr_ms.len() == WINDOW_LEN {
+            self.rr_ms.pop_front();
+        }
+        self.rr_ms.push_back(rr_ms);
+        self.first_sample.get_or_insert(now);
+        self.last_sample = Some(now);
+    }
+
+    /// 窗口内 RR 间期均值（毫秒）。
+    pub fn mean_rr_ms(&self) -> Option<f32> {
+        if self.rr_ms.is_empty() {
+            return None;
+        }
+        Some(self.rr_ms.iter().sum::<f32>() / self.rr_ms.len() as f32)
+    }
+
+    /// 窗口内 RR 间期的标准差 SDNN（毫秒），至少需要两个间期。
+    pub fn sdnn_ms(&self) -> Option<f32> {
+        if self.rr_ms.len() < 2 {
+            return None;
+        }
+        let mean = self.mean_rr_ms()?;
+        let variance = self.rr_ms.iter().map(|rr| (rr - mean).powi(2)).sum::<f32>()
+            / (self.rr_ms.len() - 1) as f32;
+        Some(variance.sqrt())
+    }
+
+    /// 压力指数 `1024 / (mean_rr² × SDNN)`（见模块文档）。
+    /// 样本少于 10 个、仍在 30 秒预热期内或 SDNN 为 0 时返回 None。
+    pub fn stress_index(&self) -> Option<f32> {
+        if self.rr_ms.len() < MIN_SAMPLES {
+            return None;
+        }
+        let (first, last) = (self.first_sample?, self.last_sample?);
+        if last.duration_since(first) < WARMUP {
+            return None;
+        }
+        let mean_rr_secs = self.mean_rr_ms()? / 1000.0;
+        let sdnn = self.sdnn_ms()?;
+        if sdnn <= f32::EPSILON {
+            return None;
+        }
+        Some(1024.0 / (mean_rr_secs * mean_rr_secs * sdnn))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 毫秒换算为 GATT 的 1/1024 秒单位。
+    fn rr(ms: f32) -> u16 {
+        (ms * 1024.0 / 1000.0).round() as u16
+    }
+
+    #[test]
+    fn stress_index_needs_enough_samples_and_warmup() {
+        let start = Instant::now();
+        let mut hrv = HrvCalculator::default();
+        for i in 0..9 {
+            hrv.push(rr(1000.0), start + Duration::from_secs(i * 5));
+        }
+        assert_eq!(hrv.stress_index(), None, "fewer than 10 samples");
+
+        let mut hrv = HrvCalculator::default();
+        for i in 0..20 {
+            let ms = if i % 2 == 0 { 900.0 } else { 1100.0 };
+            hrv.push(rr(ms), start + Duration::from_secs(i));
+        }
+        assert_eq!(hrv.stress_index(), None, "still warming up");
+
+        hrv.push(rr(1000.0), start + Duration::from_secs(31));
+        let si = hrv.stress_index().expect("warmed up");
+        // mean ≈ 1 s，SDNN ≈ 100 ms → SI ≈ 10
+        assert!((si - 10.0).abs() < 1.0, "unexpected stress index {si}");
+    }
+
+    #[test]
+    fn push_discards_implausible_intervals() {
+        let now = Instant::now();
+        let mut hrv = HrvCalculator::default();
+        hrv.push(rr(150.0), now);
+        hrv.push(rr(2500.0), now);
+        assert_eq!(hrv.mean_rr_ms(), None);
+    }
+}
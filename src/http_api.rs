@@ -0,0 +1,392 @@
+//! 本机 HTTP 接口（`http_enabled = true`）：在 `http_ip:http_port`（默认 127.0.0.1:8766）上监听，
+//! 供不支持 OSC/WebSocket 的工具轮询，也方便在 VRChat 没有反应时用浏览器直接查看程序看到的数据：
+//!
+//! - `GET /api/state`：当前状态，字段与 status.json 相同（见 status_file 模块）
+//! - `GET /api/history?seconds=300`：最近若干秒的读数（默认 300，最多 `http_history_secs`），例如
+//!
+//! ```json
+//! {"seconds":300,"readings":[{"timestamp_ms":1760000000000,"bpm":87}, ...]}
+//! ```
+//!
+//! - `GET /overlay`：现成的 OBS 浏览器源页面（跳动的心形、心率数字、可选折线，见 overlay.html），
+//!   启用 WebSocket 服务时通过它实时更新，否则每秒轮询 `/api/state`
+//!
+//! 设置 `http_cors_origin`（如 `"*"` 或 `"http://localhost:3000"`）后响应带 CORS 头，
+//! 其他端口上的浏览器 overlay 可以直接 fetch。
+//!
+//! 只有几个只读的 GET 接口，这里直接解析请求行，不引入 HTTP 框架。每个请求处理完即关闭连接；
+//! 状态和历史只在锁内复制，心率处理路径上只有一次短暂加锁，不会被慢客户端拖住。
+
+use std::collections::VecDeque;
+use std::io;
+use std::net::{SocketAddr, SocketAddrV4};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time;
+use tracing::{debug, warn};
+
+use crate::status_file::{self, Status};
+
+/// 读取请求或发送响应的最长等待时间，超时的连接直接关闭。
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+/// 请求头的最大长度，超过则返回 431。
+const MAX_REQUEST_HEAD: usize = 8 * 1024;
+/// `/api/history` 不带 `seconds` 参数时返回的时长。
+const DEFAULT_HISTORY_SECS: u64 = 300;
+/// `/overlay` 页面，`__WEBSOCKET_PORT__` 在启动时替换为 WebSocket 端口（未启用时为空）。
+const OVERLAY_TEMPLATE: &str = include_str!("overlay.html");
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+struct Reading {
+    timestamp_ms: u64,
+    bpm: u8,
+}
+
+#[derive(Serialize)]
+struct History {
+    seconds: u64,
+    readings: Vec<Reading>,
+}
+
+struct Shared {
+    /// 最新状态（单行 JSON）
+    state: String,
+    /// 按时间顺序的读数，只保留最近 `retention_ms` 内的
+    history: VecDeque<Reading>,
+    retention_ms: u64,
+    cors_origin: Option<String>,
+    /// `/overlay` 页面（已填入 WebSocket 端口）
+    overlay: String,
+}
+
+impl Shared {
+    fn record(&mut self, reading: Reading) {
+        self.history.push_back(reading);
+        let cutoff = reading.timestamp_ms.saturating_sub(self.retention_ms);
+        while self
+            .history
+            .front()
+            .is_some_and(|oldest| oldest.timestamp_ms < cutoff)
+        {
+            self.history.pop_front();
+        }
+    }
+
+    /// 最近 `seconds` 秒内的读数（不超过保留时长）。
+    fn recent(&self, seconds: u64, now_ms: u64) -> Vec<Reading> {
+        let cutoff = now_ms.saturating_sub(seconds.saturating_mul(1000));
+        self.history
+            .iter()
+            .filter(|reading| reading.timestamp_ms >= cutoff)
+            .copied()
+            .collect()
+    }
+}
+
+static SHARED: Mutex<Option<Shared>> = Mutex::new(None);
+
+/// 启动时调用：开始监听并在后台处理请求，成功后 `publish`/`record` 才会生效。
+/// `websocket_port` 为已启用的 WebSocket 服务端口，供 `/overlay` 页面连接。
+pub async fn start(
+    addr: SocketAddrV4,
+    cors_origin: Option<String>,
+    history_secs: u64,
+    websocket_port: Option<u16>,
+) -> io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    let state = status_file::to_json_line(&Status::default()).map_err(io::Error::other)?;
+    *SHARED.lock().unwrap() = Some(Shared {
+        state,
+        history: VecDeque::new(),
+        retention_ms: history_secs.saturating_mul(1000),
+        cors_origin,
+        overlay: overlay_page(websocket_port),
+    });
+    tokio::spawn(accept_loop(listener));
+    Ok(())
+}
+
+/// 是否已启用（`start` 成功）。
+pub fn is_enabled() -> bool {
+    SHARED.lock().unwrap().is_some()
+}
+
+/// 更新 `/api/state` 返回的状态；未启用时不做任何事。
+pub fn publish(status: &Status) {
+    let mut shared = SHARED.lock().unwrap();
+    let Some(shared) = shared.as_mut() else {
+        return;
+    };
+    match status_file::to_json_line(status) {
+        Ok(json) => shared.state = json,
+        Err(e) => warn!("生成 HTTP 状态内容时出错: {}", e),
+    }
+}
+
+/// 记录一次读数到 `/api/history`；未启用时不做任何事。
+pub fn record(bpm: u8) {
+    let mut shared = SHARED.lock().unwrap();
+    let Some(shared) = shared.as_mut() else {
+        return;
+    };
+    shared.record(Reading {
+        timestamp_ms: now_ms(),
+        bpm,
+    });
+}
+
+fn overlay_page(websocket_port: Option<u16>) -> String {
+    let port = websocket_port.map_or(String::new(), |port| port.to_string());
+    OVERLAY_TEMPLATE.replace("__WEBSOCKET_PORT__", &port)
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+async fn accept_loop(listener: TcpListener) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                tokio::spawn(serve(stream, peer));
+            }
+            Err(e) => {
+                warn!("接受 HTTP 连接失败: {}", e);
+                time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+async fn serve(mut stream: TcpStream, peer: SocketAddr) {
+    let response = match time::timeout(IO_TIMEOUT, read_request_line(&mut stream)).await {
+        Ok(Ok(Some(line))) => respond(&line),
+        Ok(Ok(None)) => Response::error("431 Request Header Fields Too Large"),
+        Ok(Err(e)) => {
+            debug!("读取 HTTP 请求失败（{}）: {}", peer, e);
+            return;
+        }
+        Err(_) => {
+            debug!("读取 HTTP 请求超时（{}）", peer);
+            return;
+        }
+    };
+    let cors_origin = SHARED
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|shared| shared.cors_origin.clone());
+    let bytes = response.to_bytes(cors_origin.as_deref());
+    match time::timeout(IO_TIMEOUT, stream.write_all(&bytes)).await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => debug!("发送 HTTP 响应失败（{}）: {}", peer, e),
+        Err(_) => debug!("发送 HTTP 响应超时（{}）", peer),
+    }
+}
+
+/// 读到请求头结束（空行）为止，返回请求行；请求头过长时返回 `None`。
+async fn read_request_line(stream: &mut TcpStream) -> io::Result<Option<String>> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        if head.len() > MAX_REQUEST_HEAD {
+            return Ok(None);
+        }
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+    let line = head.split(|&b| b == b'\r').next().unwrap_or_default();
+    Ok(Some(String::from_utf8_lossy(line).into_owned()))
+}
+
+const JSON: &str = "application/json; charset=utf-8";
+const HTML: &str = "text/html; charset=utf-8";
+
+#[derive(Debug, PartialEq)]
+struct Response {
+    status: &'static str,
+    /// 响应体及其 Content-Type
+    body: Option<(&'static str, String)>,
+}
+
+impl Response {
+    fn ok(content_type: &'static str, body: String) -> Self {
+        Response {
+            status: "200 OK",
+            body: Some((content_type, body)),
+        }
+    }
+
+    fn error(status: &'static str) -> Self {
+        Response {
+            status,
+            body: Some((JSON, format!("{{\"error\":\"{}\"}}", status))),
+        }
+    }
+
+    fn to_bytes(&self, cors_origin: Option<&str>) -> Vec<u8> {
+        let mut head = format!(
+            "HTTP/1.1 {}\r\nCache-Control: no-store\r\nConnection: close\r\n",
+            self.status
+        );
+        if let Some(origin) = cors_origin {
+            head.push_str(&format!(
+                "Access-Control-Allow-Origin: {}\r\n\
+                 Access-Control-Allow-Methods: GET, OPTIONS\r\n\
+                 Access-Control-Allow-Headers: *\r\n",
+                origin
+            ));
+        }
+        let body = match &self.body {
+            Some((content_type, body)) => {
+                head.push_str(&format!("Content-Type: {}\r\n", content_type));
+                body.as_str()
+            }
+            None => "",
+        };
+        head.push_str(&format!("Content-Length: {}\r\n\r\n", body.len()));
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(body.as_bytes());
+        bytes
+    }
+}
+
+/// 根据请求行（如 `GET /api/history?seconds=60 HTTP/1.1`）生成响应。
+fn respond(request_line: &str) -> Response {
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Response::error("400 Bad Request");
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    if !matches!(path, "/api/state" | "/api/history" | "/overlay") {
+        return Response::error("404 Not Found");
+    }
+    match method {
+        "GET" => {}
+        // CORS 预检
+        "OPTIONS" => {
+            return Response {
+                status: "204 No Content",
+                body: None,
+            }
+        }
+        _ => return Response::error("405 Method Not Allowed"),
+    }
+
+    let shared = SHARED.lock().unwrap();
+    let Some(shared) = shared.as_ref() else {
+        return Response::error("503 Service Unavailable");
+    };
+    match path {
+        "/api/state" => return Response::ok(JSON, shared.state.clone()),
+        "/overlay" => return Response::ok(HTML, shared.overlay.clone()),
+        _ => {}
+    }
+    let Some(seconds) = history_seconds(query) else {
+        return Response::error("400 Bad Request");
+    };
+    let seconds = seconds.min(shared.retention_ms / 1000);
+    let history = History {
+        seconds,
+        readings: shared.recent(seconds, now_ms()),
+    };
+    match serde_json::to_string(&history) {
+        Ok(json) => Response::ok(JSON, json),
+        Err(_) => Response::error("500 Internal Server Error"),
+    }
+}
+
+/// 解析查询字符串中的 `seconds`，没有时为默认值，不是非负整数时返回 `None`。
+fn history_seconds(query: &str) -> Option<u64> {
+    match query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("seconds="))
+    {
+        Some(value) => value.parse().ok(),
+        None => Some(DEFAULT_HISTORY_SECS),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(timestamp_ms: u64, bpm: u8) -> Reading {
+        Reading { timestamp_ms, bpm }
+    }
+
+    #[test]
+    fn history_keeps_only_retention_window() {
+        let mut shared = Shared {
+            state: String::new(),
+            history: VecDeque::new(),
+            retention_ms: 10_000,
+            cors_origin: None,
+            overlay: String::new(),
+        };
+        shared.record(reading(1_000, 70));
+        shared.record(reading(5_000, 75));
+        shared.record(reading(12_000, 80));
+        // 1_000 早于 12_000 - 10_000，被丢弃
+        assert_eq!(
+            shared.history,
+            VecDeque::from([reading(5_000, 75), reading(12_000, 80)])
+        );
+        assert_eq!(shared.recent(5, 12_000), vec![reading(12_000, 80)]);
+        assert_eq!(shared.recent(300, 12_000).len(), 2);
+    }
+
+    #[test]
+    fn history_seconds_parses_query() {
+        assert_eq!(history_seconds(""), Some(DEFAULT_HISTORY_SECS));
+        assert_eq!(history_seconds("seconds=60"), Some(60));
+        assert_eq!(history_seconds("pretty=1&seconds=5"), Some(5));
+        assert_eq!(history_seconds("seconds=-1"), None);
+        assert_eq!(history_seconds("seconds=abc"), None);
+    }
+
+    #[test]
+    fn unknown_paths_and_methods_are_rejected() {
+        assert_eq!(respond("GET / HTTP/1.1").status, "404 Not Found");
+        assert_eq!(
+            respond("POST /api/state HTTP/1.1").status,
+            "405 Method Not Allowed"
+        );
+        assert_eq!(
+            respond("OPTIONS /api/history HTTP/1.1"),
+            Response {
+                status: "204 No Content",
+                body: None,
+            }
+        );
+        assert_eq!(respond("garbage").status, "400 Bad Request");
+    }
+
+    #[test]
+    fn overlay_page_gets_websocket_port() {
+        assert!(overlay_page(Some(8765)).contains("WEBSOCKET_PORT = \"8765\";"));
+        // 未启用 WebSocket：页面退回轮询 /api/state
+        assert!(overlay_page(None).contains("WEBSOCKET_PORT = \"\";"));
+    }
+
+    #[test]
+    fn cors_headers_only_when_configured() {
+        let response = Response::ok(JSON, "{}".to_string());
+        let plain = String::from_utf8(response.to_bytes(None)).unwrap();
+        assert!(!plain.contains("Access-Control-Allow-Origin"));
+        assert!(plain.ends_with("Content-Length: 2\r\n\r\n{}"));
+
+        let cors = String::from_utf8(response.to_bytes(Some("*"))).unwrap();
+        assert!(cors.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(cors.contains("Access-Control-Allow-Origin: *\r\n"));
+    }
+}
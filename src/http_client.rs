@@ -0,0 +1,406 @@
+//! 向外发送 HTTP POST 的最小实现（InfluxDB、Webhook、Discord 输出共用）。
+//!
+//! 只需发一个请求、看响应状态码，这里直接拼写 HTTP/1.1 请求（`Connection: close`），不引入 HTTP 客户端库。
+//! `https://` 使用 rustls，以内置的 Mozilla 根证书（webpki-roots）验证服务器。
+//! 异步版本用于后台任务，同步版本用于退出清理等不能等待异步任务的地方；两者都限制连接、发送和等待响应的总时长。
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream as StdTcpStream, ToSocketAddrs};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use tokio_rustls::TlsConnector;
+
+/// 连接、发送和等待响应的最长时间。
+pub const IO_TIMEOUT: Duration = Duration::from_secs(5);
+/// 只需读取状态行，响应体（出错时的说明）截断到该长度。
+const MAX_RESPONSE: usize = 1024;
+
+/// 解析后的请求地址。
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    /// `host:port`，用于连接
+    pub authority: String,
+    /// Host 请求头
+    pub host: String,
+    /// 请求路径（含查询参数），至少为 `/`
+    pub path: String,
+    /// https:// 时为 TLS 校验用的主机名（不含端口和 IPv6 的方括号）
+    pub tls_name: Option<String>,
+}
+
+/// 解析 `http(s)://host[:port][/path][?query]`，未写端口时为 80 / 443。
+pub fn parse_url(url: &str) -> Result<Target, String> {
+    let (rest, tls, default_port) = if let Some(rest) = url.strip_prefix("http://") {
+        (rest, false, 80)
+    } else if let Some(rest) = url.strip_prefix("https://") {
+        (rest, true, 443)
+    } else {
+        return Err("地址应以 http:// 或 https:// 开头".to_string());
+    };
+    let (host, path) = match rest.find(['/', '?']) {
+        Some(i) if rest[i..].starts_with('?') => (&rest[..i], format!("/{}", &rest[i..])),
+        Some(i) => (&rest[..i], rest[i..].to_string()),
+        None => (rest, "/".to_string()),
+    };
+    if host.is_empty() {
+        return Err("缺少主机名".to_string());
+    }
+    // IPv6 地址形如 [::1]:8086，端口在最后一个 ] 之后
+    let (name, has_port) = match host.rfind(']') {
+        Some(i) => (host[..i].trim_start_matches('['), host[i..].contains(':')),
+        None => (host.split(':').next().unwrap_or(host), host.contains(':')),
+    };
+    let authority = if has_port {
+        host.to_string()
+    } else {
+        format!("{}:{}", host, default_port)
+    };
+    Ok(Target {
+        authority,
+        host: host.to_string(),
+        path,
+        tls_name: tls.then(|| name.to_string()),
+    })
+}
+
+/// 进程内共用的 TLS 配置。
+fn tls_config() -> Arc<ClientConfig> {
+    static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+    CONFIG
+        .get_or_init(|| {
+            let roots = RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.into(),
+            };
+            Arc::new(
+                ClientConfig::builder()
+                    .with_root_certificates(roots)
+                    .with_no_client_auth(),
+            )
+        })
+        .clone()
+}
+
+fn server_name(name: &str) -> io::Result<ServerName<'static>> {
+    ServerName::try_from(name.to_string())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+/// 建立 TLS 连接；Twitch IRC 等非 HTTP 的出站连接也共用这里的证书配置。
+pub async fn connect_tls(authority: &str, name: &str) -> io::Result<TlsStream<TcpStream>> {
+    let stream = TcpStream::connect(authority).await?;
+    TlsConnector::from(tls_config())
+        .connect(server_name(name)?, stream)
+        .await
+}
+
+/// 非 2xx 响应。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusError {
+    pub code: u16,
+    /// 响应带 `Retry-After`（秒）时的等待时间，如 429 Too Many Requests
+    pub retry_after: Option<Duration>,
+    /// 状态行和响应体开头
+    message: String,
+}
+
+impl fmt::Display for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl StdError for StatusError {}
+
+impl StatusError {
+    /// 发送出错是否因为服务器返回了非 2xx 响应。
+    pub fn from_io(e: &io::Error) -> Option<&StatusError> {
+        e.get_ref()?.downcast_ref()
+    }
+}
+
+/// 查询参数值的百分号编码（只保留 RFC 3986 的非保留字符）。
+pub fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// 一个待发送的 POST 请求。
+#[derive(Debug, Clone, PartialEq)]
+pub struct Post<'a> {
+    pub target: &'a Target,
+    pub content_type: &'static str,
+    /// 额外的请求头（名称, 值）
+    pub headers: &'a [(&'static str, String)],
+    pub body: &'a str,
+}
+
+impl Post<'_> {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut head = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            self.target.path,
+            self.target.host,
+            self.content_type,
+            self.body.len()
+        );
+        for (name, value) in self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+        let mut request = head.into_bytes();
+        request.extend_from_slice(self.body.as_bytes());
+        request
+    }
+
+    /// 发送请求，2xx 以外的响应也视为出错（错误内为 [`StatusError`]）。
+    pub async fn send(&self) -> io::Result<()> {
+        let exchange = async {
+            match &self.target.tls_name {
+                Some(name) => {
+                    let stream = connect_tls(&self.target.authority, name).await?;
+                    self.exchange(stream).await
+                }
+                None => {
+                    let stream = TcpStream::connect(&self.target.authority).await?;
+                    self.exchange(stream).await
+                }
+            }
+        };
+        time::timeout(IO_TIMEOUT, exchange)
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?
+    }
+
+    async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(&self, mut stream: S) -> io::Result<()> {
+        stream.write_all(&self.to_bytes()).await?;
+        let mut response = Vec::new();
+        let mut chunk = [0; 256];
+        while response.len() < MAX_RESPONSE {
+            match stream.read(&mut chunk).await {
+                Ok(0) => break,
+                Ok(n) => response.extend_from_slice(&chunk[..n]),
+                // 部分服务器关闭 TLS 连接时不发送 close_notify，已读到的响应照常检查
+                Err(_) if !response.is_empty() => break,
+                Err(e) => return Err(e),
+            }
+        }
+        check_response(&response)
+    }
+
+    /// 同步发送，用于不能等待异步任务的地方（如退出清理），总时长不超过 `IO_TIMEOUT`。
+    pub fn send_blocking(&self) -> io::Result<()> {
+        self.send_blocking_until(Instant::now() + IO_TIMEOUT)
+    }
+
+    /// 同步发送，连接、发送和等待响应都须在 `deadline` 之前完成（多个请求可共用一个截止时间）。
+    pub fn send_blocking_until(&self, deadline: Instant) -> io::Result<()> {
+        let stream = connect_blocking(&self.target.authority, deadline)?;
+        let remaining = remaining(deadline)?;
+        stream.set_read_timeout(Some(remaining))?;
+        stream.set_write_timeout(Some(remaining))?;
+        match &self.target.tls_name {
+            Some(name) => {
+                let connection = ClientConnection::new(tls_config(), server_name(name)?)
+                    .map_err(io::Error::other)?;
+                self.exchange_blocking(StreamOwned::new(connection, stream), deadline)
+            }
+            None => self.exchange_blocking(stream, deadline),
+        }
+    }
+
+    fn exchange_blocking(
+        &self,
+        mut stream: impl Read + Write,
+        deadline: Instant,
+    ) -> io::Result<()> {
+        stream.write_all(&self.to_bytes())?;
+        let mut response = Vec::new();
+        let mut chunk = [0; 256];
+        while response.len() < MAX_RESPONSE {
+            // 每次读取各有超时，还要保证总时长不超过截止时间
+            if response.is_empty() {
+                remaining(deadline)?;
+            } else if Instant::now() >= deadline {
+                break;
+            }
+            match stream.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => response.extend_from_slice(&chunk[..n]),
+                Err(_) if !response.is_empty() => break,
+                Err(e) => return Err(e),
+            }
+        }
+        check_response(&response)
+    }
+}
+
+/// 距截止时间的剩余时间；已到期时为 TimedOut 错误。
+fn remaining(deadline: Instant) -> io::Result<Duration> {
+    deadline
+        .checked_duration_since(Instant::now())
+        .filter(|left| !left.is_zero())
+        .ok_or_else(|| io::Error::from(io::ErrorKind::TimedOut))
+}
+
+/// 带超时的同步连接：依次尝试解析出的各个地址，全部尝试须在 `deadline` 之前完成。
+fn connect_blocking(authority: &str, deadline: Instant) -> io::Result<StdTcpStream> {
+    let mut last_error = None;
+    for addr in authority.to_socket_addrs()? {
+        match StdTcpStream::connect_timeout(&addr, remaining(deadline)?) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("无法解析 {}", authority))
+    }))
+}
+
+/// 检查响应的状态行：2xx 为成功，否则返回 [`StatusError`]。
+fn check_response(response: &[u8]) -> io::Result<()> {
+    let text = String::from_utf8_lossy(response);
+    let status_line = text.lines().next().unwrap_or_default();
+    let code: u16 = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .unwrap_or(0);
+    if (200..300).contains(&code) {
+        return Ok(());
+    }
+    let (head, detail) = text
+        .split_once("\r\n\r\n")
+        .map_or((text.as_ref(), ""), |(head, body)| (head, body.trim()));
+    let retry_after = head.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        if !name.trim().eq_ignore_ascii_case("Retry-After") {
+            return None;
+        }
+        // Discord 等会给出小数秒
+        let secs: f64 = value.trim().parse().ok()?;
+        Duration::try_from_secs_f64(secs).ok()
+    });
+    Err(io::Error::other(StatusError {
+        code,
+        retry_after,
+        message: format!("{} {}", status_line, detail).trim().to_string(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_url_splits_authority_and_path() {
+        let target = parse_url("http://localhost:8086/").unwrap();
+        assert_eq!(target.authority, "localhost:8086");
+        assert_eq!(target.path, "/");
+
+        let target = parse_url("http://hooks.lan/api/webhook/hr?x=1").unwrap();
+        assert_eq!(target.authority, "hooks.lan:80");
+        assert_eq!(target.host, "hooks.lan");
+        assert_eq!(target.path, "/api/webhook/hr?x=1");
+
+        assert_eq!(parse_url("http://10.0.0.2?x=1").unwrap().path, "/?x=1");
+        assert_eq!(
+            parse_url("http://[::1]:8086").unwrap().authority,
+            "[::1]:8086"
+        );
+        let target = parse_url("https://discord.com/api/webhooks/1/abc").unwrap();
+        assert_eq!(target.authority, "discord.com:443");
+        assert_eq!(target.tls_name.as_deref(), Some("discord.com"));
+        assert_eq!(
+            parse_url("https://[::1]:8443/")
+                .unwrap()
+                .tls_name
+                .as_deref(),
+            Some("::1")
+        );
+        assert_eq!(parse_url("http://localhost:8086").unwrap().tls_name, None);
+        assert!(parse_url("localhost:8086").is_err());
+        assert!(parse_url("http:///path").is_err());
+    }
+
+    #[test]
+    fn request_has_length_and_extra_headers() {
+        let target = parse_url("http://127.0.0.1:9000/hook").unwrap();
+        let headers = [("Authorization", "Token abc".to_string())];
+        let post = Post {
+            target: &target,
+            content_type: "application/json",
+            headers: &headers,
+            body: "{}",
+        };
+        assert_eq!(
+            String::from_utf8(post.to_bytes()).unwrap(),
+            "POST /hook HTTP/1.1\r\nHost: 127.0.0.1:9000\r\nContent-Type: application/json\r\n\
+             Content-Length: 2\r\nConnection: close\r\nAuthorization: Token abc\r\n\r\n{}"
+        );
+    }
+
+    #[test]
+    fn only_2xx_responses_succeed() {
+        assert!(check_response(b"HTTP/1.1 204 No Content\r\n\r\n").is_ok());
+        let err = check_response(
+            b"HTTP/1.1 401 Unauthorized\r\nContent-Type: application/json\r\n\r\n{\"code\":\"unauthorized\"}",
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "HTTP/1.1 401 Unauthorized {\"code\":\"unauthorized\"}"
+        );
+        assert!(check_response(b"").is_err());
+
+        let err = check_response(b"HTTP/1.1 429 Too Many Requests\r\nretry-after: 1.5\r\n\r\n")
+            .unwrap_err();
+        let status = StatusError::from_io(&err).unwrap();
+        assert_eq!(status.code, 429);
+        assert_eq!(status.retry_after, Some(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn blocking_send_gives_up_at_the_deadline() {
+        // 接受连接但从不应答的服务器
+        let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = parse_url(&format!("http://{}/write", server.local_addr().unwrap())).unwrap();
+        let post = Post {
+            target: &target,
+            content_type: "text/plain",
+            headers: &[],
+            body: "hr value=72",
+        };
+        let started = Instant::now();
+        let err = post
+            .send_blocking_until(started + Duration::from_millis(200))
+            .unwrap_err();
+        assert!(
+            matches!(
+                err.kind(),
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+            ),
+            "unexpected error {:?}",
+            err
+        );
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let err = post.send_blocking_until(Instant::now()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+}
@@ -0,0 +1,375 @@
+//! InfluxDB 输出（`influx_enabled = true`）：每条读数生成一行 line protocol，
+//! 每 `influx_flush_secs` 秒批量 POST 到 InfluxDB 2.x 的 `/api/v2/write`，
+//! 供已有的 InfluxDB / Grafana 面板与其他生理数据一起展示：
+//!
+//! ```text
+//! heart_rate,device=A0:9E:1A:00:BC:12,session=1760000000 bpm=72i,rr="812;790" 1760000000123
+//! ```
+//!
+//! - 标签 `device` 为设备地址（广播模式下没有连接事件，不带该标签），`session` 为本次连接开始的 Unix 秒，
+//!   每次重连是一个新的 session；
+//! - 字段 `bpm` 为整数，`rr` 为毫秒、多个用 `;` 分隔（没有 RR 间期时省略）；时间戳精度为毫秒。
+//!
+//! 读数只在内存中排队，由后台任务发送，心率处理路径上只有一次短暂加锁。InfluxDB 不可用时读数留在队列中，
+//! 恢复后一并补发；超过 `influx_max_buffered_points` 条时丢弃最旧的。退出时同步补发一次剩余读数。
+//! `influx_dry_run = true` 时不发送，改为把每批 line protocol 打印到控制台，便于检查配置。
+//!
+//! 支持 `http://` 和 `https://`（见 http_client 模块）。
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use tokio::time;
+use tracing::{debug, info, warn};
+
+use crate::http_client::{self, Post, Target};
+use crate::recorder::{self, Event, Reading, Recorder};
+use crate::Config;
+
+/// 单次请求最多携带的行数（InfluxDB 建议每批 5000 行左右）。
+const MAX_BATCH: usize = 5000;
+
+/// 写入地址及认证头。
+#[derive(Debug, Clone, PartialEq)]
+struct Endpoint {
+    target: Target,
+    headers: Vec<(&'static str, String)>,
+}
+
+impl Endpoint {
+    fn post<'a>(&'a self, body: &'a str) -> Post<'a> {
+        Post {
+            target: &self.target,
+            content_type: "text/plain; charset=utf-8",
+            headers: &self.headers,
+            body,
+        }
+    }
+}
+
+/// 在 `http://host[:port][/prefix]` 后拼上 `/api/v2/write` 及查询参数。
+fn endpoint(url: &str, org: &str, bucket: &str, token: Option<&str>) -> Result<Endpoint, String> {
+    let mut target = http_client::parse_url(url)?;
+    if target.path.contains('?') {
+        return Err("地址中不能带查询参数".to_string());
+    }
+    target.path = format!(
+        "{}/api/v2/write?org={}&bucket={}&precision=ms",
+        target.path.trim_end_matches('/'),
+        http_client::percent_encode(org),
+        http_client::percent_encode(bucket)
+    );
+    Ok(Endpoint {
+        target,
+        headers: token
+            .map(|token| ("Authorization", format!("Token {}", token)))
+            .into_iter()
+            .collect(),
+    })
+}
+
+/// 转义度量名或标签中的逗号、空格（标签还需转义等号）。
+fn escape(value: &str, equals: bool) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c == ',' || c == ' ' || (equals && c == '=') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// 当前连接的标签。
+#[derive(Debug, Clone, PartialEq)]
+struct Session {
+    device: Option<String>,
+    id: i64,
+}
+
+/// 生成一行 line protocol。
+fn line(measurement: &str, session: &Session, at: DateTime<Local>, reading: &Reading) -> String {
+    let mut line = escape(measurement, false);
+    if let Some(device) = &session.device {
+        line.push_str(",device=");
+        line.push_str(&escape(device, true));
+    }
+    line.push_str(&format!(",session={} bpm={}i", session.id, reading.bpm));
+    let rr = recorder::rr_ms(reading.rr_intervals);
+    if !rr.is_empty() {
+        // 只含数字和分号，字符串字段无需转义
+        line.push_str(&format!(",rr=\"{}\"", rr));
+    }
+    line.push_str(&format!(" {}", at.timestamp_millis()));
+    line
+}
+
+/// 等待发送的行；超过上限时丢弃最旧的。
+#[derive(Debug)]
+struct Buffer {
+    lines: VecDeque<String>,
+    max: usize,
+    /// 因超过上限丢弃的累计行数
+    dropped: u64,
+}
+
+impl Buffer {
+    fn new(max: usize) -> Self {
+        Buffer {
+            lines: VecDeque::new(),
+            max,
+            dropped: 0,
+        }
+    }
+
+    fn push(&mut self, line: String) {
+        self.lines.push_back(line);
+        self.trim();
+    }
+
+    /// 取出最多 `MAX_BATCH` 行。
+    fn take_batch(&mut self) -> Vec<String> {
+        let n = self.lines.len().min(MAX_BATCH);
+        self.lines.drain(..n).collect()
+    }
+
+    /// 发送失败：把这一批放回队首，仍超过上限时丢弃最旧的。
+    fn restore(&mut self, batch: Vec<String>) {
+        for line in batch.into_iter().rev() {
+            self.lines.push_front(line);
+        }
+        self.trim();
+    }
+
+    fn trim(&mut self) {
+        while self.lines.len() > self.max {
+            self.lines.pop_front();
+            self.dropped += 1;
+        }
+    }
+}
+
+/// 注册到 recorder 的输出：生成行并放入队列，由后台任务发送。
+#[derive(Debug)]
+pub struct InfluxOutput {
+    measurement: String,
+    session: Option<Session>,
+    buffer: Arc<Mutex<Buffer>>,
+    /// dry run 时为 None
+    endpoint: Option<Endpoint>,
+}
+
+impl InfluxOutput {
+    /// 启动后台发送任务（需在 tokio 运行时内）。地址无效时返回说明。
+    pub fn start(config: &Config) -> Result<Self, String> {
+        let endpoint = if config.influx_dry_run {
+            None
+        } else {
+            Some(endpoint(
+                &config.influx_url,
+                &config.influx_org,
+                &config.influx_bucket,
+                config.influx_token.as_deref(),
+            )?)
+        };
+        let buffer = Arc::new(Mutex::new(Buffer::new(config.influx_max_buffered_points)));
+        tokio::spawn(run(
+            Arc::clone(&buffer),
+            endpoint.clone(),
+            Duration::from_secs(config.influx_flush_secs),
+        ));
+        Ok(InfluxOutput {
+            measurement: config.influx_measurement.clone(),
+            session: None,
+            buffer,
+            endpoint,
+        })
+    }
+}
+
+impl Recorder for InfluxOutput {
+    fn reading(&mut self, at: DateTime<Local>, reading: &Reading) {
+        // 广播模式没有连接事件，第一条读数开始一个 session
+        let session = self.session.get_or_insert_with(|| Session {
+            device: None,
+            id: at.timestamp(),
+        });
+        let line = line(&self.measurement, session, at, reading);
+        self.buffer.lock().unwrap().push(line);
+    }
+
+    fn event(&mut self, at: DateTime<Local>, event: &Event) {
+        match event {
+            Event::Connected(device) => {
+                self.session = Some(Session {
+                    device: Some(device.clone()),
+                    id: at.timestamp(),
+                })
+            }
+            Event::Disconnected | Event::Rescan | Event::Stop => self.session = None,
+            Event::Start | Event::Idle | Event::Active => {}
+        }
+    }
+
+    fn finish(&mut self) {
+        // 退出清理路径上不能等待异步任务，直接同步发送剩余读数；
+        // 所有批次共用一个截止时间，InfluxDB 不可达时也不会拖住退出
+        let deadline = Instant::now() + http_client::IO_TIMEOUT;
+        let mut buffer = self.buffer.lock().unwrap();
+        while !buffer.lines.is_empty() {
+            let batch = buffer.take_batch();
+            let Some(endpoint) = &self.endpoint else {
+                print_batch(&batch);
+                continue;
+            };
+            if let Err(e) = endpoint.post(&body(&batch)).send_blocking_until(deadline) {
+                warn!(
+                    "退出时未能把剩余 {} 条读数写入 InfluxDB: {}",
+                    batch.len() + buffer.lines.len(),
+                    e
+                );
+                return;
+            }
+        }
+    }
+}
+
+fn body(batch: &[String]) -> String {
+    let mut body = batch.join("\n");
+    body.push('\n');
+    body
+}
+
+fn print_batch(batch: &[String]) {
+    for line in batch {
+        println!("{}", line);
+    }
+}
+
+/// 每隔 `interval` 发送队列中的读数；失败时放回队列，等下一轮重试。
+async fn run(buffer: Arc<Mutex<Buffer>>, endpoint: Option<Endpoint>, interval: Duration) {
+    let mut ticker = time::interval(interval);
+    let mut warned = false;
+    let mut reported_dropped = 0;
+    loop {
+        ticker.tick().await;
+        loop {
+            let batch = buffer.lock().unwrap().take_batch();
+            if batch.is_empty() {
+                break;
+            }
+            let Some(endpoint) = &endpoint else {
+                print_batch(&batch);
+                continue;
+            };
+            match endpoint.post(&body(&batch)).send().await {
+                Ok(()) => {
+                    if warned {
+                        info!("已恢复写入 InfluxDB。");
+                        warned = false;
+                    }
+                    debug!("已写入 {} 条读数到 InfluxDB", batch.len());
+                }
+                Err(e) => {
+                    let mut buffer = buffer.lock().unwrap();
+                    buffer.restore(batch);
+                    if !warned {
+                        warn!(
+                            "写入 InfluxDB 失败: {}，读数暂存在内存中（最多 {} 条），将继续重试。",
+                            e, buffer.max
+                        );
+                        warned = true;
+                    } else {
+                        debug!("写入 InfluxDB 失败: {}", e);
+                    }
+                    if buffer.dropped > reported_dropped {
+                        warn!(
+                            "InfluxDB 暂存的读数已满，累计丢弃 {} 条最旧的读数。",
+                            buffer.dropped
+                        );
+                        reported_dropped = buffer.dropped;
+                    }
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn endpoint_adds_write_path_and_token() {
+        let endpoint = endpoint(
+            "http://localhost:8086/",
+            "my org",
+            "heartrate",
+            Some("secret"),
+        )
+        .unwrap();
+        assert_eq!(endpoint.target.authority, "localhost:8086");
+        assert_eq!(
+            endpoint.target.path,
+            "/api/v2/write?org=my%20org&bucket=heartrate&precision=ms"
+        );
+        assert_eq!(
+            endpoint.headers,
+            [("Authorization", "Token secret".to_string())]
+        );
+
+        let endpoint = super::endpoint("http://influx.lan/proxy", "o", "b", None).unwrap();
+        assert!(endpoint.target.path.starts_with("/proxy/api/v2/write?"));
+        assert!(endpoint.headers.is_empty());
+        assert!(super::endpoint("http://influx.lan/?org=o", "o", "b", None).is_err());
+    }
+
+    #[test]
+    fn line_has_tags_fields_and_millisecond_timestamp() {
+        let at = Local.timestamp_millis_opt(1_760_000_000_123).unwrap();
+        let session = Session {
+            device: Some("A0:9E, Polar H10".to_string()),
+            id: 1_760_000_000,
+        };
+        let reading = Reading {
+            bpm: 72,
+            rr_intervals: &[832, 809],
+            rssi: None,
+            energy_kj: None,
+        };
+        assert_eq!(
+            line("heart_rate", &session, at, &reading),
+            "heart_rate,device=A0:9E\\,\\ Polar\\ H10,session=1760000000 bpm=72i,rr=\"812;790\" 1760000000123"
+        );
+        let session = Session {
+            device: None,
+            ..session
+        };
+        let reading = Reading {
+            rr_intervals: &[],
+            ..reading
+        };
+        assert_eq!(
+            line("heart_rate", &session, at, &reading),
+            "heart_rate,session=1760000000 bpm=72i 1760000000123"
+        );
+    }
+
+    #[test]
+    fn buffer_keeps_newest_points_when_sends_fail() {
+        let mut buffer = Buffer::new(3);
+        for i in 0..3 {
+            buffer.push(i.to_string());
+        }
+        let batch = buffer.take_batch();
+        buffer.push("3".to_string());
+        buffer.push("4".to_string());
+        buffer.restore(batch);
+        assert_eq!(buffer.lines, ["2", "3", "4"]);
+        assert_eq!(buffer.dropped, 2);
+    }
+}
@@ -0,0 +1,53 @@
+//! 日志输出（tracing）：诊断信息按 `log_level` 过滤后输出到控制台。
+//!
+//! 控制台上还有一行用 `\r` 原地刷新的状态行；日志写出前先换行，避免覆盖在状态行上。
+//! 横幅、帮助、`--config-dump` 等程序本身的输出以及状态行不属于日志，仍直接打印。
+
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tracing_subscriber::EnvFilter;
+
+/// 当前行是否是尚未换行的状态行。
+static STATUS_LINE_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// 打印（覆盖）状态行，光标留在行首。
+pub fn status_line(line: &str) {
+    print!("{}   \r", line);
+    let _ = io::stdout().flush();
+    STATUS_LINE_ACTIVE.store(true, Ordering::Relaxed);
+}
+
+/// 初始化全局日志；`level` 为 EnvFilter 语法（如 `info`、`debug`、`HeartRate_For_VRChat=trace`），
+/// 无效时提示并使用 info。
+pub fn init(level: &str) {
+    let filter = EnvFilter::try_new(level).unwrap_or_else(|e| {
+        eprintln!(
+            "警告：log_level = \"{}\" 无效（{}），将使用 info。",
+            level, e
+        );
+        EnvFilter::new("info")
+    });
+    let _ = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_target(false)
+        .with_writer(|| ConsoleWriter)
+        .try_init();
+}
+
+/// 写到标准输出；状态行之后的第一次写入先补一个换行。
+struct ConsoleWriter;
+
+impl Write for ConsoleWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut stdout = io::stdout().lock();
+        if STATUS_LINE_ACTIVE.swap(false, Ordering::Relaxed) {
+            stdout.write_all(b"\n")?;
+        }
+        stdout.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
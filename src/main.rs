@@ -1,56 +1,524 @@
+mod auto_gain;
+mod benchmark;
+mod broadcast;
+mod console_display;
+mod custom_params;
+mod device_selector;
+mod devices;
+mod discord;
+mod discord_presence;
+mod discover;
+mod file_writer;
+mod ghost;
+mod ha_discovery;
+mod haptics;
+mod history_db;
+mod hrv;
+mod http_api;
+mod http_client;
+mod influx;
+mod logging;
+mod mi_auth;
+mod mqtt;
+mod native_watchdog;
+mod obs;
+mod osc_feedback;
+mod osc_proxy;
+mod osc_test;
+mod pair;
+mod pipe;
+mod plugin;
+mod polar_pmd;
+mod pulsoid;
+mod recorder;
+mod registry_output;
+mod replay;
+mod resonite;
+mod scan_only;
+mod scheduler;
+mod script;
+mod serial_source;
+mod session_log;
+mod shm;
+mod source;
+mod status_file;
+mod summary;
+mod template;
+#[cfg(test)]
+mod test_osc;
+mod tray;
+mod twitch;
+mod vr_notify;
+mod webhook;
+mod websocket;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::env;
 use std::future::Future;
 use std::io::{self, Write};
-use std::net::{Ipv4Addr, SocketAddrV4, UdpSocket};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, UdpSocket};
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use std::sync::OnceLock;
-use std::time::Duration;
-use std::{error, fmt, fs};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{error, fmt, fs, mem};
 
-use futures_util::stream::StreamExt;
-use serde::Deserialize;
+use futures_util::stream::{Stream, StreamExt};
+use futures_util::FutureExt;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{oneshot, Notify};
 use tokio::time;
+use unicode_segmentation::UnicodeSegmentation;
 use uuid::Uuid;
 
-use btleplug::api::{Central, CharPropFlags, Manager as _, Peripheral as _, ScanFilter};
-use btleplug::platform::{Manager, Peripheral};
+use btleplug::api::{
+    BDAddr, Central, CentralEvent, CentralState, CharPropFlags, Characteristic, Manager as _,
+    Peripheral as _, PeripheralProperties, ScanFilter, ValueNotification, WriteType,
+};
+use btleplug::platform::{Adapter, Manager, Peripheral};
+
+use device_selector::DeviceSelector;
+use hrv::HrvCalculator;
+use native_watchdog::NativeWatchdog;
+use tracing::{debug, error, info, info_span, trace, warn, Instrument};
 
 // --- 蓝牙标准 UUID（固定值，无需配置） ---
 const HEART_RATE_SERVICE_UUID: Uuid = Uuid::from_u128(0x0000180d_0000_1000_8000_00805f9b34fb);
 const HEART_RATE_CHAR_UUID: Uuid = Uuid::from_u128(0x00002a37_0000_1000_8000_00805f9b34fb);
-
-/// connect / discover_services 的超时（秒）：WinRT 上对不可达设备
-/// 这些调用可能挂起数十秒甚至不返回，需要兜底。
-const BLE_OP_TIMEOUT_SECS: u64 = 30;
+const HEART_RATE_CONTROL_POINT_UUID: Uuid = Uuid::from_u128(0x00002a39_0000_1000_8000_00805f9b34fb);
 
 /// 连续多少次连接失败（期间未收到任何心率数据）后放弃该设备、重新扫描。
 const MAX_CONSECUTIVE_FAILURES: u32 = 3;
 
+/// 发现服务/订阅失败后，在同一连接上重试前的等待时间（秒）。
+const SUBSCRIBE_RETRY_DELAY_SECS: u64 = 1;
+
+/// 收尾时退订 / 断开 / 查询连接状态各自的超时（秒）。
+const TEARDOWN_TIMEOUT_SECS: u64 = 5;
+
+/// 断开后确认 is_connected() 为 false 的最多检查次数（每次间隔 0.5 秒并补发一次断开）。
+const TEARDOWN_VERIFY_ATTEMPTS: u32 = 3;
+
+/// 记录上次成功收到心率的设备地址的文件名（位于程序目录），扫描时优先选择该设备。
+const LAST_DEVICE_FILE: &str = "last_device.txt";
+
+/// 退出时追加本次运行总结的文件名（位于程序目录，session_summary_log = true 时）。
+const SESSION_SUMMARY_LOG_FILE: &str = "sessions.log";
+
+/// 蓝牙适配器不可用（关闭/拔出）时检查其是否恢复的间隔（秒），连接期间也以此间隔检查其是否仍然可用。
+const ADAPTER_POLL_SECS: u64 = 2;
+
+/// 连接期间查询适配器状态的超时（秒）：适配器被拔出后查询本身可能永不返回，超时即视为已移除。
+const ADAPTER_QUERY_TIMEOUT_SECS: u64 = 5;
+
+/// 心跳超时但链路仍在时"原地重新订阅"的累计尝试/成功次数（整个运行期间）。
+static SOFT_RESUBSCRIBE_ATTEMPTS: AtomicU32 = AtomicU32::new(0);
+static SOFT_RESUBSCRIBE_SUCCESSES: AtomicU32 = AtomicU32::new(0);
+
+/// start_scan 卡死后重新初始化蓝牙栈的累计次数（整个运行期间）。
+static BLE_STACK_RESET_COUNT: AtomicU32 = AtomicU32::new(0);
+
 // --- 配置（从可执行文件同目录的 config.toml 加载，缺失时使用默认值并自动生成模板） ---
-#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 #[serde(default)]
 struct Config {
+    /// 心率来源：ble（蓝牙直连，默认）或 pulsoid（Pulsoid 实时接口，见 pulsoid 模块）
+    source: SourceKind,
+    /// Pulsoid 访问令牌（source = "pulsoid" 时必填）
+    pulsoid_token: Option<String>,
     /// 设备选择模式:
     /// "auto"      = 优先匹配 target_device_names，无匹配时回退到信号最强（默认）
     /// "name"      = 仅按名称匹配，找不到则重试扫描
     /// "strongest" = 仅选择信号最强的心率设备
     selection_mode: String,
     target_device_names: Vec<String>,
+    /// 首选设备（devices.toml 中的别名或 MAC 地址），在附近时按顺序优先于上次使用的设备和选择模式
+    preferred_device_order: Vec<String>,
+    /// 除标准 0x180D 外额外扫描/查找的心率服务 UUID（如部分 Garmin 手表的私有服务），
+    /// 按填写顺序排在标准服务之后
+    additional_hr_service_uuids: Vec<Uuid>,
+    /// 除标准 0x2A37 外额外查找的心率特征 UUID（私有特征），可用 --discover-uuids 探测
+    additional_hr_char_uuids: Vec<Uuid>,
+    /// 各心率特征的数据格式提示（特征 UUID → 格式），未列出的按标准心率测量格式解析
+    hr_char_formats: BTreeMap<Uuid, PayloadFormat>,
+    /// OSC 平台预设（vrchat / chilloutvr / custom），决定下面三项未设置时的默认值
+    osc_platform: OscPlatform,
     osc_ip: String,
-    osc_port: u16,
+    /// OSC 目标端口，不设置则使用平台预设的端口
+    osc_port: Option<u16>,
+    /// 参数地址前缀（如 "/avatar/parameters/"），不设置则使用平台预设的前缀
+    osc_parameter_prefix: Option<String>,
+    /// 是否发送 VRChat 预制件兼容参数（isHRActive、VRCOSC/Heartrate/Normalised），不设置则由平台预设决定
+    osc_compat_parameters: Option<bool>,
+    /// 发送 OSC 时绑定的本机网卡地址（须与 osc_ip 同为 IPv4 或 IPv6）；不设置则由系统按路由表选择（多网卡/虚拟机时可指定）
+    osc_local_ip: Option<IpAddr>,
+    /// 设置后把 OSC 发往该 IPv4 组播地址（端口仍为 osc_port），代替 osc_ip
+    osc_multicast_group: Option<Ipv4Addr>,
+    /// 发往组播地址时的 TTL（可跨越的路由器数），1 表示只在本网段内
+    osc_ttl: u8,
+    /// OSC Bundle 的时间标签：immediate（"立即执行"）或 wall-clock（按系统时钟填写 NTP 时间戳）
+    osc_timetag: OscTimetag,
+    /// 旧版的 osc_timetag 开关（true = wall-clock），加载时换算后不再使用，导出配置时省略
+    #[serde(skip_serializing)]
+    use_osc_timetag: Option<bool>,
+    /// 心率数据的打包方式：bundle（一个 Bundle）或 messages（每个参数单独一个数据报）
+    osc_packet_mode: OscPacketMode,
+    /// 发送时机：scheduled（按 send_rate_hz 定时发送）或 on-notification（每次通知立即发送），见 scheduler 模块
+    send_mode: SendMode,
+    /// 定时发送的频率（次/秒）
+    send_rate_hz: f32,
+    /// 是否监听 VRChat 回传的 HR 参数并测量往返延迟
+    osc_feedback_enabled: bool,
+    /// VRChat 的 OSC 输出端口（本程序监听该端口接收回传）
+    osc_receive_port: u16,
+    /// 是否作为 OSC 代理：把发往监听端口的数据包原样转发到 osc_ip:osc_port，与心率共用发送套接字（见 osc_proxy 模块）
+    osc_proxy_enabled: bool,
+    /// 代理监听的地址；其他 OSC 工具改为发往这里
+    osc_proxy_listen_ip: Ipv4Addr,
+    osc_proxy_listen_port: u16,
+    /// hr_percent 的分母；0 表示按最近 10 分钟的最高心率自动调整，见 auto_gain 模块
     max_heart_rate_for_percent: f32,
     scan_duration_secs: u64,
+    /// 选中设备或收到心率后该时间（秒）内需要重新扫描时，先直接重连该设备，失败再扫描；0 表示关闭
+    cache_valid_secs: u64,
+    /// 只连接已与系统配对的设备（以读取电量特征作为判断依据，见 is_bonded）
+    scan_bonded_only: bool,
     retry_delay_secs: u64,
+    /// 连续多少次连接失败（未收到心率）后重新初始化蓝牙栈，避免部分 Windows 蓝牙驱动在大量失败连接后崩溃；0 表示关闭
+    max_connection_attempts_before_adapter_reset: u32,
     /// 心跳超时时间（秒）：超过该时间未收到心率数据则重连
     heartbeat_timeout_secs: u64,
+    /// 心跳超时改用系统定时器计时（Windows 可等待定时器 / Linux timerfd / macOS kqueue，见 native_watchdog 模块），
+    /// 执行器繁忙时也能按时触发；不支持的平台自动退回异步超时
+    use_native_watchdog: bool,
+    /// 断线保持（秒）：断开后继续以 1 Hz 发送最后一次有效心率的时长，之后才清零；0 = 立即清零
+    ghost_mode_secs: u64,
+    /// start_scan 的超时（秒）：Windows 蓝牙栈偶尔卡死时 start_scan 永不返回，超时后重新初始化蓝牙栈
+    scan_timeout_secs: u64,
+    /// connect 的超时（秒）：WinRT 上设备处于信号边缘时 connect 可能挂起数分钟
+    connect_timeout_secs: u64,
+    /// discover_services / subscribe 的超时（秒）
+    service_timeout_secs: u64,
+    /// 找不到心率特征或订阅失败时，在同一连接上重试的次数（之后才断开重新扫描）
+    subscribe_retries: u32,
+    /// 心率特征不支持通知、只能读取时的轮询间隔（毫秒）
+    poll_interval_ms: u64,
+    /// 订阅后写入启动命令的特征（默认心率控制点 0x2A39），设备没有该特征时跳过
+    start_command_char_uuid: Uuid,
+    /// 订阅后写入的启动命令（十六进制字节串），为空表示不写入
+    start_command_hex: String,
+    /// 小米手环的认证密钥（32 位十六进制），部分固件认证后才推送心率
+    auth_key: Option<String>,
+    /// 该时间窗口（毫秒）内内容完全相同的通知只处理第一条，0 表示关闭
+    notification_dedupe_ms: u64,
+    /// 同时积压的通知超过该数量时只处理最新的心率通知，丢弃其余的；不设置则不检测
+    max_notification_queue_depth: Option<usize>,
+    /// 跳过与上次发送的心率相同、且间隔不足 `dedup_min_interval_ms` 的读数（不发送 OSC、不写文件）
+    dedup_identical_readings: bool,
+    /// 相同心率至少间隔多少毫秒才再次发送
+    dedup_min_interval_ms: u64,
+    /// 连续多少次读数为 0（设备未佩戴）后进入空闲模式，0 表示关闭
+    idle_after_zero_readings: u32,
+    /// 空闲模式下检查设备是否重新佩戴的间隔（秒）
+    idle_check_secs: u64,
     /// 是否将心率实时写入 HeartRate.txt（供 OBS 等读取）
     write_heart_rate_file: bool,
+    /// 心率文件路径，不设置则为程序目录下的 HeartRate.txt；相对路径相对于程序目录
+    heart_rate_file_path: Option<PathBuf>,
+    /// 心率文件内容模板，占位符见 template 模块
+    heart_rate_file_format: String,
+    /// 未佩戴、断开或退出时写入心率文件的内容
+    heart_rate_file_offline: String,
+    /// 是否写入 JSON 状态文件（格式见 status_file 模块）
+    write_status_json: bool,
+    /// JSON 状态文件路径，不设置则为程序目录下的 status.json；相对路径相对于程序目录
+    status_json_path: Option<PathBuf>,
+    /// 是否把每条读数记录到 CSV 会话文件（格式见 session_log 模块）
+    write_session_log: bool,
+    /// 会话文件目录，不设置则为程序目录下的 sessions；相对路径相对于程序目录
+    session_log_dir: Option<PathBuf>,
+    /// 会话文件的落盘间隔（秒），期间的行先缓存在内存中
+    session_log_flush_secs: u64,
+    /// 单个会话文件超过该大小（MB）时换新文件，0 = 不按大小轮换
+    session_log_rotate_mb: u64,
+    /// 单个会话文件超过该时长（分钟）时换新文件，0 = 不按时长轮换
+    session_log_rotate_mins: u64,
+    /// 是否把会话和读数记录到 SQLite 历史库（表结构见 history_db 模块）
+    write_history_db: bool,
+    /// 历史库路径，不设置则为程序目录下的 heartrate.db；相对路径相对于程序目录
+    history_db_path: Option<PathBuf>,
+    /// 读数批量写入历史库的间隔（秒）
+    history_db_commit_secs: u64,
+    /// 退出时是否把本次运行总结追加到程序目录下的 sessions.log
+    session_summary_log: bool,
+    /// 是否把心率同步写入共享内存段（布局见 shm 模块）
+    shm_enabled: bool,
+    /// 共享内存段名称
+    shm_name: String,
+    /// 是否把心率写入注册表 DWORD 值（仅 Windows，需以 registry-output 特性编译，见 registry_output 模块）
+    registry_output_enabled: bool,
+    /// 是否在系统托盘显示图标并隐藏控制台窗口（仅 Windows，见 tray 模块）
+    tray_enabled: bool,
+    /// 注册表路径：根键\键\值名称
+    registry_key_path: String,
+    /// 是否通过命名管道（Windows）/ Unix 域套接字向本机程序推送 JSON 行（格式见 pipe 模块）
+    pipe_enabled: bool,
+    /// 管道名称：Windows 上为 \\.\pipe\<名称>，其他平台为默认套接字文件名
+    pipe_name: String,
+    /// 非 Windows 平台的套接字路径，不设置则为临时目录下的 <pipe_name>.sock；Windows 上忽略
+    pipe_socket_path: Option<PathBuf>,
+    /// 是否启动本机 WebSocket 服务，推送与 status.json 相同的 JSON（见 websocket 模块）
+    websocket_enabled: bool,
+    /// WebSocket 服务监听的地址；改为 0.0.0.0 可让局域网内其他设备访问
+    websocket_ip: Ipv4Addr,
+    websocket_port: u16,
+    /// 是否启动本机 HTTP 接口（GET /api/state、/api/history，见 http_api 模块）
+    http_enabled: bool,
+    /// HTTP 接口监听的地址；改为 0.0.0.0 可让局域网内其他设备访问
+    http_ip: Ipv4Addr,
+    http_port: u16,
+    /// 响应中 Access-Control-Allow-Origin 的值（如 "*"），供其他端口上的浏览器页面 fetch；不设置则不发送 CORS 头
+    http_cors_origin: Option<String>,
+    /// /api/history 在内存中保留的时长（秒）
+    http_history_secs: u64,
+    /// 是否为 Resonite 启动 WebSocket 服务，推送心率数字或 {bpm, connected} JSON（见 resonite 模块）
+    resonite_enabled: bool,
+    /// Resonite WebSocket 服务监听的地址；改为 0.0.0.0 可让局域网内其他设备访问
+    resonite_ip: Ipv4Addr,
+    resonite_port: u16,
+    /// 接受连接的路径，其他路径返回 404
+    resonite_path: String,
+    /// 消息格式："text"（只有心率数字）或 "json"
+    resonite_format: resonite::ResoniteFormat,
+    /// 是否通过 obs-websocket v5 直接更新 OBS 中的文本源（内容同 HeartRate.txt，见 obs 模块）
+    obs_enabled: bool,
+    /// OBS 所在主机（主机名或 IP）
+    obs_host: String,
+    /// OBS WebSocket 服务器端口（OBS"工具 → WebSocket 服务器设置"）
+    obs_port: u16,
+    /// OBS WebSocket 服务器密码；OBS 未开启身份验证时不设置
+    obs_password: Option<String>,
+    /// 要更新的文本源名称
+    obs_input_name: String,
+    /// 是否把心率发布到 MQTT 代理（见 mqtt 模块）
+    mqtt_enabled: bool,
+    /// MQTT 代理主机（主机名或 IP）
+    mqtt_host: String,
+    mqtt_port: u16,
+    /// 是否使用 TLS 连接代理（以系统根证书验证），通常配合 8883 端口
+    mqtt_tls: bool,
+    /// 代理要求认证时的用户名，不设置则匿名连接
+    mqtt_username: Option<String>,
+    /// 代理要求认证时的密码（需同时设置 mqtt_username）
+    mqtt_password: Option<String>,
+    /// 客户端 ID，同一代理上的多个实例需各不相同
+    mqtt_client_id: String,
+    /// 发布当前心率（纯文本数字）的主题
+    mqtt_topic: String,
+    /// 发布完整状态 JSON（字段同 status.json）的主题
+    mqtt_state_topic: String,
+    /// 可用性主题：已连接设备时为 online，设备断开或程序退出（遗嘱）时为 offline
+    mqtt_availability_topic: String,
+    /// 发布的服务质量等级 0 / 1 / 2
+    mqtt_qos: u8,
+    /// 是否发布 Home Assistant MQTT 自动发现消息（见 ha_discovery 模块）
+    mqtt_ha_discovery: bool,
+    /// Home Assistant 的发现主题前缀
+    mqtt_ha_discovery_prefix: String,
+    /// 改为发布空的发现消息，从 Home Assistant 删除本程序创建的实体
+    mqtt_ha_discovery_remove: bool,
+    /// 是否把读数批量写入 InfluxDB（line protocol，见 influx 模块）
+    influx_enabled: bool,
+    /// InfluxDB 地址（http:// 或 https://），如 "http://127.0.0.1:8086"
+    influx_url: String,
+    /// InfluxDB 组织名
+    influx_org: String,
+    /// 写入的 bucket
+    influx_bucket: String,
+    /// API Token（需有该 bucket 的写权限），不设置则不带认证头
+    influx_token: Option<String>,
+    /// 度量（measurement）名
+    influx_measurement: String,
+    /// 每隔多少秒批量发送一次
+    influx_flush_secs: u64,
+    /// InfluxDB 不可用时最多在内存中暂存的读数条数，超过则丢弃最旧的
+    influx_max_buffered_points: usize,
+    /// 不发送，把 line protocol 打印到控制台（用于检查配置）
+    influx_dry_run: bool,
+    /// 是否在连接、断开、心率越过阈值时 POST Webhook（见 webhook 模块）
+    webhook_enabled: bool,
+    /// Webhook 地址（http:// 或 https://）
+    webhook_url: String,
+    /// 是否发送 device_connected 事件
+    webhook_on_connect: bool,
+    /// 是否发送 device_disconnected 事件
+    webhook_on_disconnect: bool,
+    /// 是否发送 hr_above_threshold 事件
+    webhook_on_hr_high: bool,
+    /// 是否发送 hr_below_threshold 事件
+    webhook_on_hr_low: bool,
+    /// 心率达到该值及以上时发送 hr_above_threshold，不设置则不检测
+    webhook_hr_high: Option<u8>,
+    /// 心率降到该值及以下后才会再次触发 hr_above_threshold，不设置则为 webhook_hr_high - 5
+    webhook_hr_high_exit: Option<u8>,
+    /// 心率降到该值及以下时发送 hr_below_threshold（0 即未佩戴不算），不设置则不检测
+    webhook_hr_low: Option<u8>,
+    /// 心率升到该值及以上后才会再次触发 hr_below_threshold，不设置则为 webhook_hr_low + 5
+    webhook_hr_low_exit: Option<u8>,
+    /// 连接状态需保持该时间（秒）才发送连接/断开事件，避免频繁断连时刷屏；0 表示立即发送
+    webhook_debounce_secs: u64,
+    /// 是否向 Discord Webhook 发送断开、低电量提醒和运行总结
+    discord_enabled: bool,
+    /// Discord Webhook 地址（https://discord.com/api/webhooks/...）
+    discord_webhook_url: String,
+    /// 断开后超过该时间（秒）仍未恢复连接时提醒，0 表示不提醒
+    discord_disconnect_alert_secs: u64,
+    /// 连接时读到的电量低于该百分比时提醒，0 表示不提醒
+    discord_battery_threshold: u8,
+    /// 退出时是否发送本次运行总结
+    discord_session_summary: bool,
+    /// 断开提醒的正文模板，可用 {device}、{secs}
+    discord_disconnect_message: String,
+    /// 低电量提醒的正文模板，可用 {device}、{battery}
+    discord_battery_message: String,
+    /// 运行总结的正文模板，可用 {summary}
+    discord_summary_message: String,
+    /// 是否在 Twitch 聊天中播报心率里程碑（见 twitch 模块）
+    twitch_enabled: bool,
+    /// 播报的频道（主播的登录名，可带 #）
+    twitch_channel: String,
+    /// 发送消息的账号登录名
+    twitch_username: String,
+    /// 该账号的 OAuth 令牌（需 chat:edit 权限，可带或不带 oauth: 前缀）
+    twitch_oauth_token: Option<String>,
+    /// 心率向上越过这些值时播报
+    twitch_milestones: Vec<u8>,
+    /// 同一阈值两次播报的最短间隔（秒）
+    twitch_milestone_cooldown_secs: u64,
+    /// 里程碑消息模板，可用 {threshold}、{bpm}
+    twitch_milestone_message: String,
+    /// 定时播报当前心率的间隔（秒），0 表示不播报
+    twitch_periodic_secs: u64,
+    /// 定时播报的消息模板，可用 {bpm}
+    twitch_periodic_message: String,
+    /// 不连接 Twitch，只把将要发送的消息打印到控制台
+    twitch_dry_run: bool,
+    /// 是否在 Discord 动态（Rich Presence）中显示实时心率（见 discord_presence 模块）
+    discord_presence_enabled: bool,
+    /// Discord 开发者后台创建的应用 ID，应用名称会显示为"正在玩 …"
+    discord_presence_client_id: String,
+    /// 动态第一行的模板，占位符同 heart_rate_file_format
+    discord_presence_details: String,
+    /// 动态第二行的模板，为空则不显示
+    discord_presence_state: String,
+    /// 未连接或未佩戴时第一行显示的内容
+    discord_presence_offline: String,
+    /// 是否通过 XSOverlay 发送头显内通知（见 vr_notify 模块）
+    vr_notify_xsoverlay: bool,
+    /// 是否通过 OVR Toolkit 发送头显内通知
+    vr_notify_ovr_toolkit: bool,
+    /// 设备断开时通知
+    vr_notify_on_disconnect: bool,
+    /// 断开后重新连接时通知
+    vr_notify_on_reconnect: bool,
+    /// 连接时电量低于 vr_notify_battery_threshold 时通知
+    vr_notify_on_low_battery: bool,
+    /// 低电量通知的阈值（百分比）
+    vr_notify_battery_threshold: u8,
+    /// 心率报警（hr_alarm_high / hr_alarm_low）触发时通知
+    vr_notify_on_alarm: bool,
+    /// 是否随心跳发送震动脉冲（见 haptics 模块）
+    haptics_enabled: bool,
+    /// 脉冲发往的 OSC 地址（bHaptics 接收端或背心映射到的 avatar 参数），可填多个
+    haptics_osc_addresses: Vec<String>,
+    /// 脉冲的取值类型：float 为强度、bool 为 true/false
+    haptics_value: haptics::HapticValue,
+    /// 脉冲发往 osc_ip 的哪个端口，不设置则与心率相同
+    haptics_osc_port: Option<u16>,
+    /// 每个脉冲的持续时间（毫秒）
+    haptics_pulse_ms: u64,
+    /// 心率区间 0 时的强度（0–1）
+    haptics_intensity_min: f32,
+    /// 心率区间 5 时的强度（0–1）
+    haptics_intensity_max: f32,
+    /// 心率高于该值时报警（播放系统提示音并发送 hr_alarm=true），不设置则不检测
+    hr_alarm_high: Option<u8>,
+    /// 心率低于该值时报警（心率为 0 即未佩戴时不报警），不设置则不检测
+    hr_alarm_low: Option<u8>,
+    /// 两次报警提示音之间的最短间隔（秒），避免心率在阈值附近波动时反复响
+    alarm_cooldown_secs: u64,
+    /// hr_stress 参数的分母（压力指数/该值 = 0–1，超过记为 1）
+    max_stress_index: f32,
+    /// hr_trimp 参数的分母（本次连接累计的训练负荷/该值 = 0–1，超过记为 1）
+    max_session_trimp: f32,
+    /// 是否启用静息检测：静息时发送 isHRActive=false 与 hr_steady=true
+    steady_state_mute: bool,
+    /// 静息判定：最近 5 分钟心率标准差低于该值（BPM）
+    steady_state_sd_bpm: f32,
+    /// 静息判定：最近 5 分钟平均心率低于该值
+    resting_hr_threshold: u8,
+    /// 每隔多少秒在控制台打印一行心率统计（最低/最高/平均），0 表示关闭
+    stats_interval_secs: u64,
+    /// 连接期间读取 RSSI 的间隔（秒），0 表示关闭
+    rssi_poll_secs: u64,
+    /// RSSI 低于该值（dBm）视为弱信号
+    rssi_warn_floor: i16,
+    /// 连续多少次弱信号后提示即将断开
+    rssi_warn_samples: u32,
+    /// 是否发送归一化信号质量 /avatar/parameters/hr_signal
+    osc_signal_quality: bool,
+    /// 是否订阅血氧特征 (0x2A5F) 并发送 /avatar/parameters/hr_spo2
+    spo2_enabled: bool,
+    /// 是否订阅步数特征，由步数差分估算步频并发送 /avatar/parameters/hr_cadence_rpm
+    cadence_enabled: bool,
+    /// 实时步数特征的 UUID，默认为小米/华米手环的实时步数特征
+    step_count_char_uuid: Uuid,
+    /// 是否请求 Polar 传感器（OH1 等）的加速度数据并发送 /avatar/parameters/hr_movement，见 polar_pmd 模块
+    polar_acc_enabled: bool,
+    /// hr_movement 为 1.0 时的运动强度（去掉重力后的合加速度，单位 g）
+    polar_acc_max_g: f32,
+    /// 仅广播模式：不连接设备，从广播数据中读取心率（Garmin "广播心率"等）
+    broadcast_mode: bool,
+    /// 仅广播模式下从该厂商 ID 的厂商数据中读取心率，不设置则只读取 0x180D 服务数据
+    broadcast_manufacturer_id: Option<u16>,
+    /// 厂商数据中心率（单字节 BPM）所在的字节偏移
+    broadcast_hr_offset: usize,
+    /// 蓝牙长时间没有数据时改从串口读取心率（见 serial_source 模块）
+    serial_fallback_enabled: bool,
+    /// 串口名称，如 Windows 上的 "COM3"、Linux 上的 "/dev/ttyACM0"
+    serial_port: String,
+    /// 串口波特率
+    serial_baud: u32,
+    /// 蓝牙连续多少秒没有心率数据后切换到串口
+    serial_fallback_timeout_secs: u64,
+    /// 日志级别（tracing EnvFilter 语法，如 "info"、"debug"）
+    log_level: String,
+    /// 打印调试信息（被合并的重复通知等），等同于 log_level = "debug"
+    debug_log: bool,
+    /// 控制台状态行：simple（文字）、health-bar（血条）或 none（不显示），见 console_display 模块
+    console_display: console_display::ConsoleDisplay,
+    /// 心率变换插件（导出 hr_transform 的动态库），相对路径相对于程序目录
+    plugin_path: Option<PathBuf>,
+    /// 自定义输出脚本（Rhai，定义 on_reading(ctx)），相对路径相对于程序目录，见 script 模块
+    script_path: Option<PathBuf>,
+    /// 设置后不连接蓝牙，按原始间隔重放该心率记录（会话记录 CSV 或 JSONL，同 --replay），相对路径相对于程序目录
+    replay_file: Option<PathBuf>,
+    /// 是否把各输出项分别写入单值文件（HR.txt 等，与心率文件同目录）
+    write_split_files: bool,
+    /// 各输出项的开关，同时作用于 OSC 参数和单值文件
+    outputs: OutputToggles,
+    /// 布尔参数和 HR 在 OSC 中的类型，需与 avatar 中声明的参数类型一致
+    parameter_types: ParameterTypes,
+    /// 自定义 OSC 参数：发送时对表达式求值，附加到 Bundle 末尾，见 custom_params 模块
+    custom_osc_params: Vec<custom_params::CustomParam>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
+            source: SourceKind::Ble,
+            pulsoid_token: None,
             selection_mode: "auto".to_string(),
             target_device_names: vec![
                 "Xiaomi Smart Band 9".to_string(),
@@ -58,17 +526,228 @@ impl Default for Config {
                 "HUAWEI".to_string(),
                 "HONOR".to_string(),
             ],
+            preferred_device_order: Vec::new(),
+            additional_hr_service_uuids: Vec::new(),
+            additional_hr_char_uuids: Vec::new(),
+            hr_char_formats: BTreeMap::new(),
+            osc_platform: OscPlatform::Vrchat,
             osc_ip: "127.0.0.1".to_string(),
-            osc_port: 9000,
+            osc_port: None,
+            osc_parameter_prefix: None,
+            osc_compat_parameters: None,
+            osc_local_ip: None,
+            osc_multicast_group: None,
+            osc_ttl: 1,
+            osc_timetag: OscTimetag::Immediate,
+            use_osc_timetag: None,
+            osc_packet_mode: OscPacketMode::Bundle,
+            send_mode: SendMode::Scheduled,
+            send_rate_hz: 4.0,
+            osc_feedback_enabled: false,
+            osc_receive_port: 9001,
+            osc_proxy_enabled: false,
+            osc_proxy_listen_ip: Ipv4Addr::LOCALHOST,
+            osc_proxy_listen_port: 9010,
             max_heart_rate_for_percent: 200.0,
             scan_duration_secs: 5,
+            cache_valid_secs: 60,
+            scan_bonded_only: false,
             retry_delay_secs: 5,
+            max_connection_attempts_before_adapter_reset: 10,
             heartbeat_timeout_secs: 15,
+            use_native_watchdog: false,
+            ghost_mode_secs: 5,
+            scan_timeout_secs: 30,
+            connect_timeout_secs: 15,
+            service_timeout_secs: 15,
+            subscribe_retries: 3,
+            poll_interval_ms: 1000,
+            start_command_char_uuid: HEART_RATE_CONTROL_POINT_UUID,
+            start_command_hex: "01".to_string(),
+            auth_key: None,
+            notification_dedupe_ms: 200,
+            max_notification_queue_depth: None,
+            dedup_identical_readings: false,
+            dedup_min_interval_ms: 500,
+            idle_after_zero_readings: 60,
+            idle_check_secs: 30,
             write_heart_rate_file: false,
+            heart_rate_file_path: None,
+            heart_rate_file_format: "{hr}".to_string(),
+            heart_rate_file_offline: "0".to_string(),
+            write_status_json: false,
+            status_json_path: None,
+            write_session_log: false,
+            session_log_dir: None,
+            session_log_flush_secs: 10,
+            session_log_rotate_mb: 0,
+            session_log_rotate_mins: 0,
+            write_history_db: false,
+            history_db_path: None,
+            history_db_commit_secs: 5,
+            session_summary_log: false,
+            shm_enabled: false,
+            shm_name: "HeartRateVRC".to_string(),
+            registry_output_enabled: false,
+            tray_enabled: false,
+            registry_key_path: r"HKCU\Software\HeartRateVRC\BPM".to_string(),
+            pipe_enabled: false,
+            pipe_name: "HeartRateForVRChat".to_string(),
+            pipe_socket_path: None,
+            websocket_enabled: false,
+            websocket_ip: Ipv4Addr::LOCALHOST,
+            websocket_port: 8765,
+            http_enabled: false,
+            http_ip: Ipv4Addr::LOCALHOST,
+            http_port: 8766,
+            http_cors_origin: None,
+            http_history_secs: 600,
+            resonite_enabled: false,
+            resonite_ip: Ipv4Addr::LOCALHOST,
+            resonite_port: 8767,
+            resonite_path: "/heartrate".to_string(),
+            resonite_format: resonite::ResoniteFormat::Text,
+            obs_enabled: false,
+            obs_host: "127.0.0.1".to_string(),
+            obs_port: 4455,
+            obs_password: None,
+            obs_input_name: "HeartRate".to_string(),
+            mqtt_enabled: false,
+            mqtt_host: "127.0.0.1".to_string(),
+            mqtt_port: 1883,
+            mqtt_tls: false,
+            mqtt_username: None,
+            mqtt_password: None,
+            mqtt_client_id: "heartrate-for-vrchat".to_string(),
+            mqtt_topic: "heartrate/bpm".to_string(),
+            mqtt_state_topic: "heartrate/state".to_string(),
+            mqtt_availability_topic: "heartrate/availability".to_string(),
+            mqtt_qos: 1,
+            mqtt_ha_discovery: false,
+            mqtt_ha_discovery_prefix: "homeassistant".to_string(),
+            mqtt_ha_discovery_remove: false,
+            influx_enabled: false,
+            influx_url: "http://127.0.0.1:8086".to_string(),
+            influx_org: String::new(),
+            influx_bucket: "heartrate".to_string(),
+            influx_token: None,
+            influx_measurement: "heart_rate".to_string(),
+            influx_flush_secs: 5,
+            influx_max_buffered_points: 10_000,
+            influx_dry_run: false,
+            webhook_enabled: false,
+            webhook_url: String::new(),
+            webhook_on_connect: true,
+            webhook_on_disconnect: true,
+            webhook_on_hr_high: true,
+            webhook_on_hr_low: true,
+            webhook_hr_high: None,
+            webhook_hr_high_exit: None,
+            webhook_hr_low: None,
+            webhook_hr_low_exit: None,
+            webhook_debounce_secs: 10,
+            discord_enabled: false,
+            discord_webhook_url: String::new(),
+            discord_disconnect_alert_secs: 60,
+            discord_battery_threshold: 20,
+            discord_session_summary: true,
+            discord_disconnect_message: "心率带 {device} 已断开 {secs} 秒，仍未恢复连接。"
+                .to_string(),
+            discord_battery_message: "心率带 {device} 电量低：{battery}%".to_string(),
+            discord_summary_message: "```\n{summary}\n```".to_string(),
+            twitch_enabled: false,
+            twitch_channel: String::new(),
+            twitch_username: String::new(),
+            twitch_oauth_token: None,
+            twitch_milestones: vec![120, 150, 180],
+            twitch_milestone_cooldown_secs: 600,
+            twitch_milestone_message: "心率突破 {threshold}！当前 {bpm} BPM".to_string(),
+            twitch_periodic_secs: 0,
+            twitch_periodic_message: "当前心率：{bpm} BPM".to_string(),
+            twitch_dry_run: false,
+            discord_presence_enabled: false,
+            discord_presence_client_id: String::new(),
+            discord_presence_details: "❤ {hr} BPM".to_string(),
+            discord_presence_state: "心率区间 {zone} · 平均 {avg} BPM".to_string(),
+            discord_presence_offline: "心率带未连接".to_string(),
+            vr_notify_xsoverlay: false,
+            vr_notify_ovr_toolkit: false,
+            vr_notify_on_disconnect: true,
+            vr_notify_on_reconnect: true,
+            vr_notify_on_low_battery: true,
+            vr_notify_battery_threshold: 20,
+            vr_notify_on_alarm: true,
+            haptics_enabled: false,
+            haptics_osc_addresses: vec!["/avatar/parameters/hr_haptic".to_string()],
+            haptics_value: haptics::HapticValue::Float,
+            haptics_osc_port: None,
+            haptics_pulse_ms: 80,
+            haptics_intensity_min: 0.3,
+            haptics_intensity_max: 1.0,
+            hr_alarm_high: None,
+            hr_alarm_low: None,
+            alarm_cooldown_secs: 60,
+            max_stress_index: 10.0,
+            max_session_trimp: 200.0,
+            steady_state_mute: false,
+            steady_state_sd_bpm: 3.0,
+            resting_hr_threshold: 75,
+            stats_interval_secs: 60,
+            rssi_poll_secs: 5,
+            rssi_warn_floor: -90,
+            rssi_warn_samples: 3,
+            osc_signal_quality: false,
+            spo2_enabled: false,
+            cadence_enabled: false,
+            step_count_char_uuid: MI_REALTIME_STEPS_CHAR_UUID,
+            polar_acc_enabled: false,
+            polar_acc_max_g: 2.0,
+            broadcast_mode: false,
+            broadcast_manufacturer_id: None,
+            broadcast_hr_offset: 0,
+            serial_fallback_enabled: false,
+            serial_port: String::new(),
+            serial_baud: 9600,
+            serial_fallback_timeout_secs: 30,
+            log_level: "info".to_string(),
+            debug_log: false,
+            console_display: console_display::ConsoleDisplay::Simple,
+            plugin_path: None,
+            script_path: None,
+            replay_file: None,
+            write_split_files: false,
+            outputs: OutputToggles::default(),
+            parameter_types: ParameterTypes::default(),
+            custom_osc_params: Vec::new(),
         }
     }
 }
 
+impl Config {
+    /// OSC 目标端口：显式设置的 osc_port 优先，否则为 [`DEFAULT_OSC_PORT`]。
+    fn osc_port(&self) -> u16 {
+        self.osc_port.unwrap_or(DEFAULT_OSC_PORT)
+    }
+
+    /// 参数地址前缀：显式设置的 osc_parameter_prefix 优先，否则取平台预设。
+    fn osc_parameter_prefix(&self) -> &str {
+        self.osc_parameter_prefix
+            .as_deref()
+            .unwrap_or_else(|| self.osc_platform.parameter_prefix())
+    }
+
+    /// 是否发送 VRChat 预制件兼容参数：显式设置的 osc_compat_parameters 优先，否则取平台预设。
+    fn osc_compat_parameters(&self) -> bool {
+        self.osc_compat_parameters
+            .unwrap_or_else(|| self.osc_platform.compat_parameters())
+    }
+
+    /// hr_percent 与心率区间的分母：max_heart_rate_for_percent 为 0 时取自动增益的当前值。
+    fn max_heart_rate(&self) -> f32 {
+        auto_gain::resolve(self.max_heart_rate_for_percent)
+    }
+}
+
 const CONFIG_TEMPLATE: &str = include_str!("../config.example.toml");
 
 /// 获取 exe 所在目录；失败时回退到当前工作目录（绝对路径）。
@@ -89,51 +768,35 @@ fn exe_dir() -> PathBuf {
     }
 }
 
-/// 从 exe 同目录加载 config.toml；文件不存在则生成模板并返回默认配置。
+/// 从 exe 同目录加载 config.toml；文件不存在则生成模板，无法解析时从备份恢复（见 read_config_file）。
 /// 加载后对取值做合法性校验/钳制。
 fn load_config(dir: &Path) -> Config {
-    let path = dir.join("config.toml");
-    let mut config = match fs::read_to_string(&path) {
-        Ok(text) => match toml::from_str::<Config>(&text) {
-            Ok(config) => {
-                println!("已加载配置文件: {}", path.display());
-                config
-            }
-            Err(e) => {
-                eprintln!("=============================================");
-                eprintln!(
-                    "警告：配置文件解析失败，本次运行将忽略其中的【全部】设置，使用默认配置！"
-                );
-                eprintln!("文件: {}", path.display());
-                eprintln!("原因: {}", e);
-                eprintln!("请修正后重启程序（或删除该文件以重新生成模板）。");
-                eprintln!("=============================================");
-                Config::default()
-            }
-        },
-        Err(_) => {
-            match fs::write(&path, CONFIG_TEMPLATE) {
-                Ok(()) => println!(
-                    "已生成默认配置文件: {}（可编辑后重启程序生效）",
-                    path.display()
-                ),
-                Err(e) => eprintln!(
-                    "无法生成配置文件 {}: {}，将使用默认配置。",
-                    path.display(),
-                    e
-                ),
-            }
-            Config::default()
-        }
-    };
+    validate_config(read_config_file(&dir.join("config.toml")))
+}
+
+/// 校验配置：非法值回退默认值，过小或越界的数值钳制到有效范围，并逐项打印警告。
+fn validate_config(mut config: Config) -> Config {
+    if config.source == SourceKind::Pulsoid
+        && config
+            .pulsoid_token
+            .as_deref()
+            .is_none_or(|token| token.trim().is_empty())
+    {
+        eprintln!("警告：source = \"pulsoid\" 需要设置 pulsoid_token，将改用蓝牙连接。");
+        config.source = SourceKind::Ble;
+    }
+    if config.source == SourceKind::Pulsoid && config.broadcast_mode {
+        eprintln!("警告：broadcast_mode 只适用于蓝牙连接，source = \"pulsoid\" 时已忽略。");
+        config.broadcast_mode = false;
+    }
 
     // 校验 selection_mode，非法值回退 auto 并给出明确提示
     let mode = config.selection_mode.trim().to_ascii_lowercase();
-    if matches!(mode.as_str(), "auto" | "name" | "strongest") {
+    if matches!(mode.as_str(), "auto" | "name" | "strongest" | "first") {
         config.selection_mode = mode;
     } else {
         eprintln!(
-            "警告：selection_mode = \"{}\" 不是有效值（auto / name / strongest），将按 auto 处理。",
+            "警告：selection_mode = \"{}\" 不是有效值（auto / name / strongest / first），将按 auto 处理。",
             config.selection_mode
         );
         config.selection_mode = "auto".to_string();
@@ -152,29 +815,627 @@ fn load_config(dir: &Path) -> Config {
         eprintln!("警告：retry_delay_secs 过小，已调整为 1。");
         config.retry_delay_secs = 1;
     }
-    if config.max_heart_rate_for_percent < 1.0 {
-        eprintln!("警告：max_heart_rate_for_percent 过小，已调整为 200。");
+    if config.scan_timeout_secs < 5 {
+        eprintln!("警告：scan_timeout_secs 过小，已调整为 5。");
+        config.scan_timeout_secs = 5;
+    }
+    if config.connect_timeout_secs < 5 {
+        eprintln!("警告：connect_timeout_secs 过小，已调整为 5。");
+        config.connect_timeout_secs = 5;
+    }
+    if config.service_timeout_secs < 5 {
+        eprintln!("警告：service_timeout_secs 过小，已调整为 5。");
+        config.service_timeout_secs = 5;
+    }
+    // 绑定一次即可验证该地址确实属于本机某个网卡
+    if let Some(local_ip) = config.osc_local_ip {
+        if let Err(e) = UdpSocket::bind(SocketAddr::new(local_ip, 0)) {
+            eprintln!(
+                "警告：osc_local_ip = \"{}\" 不是本机网卡上的地址（{}），将由系统自动选择发送网卡。",
+                local_ip, e
+            );
+            config.osc_local_ip = None;
+        } else if parse_osc_ip(&config.osc_ip).is_some_and(|ip| ip.is_ipv6() != local_ip.is_ipv6())
+        {
+            eprintln!(
+                "警告：osc_local_ip = \"{}\" 与 osc_ip = \"{}\" 不是同一种地址（IPv4/IPv6），将由系统自动选择发送网卡。",
+                local_ip, config.osc_ip
+            );
+            config.osc_local_ip = None;
+        }
+    }
+    if let Some(group) = config.osc_multicast_group {
+        if !group.is_multicast() {
+            eprintln!(
+                "警告：osc_multicast_group = \"{}\" 不是组播地址（224.0.0.0–239.255.255.255），已忽略。",
+                group
+            );
+            config.osc_multicast_group = None;
+        }
+    }
+    // 原样写进响应头，不能含换行等控制字符
+    if config
+        .http_cors_origin
+        .as_ref()
+        .is_some_and(|origin| origin.is_empty() || origin.chars().any(char::is_control))
+    {
+        eprintln!("警告：http_cors_origin 为空或包含控制字符，已忽略（不发送 CORS 头）。");
+        config.http_cors_origin = None;
+    }
+    if config.serial_fallback_enabled && config.serial_port.trim().is_empty() {
+        eprintln!(
+            "警告：已开启 serial_fallback_enabled 但没有设置 serial_port，串口备用源不会启用。"
+        );
+        config.serial_fallback_enabled = false;
+    }
+    if config.serial_baud == 0 {
+        eprintln!("警告：serial_baud 不能为 0，已恢复为 9600。");
+        config.serial_baud = 9600;
+    }
+    if config.mqtt_qos > 2 {
+        eprintln!(
+            "警告：mqtt_qos = {} 无效（只能为 0、1、2），已恢复为 1。",
+            config.mqtt_qos
+        );
+        config.mqtt_qos = 1;
+    }
+    // 发布主题不能为空，也不能含通配符 + / #
+    let defaults = Config::default();
+    for (name, topic, default) in [
+        ("mqtt_topic", &mut config.mqtt_topic, defaults.mqtt_topic),
+        (
+            "mqtt_state_topic",
+            &mut config.mqtt_state_topic,
+            defaults.mqtt_state_topic,
+        ),
+        (
+            "mqtt_availability_topic",
+            &mut config.mqtt_availability_topic,
+            defaults.mqtt_availability_topic,
+        ),
+        (
+            "mqtt_ha_discovery_prefix",
+            &mut config.mqtt_ha_discovery_prefix,
+            defaults.mqtt_ha_discovery_prefix,
+        ),
+    ] {
+        if topic.is_empty() || topic.contains(['+', '#']) {
+            eprintln!(
+                "警告：{} = \"{}\" 不是有效的发布主题，已恢复为 \"{}\"。",
+                name, topic, default
+            );
+            *topic = default;
+        }
+    }
+    if config.influx_flush_secs == 0 {
+        eprintln!("警告：influx_flush_secs 不能为 0，已调整为 1。");
+        config.influx_flush_secs = 1;
+    }
+    if config.influx_max_buffered_points == 0 {
+        eprintln!("警告：influx_max_buffered_points 不能为 0，已恢复为 10000。");
+        config.influx_max_buffered_points = defaults.influx_max_buffered_points;
+    }
+    if config.influx_enabled && config.influx_measurement.is_empty() {
+        eprintln!("警告：influx_measurement 不能为空，已恢复为 \"heart_rate\"。");
+        config.influx_measurement = defaults.influx_measurement;
+    }
+    if config.webhook_enabled && config.webhook_url.is_empty() {
+        eprintln!("警告：已开启 webhook_enabled 但没有设置 webhook_url，Webhook 不会启用。");
+        config.webhook_enabled = false;
+    }
+    if let (Some(enter), Some(exit)) = (config.webhook_hr_high, config.webhook_hr_high_exit) {
+        if exit >= enter {
+            eprintln!(
+                "警告：webhook_hr_high_exit ({}) 应小于 webhook_hr_high ({})，已改用默认值。",
+                exit, enter
+            );
+            config.webhook_hr_high_exit = None;
+        }
+    }
+    if let (Some(enter), Some(exit)) = (config.webhook_hr_low, config.webhook_hr_low_exit) {
+        if exit <= enter {
+            eprintln!(
+                "警告：webhook_hr_low_exit ({}) 应大于 webhook_hr_low ({})，已改用默认值。",
+                exit, enter
+            );
+            config.webhook_hr_low_exit = None;
+        }
+    }
+    if config.registry_output_enabled {
+        if !registry_output::SUPPORTED {
+            eprintln!(
+                "警告：registry_output_enabled 仅在 Windows 上以 --features registry-output 编译时可用，已忽略。"
+            );
+            config.registry_output_enabled = false;
+        } else if let Err(e) = registry_output::parse_path(&config.registry_key_path) {
+            eprintln!(
+                "警告：registry_key_path = \"{}\" 无效（{}），注册表输出不会启用。",
+                config.registry_key_path, e
+            );
+            config.registry_output_enabled = false;
+        }
+    }
+    if config.tray_enabled && !tray::SUPPORTED {
+        eprintln!("警告：tray_enabled 仅在 Windows 上可用，已忽略。");
+        config.tray_enabled = false;
+    }
+    if config.discord_enabled && config.discord_webhook_url.is_empty() {
+        eprintln!(
+            "警告：已开启 discord_enabled 但没有设置 discord_webhook_url，Discord 通知不会启用。"
+        );
+        config.discord_enabled = false;
+    }
+    if config.discord_battery_threshold > 100 {
+        eprintln!(
+            "警告：discord_battery_threshold ({}) 超过 100，已恢复为 {}。",
+            config.discord_battery_threshold, defaults.discord_battery_threshold
+        );
+        config.discord_battery_threshold = defaults.discord_battery_threshold;
+    }
+    if config.twitch_enabled && config.twitch_channel.trim_start_matches('#').is_empty() {
+        eprintln!("警告：已开启 twitch_enabled 但没有设置 twitch_channel，Twitch 播报不会启用。");
+        config.twitch_enabled = false;
+    }
+    if config.twitch_enabled
+        && !config.twitch_dry_run
+        && (config.twitch_username.is_empty() || config.twitch_oauth_token.is_none())
+    {
+        eprintln!(
+            "警告：Twitch 播报需要设置 twitch_username 和 twitch_oauth_token（或开启 twitch_dry_run），已关闭。"
+        );
+        config.twitch_enabled = false;
+    }
+    if config.discord_presence_enabled && config.discord_presence_client_id.trim().is_empty() {
+        eprintln!(
+            "警告：已开启 discord_presence_enabled 但没有设置 discord_presence_client_id，Discord 动态不会启用。"
+        );
+        config.discord_presence_enabled = false;
+    }
+    if config.vr_notify_battery_threshold > 100 {
+        eprintln!(
+            "警告：vr_notify_battery_threshold ({}) 超过 100，已恢复为 {}。",
+            config.vr_notify_battery_threshold, defaults.vr_notify_battery_threshold
+        );
+        config.vr_notify_battery_threshold = defaults.vr_notify_battery_threshold;
+    }
+    if config.haptics_enabled && config.haptics_osc_addresses.is_empty() {
+        eprintln!("警告：已开启 haptics_enabled 但 haptics_osc_addresses 为空，心跳震动不会启用。");
+        config.haptics_enabled = false;
+    }
+    if !(10..=200).contains(&config.haptics_pulse_ms) {
+        let clamped = config.haptics_pulse_ms.clamp(10, 200);
+        eprintln!(
+            "警告：haptics_pulse_ms ({}) 应在 10–200 之间（240 BPM 时两拍相隔 250 毫秒），已调整为 {}。",
+            config.haptics_pulse_ms, clamped
+        );
+        config.haptics_pulse_ms = clamped;
+    }
+    let intensity_range = 0.0..=1.0;
+    if !intensity_range.contains(&config.haptics_intensity_min)
+        || !intensity_range.contains(&config.haptics_intensity_max)
+        || config.haptics_intensity_min > config.haptics_intensity_max
+    {
+        eprintln!(
+            "警告：haptics_intensity_min / haptics_intensity_max ({} / {}) 应在 0–1 之间且前者不大于后者，已恢复为 {} / {}。",
+            config.haptics_intensity_min,
+            config.haptics_intensity_max,
+            defaults.haptics_intensity_min,
+            defaults.haptics_intensity_max
+        );
+        config.haptics_intensity_min = defaults.haptics_intensity_min;
+        config.haptics_intensity_max = defaults.haptics_intensity_max;
+    }
+    if let Some(wall_clock) = config.use_osc_timetag.take() {
+        eprintln!(
+            "提示：use_osc_timetag 已更名为 osc_timetag，请改为 osc_timetag = \"{}\"。",
+            if wall_clock {
+                "wall-clock"
+            } else {
+                "immediate"
+            }
+        );
+        if wall_clock {
+            config.osc_timetag = OscTimetag::WallClock;
+        }
+    }
+    let mut index = 0;
+    config.custom_osc_params.retain_mut(|param| {
+        index += 1;
+        match param.compile() {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!(
+                    "警告：custom_osc_params 第 {} 项（{}）的表达式无效，已忽略：\n  {}\n  {}",
+                    index, param.address, param.expression, e
+                );
+                false
+            }
+        }
+    });
+    if let Some(prefix) = &mut config.osc_parameter_prefix {
+        if !prefix.starts_with('/') {
+            prefix.insert(0, '/');
+        }
+        if !prefix.ends_with('/') {
+            prefix.push('/');
+        }
+    }
+    if !config.resonite_path.starts_with('/') {
+        config.resonite_path.insert(0, '/');
+    }
+    if parse_hex(&config.start_command_hex).is_none() {
+        eprintln!(
+            "警告：start_command_hex = \"{}\" 不是有效的十六进制字节串，已恢复为 \"01\"。",
+            config.start_command_hex
+        );
+        config.start_command_hex = "01".to_string();
+    }
+    if let Some(key) = &config.auth_key {
+        if auth_key_bytes(key).is_none() {
+            eprintln!("警告：auth_key 应为 32 位十六进制（16 字节），已忽略。");
+            config.auth_key = None;
+        }
+    }
+    if config.idle_check_secs < 5 {
+        eprintln!("警告：idle_check_secs 过小，已调整为 5。");
+        config.idle_check_secs = 5;
+    }
+    if config.poll_interval_ms < 100 {
+        eprintln!("警告：poll_interval_ms 过小，已调整为 100。");
+        config.poll_interval_ms = 100;
+    }
+    if let (Some(high), Some(low)) = (config.hr_alarm_high, config.hr_alarm_low) {
+        if low >= high {
+            eprintln!(
+                "警告：hr_alarm_low ({}) 不小于 hr_alarm_high ({})，心率报警可能持续触发。",
+                low, high
+            );
+        }
+    }
+    // 0 表示自动增益
+    if config.max_heart_rate_for_percent < 1.0 && config.max_heart_rate_for_percent != 0.0 {
+        eprintln!("警告：max_heart_rate_for_percent 过小，已调整为 200（设为 0 则自动调整）。");
         config.max_heart_rate_for_percent = 200.0;
     }
+    if config.max_stress_index <= 0.0 {
+        eprintln!("警告：max_stress_index 必须大于 0，已调整为 10。");
+        config.max_stress_index = 10.0;
+    }
+    if config.polar_acc_max_g <= 0.0 {
+        eprintln!("警告：polar_acc_max_g 必须大于 0，已调整为 2。");
+        config.polar_acc_max_g = 2.0;
+    }
+    if config.max_session_trimp <= 0.0 {
+        eprintln!(
+            "警告：max_session_trimp 必须大于 0，已恢复为 {}。",
+            defaults.max_session_trimp
+        );
+        config.max_session_trimp = defaults.max_session_trimp;
+    }
+    if config.steady_state_sd_bpm <= 0.0 {
+        eprintln!("警告：steady_state_sd_bpm 必须大于 0，已调整为 3。");
+        config.steady_state_sd_bpm = 3.0;
+    }
+    if !(0.2..=30.0).contains(&config.send_rate_hz) {
+        let clamped = if config.send_rate_hz.is_nan() {
+            defaults.send_rate_hz
+        } else {
+            config.send_rate_hz.clamp(0.2, 30.0)
+        };
+        eprintln!(
+            "警告：send_rate_hz ({}) 应在 0.2–30 之间，已调整为 {}。",
+            config.send_rate_hz, clamped
+        );
+        config.send_rate_hz = clamped;
+    }
+    if config.rssi_warn_samples < 1 {
+        eprintln!("警告：rssi_warn_samples 过小，已调整为 1。");
+        config.rssi_warn_samples = 1;
+    }
 
     config
 }
 
-/// 将配置中的 OSC IPv4 地址和端口解析为发送目标。
-/// 地址非法时保持现有行为：提示后回退到本机，但仍使用配置的端口。
-fn resolve_osc_addr(config: &Config) -> SocketAddrV4 {
-    let osc_ip: Ipv4Addr = match config.osc_ip.parse() {
-        Ok(ip) => ip,
+/// 读取并解析配置文件。解析成功时把它另存为 config.toml.bak（最近一次可用的配置）；
+/// 解析失败时打印出错位置，把出错的文件另存为 config.toml.broken，再用备份恢复（见 restore_config_backup）。
+/// 文件不存在时生成模板。
+fn read_config_file(path: &Path) -> Config {
+    let backup = path.with_extension("toml.bak");
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
         Err(_) => {
+            write_config_template(path);
+            return Config::default();
+        }
+    };
+    match toml::from_str::<Config>(&text) {
+        Ok(config) => {
+            println!("已加载配置文件: {}", path.display());
+            if fs::read_to_string(&backup).ok().as_deref() != Some(text.as_str()) {
+                if let Err(e) = fs::write(&backup, &text) {
+                    eprintln!("无法写入配置备份 {}: {}", backup.display(), e);
+                }
+            }
+            config
+        }
+        Err(e) => {
+            eprintln!("=============================================");
+            eprintln!("警告：配置文件解析失败！");
+            eprintln!("文件: {}", path.display());
+            eprintln!("{}", describe_parse_error(&text, &e));
+            eprintln!("=============================================");
+            restore_config_backup(path, &backup)
+        }
+    }
+}
+
+/// 配置解析错误的说明：出错的行列（从 1 开始）及原因。
+fn describe_parse_error(text: &str, e: &toml::de::Error) -> String {
+    match e.span() {
+        Some(span) => {
+            let (line, column) = line_column(text, span.start);
+            format!(
+                "位置: 第 {} 行，第 {} 列\n原因: {}",
+                line,
+                column,
+                e.message()
+            )
+        }
+        None => format!("原因: {}", e),
+    }
+}
+
+/// 字节偏移量所在的行号和列号（从 1 开始，列按字符计）。
+fn line_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = text.get(..offset).unwrap_or(text);
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+/// 配置文件无法解析时：保留出错的文件为 config.toml.broken，用最近一次可用的备份覆盖并重新解析；
+/// 备份不存在或同样无法解析时使用默认配置并重新生成模板。
+/// 出错的文件无法另存时不改动 config.toml（以免丢失手动修改），本次运行只在内存中使用备份或默认配置。
+fn restore_config_backup(path: &Path, backup: &Path) -> Config {
+    let broken = path.with_extension("toml.broken");
+    let preserved = match fs::copy(path, &broken) {
+        Ok(_) => {
+            eprintln!("出错的配置文件已另存为 {}。", broken.display());
+            true
+        }
+        Err(e) => {
             eprintln!(
-                "配置中的 osc_ip \"{}\" 不是有效的 IPv4 地址，将使用 127.0.0.1。",
-                config.osc_ip
+                "无法另存出错的配置文件 {}: {}，将保留 {} 不作改动。",
+                broken.display(),
+                e,
+                path.display()
             );
-            Ipv4Addr::LOCALHOST
+            false
         }
     };
+    match fs::read_to_string(backup) {
+        Ok(text) => match toml::from_str::<Config>(&text) {
+            Ok(config) => {
+                if !preserved {
+                    eprintln!(
+                        "警告：本次运行使用上次成功加载的备份 {} 中的设置。",
+                        backup.display()
+                    );
+                    return config;
+                }
+                eprintln!(
+                    "警告：将用上次成功加载的备份 {} 恢复配置文件。",
+                    backup.display()
+                );
+                if let Err(e) = fs::write(path, &text) {
+                    eprintln!(
+                        "无法恢复配置文件 {}: {}，本次运行使用备份中的设置。",
+                        path.display(),
+                        e
+                    );
+                }
+                return config;
+            }
+            Err(e) => {
+                eprintln!("备份 {} 同样无法解析：", backup.display());
+                eprintln!("{}", describe_parse_error(&text, &e));
+            }
+        },
+        Err(_) => eprintln!("没有可用的配置备份 {}。", backup.display()),
+    }
+    if !preserved {
+        eprintln!("本次运行将使用默认配置。");
+        return Config::default();
+    }
+    eprintln!("本次运行将使用默认配置，并重新生成配置文件模板。");
+    write_config_template(path);
+    Config::default()
+}
+
+fn write_config_template(path: &Path) {
+    match fs::write(path, CONFIG_TEMPLATE) {
+        Ok(()) => println!(
+            "已生成默认配置文件: {}（可编辑后重启程序生效）",
+            path.display()
+        ),
+        Err(e) => eprintln!(
+            "无法生成配置文件 {}: {}，将使用默认配置。",
+            path.display(),
+            e
+        ),
+    }
+}
+
+/// 把生效的配置序列化为 TOML，与编译内置默认值不同的项标注 `# (overridden)`。
+/// 密码、令牌等敏感项显示为 `"***"`，输出可以直接贴到问题反馈中。
+fn dump_config(config: &Config) -> std::result::Result<String, toml::ser::Error> {
+    let config = redact_secrets(config);
+    let current = toml::Value::try_from(&config)?;
+    let default = toml::Value::try_from(Config::default())?;
+    Ok(mark_overridden(
+        &toml::to_string_pretty(&config)?,
+        &current,
+        &default,
+    ))
+}
+
+/// 已设置的敏感项替换为 `***`；未设置（或为空）的保持原样，仍能看出是否配置过。
+fn redact_secrets(config: &Config) -> Config {
+    const REDACTED: &str = "***";
+    let mut config = config.clone();
+    for secret in [
+        &mut config.pulsoid_token,
+        &mut config.auth_key,
+        &mut config.obs_password,
+        &mut config.mqtt_password,
+        &mut config.influx_token,
+        &mut config.twitch_oauth_token,
+    ] {
+        if secret.as_deref().is_some_and(|value| !value.is_empty()) {
+            *secret = Some(REDACTED.to_string());
+        }
+    }
+    for secret in [&mut config.webhook_url, &mut config.discord_webhook_url] {
+        if !secret.is_empty() {
+            *secret = REDACTED.to_string();
+        }
+    }
+    config
+}
+
+/// 逐行检查键和表标题，值与默认值不同的行末尾追加 `# (overridden)`。
+/// 键按所在的表查找：`[a.b]` 嵌套表、`a.b = …` 点分键，以及 `[[数组]]` 标题下的第几项。
+fn mark_overridden(text: &str, current: &toml::Value, default: &toml::Value) -> String {
+    let mut out = String::new();
+    // 当前所在的表（从根开始的路径）；无法识别的标题之后为 None，其中的键不标注
+    let mut table = Some(Vec::new());
+    // 每个 `[[数组]]` 标题已出现的次数，即下一项的下标
+    let mut array_items: HashMap<String, usize> = HashMap::new();
+    for line in text.lines() {
+        let path = if let Some(header) = line.strip_prefix("[[").and_then(|l| l.strip_suffix("]]"))
+        {
+            let index = array_items.entry(header.to_string()).or_insert(0);
+            table = toml_key_path(header).map(|mut path| {
+                path.push(ConfigPathSegment::Index(*index));
+                path
+            });
+            *index += 1;
+            table.clone()
+        } else if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            table = toml_key_path(header);
+            table.clone()
+        } else {
+            line.split_once(" = ").and_then(|(key, _)| {
+                let mut path = table.clone()?;
+                path.extend(toml_key_path(key)?);
+                Some(path)
+            })
+        };
+        out.push_str(line);
+        if path.is_some_and(|path| config_value(current, &path) != config_value(default, &path)) {
+            out.push_str("  # (overridden)");
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// 配置值路径中的一段：表中的键或数组下标。
+#[derive(Debug, Clone, PartialEq)]
+enum ConfigPathSegment {
+    Key(String),
+    Index(usize),
+}
+
+/// 把键或表标题（可能带点分和引号，如 `a."b.c"`）解析为路径；不是合法的键时返回 None。
+fn toml_key_path(key: &str) -> Option<Vec<ConfigPathSegment>> {
+    let mut table = toml::from_str::<toml::Table>(&format!("{} = 0", key)).ok()?;
+    let mut path = Vec::new();
+    loop {
+        let (key, value) = table.into_iter().next()?;
+        path.push(ConfigPathSegment::Key(key));
+        match value {
+            toml::Value::Table(inner) => table = inner,
+            _ => return Some(path),
+        }
+    }
+}
+
+fn config_value<'a>(value: &'a toml::Value, path: &[ConfigPathSegment]) -> Option<&'a toml::Value> {
+    path.iter().try_fold(value, |value, segment| match segment {
+        ConfigPathSegment::Key(key) => value.get(key.as_str()),
+        ConfigPathSegment::Index(index) => value.get(*index),
+    })
+}
+
+/// 候选心率服务 UUID，按优先级排列：标准 0x180D 在前，配置的额外服务随后。
+fn hr_service_uuids(config: &Config) -> Vec<Uuid> {
+    let mut uuids = vec![HEART_RATE_SERVICE_UUID];
+    for uuid in &config.additional_hr_service_uuids {
+        if !uuids.contains(uuid) {
+            uuids.push(*uuid);
+        }
+    }
+    uuids
+}
+
+/// 候选心率特征 UUID：标准 0x2A37 在前，配置的额外特征随后。
+fn hr_char_uuids(config: &Config) -> Vec<Uuid> {
+    let mut uuids = vec![HEART_RATE_CHAR_UUID];
+    for uuid in &config.additional_hr_char_uuids {
+        if !uuids.contains(uuid) {
+            uuids.push(*uuid);
+        }
+    }
+    uuids
+}
+
+/// 解析 osc_ip：点分十进制的 IPv4 或冒号十六进制的 IPv6（可带方括号，如 "[::1]"）。
+fn parse_osc_ip(osc_ip: &str) -> Option<IpAddr> {
+    let ip = osc_ip.trim();
+    let ip = ip
+        .strip_prefix('[')
+        .and_then(|ip| ip.strip_suffix(']'))
+        .unwrap_or(ip);
+    ip.parse().ok()
+}
+
+/// 将配置中的 OSC 地址（IPv4 或 IPv6）和端口解析为发送目标；配置了组播地址时发往组播地址。
+/// 地址非法时保持现有行为：提示后回退到本机，但仍使用配置的端口。
+fn resolve_osc_addr(config: &Config) -> SocketAddr {
+    if let Some(group) = config.osc_multicast_group {
+        return SocketAddr::from((group, config.osc_port()));
+    }
+    let osc_ip = parse_osc_ip(&config.osc_ip).unwrap_or_else(|| {
+        warn!(
+            "配置中的 osc_ip \"{}\" 不是有效的 IPv4/IPv6 地址，将使用 127.0.0.1。",
+            config.osc_ip
+        );
+        IpAddr::V4(Ipv4Addr::LOCALHOST)
+    });
+
+    SocketAddr::new(osc_ip, config.osc_port())
+}
+
+/// 创建发送 OSC 的 UDP 套接字；目标是 IPv4 组播地址时设置组播 TTL。
+fn osc_socket(config: &Config, osc_addr: SocketAddr) -> io::Result<UdpSocket> {
+    let socket = UdpSocket::bind(osc_bind_addr(config, osc_addr))?;
+    if osc_addr.ip().is_multicast() && osc_addr.is_ipv4() {
+        socket.set_multicast_ttl_v4(u32::from(config.osc_ttl))?;
+    }
+    Ok(socket)
+}
 
-    SocketAddrV4::new(osc_ip, config.osc_port)
+/// OSC 套接字的本地绑定地址：指定了 osc_local_ip 时绑定该网卡，
+/// 否则按发送目标的地址族绑定 0.0.0.0 或 [::]，端口都由系统分配。
+fn osc_bind_addr(config: &Config, osc_addr: SocketAddr) -> SocketAddr {
+    match config.osc_local_ip {
+        Some(local_ip) if local_ip.is_ipv6() == osc_addr.is_ipv6() => SocketAddr::new(local_ip, 0),
+        _ if osc_addr.is_ipv6() => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+        _ => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+    }
 }
 
 // --- 自定义错误类型 ---
@@ -183,10 +1444,29 @@ enum AppError {
     Btleplug(btleplug::Error),
     Io(io::Error),
     Rosc(rosc::OscError),
-    AdapterNotFound,
+    Plugin(libloading::Error),
+    Database(rusqlite::Error),
     DeviceNotFound,
     CharacteristicNotFound,
     SubscriptionFailed,
+    /// 使用旧版认证的小米手环需要认证密钥但未配置 auth_key
+    AuthRequired,
+    /// 小米设备拒绝了认证（密钥不匹配等）
+    AuthFailed,
+    /// 没有旧版认证特征的小米手环（Smart Band 8 及之后的型号）没有推送心率，需要开启心率广播
+    XiaomiBroadcastRequired,
+    /// BLE 操作（start_scan / connect / discover_services / subscribe 等）在限定时间内未返回
+    Timeout {
+        op: &'static str,
+        secs: u64,
+    },
+    /// 连接期间蓝牙适配器被关闭或移除（见 AdapterMonitor）
+    AdapterRemoved,
+    /// 附带"正在做什么"的说明（见 `ResultExt::context`），原始错误作为 source
+    Context {
+        context: String,
+        source: Box<AppError>,
+    },
 }
 
 impl fmt::Display for AppError {
@@ -195,15 +1475,91 @@ impl fmt::Display for AppError {
             AppError::Btleplug(e) => write!(f, "蓝牙错误: {}", e),
             AppError::Io(e) => write!(f, "I/O 错误: {}", e),
             AppError::Rosc(e) => write!(f, "OSC 编码错误: {}", e),
-            AppError::AdapterNotFound => write!(f, "未找到蓝牙适配器。"),
+            AppError::Plugin(e) => write!(f, "插件加载错误: {}", e),
+            AppError::Database(e) => write!(f, "数据库错误: {}", e),
             AppError::DeviceNotFound => write!(f, "未能找到目标设备。"),
             AppError::CharacteristicNotFound => write!(f, "未找到心率特征。"),
             AppError::SubscriptionFailed => write!(f, "订阅通知失败。"),
+            AppError::AuthRequired => write!(
+                f,
+                "该小米手环使用旧版认证（Mi Band 2–4 等），可能需要认证后才推送心率：\
+                 请在 config.toml 中填写 auth_key（32 位十六进制）。\
+                 密钥与手环绑定，可参考 Gadgetbridge 文档从小米运动健康 / Zepp Life 账号数据中获取。"
+            ),
+            AppError::AuthFailed => write!(
+                f,
+                "小米设备认证失败：请确认 auth_key 与该手环匹配（重新配对或恢复出厂后密钥会改变）。"
+            ),
+            AppError::XiaomiBroadcastRequired => write!(
+                f,
+                "该小米手环（Smart Band 8 及之后的型号）没有推送心率：请在手环设置中开启\"心率广播\"\
+                 （部分型号在小米运动健康 App 中开启），开启后通过标准心率服务连接，无需 auth_key。\
+                 这些型号使用小米的新版认证协议，本程序不支持，填写 auth_key 对它们无效。"
+            ),
+            AppError::Timeout { op, secs } => {
+                write!(f, "{} 超时（{} 秒内未完成）。", op, secs)
+            }
+            AppError::AdapterRemoved => write!(f, "蓝牙适配器已被关闭或移除，已中断当前连接。"),
+            AppError::Context { context, .. } => write!(f, "{}时出错", context),
+        }
+    }
+}
+
+// 其他变体的 Display 已包含底层错误的信息，只有 Context 报告 source，避免原因链重复
+impl error::Error for AppError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            AppError::Context { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl AppError {
+    /// 去掉所有 Context 包装后的原始错误，供按变体判断错误类型的地方使用。
+    fn root(&self) -> &AppError {
+        match self {
+            AppError::Context { source, .. } => source.root(),
+            e => e,
         }
     }
 }
 
-impl error::Error for AppError {}
+/// 完整的原因链，每个原因一行，用于退出前和连接出错时打印：
+///
+/// ```text
+/// 连接设备 AA:BB:CC:DD:EE:FF时出错
+///   原因: connect 超时（15 秒内未完成）。
+/// ```
+fn error_chain(e: &AppError) -> String {
+    let mut text = e.to_string();
+    let mut source = error::Error::source(e);
+    while let Some(cause) = source {
+        text.push_str(&format!("\n  原因: {}", cause));
+        source = cause.source();
+    }
+    text
+}
+
+/// 给 `Result` 附加上下文：`device.connect().await.context("连接设备")?`。
+trait ResultExt<T> {
+    fn context(self, context: &str) -> Result<T>;
+    /// 上下文需要格式化时使用，只在出错时才生成字符串。
+    fn with_context<F: FnOnce() -> String>(self, context: F) -> Result<T>;
+}
+
+impl<T, E: Into<AppError>> ResultExt<T> for std::result::Result<T, E> {
+    fn context(self, context: &str) -> Result<T> {
+        self.with_context(|| context.to_string())
+    }
+
+    fn with_context<F: FnOnce() -> String>(self, context: F) -> Result<T> {
+        self.map_err(|e| AppError::Context {
+            context: context(),
+            source: Box::new(e.into()),
+        })
+    }
+}
 
 // --- 转换器，以便可以使用 `?` 运算符 ---
 impl From<btleplug::Error> for AppError {
@@ -221,656 +1577,5345 @@ impl From<rosc::OscError> for AppError {
         AppError::Rosc(e)
     }
 }
-
-type Result<T> = std::result::Result<T, AppError>;
-
-/// 为可能挂起的 BLE 操作加超时兜底。
-async fn ble_timeout<F, T>(fut: F) -> Result<T>
+impl From<libloading::Error> for AppError {
+    fn from(e: libloading::Error) -> Self {
+        AppError::Plugin(e)
+    }
+}
+impl From<rusqlite::Error> for AppError {
+    fn from(e: rusqlite::Error) -> Self {
+        AppError::Database(e)
+    }
+}
+
+type Result<T> = std::result::Result<T, AppError>;
+
+/// 为可能挂起的 BLE 操作加超时兜底：WinRT 上对不可达设备
+/// 这些调用可能挂起数十秒甚至不返回。超时映射为 `AppError::Timeout`，
+/// 交由 main_loop 的重连循环处理。
+async fn ble_timeout<F, T>(op: &'static str, secs: u64, fut: F) -> Result<T>
 where
     F: Future<Output = btleplug::Result<T>>,
 {
-    let dur = Duration::from_secs(BLE_OP_TIMEOUT_SECS);
-    match time::timeout(dur, fut).await {
+    match time::timeout(Duration::from_secs(secs), fut).await {
         Ok(r) => Ok(r?),
-        Err(_) => Err(AppError::Btleplug(btleplug::Error::TimedOut(dur))),
+        Err(_) => Err(AppError::Timeout { op, secs }),
     }
 }
 
 // --- OSC 通信 ---
 
-/// 通过 OSC 格式化并发送心率数据。
-/// 使用 OSC Bundle 将所有消息合并到一个网络数据包中发送。
-/// Windows 上目标端口无人监听（VRChat 未启动）时 UDP 可能返回
-/// WSAECONNRESET(10054)——这只表示"对端没人听"，视为已发送。
-fn send_osc(
-    socket: &UdpSocket,
-    osc_addr: SocketAddrV4,
-    heart_rate: u8,
-    config: &Config,
-) -> Result<String> {
-    // 心率大于 0 视为已佩戴/有数据；0 视为未佩戴或已断开。
-    let is_active = heart_rate > 0;
+/// 可单独开关的输出项。每项对应一个 OSC 参数和一个单值文件，开关同时作用于两者。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HrOutput {
+    Hr,
+    Percent,
+    Connected,
+    Zone,
+}
 
-    let max_hr = config.max_heart_rate_for_percent.max(1.0);
-    let percent = (heart_rate as f32).min(max_hr) / max_hr;
+impl HrOutput {
+    const ALL: [HrOutput; 4] = [
+        HrOutput::Hr,
+        HrOutput::Percent,
+        HrOutput::Connected,
+        HrOutput::Zone,
+    ];
 
-    let percent2 = (heart_rate as f32).min(240.0) / 240.0;
+    fn enabled(self, outputs: &OutputToggles) -> bool {
+        match self {
+            HrOutput::Hr => outputs.hr,
+            HrOutput::Percent => outputs.percent,
+            HrOutput::Connected => outputs.connected,
+            HrOutput::Zone => outputs.zone,
+        }
+    }
 
-    let hr_for_int = heart_rate.min(240);
+    fn file_name(self) -> &'static str {
+        match self {
+            HrOutput::Hr => "HR.txt",
+            HrOutput::Percent => "HRPercent.txt",
+            HrOutput::Connected => "HRConnected.txt",
+            HrOutput::Zone => "HRZone.txt",
+        }
+    }
 
-    let bundle = rosc::OscPacket::Bundle(rosc::OscBundle {
-        // {0, 1} 是 OSC 规范中的 "immediately"
-        timetag: rosc::OscTime {
-            seconds: 0,
-            fractional: 1,
-        },
-        content: vec![
-            rosc::OscPacket::Message(rosc::OscMessage {
-                addr: "/avatar/parameters/hr_connected".to_string(),
-                args: vec![rosc::OscType::Bool(is_active)],
-            }),
-            rosc::OscPacket::Message(rosc::OscMessage {
-                addr: "/avatar/parameters/isHRActive".to_string(),
-                args: vec![rosc::OscType::Bool(is_active)],
-            }),
-            rosc::OscPacket::Message(rosc::OscMessage {
-                addr: "/avatar/parameters/hr_percent".to_string(),
-                args: vec![rosc::OscType::Float(percent)],
-            }),
-            rosc::OscPacket::Message(rosc::OscMessage {
-                addr: "/avatar/parameters/VRCOSC/Heartrate/Normalised".to_string(),
-                args: vec![rosc::OscType::Float(percent2)],
-            }),
-            rosc::OscPacket::Message(rosc::OscMessage {
-                addr: "/avatar/parameters/HR".to_string(),
-                args: vec![rosc::OscType::Int(hr_for_int as i32)],
-            }),
-        ],
-    });
+    /// 单值文件的内容：心率、百分比（0–100 整数）、true/false、区间（0–5）。
+    fn file_value(self, heart_rate: u8, config: &Config) -> String {
+        let v = OscValues::new(heart_rate, config);
+        match self {
+            HrOutput::Hr => v.hr_for_int.to_string(),
+            HrOutput::Percent => ((v.percent * 100.0).round() as u8).to_string(),
+            HrOutput::Connected => v.is_active.to_string(),
+            HrOutput::Zone => template::zone(heart_rate, v.max_hr).to_string(),
+        }
+    }
+}
+
+/// 输出项开关（config.toml 中的 [outputs] 表）。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+struct OutputToggles {
+    /// /avatar/parameters/HR 与 HR.txt
+    hr: bool,
+    /// /avatar/parameters/hr_percent 与 HRPercent.txt
+    percent: bool,
+    /// /avatar/parameters/hr_connected 与 HRConnected.txt
+    connected: bool,
+    /// /avatar/parameters/hr_zone 与 HRZone.txt（心率区间 0–5）
+    zone: bool,
+}
 
-    let buf = rosc::encoder::encode(&bundle)?;
-    if let Err(e) = socket.send_to(&buf, osc_addr) {
-        if e.kind() != io::ErrorKind::ConnectionReset {
-            return Err(e.into());
+impl Default for OutputToggles {
+    fn default() -> Self {
+        OutputToggles {
+            hr: true,
+            percent: true,
+            connected: true,
+            zone: false,
         }
     }
+}
 
-    Ok(format!(
-        "心率: {} -> (OSC数据) -> Active: {}, Int: {}, Float/{}: {:.2}  Float/240: {:.2}",
-        heart_rate, is_active, hr_for_int, max_hr, percent, percent2
-    ))
+/// 布尔参数的发送类型：avatar 把参数声明为 Int 或 Float 时，VRChat 会忽略 Bool 类型的消息。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum OscBoolType {
+    #[default]
+    Bool,
+    /// 1 / 0
+    Int,
+    /// 1.0 / 0.0
+    Float,
 }
 
-/// 断开/退出时向 VRChat 发送清零状态（is_active=false, HR=0），
-/// 若启用了文件输出则把 HeartRate.txt 写为 0，避免 avatar 和 OBS 残留旧心率。
-fn clear_state(socket: &UdpSocket, osc_addr: SocketAddrV4, config: &Config, hr_file: &Path) {
-    let _ = send_osc(socket, osc_addr, 0, config);
-    if config.write_heart_rate_file {
-        let _ = fs::write(hr_file, "0");
+impl OscBoolType {
+    fn arg(self, value: bool) -> rosc::OscType {
+        match self {
+            OscBoolType::Bool => rosc::OscType::Bool(value),
+            OscBoolType::Int => rosc::OscType::Int(i32::from(value)),
+            OscBoolType::Float => rosc::OscType::Float(if value { 1.0 } else { 0.0 }),
+        }
     }
 }
 
-// --- 退出清理（Windows 控制台事件 / Unix SIGINT、SIGTERM） ---
+/// 整数参数的发送类型：avatar 把参数声明为 Float 时改为发送浮点数（数值不变，不做归一化）。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum OscIntType {
+    #[default]
+    Int,
+    Float,
+}
 
-struct CleanupCtx {
-    osc_addr: SocketAddrV4,
-    config: Config,
-    hr_file: PathBuf,
+impl OscIntType {
+    fn arg(self, value: i32) -> rosc::OscType {
+        match self {
+            OscIntType::Int => rosc::OscType::Int(value),
+            OscIntType::Float => rosc::OscType::Float(value as f32),
+        }
+    }
 }
 
-static CLEANUP_CTX: OnceLock<CleanupCtx> = OnceLock::new();
-static CLEANUP_DONE: AtomicBool = AtomicBool::new(false);
+/// 参数的发送类型（config.toml 中的 [parameter_types] 表）。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+struct ParameterTypes {
+    /// /avatar/parameters/hr_connected
+    connected: OscBoolType,
+    /// /avatar/parameters/isHRActive
+    active: OscBoolType,
+    /// /avatar/parameters/hr_alarm
+    alarm: OscBoolType,
+    /// /avatar/parameters/hr_steady
+    steady: OscBoolType,
+    /// /avatar/parameters/HR
+    hr: OscIntType,
+}
 
-/// 只执行一次的退出清理。
-fn run_exit_cleanup() {
-    if CLEANUP_DONE.swap(true, Ordering::SeqCst) {
-        return;
+/// OSC 平台预设：提供参数地址前缀和默认参数集（目标端口各平台相同，见 [`DEFAULT_OSC_PORT`]），
+/// 显式设置的 osc_port / osc_parameter_prefix / osc_compat_parameters 与 [outputs] 表覆盖预设。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum OscPlatform {
+    #[default]
+    Vrchat,
+    /// ChilloutVR 的 OSC 模组：参数地址为 /avatar/parameter/<名称>，没有 VRChat 预制件兼容参数
+    Chilloutvr,
+    /// 不套用平台特有的设置：只发送本程序自己的参数，前缀等按需自行填写
+    Custom,
+}
+
+/// 未设置 osc_port 时的 OSC 目标端口。VRChat 默认在 9000 接收 OSC；ChilloutVR 的 OSC 模组
+/// 为了兼容 VRChat 的 OSC 工具也监听 9000，所以各平台预设共用这一个端口。
+const DEFAULT_OSC_PORT: u16 = 9000;
+
+impl OscPlatform {
+    fn parameter_prefix(self) -> &'static str {
+        match self {
+            OscPlatform::Vrchat | OscPlatform::Custom => "/avatar/parameters/",
+            OscPlatform::Chilloutvr => "/avatar/parameter/",
+        }
     }
-    if let Some(ctx) = CLEANUP_CTX.get() {
-        match UdpSocket::bind("0.0.0.0:0") {
-            Ok(socket) => clear_state(&socket, ctx.osc_addr, &ctx.config, &ctx.hr_file),
-            Err(_) => {
-                if ctx.config.write_heart_rate_file {
-                    let _ = fs::write(&ctx.hr_file, "0");
-                }
+
+    /// isHRActive、VRCOSC/Heartrate/Normalised 只有 VRChat 上的预制件使用。
+    fn compat_parameters(self) -> bool {
+        self == OscPlatform::Vrchat
+    }
+}
+
+impl fmt::Display for OscPlatform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OscPlatform::Vrchat => "VRChat",
+            OscPlatform::Chilloutvr => "ChilloutVR",
+            OscPlatform::Custom => "自定义",
+        })
+    }
+}
+
+/// Bundle 的时间标签（osc_timetag）。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum OscTimetag {
+    /// OSC 规范中的 "immediately"：接收端收到即处理（VRChat 用这个即可）
+    #[default]
+    Immediate,
+    /// 按系统时钟填写 NTP 时间戳，供按时间标签排序的记录类接收端使用
+    WallClock,
+}
+
+impl OscTimetag {
+    /// OSC 1.0 规范规定的特殊时间标签：63 个 0 位后接一个 1 位，即秒 0、小数 1，表示 "immediately"。
+    const IMMEDIATELY: rosc::OscTime = rosc::OscTime {
+        seconds: 0,
+        fractional: 1,
+    };
+
+    fn at(self, now: SystemTime) -> rosc::OscTime {
+        match self {
+            OscTimetag::Immediate => Self::IMMEDIATELY,
+            OscTimetag::WallClock => osc_timetag(now),
+        }
+    }
+}
+
+/// 心率数据的打包方式（osc_packet_mode）。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum OscPacketMode {
+    /// 所有参数合并为一个 Bundle、一个数据报
+    #[default]
+    Bundle,
+    /// 每个参数单独一条消息、一个数据报，供不接受 Bundle 的旧 OSC 路由器使用
+    Messages,
+}
+
+/// 心率来源（source）。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum SourceKind {
+    /// 扫描并直接连接蓝牙心率设备
+    #[default]
+    Ble,
+    /// 从 Pulsoid 的 WebSocket 接口接收心率
+    Pulsoid,
+}
+
+/// OSC 的发送时机（send_mode）。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum SendMode {
+    /// 收到读数只更新状态，由 scheduler 按 send_rate_hz 定时发送
+    #[default]
+    Scheduled,
+    /// 每收到一次通知立即发送一次完整数据（旧行为）
+    OnNotification,
+}
+
+/// 随心率一起发送的附加参数；断开/退出清零时使用默认值。
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct OscExtras {
+    /// 心率报警是否处于触发状态（/avatar/parameters/hr_alarm）
+    alarm: bool,
+    /// 归一化压力指数 0–1（/avatar/parameters/hr_stress），RR 样本不足时不发送
+    stress: Option<f32>,
+    /// 归一化训练负荷 0–1（/avatar/parameters/hr_trimp），断开/清零时不发送
+    trimp: Option<f32>,
+    /// 归一化信号质量 0–1（/avatar/parameters/hr_signal），未启用或读不到 RSSI 时不发送
+    signal: Option<f32>,
+    /// 是否处于静息状态（/avatar/parameters/hr_steady），此时 isHRActive 发送 false
+    steady: bool,
+    /// 最近测得的 OSC 往返延迟（/avatar/parameters/hr_rtt_ms），未启用回传或尚无数据时不发送
+    rtt_ms: Option<u32>,
+    /// 最近一次收到的血氧饱和度 0–100（/avatar/parameters/hr_spo2），未启用或设备未发送时不发送
+    spo2: Option<u8>,
+    /// 由步数估算的步频（/avatar/parameters/hr_cadence_rpm），未启用或设备未发送步数时不发送
+    cadence_rpm: Option<u8>,
+    /// 归一化运动强度 0–1（/avatar/parameters/hr_movement），未启用或设备未发送加速度时不发送
+    movement: Option<f32>,
+    /// 以下只作为自定义参数表达式中的变量，不单独发送：
+    /// RR 间期的标准差（毫秒，sdnn）
+    sdnn_ms: Option<f32>,
+    /// 本次通知中最后一个 RR 间期（毫秒，rr）
+    rr_ms: Option<f32>,
+    /// 本次连接的平均心率（avg）
+    avg_bpm: Option<f32>,
+    /// 连接时读到的电量（battery）
+    battery: Option<u8>,
+}
+
+/// 由心率换算出的各个 OSC 参数值。
+struct OscValues {
+    is_active: bool,
+    max_hr: f32,
+    percent: f32,
+    percent2: f32,
+    hr_for_int: u8,
+}
+
+impl OscValues {
+    fn new(heart_rate: u8, config: &Config) -> Self {
+        let max_hr = config.max_heart_rate().max(1.0);
+        Self {
+            // 心率大于 0 视为已佩戴/有数据；0 视为未佩戴或已断开。
+            is_active: heart_rate > 0,
+            max_hr,
+            percent: if config.max_heart_rate_for_percent == 0.0 {
+                auto_gain::hr_percent(heart_rate)
+            } else {
+                (heart_rate as f32).min(max_hr) / max_hr
+            },
+            percent2: (heart_rate as f32).min(240.0) / 240.0,
+            hr_for_int: heart_rate.min(240),
+        }
+    }
+}
+
+/// NTP 纪元（1900-01-01）与 Unix 纪元（1970-01-01）相差的秒数。
+const NTP_UNIX_OFFSET_SECS: u64 = 2_208_988_800;
+
+/// 把系统时间换算为 OSC 时间标签（NTP 格式：秒 + 2^-32 秒为单位的小数部分）。
+/// 系统时间早于 1970 年时退回 "immediately"。
+fn osc_timetag(now: SystemTime) -> rosc::OscTime {
+    match now.duration_since(UNIX_EPOCH) {
+        Ok(since_unix) => rosc::OscTime {
+            seconds: (since_unix.as_secs() + NTP_UNIX_OFFSET_SECS) as u32,
+            fractional: ((u64::from(since_unix.subsec_nanos()) << 32) / 1_000_000_000) as u32,
+        },
+        Err(_) => OscTimetag::IMMEDIATELY,
+    }
+}
+
+/// 心率及附加参数对应的 OSC 消息（按 [outputs] 与平台预设筛选），两种 osc_packet_mode 共用。
+fn hr_messages(heart_rate: u8, extras: OscExtras, config: &Config) -> Vec<rosc::OscPacket> {
+    let OscValues {
+        is_active,
+        max_hr,
+        percent,
+        percent2,
+        hr_for_int,
+    } = OscValues::new(heart_rate, config);
+
+    let outputs = &config.outputs;
+    let types = &config.parameter_types;
+    let prefix = config.osc_parameter_prefix();
+    let compat = config.osc_compat_parameters();
+    let mut content = Vec::new();
+    if outputs.connected {
+        content.push(rosc::OscPacket::Message(rosc::OscMessage {
+            addr: format!("{}hr_connected", prefix),
+            args: vec![types.connected.arg(is_active)],
+        }));
+    }
+    if compat {
+        content.push(rosc::OscPacket::Message(rosc::OscMessage {
+            addr: format!("{}isHRActive", prefix),
+            args: vec![types.active.arg(is_active && !extras.steady)],
+        }));
+    }
+    if outputs.percent {
+        content.push(rosc::OscPacket::Message(rosc::OscMessage {
+            addr: format!("{}hr_percent", prefix),
+            args: vec![rosc::OscType::Float(percent)],
+        }));
+    }
+    if compat {
+        content.push(rosc::OscPacket::Message(rosc::OscMessage {
+            addr: format!("{}VRCOSC/Heartrate/Normalised", prefix),
+            args: vec![rosc::OscType::Float(percent2)],
+        }));
+    }
+    if outputs.hr {
+        content.push(rosc::OscPacket::Message(rosc::OscMessage {
+            addr: format!("{}HR", prefix),
+            args: vec![types.hr.arg(i32::from(hr_for_int))],
+        }));
+    }
+    if outputs.zone {
+        content.push(rosc::OscPacket::Message(rosc::OscMessage {
+            addr: format!("{}hr_zone", prefix),
+            args: vec![rosc::OscType::Int(i32::from(template::zone(
+                heart_rate, max_hr,
+            )))],
+        }));
+    }
+    // 没配置报警阈值时不发送，不给现有 avatar 增加参数
+    if config.hr_alarm_high.is_some() || config.hr_alarm_low.is_some() {
+        content.push(rosc::OscPacket::Message(rosc::OscMessage {
+            addr: format!("{}hr_alarm", prefix),
+            args: vec![types.alarm.arg(extras.alarm)],
+        }));
+    }
+    // 没开启静息检测时不发送，不给现有 avatar 增加参数
+    if config.steady_state_mute {
+        content.push(rosc::OscPacket::Message(rosc::OscMessage {
+            addr: format!("{}hr_steady", prefix),
+            args: vec![types.steady.arg(extras.steady)],
+        }));
+    }
+    if let Some(stress) = extras.stress {
+        content.push(rosc::OscPacket::Message(rosc::OscMessage {
+            addr: format!("{}hr_stress", prefix),
+            args: vec![rosc::OscType::Float(stress)],
+        }));
+    }
+    if let Some(trimp) = extras.trimp {
+        content.push(rosc::OscPacket::Message(rosc::OscMessage {
+            addr: format!("{}hr_trimp", prefix),
+            args: vec![rosc::OscType::Float(trimp)],
+        }));
+    }
+    if let Some(rtt_ms) = extras.rtt_ms {
+        content.push(rosc::OscPacket::Message(rosc::OscMessage {
+            addr: format!("{}hr_rtt_ms", prefix),
+            args: vec![rosc::OscType::Int(rtt_ms.min(i32::MAX as u32) as i32)],
+        }));
+    }
+    if let Some(signal) = extras.signal {
+        content.push(rosc::OscPacket::Message(rosc::OscMessage {
+            addr: format!("{}hr_signal", prefix),
+            args: vec![rosc::OscType::Float(signal)],
+        }));
+    }
+    if let Some(spo2) = extras.spo2 {
+        content.push(rosc::OscPacket::Message(rosc::OscMessage {
+            addr: format!("{}hr_spo2", prefix),
+            args: vec![rosc::OscType::Int(i32::from(spo2))],
+        }));
+        content.push(rosc::OscPacket::Message(rosc::OscMessage {
+            addr: format!("{}hr_spo2_float", prefix),
+            args: vec![rosc::OscType::Float(f32::from(spo2) / 100.0)],
+        }));
+    }
+    if let Some(cadence_rpm) = extras.cadence_rpm {
+        content.push(rosc::OscPacket::Message(rosc::OscMessage {
+            addr: format!("{}hr_cadence_rpm", prefix),
+            args: vec![rosc::OscType::Int(i32::from(cadence_rpm))],
+        }));
+    }
+    if let Some(movement) = extras.movement {
+        content.push(rosc::OscPacket::Message(rosc::OscMessage {
+            addr: format!("{}hr_movement", prefix),
+            args: vec![rosc::OscType::Float(movement)],
+        }));
+    }
+    if !config.custom_osc_params.is_empty() {
+        let context = custom_params::Variables {
+            bpm: heart_rate,
+            percent,
+            active: is_active,
+            zone: template::zone(heart_rate, max_hr),
+            avg: extras.avg_bpm,
+            rr: extras.rr_ms,
+            sdnn: extras.sdnn_ms,
+            battery: extras.battery,
+        }
+        .context();
+        for param in &config.custom_osc_params {
+            match param.eval(&context) {
+                Ok(arg) => content.push(rosc::OscPacket::Message(rosc::OscMessage {
+                    addr: param.full_address(prefix),
+                    args: vec![arg],
+                })),
+                Err(e) => debug!("自定义参数 {} 本次未发送: {}", param.address, e),
             }
         }
     }
+    content
 }
 
-/// 注册控制台事件处理器。
-/// 不使用 ctrlc crate：它的处理例程立即返回、闭包在另一线程异步执行，
-/// CTRL_CLOSE_EVENT（点 X 关窗）下会与进程终止竞争，清理大概率来不及跑。
-/// 这里直接在处理例程内【同步】完成清理再返回（CLOSE 事件下例程有约 5 秒预算）。
-#[cfg(windows)]
-fn register_exit_handler() -> bool {
-    use windows_sys::Win32::System::Console::{
-        SetConsoleCtrlHandler, CTRL_BREAK_EVENT, CTRL_C_EVENT,
+/// 把心率及附加参数编码为一个 OSC Bundle（不发送），便于单独测试编码结果。
+fn encode_hr_bundle(heart_rate: u8, extras: OscExtras, config: &Config) -> Result<Vec<u8>> {
+    let bundle = rosc::OscPacket::Bundle(rosc::OscBundle {
+        timetag: config.osc_timetag.at(SystemTime::now()),
+        content: hr_messages(heart_rate, extras, config),
+    });
+
+    Ok(rosc::encoder::encode(&bundle)?)
+}
+
+/// 按 osc_packet_mode 编码要发送的数据报：一个 Bundle，或每个参数一条单独的消息。
+fn encode_hr_packets(heart_rate: u8, extras: OscExtras, config: &Config) -> Result<Vec<Vec<u8>>> {
+    match config.osc_packet_mode {
+        OscPacketMode::Bundle => Ok(vec![encode_hr_bundle(heart_rate, extras, config)?]),
+        OscPacketMode::Messages => hr_messages(heart_rate, extras, config)
+            .iter()
+            .map(|message| Ok(rosc::encoder::encode(message)?))
+            .collect(),
+    }
+}
+
+/// 发送已编码的 OSC 数据包。
+/// Windows 上目标端口无人监听（VRChat 未启动）时 UDP 可能返回
+/// WSAECONNRESET(10054)——这只表示"对端没人听"，视为已发送。
+fn send_raw_osc(socket: &UdpSocket, osc_addr: SocketAddr, data: &[u8]) -> Result<()> {
+    match socket.send_to(data, osc_addr) {
+        Err(e) if e.kind() != io::ErrorKind::ConnectionReset => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// 按 osc_packet_mode 发送一组 OSC 消息（自定义脚本排队的消息、定时发送的参数）。
+fn send_osc_messages(
+    socket: &UdpSocket,
+    osc_addr: SocketAddr,
+    messages: Vec<rosc::OscMessage>,
+    config: &Config,
+) -> Result<()> {
+    let content = messages.into_iter().map(rosc::OscPacket::Message);
+    let packets: Vec<rosc::OscPacket> = match config.osc_packet_mode {
+        OscPacketMode::Bundle => vec![rosc::OscPacket::Bundle(rosc::OscBundle {
+            timetag: config.osc_timetag.at(SystemTime::now()),
+            content: content.collect(),
+        })],
+        OscPacketMode::Messages => content.collect(),
     };
+    for packet in &packets {
+        send_raw_osc(socket, osc_addr, &rosc::encoder::encode(packet)?)?;
+    }
+    Ok(())
+}
 
-    unsafe extern "system" fn handler(ctrl_type: u32) -> i32 {
-        run_exit_cleanup();
-        match ctrl_type {
-            // Ctrl-C / Ctrl-Break：系统不主动终止进程，由我们自己退出
-            CTRL_C_EVENT | CTRL_BREAK_EVENT => std::process::exit(0),
-            // CLOSE / LOGOFF / SHUTDOWN：返回 FALSE，交给系统继续默认终止流程
-            _ => 0,
-        }
+/// 通过 OSC 格式化并发送心率数据，返回用于状态行的描述。
+/// 默认使用 OSC Bundle 将所有消息合并到一个网络数据包中发送；osc_packet_mode = "messages" 时逐条发送。
+fn send_osc(
+    socket: &UdpSocket,
+    osc_addr: SocketAddr,
+    heart_rate: u8,
+    extras: OscExtras,
+    config: &Config,
+) -> Result<String> {
+    let packets = encode_hr_packets(heart_rate, extras, config)?;
+    for data in &packets {
+        send_raw_osc(socket, osc_addr, data)?;
     }
 
-    unsafe { SetConsoleCtrlHandler(Some(handler), 1) != 0 }
+    let v = publish_local_outputs(heart_rate, config);
+    if config.outputs.hr {
+        osc_feedback::record_sent(i32::from(v.hr_for_int), Instant::now());
+    }
+    let mut status = osc_status(heart_rate, &v, extras);
+    if config.osc_packet_mode == OscPacketMode::Messages {
+        status.push_str(&format!("  数据报: {}", packets.len()));
+    }
+    Ok(status)
 }
 
-/// 等待 Unix 的交互退出或服务终止信号。
-#[cfg(unix)]
-async fn wait_for_exit_signal() -> io::Result<()> {
-    use tokio::signal::unix::{signal, SignalKind};
+/// 写入本机的心率输出（共享内存、注册表、命名管道），返回换算后的 OSC 数值。
+/// 与 OSC 的发送时机无关：定时发送模式下在收到读数时调用。
+fn publish_local_outputs(heart_rate: u8, config: &Config) -> OscValues {
+    let v = OscValues::new(heart_rate, config);
+    shm::write(v.hr_for_int, v.is_active);
+    registry_output::write(v.hr_for_int);
+    pipe::publish(v.hr_for_int, v.is_active);
+    tray::publish(v.hr_for_int);
+    v
+}
 
-    let mut terminate = signal(SignalKind::terminate())?;
-    tokio::select! {
-        result = tokio::signal::ctrl_c() => result,
-        _ = terminate.recv() => Ok(()),
+/// 状态行中的 OSC 数值描述。
+fn osc_status(heart_rate: u8, v: &OscValues, extras: OscExtras) -> String {
+    let mut status = format!(
+        "心率: {} -> (OSC数据) -> Active: {}, Int: {}, Float/{}: {:.2}  Float/240: {:.2}",
+        heart_rate, v.is_active, v.hr_for_int, v.max_hr, v.percent, v.percent2
+    );
+    if let Some(rtt_ms) = extras.rtt_ms {
+        status.push_str(&format!("  RTT: {} ms", rtt_ms));
     }
+    status
 }
 
-// --- 蓝牙逻辑 ---
+/// 断开/退出时向 VRChat 发送清零状态（is_active=false, HR=0）并以 `connected = false` 通知自定义脚本，
+/// 若启用了文件输出则把 HeartRate.txt（及 OBS 文本源）写为离线内容、单值文件写为 0 / false、status.json 写为未连接，
+/// 避免 avatar 和 OBS 残留旧心率。
+fn clear_state(socket: &UdpSocket, osc_addr: SocketAddr, config: &Config, hr_file: &Path) {
+    scheduler::clear();
+    let _ = send_osc(socket, osc_addr, 0, OscExtras::default(), config);
+    let messages = script::disconnected();
+    if !messages.is_empty() {
+        let _ = send_osc_messages(socket, osc_addr, messages, config);
+    }
+    if config.write_heart_rate_file {
+        file_writer::submit(
+            hr_file.to_path_buf(),
+            config.heart_rate_file_offline.clone(),
+        );
+    }
+    for (output, path) in split_files(hr_file, config) {
+        file_writer::submit(path, output.file_value(0, config));
+    }
+    obs::publish(&config.heart_rate_file_offline);
+    discord_presence::publish(None);
+    haptics::stop();
+    publish_status(&status_file::Status::default());
+}
 
-/// 扫描并返回一个目标外围设备。
-async fn find_target_device(manager: &Manager, config: &Config) -> Result<Peripheral> {
-    println!("正在扫描蓝牙设备...");
-    let adapters = manager.adapters().await?;
-    let central = adapters
+/// 是否有输出需要完整状态（status.json、WebSocket 或 HTTP 接口）；都没启用时不必读取电量等信息。
+fn status_enabled() -> bool {
+    status_file::is_enabled()
+        || websocket::is_enabled()
+        || http_api::is_enabled()
+        || mqtt::is_enabled()
+        || resonite::is_enabled()
+}
+
+/// 把最新状态交给 status.json、WebSocket 推送、HTTP 接口、MQTT 和 Resonite。
+fn publish_status(status: &status_file::Status) {
+    status_file::write(status);
+    websocket::publish(status);
+    http_api::publish(status);
+    mqtt::publish(status);
+    resonite::publish(status.bpm, status.connected);
+}
+
+/// 心率文件路径：默认在程序目录（而不是当前工作目录）下，从快捷方式或启动器运行时也能找到。
+fn heart_rate_file(dir: &Path, config: &Config) -> PathBuf {
+    match &config.heart_rate_file_path {
+        Some(path) => dir.join(path),
+        None => dir.join("HeartRate.txt"),
+    }
+}
+
+/// 启动时创建心率文件所在目录并写入离线内容，提前暴露路径不可写的问题。
+fn prepare_heart_rate_file(hr_file: &Path, config: &Config) -> io::Result<()> {
+    if let Some(parent) = hr_file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    replace_file(hr_file, &config.heart_rate_file_offline)
+}
+
+/// 已开启的输出项及其单值文件路径（与心率文件同目录）；未启用 write_split_files 时为空。
+fn split_files(hr_file: &Path, config: &Config) -> Vec<(HrOutput, PathBuf)> {
+    if !config.write_split_files {
+        return Vec::new();
+    }
+    HrOutput::ALL
         .into_iter()
-        .next()
-        .ok_or(AppError::AdapterNotFound)?;
+        .filter(|output| output.enabled(&config.outputs))
+        .map(|output| (output, hr_file.with_file_name(output.file_name())))
+        .collect()
+}
 
-    // 只扫描广播了心率服务 (0x180D) 的设备
-    let scan_filter = ScanFilter {
-        services: vec![HEART_RATE_SERVICE_UUID],
+/// 启动时创建单值文件所在目录并写入未连接时的值；关闭的输出项不创建文件。
+fn prepare_split_files(hr_file: &Path, config: &Config) -> io::Result<()> {
+    for (output, path) in split_files(hr_file, config) {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        replace_file(&path, &output.file_value(0, config))?;
+    }
+    Ok(())
+}
+
+/// JSON 状态文件路径：默认为程序目录下的 status.json。
+fn status_json_file(dir: &Path, config: &Config) -> PathBuf {
+    dir.join(
+        config
+            .status_json_path
+            .as_deref()
+            .unwrap_or(Path::new("status.json")),
+    )
+}
+
+/// CSV 会话记录的目录与落盘/轮换设置：默认目录为程序目录下的 sessions。
+fn session_log_settings(dir: &Path, config: &Config) -> (PathBuf, session_log::Settings) {
+    let log_dir = dir.join(
+        config
+            .session_log_dir
+            .as_deref()
+            .unwrap_or(Path::new("sessions")),
+    );
+    let settings = session_log::Settings {
+        flush_interval: Duration::from_secs(config.session_log_flush_secs),
+        max_bytes: config.session_log_rotate_mb * 1024 * 1024,
+        max_age: (config.session_log_rotate_mins > 0)
+            .then(|| Duration::from_secs(config.session_log_rotate_mins * 60)),
     };
-    central.start_scan(scan_filter).await?;
-    time::sleep(Duration::from_secs(config.scan_duration_secs)).await;
+    (log_dir, settings)
+}
 
-    let peripherals = central.peripherals().await?;
-    println!("附近设备列表:");
+/// SQLite 历史库路径：默认为程序目录下的 heartrate.db。
+fn history_db_file(dir: &Path, config: &Config) -> PathBuf {
+    dir.join(
+        config
+            .history_db_path
+            .as_deref()
+            .unwrap_or(Path::new("heartrate.db")),
+    )
+}
 
-    let mut strongest_candidate: Option<(Peripheral, i16)> = None;
-    let mut name_match_candidate: Option<Peripheral> = None;
+/// 按 heart_rate_file_format 渲染心率文件内容；心率 0（未佩戴）时为离线内容。
+/// `recent` 为最近的非 0 心率（不含本次），用于 `{avg}`。
+fn heart_rate_file_content(heart_rate: u8, recent: &[u8], config: &Config) -> String {
+    if heart_rate == 0 {
+        return config.heart_rate_file_offline.clone();
+    }
+    template::render(
+        &config.heart_rate_file_format,
+        &template_values(heart_rate, recent, config),
+    )
+}
 
-    if peripherals.is_empty() {
-        println!("未发现任何设备。请检查设备是否开启并处于广播状态。");
+/// 模板占位符的取值（心率文件、OBS 文本源和 Discord 动态共用）。
+fn template_values(heart_rate: u8, recent: &[u8], config: &Config) -> template::Values {
+    let max_hr = config.max_heart_rate();
+    let sum: u32 = recent.iter().map(|&hr| u32::from(hr)).sum::<u32>() + u32::from(heart_rate);
+    let count = recent.len() as f32 + 1.0;
+    template::Values {
+        hr: heart_rate,
+        percent: (f32::from(heart_rate).min(max_hr) / max_hr * 100.0).round() as u8,
+        zone: template::zone(heart_rate, max_hr),
+        avg: (sum as f32 / count).round() as u8,
     }
+}
 
-    for p in peripherals {
-        // 获取不到属性的设备直接跳过
-        let properties = match p.properties().await {
-            Ok(Some(props)) => props,
-            _ => continue,
-        };
+/// 写入 HeartRate.txt / 单值文件 / status.json：先写同目录下的临时文件再重命名覆盖，
+/// 避免 OBS 等读取方读到 fs::write 截断后、写入前的空文件。
+/// 重命名失败（例如 Windows 上读取方以独占方式打开了文件）时退回直接覆盖写入。
+fn replace_file(path: &Path, content: &str) -> io::Result<()> {
+    let mut tmp_file = path.as_os_str().to_owned();
+    tmp_file.push(".tmp");
+    let tmp_file = PathBuf::from(tmp_file);
+    let renamed = fs::write(&tmp_file, content).and_then(|()| fs::rename(&tmp_file, path));
+    if renamed.is_ok() {
+        return Ok(());
+    }
+    let _ = fs::remove_file(&tmp_file);
+    fs::write(path, content)
+}
 
-        let mac_address = p.address();
-        let device_name = properties
-            .local_name
-            .clone()
-            .unwrap_or_else(|| "未知设备 Unknown Device".to_string());
-        let rssi_str = properties
-            .rssi
-            .map_or("N/A".to_string(), |rssi| format!("{} dBm", rssi));
+// --- 心率统计 ---
 
-        let filtered_device_name: String = device_name
-            .chars()
-            .filter(|c| c.is_ascii_alphanumeric())
-            .collect();
+/// 一个统计周期的汇总。
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct StatsSnapshot {
+    min: u8,
+    max: u8,
+    mean: f32,
+    samples: u32,
+}
 
-        println!(
-            "名称: {:<15} | MAC: {} | 信号强度: {}",
-            filtered_device_name.chars().take(15).collect::<String>(),
-            mac_address,
-            rssi_str
-        );
+/// 按周期累计心率，`flush` 取出本周期汇总并清零（`snapshot` 只取不清零）。心率 0（未佩戴）不计入。
+#[derive(Debug, Default)]
+struct PeriodicStats {
+    min: u8,
+    max: u8,
+    sum: u32,
+    samples: u32,
+}
 
-        // 名称匹配候选（保留第一个匹配项）
-        if name_match_candidate.is_none() {
-            if let Some(name) = &properties.local_name {
-                if config
-                    .target_device_names
-                    .iter()
-                    .any(|target| name.contains(target.as_str()))
-                {
-                    name_match_candidate = Some(p.clone());
-                }
-            }
+impl PeriodicStats {
+    fn update(&mut self, bpm: u8) {
+        if bpm == 0 {
+            return;
+        }
+        if self.samples == 0 {
+            self.min = bpm;
+            self.max = bpm;
+        } else {
+            self.min = self.min.min(bpm);
+            self.max = self.max.max(bpm);
+        }
+        self.sum += u32::from(bpm);
+        self.samples += 1;
+    }
+
+    fn snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            min: self.min,
+            max: self.max,
+            mean: if self.samples == 0 {
+                0.0
+            } else {
+                self.sum as f32 / self.samples as f32
+            },
+            samples: self.samples,
+        }
+    }
+
+    fn flush(&mut self) -> StatsSnapshot {
+        let snapshot = self.snapshot();
+        *self = PeriodicStats::default();
+        snapshot
+    }
+}
+
+/// 估算步频所用的时间窗口。
+const CADENCE_WINDOW: Duration = Duration::from_secs(5);
+/// 超过该时间没有新的加速度帧（设备停止推送或请求被拒绝）时 hr_movement 归零。
+const MOVEMENT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// 由累计步数估算步频（步/分钟）：对最近 `CADENCE_WINDOW` 内的读数做差分。
+/// 设备通常只在步数变化时推送，因此窗口开始前的最后一个读数就是窗口起点的步数。
+#[derive(Debug, Default)]
+struct CadenceEstimator {
+    samples: VecDeque<(Instant, u32)>,
+}
+
+impl CadenceEstimator {
+    /// 收到新的累计步数，返回窗口内的步频（上限 255）；还没有可差分的读数时为 None。
+    fn update(&mut self, steps: u32, now: Instant) -> Option<u8> {
+        // 计数回退（设备重置、跨天清零）时重新开始
+        if self.samples.back().is_some_and(|&(_, last)| steps < last) {
+            self.samples.clear();
+        }
+        self.samples.push_back((now, steps));
+        while self.samples.len() > 2 && now.duration_since(self.samples[1].0) >= CADENCE_WINDOW {
+            self.samples.pop_front();
+        }
+        // 起点可能早于窗口（窗口内只有一次读数时保留之前的一次），按实际经过的时间计算，
+        // 否则稀疏的步数推送会把步频放大
+        let &(first_at, first) = self.samples.front()?;
+        let elapsed = now.duration_since(first_at);
+        if self.samples.len() < 2 || elapsed.is_zero() {
+            return None;
         }
+        let per_minute = f64::from(steps - first) * 60.0 / elapsed.as_secs_f64();
+        Some(per_minute.round().min(255.0) as u8)
+    }
+}
+
+/// 两次读数间隔超过该值时只按该值累计训练负荷（期间的数据已丢失，不按最后一次心率补算）。
+const TRIMP_MAX_GAP: Duration = Duration::from_secs(5);
+
+/// 本次连接累计的训练负荷（Banister TRIMP）：每次读数累加
+/// `间隔分钟数 × r × e^(1.92 r)`，其中 `r = 心率 / max_heart_rate_for_percent`（超过记为 1）。
+/// 心率 0（未佩戴）不计入，也不把未佩戴的时间算进下一次的间隔。
+#[derive(Debug, Default)]
+struct TrainingLoad {
+    total: f32,
+    last: Option<Instant>,
+}
+
+impl TrainingLoad {
+    fn update(&mut self, bpm: u8, now: Instant, max_hr: f32) {
+        if bpm == 0 {
+            self.last = None;
+            return;
+        }
+        if let Some(last) = self.last {
+            let minutes = now.duration_since(last).min(TRIMP_MAX_GAP).as_secs_f32() / 60.0;
+            let ratio = (f32::from(bpm) / max_hr).min(1.0);
+            self.total += minutes * ratio * (1.92 * ratio).exp();
+        }
+        self.last = Some(now);
+    }
+}
+
+// --- 心率报警 ---
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AlarmKind {
+    High,
+    Low,
+}
+
+/// 心率报警状态：进入报警状态时（且距上次提示音已过冷却期）才播放提示音。
+#[derive(Debug, Default)]
+struct HrAlarm {
+    active: Option<AlarmKind>,
+    last_sound: Option<Instant>,
+}
+
+impl HrAlarm {
+    /// 用新读数更新报警状态，返回本次需要播放提示音的报警类型。
+    fn update(&mut self, heart_rate: u8, now: Instant, config: &Config) -> Option<AlarmKind> {
+        let kind = if heart_rate == 0 {
+            None
+        } else if config.hr_alarm_high.is_some_and(|high| heart_rate > high) {
+            Some(AlarmKind::High)
+        } else if config.hr_alarm_low.is_some_and(|low| heart_rate < low) {
+            Some(AlarmKind::Low)
+        } else {
+            None
+        };
+        let entered = kind.is_some() && kind != self.active;
+        self.active = kind;
+        if !entered {
+            return None;
+        }
+
+        let cooldown = Duration::from_secs(config.alarm_cooldown_secs);
+        if self
+            .last_sound
+            .is_some_and(|last| now.duration_since(last) < cooldown)
+        {
+            return None;
+        }
+        self.last_sound = Some(now);
+        kind
+    }
+
+    fn is_active(&self) -> bool {
+        self.active.is_some()
+    }
+}
+
+/// 播放系统提示音（不阻塞通知循环）。
+#[cfg(windows)]
+fn play_alarm_sound() {
+    use windows_sys::Win32::UI::WindowsAndMessaging::{MessageBeep, MB_ICONEXCLAMATION};
+
+    unsafe {
+        MessageBeep(MB_ICONEXCLAMATION);
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn play_alarm_sound() {
+    let _ = std::process::Command::new("afplay")
+        .arg("/System/Library/Sounds/Basso.aiff")
+        .spawn();
+}
+
+/// 其他平台没有统一的系统提示音接口，使用终端响铃字符。
+#[cfg(not(any(windows, target_os = "macos")))]
+fn play_alarm_sound() {
+    print!("\x07");
+    let _ = io::stdout().flush();
+}
+
+// --- 静息检测 ---
+
+/// 静息判定所用的时间窗口。
+const STEADY_STATE_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// 久坐静息检测：连续佩戴满一个窗口，且窗口内心率标准差低于 `steady_state_sd_bpm`、
+/// 平均心率低于 `resting_hr_threshold` 时进入静息状态；任一条件不再满足时立即退出。
+#[derive(Debug, Default)]
+struct SteadyStateDetector {
+    samples: VecDeque<(Instant, u8)>,
+    /// 本段连续佩戴（心率 > 0）的开始时间
+    worn_since: Option<Instant>,
+    active: bool,
+}
+
+impl SteadyStateDetector {
+    /// 用新读数更新状态，状态发生变化时返回新状态。
+    fn update(&mut self, heart_rate: u8, now: Instant, config: &Config) -> Option<bool> {
+        let steady = if heart_rate == 0 {
+            self.samples.clear();
+            self.worn_since = None;
+            false
+        } else {
+            let worn_since = *self.worn_since.get_or_insert(now);
+            self.samples.push_back((now, heart_rate));
+            while self
+                .samples
+                .front()
+                .is_some_and(|(t, _)| now.duration_since(*t) > STEADY_STATE_WINDOW)
+            {
+                self.samples.pop_front();
+            }
+
+            let n = self.samples.len() as f32;
+            let mean = self
+                .samples
+                .iter()
+                .map(|(_, hr)| f32::from(*hr))
+                .sum::<f32>()
+                / n;
+            let variance = self
+                .samples
+                .iter()
+                .map(|(_, hr)| (f32::from(*hr) - mean).powi(2))
+                .sum::<f32>()
+                / n;
+            now.duration_since(worn_since) >= STEADY_STATE_WINDOW
+                && variance.sqrt() < config.steady_state_sd_bpm
+                && mean < f32::from(config.resting_hr_threshold)
+        };
+
+        let changed = steady != self.active;
+        self.active = steady;
+        changed.then_some(steady)
+    }
+
+    fn is_active(&self) -> bool {
+        self.active
+    }
+}
+
+// --- 退出清理（Windows 控制台事件 / Unix SIGINT、SIGTERM） ---
+
+struct CleanupCtx {
+    osc_addr: SocketAddr,
+    config: Config,
+    hr_file: PathBuf,
+}
+
+static CLEANUP_CTX: OnceLock<CleanupCtx> = OnceLock::new();
+static CLEANUP_DONE: AtomicBool = AtomicBool::new(false);
+
+/// 退出时等待输出文件写完的最长时间
+const EXIT_FLUSH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// 只执行一次的退出清理。
+fn run_exit_cleanup() {
+    if CLEANUP_DONE.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    ghost::cancel();
+    haptics::shutdown();
+    if let Some(ctx) = CLEANUP_CTX.get() {
+        match osc_socket(&ctx.config, ctx.osc_addr) {
+            Ok(socket) => clear_state(&socket, ctx.osc_addr, &ctx.config, &ctx.hr_file),
+            Err(_) => {
+                if ctx.config.write_heart_rate_file {
+                    file_writer::submit(
+                        ctx.hr_file.clone(),
+                        ctx.config.heart_rate_file_offline.clone(),
+                    );
+                }
+                publish_status(&status_file::Status::default());
+            }
+        }
+    }
+    websocket::shutdown();
+    resonite::shutdown();
+    discord_presence::shutdown();
+    // 离线内容由写入线程写入，等它落盘再退出（Windows 关闭窗口时处理例程约有 5 秒）
+    if !file_writer::flush(EXIT_FLUSH_TIMEOUT) {
+        warn!("退出前未能写完输出文件");
+    }
+    // 先清零 OSC 与文件再收尾各记录输出：同步写入 InfluxDB 等可能要等到超时，
+    // 不能让它占用关闭窗口时的处理时间。总结依赖 Stop 事件，须在此之后生成
+    recorder::finish();
+    // 所有平台的正常退出都经过这里（Windows 的 Ctrl-C 在处理例程内直接退出，不会回到 main）
+    if let Some(summary) = summary::finish() {
+        println!("\n{}", summary);
+        discord::session_summary(&summary);
+    }
+}
+
+/// 注册控制台事件处理器。
+/// 不使用 ctrlc crate：它的处理例程立即返回、闭包在另一线程异步执行，
+/// CTRL_CLOSE_EVENT（点 X 关窗）下会与进程终止竞争，清理大概率来不及跑。
+/// 这里直接在处理例程内【同步】完成清理再返回（CLOSE 事件下例程有约 5 秒预算）。
+#[cfg(windows)]
+fn register_exit_handler() -> bool {
+    use windows_sys::Win32::System::Console::{
+        SetConsoleCtrlHandler, CTRL_BREAK_EVENT, CTRL_C_EVENT,
+    };
+
+    unsafe extern "system" fn handler(ctrl_type: u32) -> i32 {
+        run_exit_cleanup();
+        match ctrl_type {
+            // Ctrl-C / Ctrl-Break：系统不主动终止进程，由我们自己退出
+            CTRL_C_EVENT | CTRL_BREAK_EVENT => std::process::exit(0),
+            // CLOSE / LOGOFF / SHUTDOWN：返回 FALSE，交给系统继续默认终止流程
+            _ => 0,
+        }
+    }
+
+    unsafe { SetConsoleCtrlHandler(Some(handler), 1) != 0 }
+}
+
+/// 等待 Unix 的交互退出或服务终止信号。
+#[cfg(unix)]
+async fn wait_for_exit_signal() -> io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result,
+        _ = terminate.recv() => Ok(()),
+    }
+}
+
+// --- 信号强度 ---
+
+/// 信号质量映射区间：RSSI 不高于下限记为 0，不低于上限记为 1，其间线性插值。
+const RSSI_QUALITY_MIN_DBM: i16 = -100;
+const RSSI_QUALITY_MAX_DBM: i16 = -50;
+
+/// 连接期间周期性读取的 RSSI 及连续弱信号计数。
+#[derive(Debug, Default)]
+struct SignalMonitor {
+    /// 最近一次读到的 RSSI（dBm）；平台在连接后不提供 RSSI 时为 None
+    rssi: Option<i16>,
+    weak_streak: u32,
+}
+
+impl SignalMonitor {
+    /// 记录一次读数。连续 `rssi_warn_samples` 次低于 `rssi_warn_floor` 时返回 true，
+    /// 每段连续弱信号只返回一次；读不到 RSSI 时清零计数。
+    fn update(&mut self, rssi: Option<i16>, config: &Config) -> bool {
+        self.rssi = rssi;
+        match rssi {
+            Some(rssi) if rssi < config.rssi_warn_floor => {
+                self.weak_streak += 1;
+                self.weak_streak == config.rssi_warn_samples
+            }
+            _ => {
+                self.weak_streak = 0;
+                false
+            }
+        }
+    }
+
+    /// 0–1 的信号质量，读不到 RSSI 时为 None。
+    fn quality(&self) -> Option<f32> {
+        self.rssi.map(rssi_quality)
+    }
+}
+
+/// 把 RSSI 线性映射为 0–1 的信号质量。
+fn rssi_quality(rssi: i16) -> f32 {
+    let span = f32::from(RSSI_QUALITY_MAX_DBM - RSSI_QUALITY_MIN_DBM);
+    let clamped = rssi.clamp(RSSI_QUALITY_MIN_DBM, RSSI_QUALITY_MAX_DBM);
+    f32::from(clamped - RSSI_QUALITY_MIN_DBM) / span
+}
+
+impl fmt::Display for SignalMonitor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.rssi {
+            Some(rssi) => write!(f, "{} dBm", rssi),
+            None => write!(f, "N/A"),
+        }
+    }
+}
+
+/// 读取设备当前 RSSI。btleplug 只提供系统缓存的属性值，
+/// 部分平台连接后不再更新或不提供 RSSI，此时返回 None 而不是报错。
+async fn read_rssi(device: &Peripheral, config: &Config) -> Option<i16> {
+    match time::timeout(
+        Duration::from_secs(config.service_timeout_secs),
+        device.properties(),
+    )
+    .await
+    {
+        Ok(Ok(Some(props))) => props.rssi,
+        _ => None,
+    }
+}
+
+// --- 通知积压 ---
+
+/// 因积压被丢弃的心率通知累计数（整个运行期间），随警告以 hr_notifications_dropped_total 输出。
+static NOTIFICATIONS_DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// 通知积压检测（`max_notification_queue_depth`）：CPU 繁忙时通知流内部的通道会堆积，
+/// 之后逐条处理的都是过时的心率。每次等到一条通知后，把已经到达、无需等待的通知一并取出；
+/// 数量超过上限时只保留最新的一条心率通知（其他特征的通知保留），其余丢弃。
+#[derive(Debug, Default)]
+struct NotificationBacklog {
+    /// 已取出、尚未处理的通知
+    pending: VecDeque<ValueNotification>,
+    /// 取积压时发现通知流已关闭
+    closed: bool,
+}
+
+impl NotificationBacklog {
+    /// 下一条要处理的通知；未设置上限时等同于 `stream.next()`。
+    async fn next<S>(
+        &mut self,
+        stream: &mut S,
+        hr_uuid: Uuid,
+        max_depth: Option<usize>,
+    ) -> Option<ValueNotification>
+    where
+        S: Stream<Item = ValueNotification> + Unpin,
+    {
+        if let Some(notification) = self.pending.pop_front() {
+            return Some(notification);
+        }
+        if self.closed {
+            return None;
+        }
+        let first = stream.next().await?;
+        let Some(max_depth) = max_depth else {
+            return Some(first);
+        };
+        self.pending.push_back(first);
+        loop {
+            match stream.next().now_or_never() {
+                Some(Some(notification)) => self.pending.push_back(notification),
+                Some(None) => {
+                    self.closed = true;
+                    break;
+                }
+                None => break,
+            }
+        }
+        let depth = self.pending.len();
+        if depth > max_depth {
+            let dropped = keep_latest(&mut self.pending, hr_uuid);
+            let total = NOTIFICATIONS_DROPPED.fetch_add(dropped, Ordering::Relaxed) + dropped;
+            warn!(
+                "通知积压 {} 条（超过 max_notification_queue_depth = {}），已丢弃 {} 条过时的心率通知，只处理最新一条（hr_notifications_dropped_total={}）。",
+                depth, max_depth, dropped, total
+            );
+        }
+        self.pending.pop_front()
+    }
+}
+
+/// 只保留最后一条心率通知和所有其他特征的通知（保持顺序），返回丢弃的条数。
+fn keep_latest(pending: &mut VecDeque<ValueNotification>, hr_uuid: Uuid) -> u64 {
+    let Some(latest) = pending.iter().rposition(|n| n.uuid == hr_uuid) else {
+        return 0;
+    };
+    let before = pending.len();
+    let mut index = 0;
+    pending.retain(|n| {
+        let keep = n.uuid != hr_uuid || index == latest;
+        index += 1;
+        keep
+    });
+    (before - pending.len()) as u64
+}
+
+// --- 重复通知合并 ---
+
+/// 合并突发的重复通知：部分手环唤醒后会在约 100 ms 内连发多条内容相同的通知，
+/// 每条都会触发一次文件写入和完整的 OSC 发送。在解析之前丢弃窗口内的重复内容。
+#[derive(Debug, Default)]
+struct NotificationDeduper {
+    /// 最近一条被处理的通知内容及其接收时间
+    last: Option<(Vec<u8>, Instant)>,
+    /// 本次连接中被丢弃的重复通知数
+    suppressed: u64,
+}
+
+impl NotificationDeduper {
+    /// 需要处理该通知时返回 true；与上一条被处理的通知内容相同且间隔小于 `window` 时计数并返回 false。
+    fn accept(&mut self, value: &[u8], now: Instant, window: Duration) -> bool {
+        if let Some((last_value, last_at)) = &self.last {
+            if last_value.as_slice() == value && now.duration_since(*last_at) < window {
+                self.suppressed += 1;
+                return false;
+            }
+        }
+        self.last = Some((value.to_vec(), now));
+        true
+    }
+}
+
+/// 部分手环每秒多次上报相同的心率，人为抬高了采样率。启用 `dedup_identical_readings` 后，
+/// 心率与上次发送的相同且间隔不足 `dedup_min_interval_ms` 的读数不发送 OSC、不写文件
+/// （统计和记录照常）。与 `NotificationDeduper` 不同，比较的是解析后的心率而不是原始通知内容。
+#[derive(Debug, Default)]
+struct ReadingDeduper {
+    /// 上次发送的心率及其时间
+    last_sent: Option<(u8, Instant)>,
+    /// 当前统计周期内跳过的读数
+    skipped: u32,
+}
+
+impl ReadingDeduper {
+    /// 需要跳过该读数时计数并返回 true。
+    fn skip(&mut self, bpm: u8, now: Instant, config: &Config) -> bool {
+        if !config.dedup_identical_readings {
+            return false;
+        }
+        if let Some((last_bpm, last_at)) = self.last_sent {
+            let interval = Duration::from_millis(config.dedup_min_interval_ms);
+            if last_bpm == bpm && now.duration_since(last_at) < interval {
+                self.skipped += 1;
+                return true;
+            }
+        }
+        self.last_sent = Some((bpm, now));
+        false
+    }
+}
+
+// --- 空闲模式 ---
+
+/// 设备摘下后仍会持续上报 0：连续 `idle_after_zero_readings` 次为 0 时进入空闲模式
+/// （停止发送 OSC、停止写文件、降低订阅频率），首个非 0 读数立即恢复。
+#[derive(Debug, Default)]
+struct IdleTracker {
+    zero_streak: u32,
+    idle: bool,
+}
+
+impl IdleTracker {
+    /// 用新读数更新状态，进入空闲时返回 Some(true)，退出空闲时返回 Some(false)。
+    fn update(&mut self, heart_rate: u16, config: &Config) -> Option<bool> {
+        if heart_rate > 0 {
+            self.zero_streak = 0;
+            let was_idle = self.idle;
+            self.idle = false;
+            return was_idle.then_some(false);
+        }
+        self.zero_streak = self.zero_streak.saturating_add(1);
+        if !self.idle
+            && config.idle_after_zero_readings > 0
+            && self.zero_streak >= config.idle_after_zero_readings
+        {
+            self.idle = true;
+            return Some(true);
+        }
+        None
+    }
+}
+
+// --- 心率测量解析 ---
+
+/// 心率特征的数据格式。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum PayloadFormat {
+    /// GATT Heart Rate Measurement：首字节 flags，其后心率（及可选字段）
+    #[default]
+    Standard,
+    /// 首字节直接是心率（BPM），没有 flags（部分私有特征）
+    RawU8,
+}
+
+impl fmt::Display for PayloadFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PayloadFormat::Standard => write!(f, "standard"),
+            PayloadFormat::RawU8 => write!(f, "raw-u8"),
+        }
+    }
+}
+
+/// 按格式提示解析一次心率数据。
+fn parse_payload(value: &[u8], format: PayloadFormat) -> Option<HeartRateMeasurement> {
+    match format {
+        PayloadFormat::Standard => parse_heart_rate_measurement(value),
+        PayloadFormat::RawU8 => value.first().map(|&bpm| HeartRateMeasurement {
+            heart_rate: u16::from(bpm),
+            rr_intervals: Vec::new(),
+            energy_expended: None,
+        }),
+    }
+}
+
+/// GATT Heart Rate Measurement (0x2A37) 的解析结果。
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct HeartRateMeasurement {
+    heart_rate: u16,
+    /// RR 间期，单位 1/1024 秒（规范原始值）
+    rr_intervals: Vec<u16>,
+    /// 累计能量消耗（kJ），设备不提供时为 None
+    energy_expended: Option<u16>,
+}
+
+/// 解析 Heart Rate Measurement：flags 位 0 决定 8/16 位心率格式，
+/// 位 3 表示其后带 2 字节能量消耗字段，位 4 表示末尾跟随若干 RR 间期。
+/// 数据不完整时返回 None。
+fn parse_heart_rate_measurement(value: &[u8]) -> Option<HeartRateMeasurement> {
+    let (&flags, rest) = value.split_first()?;
+    let (heart_rate, mut rest) = if flags & 0x01 == 0 {
+        let (&heart_rate, rest) = rest.split_first()?;
+        (u16::from(heart_rate), rest)
+    } else {
+        if rest.len() < 2 {
+            return None;
+        }
+        (u16::from_le_bytes([rest[0], rest[1]]), &rest[2..])
+    };
+    let mut energy_expended = None;
+    if flags & 0x08 != 0 {
+        energy_expended = rest.get(..2).map(|e| u16::from_le_bytes([e[0], e[1]]));
+        rest = rest.get(2..).unwrap_or(&[]);
+    }
+    let rr_intervals = if flags & 0x10 != 0 {
+        rest.chunks_exact(2)
+            .map(|rr| u16::from_le_bytes([rr[0], rr[1]]))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    Some(HeartRateMeasurement {
+        heart_rate,
+        rr_intervals,
+        energy_expended,
+    })
+}
+
+// --- 设备信息 (Device Information Service 0x180A) ---
+const DEVICE_INFO_SERVICE_UUID: Uuid = Uuid::from_u128(0x0000180a_0000_1000_8000_00805f9b34fb);
+const MANUFACTURER_NAME_UUID: Uuid = Uuid::from_u128(0x00002a29_0000_1000_8000_00805f9b34fb);
+const MODEL_NUMBER_UUID: Uuid = Uuid::from_u128(0x00002a24_0000_1000_8000_00805f9b34fb);
+const FIRMWARE_REVISION_UUID: Uuid = Uuid::from_u128(0x00002a26_0000_1000_8000_00805f9b34fb);
+
+/// 连接后读到的设备信息；很多手环只实现其中一部分，缺失的字段为 None。
+#[derive(Debug, Clone, Default, PartialEq)]
+struct DeviceInfo {
+    manufacturer: Option<String>,
+    model: Option<String>,
+    firmware: Option<String>,
+}
+
+impl DeviceInfo {
+    fn is_empty(&self) -> bool {
+        self.manufacturer.is_none() && self.model.is_none() && self.firmware.is_none()
+    }
+
+    /// 设备缓存中地址之后的 `键=值` 行，缺失的项不写。
+    fn cache_lines(&self) -> String {
+        [
+            ("manufacturer", &self.manufacturer),
+            ("model", &self.model),
+            ("firmware", &self.firmware),
+        ]
+        .iter()
+        .filter_map(|(key, value)| value.as_ref().map(|v| format!("{}={}\n", key, v)))
+        .collect()
+    }
+}
+
+impl fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields = [
+            ("厂商", &self.manufacturer),
+            ("型号", &self.model),
+            ("固件", &self.firmware),
+        ];
+        let parts: Vec<String> = fields
+            .iter()
+            .filter_map(|(label, value)| value.as_ref().map(|v| format!("{}: {}", label, v)))
+            .collect();
+        write!(f, "{}", parts.join(" | "))
+    }
+}
+
+/// 设备信息字符串为 UTF-8，部分设备会带结尾的 NUL 或空白；空字符串视为未提供。
+fn decode_device_info_string(value: &[u8]) -> Option<String> {
+    let text = sanitize_device_name(&String::from_utf8_lossy(value));
+    (!text.is_empty()).then_some(text)
+}
+
+/// 逐项读取设备信息。每一项单独容错：缺失、不可读或读取失败都只跳过该项。
+async fn read_device_info(device: &Peripheral, config: &Config) -> DeviceInfo {
+    let characteristics = device.characteristics();
+    let mut info = DeviceInfo::default();
+    for (uuid, slot) in [
+        (MANUFACTURER_NAME_UUID, &mut info.manufacturer),
+        (MODEL_NUMBER_UUID, &mut info.model),
+        (FIRMWARE_REVISION_UUID, &mut info.firmware),
+    ] {
+        let Some(characteristic) = characteristics.iter().find(|c| {
+            c.service_uuid == DEVICE_INFO_SERVICE_UUID
+                && c.uuid == uuid
+                && c.properties.contains(CharPropFlags::READ)
+        }) else {
+            continue;
+        };
+        match ble_timeout(
+            "read",
+            config.service_timeout_secs,
+            device.read(characteristic),
+        )
+        .await
+        {
+            Ok(value) => *slot = decode_device_info_string(&value),
+            Err(e) => warn!("读取设备信息 {} 失败: {}", uuid, e),
+        }
+    }
+    info
+}
+
+const BATTERY_SERVICE_UUID: Uuid = Uuid::from_u128(0x0000180f_0000_1000_8000_00805f9b34fb);
+const BATTERY_LEVEL_UUID: Uuid = Uuid::from_u128(0x00002a19_0000_1000_8000_00805f9b34fb);
+
+/// 可读的电量特征（需先 discover_services）。
+fn battery_level_characteristic(device: &Peripheral) -> Option<Characteristic> {
+    device.characteristics().into_iter().find(|c| {
+        c.service_uuid == BATTERY_SERVICE_UUID
+            && c.uuid == BATTERY_LEVEL_UUID
+            && c.properties.contains(CharPropFlags::READ)
+    })
+}
+
+/// 读取电量百分比（Battery Service 0x180F）；没有该服务、不可读或读取失败时为 None。
+async fn read_battery_level(device: &Peripheral, config: &Config) -> Option<u8> {
+    let characteristic = battery_level_characteristic(device)?;
+    let value = ble_timeout(
+        "read",
+        config.service_timeout_secs,
+        device.read(&characteristic),
+    )
+    .await
+    .ok()?;
+    value.first().copied().filter(|level| *level <= 100)
+}
+
+const SPO2_CHAR_UUID: Uuid = Uuid::from_u128(0x00002a5f_0000_1000_8000_00805f9b34fb);
+
+/// 小米/华米手环的实时步数特征（需先完成认证，见 mi_auth 模块）。
+const MI_REALTIME_STEPS_CHAR_UUID: Uuid = Uuid::from_u128(0x00000007_0000_3512_2118_0009af100700);
+
+/// 订阅心率以外的可选特征（血氧、步数）；设备没有该特征或订阅失败只提示，不影响心率。
+/// `name` 用于日志，`param` 为因此不会发送的 OSC 参数。
+async fn subscribe_optional(
+    device: &Peripheral,
+    config: &Config,
+    uuid: Uuid,
+    name: &str,
+    param: &str,
+) -> Option<Characteristic> {
+    let Some(characteristic) = device.characteristics().into_iter().find(|c| {
+        c.uuid == uuid
+            && c.properties
+                .intersects(CharPropFlags::NOTIFY | CharPropFlags::INDICATE)
+    }) else {
+        warn!("设备没有可订阅的{}，将不发送 {}。", name, param);
+        return None;
+    };
+    match ble_timeout(
+        "subscribe",
+        config.service_timeout_secs,
+        device.subscribe(&characteristic),
+    )
+    .await
+    {
+        Ok(()) => {
+            info!("已订阅{}的通知。", name);
+            Some(characteristic)
+        }
+        Err(e) => {
+            warn!("订阅{}失败: {}，将不发送 {}。", name, e, param);
+            None
+        }
+    }
+}
+
+/// 解析累计步数：4 字节小端计数，或小米/华米实时步数格式（0x0C 开头，其后 2 字节小端步数、
+/// 距离、卡路里）。格式不符时返回 None。
+fn parse_step_count(value: &[u8]) -> Option<u32> {
+    match value {
+        [a, b, c, d] => Some(u32::from_le_bytes([*a, *b, *c, *d])),
+        [0x0c, lo, hi, ..] => Some(u32::from(u16::from_le_bytes([*lo, *hi]))),
+        _ => None,
+    }
+}
+
+/// 解析血氧饱和度（0x2A5F）：华为/荣耀设备发送 1 字节百分比；
+/// 按 PLX Continuous Measurement 规范发送时为 flags + SFLOAT 格式的 SpO2。
+/// 超出 0–100 或为特殊值（NaN 等）时返回 None。
+fn parse_spo2(value: &[u8]) -> Option<u8> {
+    let spo2 = match value {
+        [percent] => f32::from(*percent),
+        [_flags, lo, hi, ..] => sfloat(u16::from_le_bytes([*lo, *hi]))?,
+        _ => return None,
+    };
+    (0.0..=100.0).contains(&spo2).then(|| spo2.round() as u8)
+}
+
+/// IEEE-11073 16 位 SFLOAT：高 4 位为有符号指数，低 12 位为有符号尾数；
+/// 保留值（NaN、NRes、±INF）返回 None。
+fn sfloat(raw: u16) -> Option<f32> {
+    let mantissa = raw & 0x0fff;
+    if (0x07fe..=0x0802).contains(&mantissa) {
+        return None;
+    }
+    let mantissa = ((mantissa << 4) as i16 >> 4) as f32;
+    let exponent = i32::from(raw as i16 >> 12);
+    Some(mantissa * 10_f32.powi(exponent))
+}
+
+/// 粗略判断设备是否已与系统配对（scan_bonded_only）。btleplug 不提供配对状态，
+/// 广播数据中也没有通用的配对标志，因此临时连接并读取电量特征作为代理：
+/// 未配对的设备在需要加密的特征上会返回错误。连接失败视为不可用；
+/// 没有电量特征的设备无法判断，视为已配对。检查完即断开。
+async fn is_bonded(device: &Peripheral, config: &Config) -> bool {
+    let was_connected = device.is_connected().await.unwrap_or(false);
+    if !was_connected
+        && ble_timeout("connect", config.connect_timeout_secs, device.connect())
+            .await
+            .is_err()
+    {
+        return false;
+    }
+    let bonded = match ble_timeout(
+        "discover_services",
+        config.service_timeout_secs,
+        device.discover_services(),
+    )
+    .await
+    {
+        Ok(()) => match battery_level_characteristic(device) {
+            Some(characteristic) => ble_timeout(
+                "read",
+                config.service_timeout_secs,
+                device.read(&characteristic),
+            )
+            .await
+            .is_ok(),
+            None => true,
+        },
+        Err(_) => false,
+    };
+    if !was_connected {
+        let _ = time::timeout(
+            Duration::from_secs(TEARDOWN_TIMEOUT_SECS),
+            device.disconnect(),
+        )
+        .await;
+    }
+    bonded
+}
+
+// --- 上次使用的设备 ---
+
+/// 设备的持久标识：MAC 地址；macOS 不提供 MAC（地址全 0），改用系统分配的设备 ID。
+fn device_key(device: &Peripheral) -> String {
+    let address = device.address();
+    if address == BDAddr::default() {
+        device.id().to_string()
+    } else {
+        address.to_string()
+    }
+}
+
+/// 设备缓存的第一行是设备地址，其后是读到的设备信息（`manufacturer=…` 等，供查看，不影响连接）。
+fn load_last_device(cache_file: &Path) -> Option<String> {
+    let contents = fs::read_to_string(cache_file).ok()?;
+    let key = contents.lines().next()?.trim().to_string();
+    (!key.is_empty()).then_some(key)
+}
+
+fn save_last_device(cache_file: &Path, key: &str, info: Option<&DeviceInfo>) {
+    let contents = format!(
+        "{}\n{}",
+        key,
+        info.map(DeviceInfo::cache_lines).unwrap_or_default()
+    );
+    if let Err(e) = fs::write(cache_file, contents) {
+        warn!("保存设备缓存 {} 失败: {}", cache_file.display(), e);
+    }
+}
+
+/// 删除设备缓存。返回是否确实删除了文件（文件本不存在时为 false）。
+fn reset_last_device(cache_file: &Path) -> io::Result<bool> {
+    match fs::remove_file(cache_file) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// 运行期间的"重置缓存并重新扫描"请求：Unix 为 SIGUSR1，Windows 为命名事件或托盘菜单
+/// （见 `start_rescan_listener`）。没有人等待时保留一次请求，扫描或重连间隔中到达的请求不会丢失。
+static RESCAN_REQUESTS: Notify = Notify::const_new();
+
+/// 等待下一次重置请求。
+async fn wait_for_rescan_request() {
+    RESCAN_REQUESTS.notified().await;
+}
+
+/// 启动时注册一次 SIGUSR1 处理并保持到退出：没有处理器时 SIGUSR1 的默认动作会终止进程。
+#[cfg(unix)]
+fn start_rescan_listener() -> bool {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let Ok(mut usr1) = signal(SignalKind::user_defined1()) else {
+        return false;
+    };
+    tokio::spawn(async move {
+        while usr1.recv().await.is_some() {
+            RESCAN_REQUESTS.notify_one();
+        }
+    });
+    true
+}
+
+/// 另一个进程执行 `--reset-cache` 时触发的命名事件，用来通知正在运行的实例重新扫描。
+#[cfg(windows)]
+const RESCAN_EVENT_NAME: &str = "Local\\HeartRateForVRChat.Rescan";
+
+#[cfg(windows)]
+fn wide_null(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+/// 创建命名事件并在后台线程等待，被触发时通知 RESCAN_REQUESTS。
+#[cfg(windows)]
+fn start_rescan_listener() -> bool {
+    use windows_sys::Win32::Foundation::{HANDLE, WAIT_OBJECT_0};
+    use windows_sys::Win32::System::Threading::{CreateEventW, WaitForSingleObject, INFINITE};
+
+    let name = wide_null(RESCAN_EVENT_NAME);
+    // SAFETY: name 以 NUL 结尾；自动复位、初始未触发的事件，句柄在进程生命周期内不关闭。
+    let handle = unsafe { CreateEventW(std::ptr::null(), 0, 0, name.as_ptr()) };
+    if handle.is_null() {
+        return false;
+    }
+    // HANDLE 是裸指针，不能直接跨线程移动
+    let handle = handle as usize;
+    std::thread::spawn(move || loop {
+        // SAFETY: 句柄始终有效（见上）。
+        if unsafe { WaitForSingleObject(handle as HANDLE, INFINITE) } != WAIT_OBJECT_0 {
+            break;
+        }
+        RESCAN_REQUESTS.notify_one();
+    });
+    true
+}
+
+/// 通知正在运行的实例（若有）重置缓存并重新扫描。返回是否找到了运行中的实例。
+#[cfg(windows)]
+fn notify_running_instance() -> bool {
+    use windows_sys::Win32::Foundation::CloseHandle;
+    use windows_sys::Win32::System::Threading::{OpenEventW, SetEvent, EVENT_MODIFY_STATE};
+
+    let name = wide_null(RESCAN_EVENT_NAME);
+    // SAFETY: name 以 NUL 结尾；打开的句柄用完即关闭。
+    unsafe {
+        let handle = OpenEventW(EVENT_MODIFY_STATE, 0, name.as_ptr());
+        if handle.is_null() {
+            return false;
+        }
+        let signalled = SetEvent(handle) != 0;
+        CloseHandle(handle);
+        signalled
+    }
+}
+
+/// 本进程内请求放弃当前连接并重新扫描（托盘菜单"断开连接"）。
+#[cfg(windows)]
+fn request_rescan() {
+    RESCAN_REQUESTS.notify_one();
+}
+
+// --- 设备名显示 ---
+
+/// 设备列表中名称列的宽度（按字素簇计数）。
+const DEVICE_NAME_COLUMN_WIDTH: usize = 15;
+
+/// 去掉设备名中的控制字符和零宽字符（部分固件的广播名里混有 \0、换行或 ZWJ），
+/// 保留中日韩等可打印的非 ASCII 字符——过去只保留 ASCII 字母数字，中文名会被清空。
+fn sanitize_device_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| !c.is_control() && !is_zero_width(*c))
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+fn is_zero_width(c: char) -> bool {
+    matches!(c, '\u{200B}'..='\u{200F}' | '\u{2060}' | '\u{FEFF}')
+}
+
+/// 按字素簇截断到 `width` 个并用空格补齐，不会把多码点字符从中间切开。
+fn fit_device_name(name: &str, width: usize) -> String {
+    let mut fitted = String::new();
+    let mut count = 0;
+    for grapheme in name.graphemes(true).take(width) {
+        fitted.push_str(grapheme);
+        count += 1;
+    }
+    fitted.extend(std::iter::repeat_n(' ', width - count));
+    fitted
+}
+
+// --- 蓝牙逻辑 ---
+
+/// 返回第一个处于开启状态的蓝牙适配器；没有适配器或已被关闭时返回 None。
+async fn powered_adapter(manager: &Manager) -> Option<Adapter> {
+    let central = manager.adapters().await.ok()?.into_iter().next()?;
+    match central.adapter_state().await {
+        Ok(CentralState::PoweredOff) => None,
+        // 部分平台只会报告 Unknown，视为可用，交给后续蓝牙操作自行报错
+        _ => Some(central),
+    }
+}
+
+/// 创建 Manager 并取得可用的适配器；暂不可用时等待其恢复。
+async fn acquire_adapter() -> Result<(Manager, Adapter)> {
+    let manager = Manager::new().await.context("初始化蓝牙")?;
+    match powered_adapter(&manager).await {
+        Some(central) => Ok((manager, central)),
+        None => Ok(wait_for_adapter().await),
+    }
+}
+
+/// start_scan 超时说明蓝牙栈已卡死（Windows 上偶发且不会自行恢复）。
+fn is_scan_hang(e: &AppError) -> bool {
+    matches!(
+        e.root(),
+        AppError::Timeout {
+            op: "start_scan",
+            ..
+        }
+    )
+}
+
+/// 丢弃 Manager 和全部适配器句柄后重新初始化蓝牙栈；调用方先记录原因。
+async fn reset_ble_stack(manager: Manager, central: Adapter) -> Result<(Manager, Adapter)> {
+    drop(central);
+    drop(manager);
+    let resets = BLE_STACK_RESET_COUNT.fetch_add(1, Ordering::Relaxed) + 1;
+    info!("正在重新初始化蓝牙栈（本次运行第 {} 次）...", resets);
+    acquire_adapter().await
+}
+
+/// 连续失败的连接次数（跨设备、跨扫描累计），达到上限时应重新初始化蓝牙栈。
+#[derive(Debug)]
+struct ConnectionAttempts {
+    failed: u32,
+    limit: u32,
+}
+
+impl ConnectionAttempts {
+    fn new(limit: u32) -> Self {
+        ConnectionAttempts { failed: 0, limit }
+    }
+
+    /// 记录一次连接的结果；返回 Some(失败次数) 表示已达上限（计数随之清零）。
+    fn record(&mut self, success: bool) -> Option<u32> {
+        if success {
+            self.failed = 0;
+            return None;
+        }
+        self.failed += 1;
+        if self.limit == 0 || self.failed < self.limit {
+            return None;
+        }
+        Some(mem::take(&mut self.failed))
+    }
+}
+
+/// 阻塞直到蓝牙适配器恢复可用，期间只打印一次提示。
+/// 每次检查都重新创建 Manager：适配器关闭/拔出后，旧的 Manager/Adapter 句柄在部分平台上会失效。
+async fn wait_for_adapter() -> (Manager, Adapter) {
+    warn!("蓝牙适配器不可用（已关闭或被移除），正在等待其恢复...");
+    loop {
+        time::sleep(Duration::from_secs(ADAPTER_POLL_SECS)).await;
+        let Ok(manager) = Manager::new().await else {
+            continue;
+        };
+        if let Some(central) = powered_adapter(&manager).await {
+            info!("蓝牙适配器已恢复，继续扫描。");
+            return (manager, central);
+        }
+    }
+}
+
+/// 连接期间在后台监视蓝牙适配器：事件流报告关闭（`CentralEvent::StateUpdate`），
+/// 或每 ADAPTER_POLL_SECS 秒查询发现适配器已关闭/被移除/查询无响应时，`lost` 返回。
+/// USB 适配器被拔出后 btleplug 的 future 可能永远不返回，靠它让连接任务及时退出。
+struct AdapterMonitor {
+    lost: oneshot::Receiver<()>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl AdapterMonitor {
+    fn start(manager: &Manager, central: &Adapter) -> Self {
+        let (sender, lost) = oneshot::channel();
+        let task = tokio::spawn(watch_adapter(manager.clone(), central.clone(), sender));
+        AdapterMonitor { lost, task }
+    }
+
+    /// 等待适配器不可用。
+    async fn lost(&mut self) {
+        let _ = (&mut self.lost).await;
+    }
+}
+
+impl Drop for AdapterMonitor {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn watch_adapter(manager: Manager, central: Adapter, lost: oneshot::Sender<()>) {
+    // 部分平台不提供事件流或不报告状态变化，仍有定期查询兜底
+    let mut events = central.events().await.ok();
+    let mut poll = time::interval(Duration::from_secs(ADAPTER_POLL_SECS));
+    loop {
+        tokio::select! {
+            event = async {
+                match events.as_mut() {
+                    Some(events) => events.next().await,
+                    None => std::future::pending().await,
+                }
+            } => match event {
+                Some(CentralEvent::StateUpdate(CentralState::PoweredOff)) => {
+                    debug!("收到蓝牙适配器关闭事件");
+                    break;
+                }
+                Some(_) => {}
+                None => events = None,
+            },
+            _ = poll.tick() => {
+                match time::timeout(
+                    Duration::from_secs(ADAPTER_QUERY_TIMEOUT_SECS),
+                    powered_adapter(&manager),
+                )
+                .await
+                {
+                    Ok(Some(_)) => {}
+                    Ok(None) => break,
+                    Err(_) => {
+                        debug!("查询蓝牙适配器状态无响应");
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    let _ = lost.send(());
+}
+
+/// 扫描并返回一个目标外围设备。
+async fn find_target_device(
+    central: &Adapter,
+    config: &Config,
+    last_device: Option<&str>,
+    selector: &dyn DeviceSelector,
+) -> Result<Peripheral> {
+    info!("正在扫描蓝牙设备...");
+
+    // 只扫描广播了心率服务 (0x180D 及配置的额外服务) 的设备
+    let scan_filter = ScanFilter {
+        services: hr_service_uuids(config),
+    };
+    ble_timeout(
+        "start_scan",
+        config.scan_timeout_secs,
+        central.start_scan(scan_filter),
+    )
+    .await?;
+    time::sleep(Duration::from_secs(config.scan_duration_secs)).await;
+
+    let peripherals = central.peripherals().await?;
+    info!("附近设备列表:");
+
+    let mut candidates: Vec<(Peripheral, PeripheralProperties)> = Vec::new();
+
+    if peripherals.is_empty() {
+        info!("未发现任何设备。请检查设备是否开启并处于广播状态。");
+    }
+
+    for p in peripherals {
+        // 获取不到属性的设备直接跳过
+        let properties = match p.properties().await {
+            Ok(Some(props)) => props,
+            _ => continue,
+        };
+
+        let mac_address = p.address();
+        let device_name = properties
+            .local_name
+            .clone()
+            .unwrap_or_else(|| "未知设备 Unknown Device".to_string());
+        let rssi_str = properties
+            .rssi
+            .map_or("N/A".to_string(), |rssi| format!("{} dBm", rssi));
+        let key = device_key(&p);
+        let alias_str =
+            devices::alias(&key).map_or(String::new(), |alias| format!(" | 别名: {}", alias));
+
+        info!(
+            "名称: {} | MAC: {} | 信号强度: {}{}",
+            fit_device_name(
+                &sanitize_device_name(&device_name),
+                DEVICE_NAME_COLUMN_WIDTH
+            ),
+            mac_address,
+            rssi_str,
+            alias_str
+        );
+
+        candidates.push((p, properties));
+    }
+
+    // 无论成功与否都停止扫描
+    let _ = central.stop_scan().await;
+
+    info!("选择模式: {}", selector.describe());
+    // scan_bonded_only 只检查选中的设备，未配对时再选下一个：
+    // 检查需要临时连接，逐个检查所有候选设备太慢，也会连上附近其他人的设备
+    let mut skipped_unbonded = false;
+    let chosen_peripheral = loop {
+        let Some((index, reason)) = pick_candidate(&candidates, last_device, selector) else {
+            break None;
+        };
+        let (p, properties) = candidates.remove(index);
+        if config.scan_bonded_only && !is_bonded(&p, config).await {
+            info!(
+                "跳过 {}：未配对 (not bonded)",
+                sanitize_device_name(properties.local_name.as_deref().unwrap_or_default())
+            );
+            skipped_unbonded = true;
+            continue;
+        }
+        if let Some(reason) = reason {
+            info!("{}", reason);
+        }
+        break Some(p);
+    };
+
+    if skipped_unbonded {
+        info!(
+            "scan_bonded_only 已开启：请先在系统中配对设备（Windows：设置 → 蓝牙和其他设备 → 添加设备；\
+             Linux：bluetoothctl 中执行 pair <MAC> 与 trust <MAC>；macOS：系统设置 → 蓝牙），\
+             或在 config.toml 中关闭 scan_bonded_only。"
+        );
+    }
+    match chosen_peripheral {
+        Some(p) => {
+            let props = p.properties().await?.unwrap_or_default();
+            let name = props
+                .local_name
+                .unwrap_or_else(|| "未知设备 Unknown Device".to_string());
+            let key = device_key(&p);
+            match devices::alias(&key) {
+                Some(alias) => info!(
+                    "选择设备: {} {:?} ({})",
+                    alias,
+                    sanitize_device_name(&name),
+                    key
+                ),
+                None => info!("选择设备: {:?} ({})", sanitize_device_name(&name), key),
+            }
+            Ok(p)
+        }
+        None => {
+            info!("未找到符合条件的设备。");
+            Err(AppError::DeviceNotFound)
+        }
+    }
+}
+
+/// 从候选设备中选出要连接的一个，附带需要打印的选择原因：附近排位最高的首选设备
+/// （devices use / preferred_device_order）最优先，其次是上次成功使用的设备
+/// （换设备后可用 --reset-cache 清除），最后才是选择模式。
+fn pick_candidate(
+    candidates: &[(Peripheral, PeripheralProperties)],
+    last_device: Option<&str>,
+    selector: &dyn DeviceSelector,
+) -> Option<(usize, Option<&'static str>)> {
+    let keys: Vec<String> = candidates.iter().map(|(p, _)| device_key(p)).collect();
+    let preferred = keys
+        .iter()
+        .enumerate()
+        .filter_map(|(index, key)| devices::preference_rank(key).map(|rank| (rank, index)))
+        .min();
+    if let Some((_, index)) = preferred {
+        return Some((index, Some("发现首选设备，优先连接。")));
+    }
+    if let Some(index) = keys
+        .iter()
+        .position(|key| last_device == Some(key.as_str()))
+    {
+        return Some((index, Some("发现上次使用的设备，优先连接。")));
+    }
+    let properties: Vec<PeripheralProperties> =
+        candidates.iter().map(|(_, props)| props.clone()).collect();
+    selector.select(&properties).map(|index| (index, None))
+}
+
+/// 按候选服务的优先级查找心率测量特征；候选服务下都没有时，
+/// 退回到任意服务下的 0x2A37（保持对服务 UUID 不规范设备的兼容）。
+fn find_hr_characteristic(
+    characteristics: &BTreeSet<Characteristic>,
+    service_uuids: &[Uuid],
+    char_uuids: &[Uuid],
+) -> Option<Characteristic> {
+    service_uuids
+        .iter()
+        .find_map(|service| {
+            characteristics
+                .iter()
+                .find(|c| c.service_uuid == *service && char_uuids.contains(&c.uuid))
+        })
+        .or_else(|| {
+            characteristics
+                .iter()
+                .find(|c| char_uuids.contains(&c.uuid))
+        })
+        .cloned()
+}
+
+/// 心率数据的获取方式。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HrSource {
+    /// 订阅 Notify，由设备推送
+    Notify,
+    /// 特征只支持 Indicate：同样订阅推送，只是每条数据需要确认（btleplug 自动处理）
+    Indicate,
+    /// 特征只支持 Read：按 poll_interval_ms 轮询读取（部分廉价手环）
+    Poll,
+}
+
+impl HrSource {
+    /// 是否通过订阅由设备推送数据。
+    fn is_subscribed(self) -> bool {
+        matches!(self, HrSource::Notify | HrSource::Indicate)
+    }
+}
+
+/// 按特征属性选择获取方式：Notify 优先（无需逐条确认），其次 Indicate，
+/// 再次轮询读取，都不支持时返回 None。
+fn hr_source(properties: CharPropFlags) -> Option<HrSource> {
+    if properties.contains(CharPropFlags::NOTIFY) {
+        Some(HrSource::Notify)
+    } else if properties.contains(CharPropFlags::INDICATE) {
+        Some(HrSource::Indicate)
+    } else if properties.contains(CharPropFlags::READ) {
+        Some(HrSource::Poll)
+    } else {
+        None
+    }
+}
+
+/// 一次等待心率数据的结果。
+enum Beat {
+    Value(Vec<u8>),
+    TimedOut,
+    /// 通知流关闭（例如设备主动优雅断连）
+    Closed,
+}
+
+/// 带心跳超时地等待 `future`，超时返回 `None`。传入 watchdog 时由系统定时器计时，否则用 tokio 的 timeout。
+async fn heartbeat_timeout<F: Future>(
+    watchdog: Option<&NativeWatchdog>,
+    duration: Duration,
+    future: F,
+) -> Option<F::Output> {
+    let Some(watchdog) = watchdog else {
+        return time::timeout(duration, future).await.ok();
+    };
+    let fired = watchdog.arm(duration);
+    // 发送端被丢弃（定时器出错）同样按超时处理
+    let output = tokio::select! {
+        output = future => Some(output),
+        _ = fired => None,
+    };
+    watchdog.disarm();
+    output
+}
+
+/// 轮询模式下等待下一次读数。读取失败按漏掉一次心跳处理，
+/// 距上次有效数据超过 heartbeat_timeout_secs 才视为超时。
+async fn poll_heart_rate(
+    device: &Peripheral,
+    hr_char: &Characteristic,
+    config: &Config,
+    last_beat: Instant,
+) -> Beat {
+    loop {
+        time::sleep(Duration::from_millis(config.poll_interval_ms)).await;
+        match ble_timeout("read", config.service_timeout_secs, device.read(hr_char)).await {
+            Ok(value) => return Beat::Value(value),
+            Err(_) if last_beat.elapsed() < Duration::from_secs(config.heartbeat_timeout_secs) => {}
+            Err(_) => return Beat::TimedOut,
+        }
+    }
+}
+
+/// 空闲模式下每隔 idle_check_secs 取一次读数：轮询模式直接读取一次；
+/// 订阅模式重新订阅并等待一条通知（读数仍为 0 时由调用方再次退订）。
+async fn idle_heart_rate<S>(
+    device: &Peripheral,
+    hr_char: &Characteristic,
+    source: HrSource,
+    notifications: &mut S,
+    config: &Config,
+    watchdog: Option<&NativeWatchdog>,
+) -> Beat
+where
+    S: Stream<Item = ValueNotification> + Unpin,
+{
+    time::sleep(Duration::from_secs(config.idle_check_secs)).await;
+    if !source.is_subscribed() {
+        return match ble_timeout("read", config.service_timeout_secs, device.read(hr_char)).await {
+            Ok(value) => Beat::Value(value),
+            Err(_) => Beat::TimedOut,
+        };
+    }
+
+    if ble_timeout(
+        "subscribe",
+        config.service_timeout_secs,
+        device.subscribe(hr_char),
+    )
+    .await
+    .is_err()
+    {
+        return Beat::TimedOut;
+    }
+    let next_value = async {
+        while let Some(notification) = notifications.next().await {
+            if notification.uuid == hr_char.uuid {
+                return Beat::Value(notification.value);
+            }
+        }
+        Beat::Closed
+    };
+    heartbeat_timeout(
+        watchdog,
+        Duration::from_secs(config.heartbeat_timeout_secs),
+        next_value,
+    )
+    .await
+    .unwrap_or(Beat::TimedOut)
+}
+
+/// 发现服务、查找心率特征，支持通知时订阅，返回特征及其获取方式。
+async fn subscribe_heart_rate(
+    device: &Peripheral,
+    config: &Config,
+) -> Result<(Characteristic, HrSource)> {
+    ble_timeout(
+        "discover_services",
+        config.service_timeout_secs,
+        device.discover_services(),
+    )
+    .await?;
+
+    let hr_char = find_hr_characteristic(
+        &device.characteristics(),
+        &hr_service_uuids(config),
+        &hr_char_uuids(config),
+    )
+    .ok_or(AppError::CharacteristicNotFound)?;
+
+    let Some(source) = hr_source(hr_char.properties) else {
+        error!("心率特征既不支持通知 (Notify/Indicate)，也不支持读取。");
+        return Err(AppError::SubscriptionFailed);
+    };
+
+    // Notify 和 Indicate 都可以订阅（btleplug 会自动选择正确的 CCCD 值）
+    if source.is_subscribed() {
+        ble_timeout(
+            "subscribe",
+            config.service_timeout_secs,
+            device.subscribe(&hr_char),
+        )
+        .await?;
+    }
+    send_start_command(device, config).await;
+    Ok((hr_char, source))
+}
+
+/// 解析十六进制字节串（允许空格分隔，如 "01" 或 "15 01 00"），格式错误时返回 None。
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    let digits: String = hex.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.len() % 2 != 0 {
+        return None;
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(digits.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// 把 auth_key 解析为 16 字节 AES 密钥（允许带 0x 前缀），格式不对时返回 None。
+fn auth_key_bytes(key: &str) -> Option<[u8; 16]> {
+    let key = key.trim();
+    let key = key.strip_prefix("0x").unwrap_or(key);
+    parse_hex(key)?.try_into().ok()
+}
+
+/// 部分华为/荣耀手环要先向心率控制点（或私有特征）写入命令才开始推送心率。
+/// 设备没有该特征、特征不可写或未配置命令时静默跳过；写入失败只提示，不影响连接。
+async fn send_start_command(device: &Peripheral, config: &Config) {
+    let command = parse_hex(&config.start_command_hex).unwrap_or_default();
+    if command.is_empty() {
+        return;
+    }
+    let Some(characteristic) = device
+        .characteristics()
+        .into_iter()
+        .find(|c| c.uuid == config.start_command_char_uuid)
+    else {
+        return;
+    };
+    let write_type = if characteristic.properties.contains(CharPropFlags::WRITE) {
+        WriteType::WithResponse
+    } else if characteristic
+        .properties
+        .contains(CharPropFlags::WRITE_WITHOUT_RESPONSE)
+    {
+        WriteType::WithoutResponse
+    } else {
+        return;
+    };
+
+    match ble_timeout(
+        "write",
+        config.service_timeout_secs,
+        device.write(&characteristic, &command, write_type),
+    )
+    .await
+    {
+        Ok(()) => info!(
+            "已向特征 {} 写入启动命令 {}",
+            characteristic.uuid,
+            config.start_command_hex.trim()
+        ),
+        Err(e) => warn!("写入启动命令失败（忽略）: {}", e),
+    }
+}
+
+/// 离开一次连接时（包括出错提前返回）负责退订并断开的收尾守卫。
+/// Drop 中无法 await，因此正常路径显式调用 `teardown()`；若 future 被中途取消
+/// （例如收到退出信号），Drop 会把同样的清理交给后台任务尽力完成。
+struct ConnectionGuard {
+    device: Peripheral,
+    subscribed: Vec<Characteristic>,
+    armed: bool,
+}
+
+impl ConnectionGuard {
+    fn new(device: &Peripheral) -> Self {
+        ConnectionGuard {
+            device: device.clone(),
+            subscribed: Vec::new(),
+            armed: true,
+        }
+    }
+
+    /// 记录已订阅的特征，收尾时先退订再断开。
+    fn subscribed(&mut self, characteristic: &Characteristic) {
+        self.subscribed.push(characteristic.clone());
+    }
+
+    /// 本次连接订阅过的特征 UUID。
+    fn subscribed_uuids(&self) -> HashSet<Uuid> {
+        self.subscribed.iter().map(|c| c.uuid).collect()
+    }
+
+    async fn teardown(mut self) {
+        self.armed = false;
+        teardown_connection(&self.device, mem::take(&mut self.subscribed)).await;
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            let device = self.device.clone();
+            let subscribed = mem::take(&mut self.subscribed);
+            handle.spawn(async move { teardown_connection(&device, subscribed).await });
+        }
+    }
+}
+
+/// 限时退订并断开，然后确认链路确实已断开：BlueZ 上遗留的连接会让下一次
+/// connect 失败，或让手环拒绝其他客户端。
+async fn teardown_connection(device: &Peripheral, subscribed: Vec<Characteristic>) {
+    let limit = Duration::from_secs(TEARDOWN_TIMEOUT_SECS);
+    for characteristic in subscribed {
+        let _ = time::timeout(limit, device.unsubscribe(&characteristic)).await;
+    }
+    let _ = time::timeout(limit, device.disconnect()).await;
+
+    for _ in 0..TEARDOWN_VERIFY_ATTEMPTS {
+        if !matches!(
+            time::timeout(limit, device.is_connected()).await,
+            Ok(Ok(true))
+        ) {
+            return;
+        }
+        time::sleep(Duration::from_millis(500)).await;
+        let _ = time::timeout(limit, device.disconnect()).await;
+    }
+    warn!(
+        "设备 {} 断开后仍报告为已连接，下一次连接可能失败。",
+        device.address()
+    );
+}
+
+/// 处理设备连接的整个生命周期。
+/// 返回 Ok(true) 表示本次连接期间至少收到过一次心率数据。
+/// 无论正常结束还是出错提前返回，都会经 `ConnectionGuard` 退订并断开，
+/// 确保下一轮能重新走完整的 connect/subscribe 流程，
+/// 避免链路残留导致"看似在重连、实际永不重订阅"的死循环。
+async fn handle_device_connection(
+    device: &Peripheral,
+    events: &source::Events,
+    osc_addr: SocketAddr,
+    config: &Config,
+    device_info: &mut Option<DeviceInfo>,
+) -> Result<bool> {
+    // 本次连接期间的日志都带上设备地址和连接耗时
+    let span = info_span!(
+        "connection",
+        address = %device.address(),
+        alias = tracing::field::Empty,
+        connect_ms = tracing::field::Empty
+    );
+    if let Some(alias) = devices::alias(&device_key(device)) {
+        span.record("alias", alias);
+    }
+    async {
+        let mut guard = ConnectionGuard::new(device);
+        let result =
+            run_connection(device, &mut guard, events, osc_addr, config, device_info).await;
+        guard.teardown().await;
+        result
+    }
+    .instrument(span)
+    .await
+}
+
+/// 连接、订阅并持续接收心率通知，直到超时、流关闭或出错。
+async fn run_connection(
+    device: &Peripheral,
+    guard: &mut ConnectionGuard,
+    events: &source::Events,
+    osc_addr: SocketAddr,
+    config: &Config,
+    device_info: &mut Option<DeviceInfo>,
+) -> Result<bool> {
+    // is_connected 查询失败时视为未连接，直接尝试 connect；
+    // connect 超时留下的"半连接"状态由 ConnectionGuard 收尾时断开
+    let connect_start = Instant::now();
+    if !device.is_connected().await.unwrap_or(false) {
+        info!("正在连接设备 {}...", device.address());
+        ble_timeout("connect", config.connect_timeout_secs, device.connect())
+            .await
+            .with_context(|| format!("连接设备 {}", device.address()))?;
+    }
+    let connect_ms = connect_start.elapsed().as_millis() as u64;
+    tracing::Span::current().record("connect_ms", connect_ms);
+    info!("设备连接成功（耗时 {} ms）！正在监听心率...", connect_ms);
+    recorder::event(recorder::Event::Connected(device.address().to_string()));
+    info!("正在向 OSC 地址 {} 发送数据", osc_addr);
+
+    // 小米设备：配置了认证密钥时先完成认证握手；未配置时订阅失败会提示需要密钥
+    let props = device.properties().await.ok().flatten().unwrap_or_default();
+    let xiaomi = mi_auth::is_xiaomi(props.local_name.as_deref(), &props.services);
+    if let Some(key) = config.auth_key.as_deref().and_then(auth_key_bytes) {
+        if xiaomi {
+            ble_timeout(
+                "discover_services",
+                config.service_timeout_secs,
+                device.discover_services(),
+            )
+            .await
+            .context("小米设备认证前发现服务")?;
+            if mi_auth::authenticate(device, &key, config)
+                .await
+                .context("小米设备认证")?
+            {
+                info!("小米设备认证完成。");
+            } else {
+                info!("该小米设备没有旧版认证特征，跳过认证（auth_key 只用于 Mi Band 2–4 等旧型号）。");
+            }
+        }
+    }
+
+    // 部分设备（如华为手表）连接后第一次 discover_services 返回的特征列表不完整，
+    // 先在同一连接上重试几次，仍失败再交给 main_loop 走断开/重扫流程
+    let mut attempt: u32 = 0;
+    let (hr_char, source) = loop {
+        match subscribe_heart_rate(device, config).await {
+            Ok(found) => break found,
+            Err(
+                e @ (AppError::CharacteristicNotFound
+                | AppError::SubscriptionFailed
+                | AppError::Btleplug(_)),
+            ) if attempt < config.subscribe_retries => {
+                attempt += 1;
+                info!(
+                    "发现服务/订阅失败: {}，{} 秒后重试（第 {}/{} 次）...",
+                    e, SUBSCRIBE_RETRY_DELAY_SECS, attempt, config.subscribe_retries
+                );
+                time::sleep(Duration::from_secs(SUBSCRIBE_RETRY_DELAY_SECS)).await;
+            }
+            Err(
+                e @ (AppError::CharacteristicNotFound
+                | AppError::SubscriptionFailed
+                | AppError::Timeout { .. }),
+            ) if xiaomi => {
+                return Err(mi_auth::setup_error(
+                    mi_auth::has_auth_characteristic(device),
+                    config.auth_key.is_some(),
+                )
+                .unwrap_or(e))
+            }
+            Err(e) => return Err(e),
+        }
+    };
+    if source.is_subscribed() {
+        guard.subscribed(&hr_char);
+    }
+    let spo2_char = if config.spo2_enabled {
+        subscribe_optional(
+            device,
+            config,
+            SPO2_CHAR_UUID,
+            "血氧特征 (0x2A5F)",
+            "hr_spo2",
+        )
+        .await
+    } else {
+        None
+    };
+    if let Some(characteristic) = &spo2_char {
+        guard.subscribed(characteristic);
+    }
+    let step_char = if config.cadence_enabled {
+        subscribe_optional(
+            device,
+            config,
+            config.step_count_char_uuid,
+            "步数特征",
+            "hr_cadence_rpm",
+        )
+        .await
+    } else {
+        None
+    };
+    if let Some(characteristic) = &step_char {
+        guard.subscribed(characteristic);
+    }
+    let pmd = if config.polar_acc_enabled {
+        polar_pmd::start(device, config).await
+    } else {
+        None
+    };
+    if let Some(pmd) = &pmd {
+        guard.subscribed(&pmd.control);
+        guard.subscribed(&pmd.data);
+    }
+    // 通知流包含设备上所有特征的通知：部分设备还会在同一流中推送厂商特征，
+    // 系统也可能保留之前会话的订阅。只解析本次订阅过的特征
+    let subscribed_uuids = guard.subscribed_uuids();
+
+    // 设备信息只在首次连接该设备时读取并打印，断线重连不再重复
+    if device_info.is_none() {
+        let info = read_device_info(device, config).await;
+        if !info.is_empty() {
+            info!("设备信息: {}", info);
+        }
+        *device_info = Some(info);
+    }
+
+    let format = config
+        .hr_char_formats
+        .get(&hr_char.uuid)
+        .copied()
+        .unwrap_or_default();
+    info!(
+        "使用心率特征 {}（服务 {}，数据格式 {}）",
+        hr_char.uuid, hr_char.service_uuid, format
+    );
+
+    let mut notification_stream = device.notifications().await.context("获取通知流")?;
+    match source {
+        HrSource::Notify => info!("已成功订阅心率通知 (Notify)。等待数据..."),
+        HrSource::Indicate => {
+            info!("心率特征不支持 Notify，改用 Indicate 订阅。已成功订阅心率指示，等待数据...")
+        }
+        HrSource::Poll => info!(
+            "心率特征不支持通知，改为每 {} 毫秒轮询读取。等待数据...",
+            config.poll_interval_ms
+        ),
+    }
+
+    let mut received_any = false;
+    // 本轮静默是否已尝试过重新订阅（收到数据后复位）
+    let mut resubscribed = false;
+    let mut last_beat = Instant::now();
+    let mut connected = source::SourceDevice {
+        name: props.local_name.as_deref().map(sanitize_device_name),
+        address: Some(device.address().to_string()),
+        battery: None,
+        manufacturer: device_info
+            .as_ref()
+            .and_then(|info| info.manufacturer.clone()),
+        model: device_info.as_ref().and_then(|info| info.model.clone()),
+        firmware: device_info.as_ref().and_then(|info| info.firmware.clone()),
+        rssi: config.rssi_poll_secs > 0,
+        span: Some(tracing::Span::current()),
+    };
+    // 电量只在连接时读一次，状态输出、Discord 和头显内的低电量提醒共用
+    if status_enabled() || discord::is_enabled() || vr_notify::is_enabled() {
+        let battery = read_battery_level(device, config).await;
+        if let Some(level) = battery {
+            let address = device.address().to_string();
+            discord::battery(&address, level);
+            vr_notify::battery(connected.name.as_deref().unwrap_or(&address), level);
+        }
+        connected.battery = battery;
+    }
+    events.send(source::SourceEvent::Connected(connected));
+    let mut last_rssi_poll: Option<Instant> = None;
+    let mut deduper = NotificationDeduper::default();
+    let mut backlog = NotificationBacklog::default();
+    let dedupe_window = Duration::from_millis(config.notification_dedupe_ms);
+    let mut idle = IdleTracker::default();
+    let watchdog = if config.use_native_watchdog {
+        match NativeWatchdog::new() {
+            Ok(watchdog) => Some(watchdog),
+            Err(e) => {
+                warn!("无法创建系统定时器（{}），心跳超时改用异步计时。", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // 使用 `loop` 和心跳超时（heartbeat_timeout）来实现带超时的事件接收
+    loop {
+        let beat = if idle.idle {
+            idle_heart_rate(
+                device,
+                &hr_char,
+                source,
+                &mut notification_stream,
+                config,
+                watchdog.as_ref(),
+            )
+            .await
+        } else {
+            match source {
+                HrSource::Notify | HrSource::Indicate => match heartbeat_timeout(
+                    watchdog.as_ref(),
+                    Duration::from_secs(config.heartbeat_timeout_secs),
+                    backlog.next(
+                        &mut notification_stream,
+                        hr_char.uuid,
+                        config.max_notification_queue_depth,
+                    ),
+                )
+                .await
+                {
+                    None => Beat::TimedOut,
+                    Some(Some(notification)) if !subscribed_uuids.contains(&notification.uuid) => {
+                        trace!(
+                            "忽略未订阅特征 {} 的通知: {:02x?}",
+                            notification.uuid,
+                            notification.value
+                        );
+                        continue;
+                    }
+                    Some(Some(notification)) if notification.uuid == hr_char.uuid => {
+                        Beat::Value(notification.value)
+                    }
+                    // 血氧随下一次心率一起发送，不算作心跳
+                    Some(Some(notification))
+                        if spo2_char
+                            .as_ref()
+                            .is_some_and(|c| c.uuid == notification.uuid) =>
+                    {
+                        if let Some(spo2) = parse_spo2(&notification.value) {
+                            events.send(source::SourceEvent::Spo2(spo2));
+                        }
+                        continue;
+                    }
+                    Some(Some(notification))
+                        if step_char
+                            .as_ref()
+                            .is_some_and(|c| c.uuid == notification.uuid) =>
+                    {
+                        if let Some(steps) = parse_step_count(&notification.value) {
+                            events.send(source::SourceEvent::Steps(steps, Instant::now()));
+                        }
+                        continue;
+                    }
+                    // 运动强度同样随下一次心率一起发送
+                    Some(Some(notification))
+                        if pmd
+                            .as_ref()
+                            .is_some_and(|p| p.data.uuid == notification.uuid) =>
+                    {
+                        if let Some(movement) =
+                            polar_pmd::parse_movement(&notification.value, config)
+                        {
+                            events.send(source::SourceEvent::Movement(movement, Instant::now()));
+                        }
+                        continue;
+                    }
+                    Some(Some(notification))
+                        if pmd
+                            .as_ref()
+                            .is_some_and(|p| p.control.uuid == notification.uuid) =>
+                    {
+                        polar_pmd::check_response(&notification.value);
+                        continue;
+                    }
+                    Some(Some(_)) => continue,
+                    Some(None) => Beat::Closed,
+                },
+                HrSource::Poll => poll_heart_rate(device, &hr_char, config, last_beat).await,
+            }
+        };
+
+        match beat {
+            // Case 1: 超时发生
+            Beat::TimedOut => {
+                // 链路仍在但设备不再推送（常见于手机 App 抢占了特征）时，
+                // 先原地重新订阅一次、再给一个超时窗口，避免走完整的断开重连
+                if source.is_subscribed()
+                    && !resubscribed
+                    && device.is_connected().await.unwrap_or(false)
+                {
+                    info!(
+                        "未在 {} 秒内收到心率数据，但设备仍处于连接状态，尝试重新订阅...",
+                        config.heartbeat_timeout_secs
+                    );
+                    resubscribed = true;
+                    let attempts = SOFT_RESUBSCRIBE_ATTEMPTS.fetch_add(1, Ordering::Relaxed) + 1;
+                    let _ = time::timeout(
+                        Duration::from_secs(config.service_timeout_secs),
+                        device.unsubscribe(&hr_char),
+                    )
+                    .await;
+                    match ble_timeout(
+                        "subscribe",
+                        config.service_timeout_secs,
+                        device.subscribe(&hr_char),
+                    )
+                    .await
+                    {
+                        Ok(()) => {
+                            send_start_command(device, config).await;
+                            info!("已重新订阅（本次运行第 {} 次），等待数据...", attempts);
+                            continue;
+                        }
+                        Err(e) => warn!("重新订阅失败: {}", e),
+                    }
+                }
+                warn!(
+                    "未在 {} 秒内收到心率数据，认为连接已断开。",
+                    config.heartbeat_timeout_secs
+                );
+                if xiaomi && !received_any {
+                    if let Some(e) = mi_auth::setup_error(
+                        mi_auth::has_auth_characteristic(device),
+                        config.auth_key.is_some(),
+                    ) {
+                        warn!("{}", e);
+                    }
+                }
+                break;
+            }
+            // Case 2: 成功接收到数据
+            Beat::Value(value) => {
+                let now = Instant::now();
+                // 轮询读到相同的值是正常的，只合并通知/指示的突发重复
+                if source.is_subscribed() && !deduper.accept(&value, now, dedupe_window) {
+                    debug!("已合并重复通知（本次连接共 {} 条）", deduper.suppressed);
+                    continue;
+                }
+                let Some(measurement) = parse_payload(&value, format) else {
+                    continue;
+                };
+                last_beat = now;
+                received_any = true;
+                serial_source::ble_reading(now);
+                match idle.update(measurement.heart_rate, config) {
+                    Some(true) => {
+                        info!(
+                            "连续 {} 次读数为 0，进入空闲模式 (idle，设备未佩戴？)：暂停发送 OSC 与写入文件，每 {} 秒检查一次。",
+                            config.idle_after_zero_readings, config.idle_check_secs
+                        );
+                        // 最后发送一次未连接状态并清零文件，之后保持静默
+                        events.send(source::SourceEvent::Idle);
+                        recorder::event(recorder::Event::Idle);
+                    }
+                    Some(false) => {
+                        info!("检测到心率，退出空闲模式，恢复正常发送。");
+                        recorder::event(recorder::Event::Active);
+                    }
+                    None => {}
+                }
+                if idle.idle {
+                    // 退订以降低设备的推送频率和耗电，下次检查时再订阅
+                    if source.is_subscribed() {
+                        let _ = ble_timeout(
+                            "unsubscribe",
+                            config.service_timeout_secs,
+                            device.unsubscribe(&hr_char),
+                        )
+                        .await;
+                    }
+                    console_display::status(
+                        config.console_display,
+                        &format!(
+                            "状态 -> 空闲 (idle，设备未佩戴？)，每 {} 秒检查一次",
+                            config.idle_check_secs
+                        ),
+                    );
+                    continue;
+                }
+                if resubscribed {
+                    resubscribed = false;
+                    let recovered = SOFT_RESUBSCRIBE_SUCCESSES.fetch_add(1, Ordering::Relaxed) + 1;
+                    info!(
+                        "重新订阅后已恢复接收心率数据（软恢复成功 {}/{} 次）。",
+                        recovered,
+                        SOFT_RESUBSCRIBE_ATTEMPTS.load(Ordering::Relaxed)
+                    );
+                }
+                // RSSI 随心率数据一起读取：没有数据时由心跳超时负责判断断开
+                if config.rssi_poll_secs > 0
+                    && last_rssi_poll
+                        .is_none_or(|t| now.duration_since(t).as_secs() >= config.rssi_poll_secs)
+                {
+                    last_rssi_poll = Some(now);
+                    events.send(source::SourceEvent::Rssi(read_rssi(device, config).await));
+                }
+                events.send(source::SourceEvent::Sample(source::HeartRateSample {
+                    measurement,
+                    timestamp: now,
+                }));
+            }
+            // Case 3: 通知流正常关闭 (例如设备主动优雅断连)
+            Beat::Closed => {
+                info!("通知流已关闭。");
+                break;
+            }
+        }
+    }
+
+    Ok(received_any)
+}
+
+// --- 心率输出 ---
+
+/// 一个输出文件：内容变化时才提交给写入线程（fs::write 每次都是完整的打开/截断/写/关闭，
+/// 还可能触发杀毒软件实时扫描，是本程序最重的单个动作）。写入错误由 file_writer 处理和提示。
+struct OutputFile {
+    path: PathBuf,
+    last_written: Option<String>,
+}
+
+impl OutputFile {
+    fn new(path: PathBuf) -> Self {
+        OutputFile {
+            path,
+            last_written: None,
+        }
+    }
+
+    fn write(&mut self, content: String) {
+        if self.last_written.as_ref() == Some(&content) {
+            return;
+        }
+        file_writer::submit(self.path.clone(), content.clone());
+        self.last_written = Some(content);
+    }
+}
+
+/// 把每一次心率读数送往各个输出（HeartRate.txt、单值文件、报警、统计、OSC），
+/// 并保存这些输出在两次读数之间需要的状态。每次连接（或每个广播会话）新建一个。
+struct HeartRateSink<'a> {
+    socket: &'a UdpSocket,
+    osc_addr: SocketAddr,
+    config: &'a Config,
+    hr_file: OutputFile,
+    /// 已开启的单值文件
+    split_files: Vec<(HrOutput, OutputFile)>,
+    // 错误只提示一次，恢复后重置（避免 VRChat 未启动时每秒刷屏）
+    osc_error_shown: bool,
+    alarm: HrAlarm,
+    steady: SteadyStateDetector,
+    hrv: HrvCalculator,
+    stats: PeriodicStats,
+    last_stats_flush: Instant,
+    /// 最近的非 0 原始心率（传给心率变换插件，也用于文件模板的 `{avg}`）
+    history: Vec<u8>,
+    /// 连接期间的 RSSI 监测；仅广播模式或未启用时为 None，状态行不显示信号
+    signal: Option<SignalMonitor>,
+    /// 本次连接的统计（status.json 的 session）
+    session: PeriodicStats,
+    /// 本次连接累计的训练负荷（hr_trimp 与 status.json 的 session.trimp）
+    training_load: TrainingLoad,
+    /// 最近一次写入 status.json 的内容；设备名、地址、电量由创建者填入
+    status: status_file::Status,
+    /// 最近一次收到的血氧饱和度，由接收循环更新
+    spo2: Option<u8>,
+    cadence: CadenceEstimator,
+    /// 最近一次估算的步频及其时间，由接收循环更新
+    cadence_rpm: Option<(u8, Instant)>,
+    /// 最近一帧加速度数据的运动强度及其时间，由接收循环更新
+    movement: Option<(f32, Instant)>,
+    dedup: ReadingDeduper,
+    /// 创建时间，即本次连接开始的时间（脚本的 ctx.elapsed）
+    connected_at: Instant,
+}
+
+impl<'a> HeartRateSink<'a> {
+    fn new(
+        socket: &'a UdpSocket,
+        osc_addr: SocketAddr,
+        config: &'a Config,
+        hr_file: &'a Path,
+    ) -> Self {
+        HeartRateSink {
+            socket,
+            osc_addr,
+            config,
+            hr_file: OutputFile::new(hr_file.to_path_buf()),
+            split_files: split_files(hr_file, config)
+                .into_iter()
+                .map(|(output, path)| (output, OutputFile::new(path)))
+                .collect(),
+            osc_error_shown: false,
+            alarm: HrAlarm::default(),
+            steady: SteadyStateDetector::default(),
+            hrv: HrvCalculator::default(),
+            stats: PeriodicStats::default(),
+            last_stats_flush: Instant::now(),
+            history: Vec::new(),
+            signal: None,
+            session: PeriodicStats::default(),
+            training_load: TrainingLoad::default(),
+            status: status_file::Status::default(),
+            spo2: None,
+            cadence: CadenceEstimator::default(),
+            cadence_rpm: None,
+            movement: None,
+            dedup: ReadingDeduper::default(),
+            connected_at: Instant::now(),
+        }
+    }
+
+    /// 收到新的累计步数（接收循环调用），更新步频，随下一次心率一起发送。
+    fn steps(&mut self, steps: u32, now: Instant) {
+        if let Some(rpm) = self.cadence.update(steps, now) {
+            self.cadence_rpm = Some((rpm, now));
+        }
+    }
+
+    /// 连接期间需要清零时（进入空闲模式）调用：与断开时共用 `clear_state`，
+    /// 并让文件与 status.json 的去重缓存失效，恢复读数后即使数值与清零前相同也会重新写入，
+    /// 避免 OSC 已恢复而文件仍停留在离线内容。
+    fn clear(&mut self) {
+        clear_state(self.socket, self.osc_addr, self.config, &self.hr_file.path);
+        self.hr_file.last_written = None;
+        for (_, file) in &mut self.split_files {
+            file.last_written = None;
+        }
+        self.status.connected = false;
+        self.dedup.last_sent = None;
+    }
+
+    /// 把读数交给自定义脚本并发送脚本排队的 OSC 消息；未设置 script_path 时什么也不做。
+    fn run_script(&self, heart_rate: u8, rr_intervals: &[u16], connected: bool, now: Instant) {
+        let session = self.session.snapshot();
+        let messages = script::reading(&script::Reading {
+            hr: heart_rate,
+            rr_ms: rr_intervals
+                .iter()
+                .map(|&rr| f32::from(rr) * 1000.0 / 1024.0)
+                .collect(),
+            connected,
+            elapsed_secs: now.duration_since(self.connected_at).as_secs_f64(),
+            session: script::Session {
+                min: session.min,
+                max: session.max,
+                avg: session.mean,
+                samples: session.samples,
+            },
+        });
+        if messages.is_empty() {
+            return;
+        }
+        if let Err(e) = send_osc_messages(self.socket, self.osc_addr, messages, self.config) {
+            debug!("发送脚本的 OSC 消息时出错: {}", e);
+        }
+    }
+
+    fn handle(&mut self, measurement: &HeartRateMeasurement, now: Instant) {
+        let config = self.config;
+        for rr in &measurement.rr_intervals {
+            self.hrv.push(*rr, now);
+        }
+        let heart_rate_u8 = measurement.heart_rate.min(255) as u8;
+        recorder::reading(&recorder::Reading {
+            bpm: heart_rate_u8,
+            rr_intervals: &measurement.rr_intervals,
+            rssi: self.signal.as_ref().and_then(|signal| signal.rssi),
+            energy_kj: measurement.energy_expended,
+        });
+        http_api::record(heart_rate_u8);
+        haptics::reading(heart_rate_u8, &measurement.rr_intervals);
+        let skip = self.dedup.skip(heart_rate_u8, now, config);
+
+        self.stats.update(heart_rate_u8);
+        self.session.update(heart_rate_u8);
+        auto_gain::record(heart_rate_u8, now);
+        self.training_load
+            .update(heart_rate_u8, now, config.max_heart_rate());
+        if config.stats_interval_secs > 0
+            && now.duration_since(self.last_stats_flush).as_secs() >= config.stats_interval_secs
+        {
+            self.last_stats_flush = now;
+            let snapshot = self.stats.flush();
+            let skipped = std::mem::take(&mut self.dedup.skipped);
+            // 未佩戴的整个周期不打印
+            if snapshot.samples > 0 {
+                let dedup = if config.dedup_identical_readings {
+                    format!(
+                        " deduplicated={:.1}/min",
+                        f64::from(skipped) * 60.0 / config.stats_interval_secs as f64
+                    )
+                } else {
+                    String::new()
+                };
+                info!(
+                    "[{}] {}s stats: min={} max={} mean={:.0} samples={}{}",
+                    chrono::Local::now().format("%H:%M:%S"),
+                    config.stats_interval_secs,
+                    snapshot.min,
+                    snapshot.max,
+                    snapshot.mean,
+                    snapshot.samples,
+                    dedup
+                );
+            }
+        }
+
+        if !skip && (config.write_heart_rate_file || obs::is_enabled()) {
+            let content = heart_rate_file_content(heart_rate_u8, &self.history, config);
+            obs::publish(&content);
+            if config.write_heart_rate_file {
+                self.hr_file.write(content);
+            }
+        }
+        if discord_presence::is_enabled() {
+            discord_presence::publish(
+                (heart_rate_u8 > 0).then(|| template_values(heart_rate_u8, &self.history, config)),
+            );
+        }
+        if !skip {
+            for (output, file) in &mut self.split_files {
+                file.write(output.file_value(heart_rate_u8, config));
+            }
+        }
+
+        if let Some(kind) = self.alarm.update(heart_rate_u8, now, config) {
+            let direction = match kind {
+                AlarmKind::High => "高于",
+                AlarmKind::Low => "低于",
+            };
+            warn!(
+                "心率报警：当前心率 {} {}设定阈值！",
+                heart_rate_u8, direction
+            );
+            play_alarm_sound();
+            vr_notify::alarm(kind, heart_rate_u8);
+        }
+        if config.steady_state_mute {
+            match self.steady.update(heart_rate_u8, now, config) {
+                Some(true) => {
+                    info!("心率已平稳一段时间，进入静息状态（isHRActive 暂时发送 false）。")
+                }
+                Some(false) => info!("心率波动恢复，退出静息状态。"),
+                None => {}
+            }
+        }
+        let extras = OscExtras {
+            alarm: self.alarm.is_active(),
+            steady: self.steady.is_active(),
+            rtt_ms: osc_feedback::rtt_ms(),
+            stress: self
+                .hrv
+                .stress_index()
+                .map(|si| (si / config.max_stress_index).min(1.0)),
+            trimp: Some((self.training_load.total / config.max_session_trimp).min(1.0)),
+            signal: self
+                .signal
+                .as_ref()
+                .filter(|_| config.osc_signal_quality)
+                .and_then(SignalMonitor::quality),
+            spo2: self.spo2,
+            sdnn_ms: self.hrv.sdnn_ms(),
+            rr_ms: measurement
+                .rr_intervals
+                .last()
+                .map(|&rr| f32::from(rr) * 1000.0 / 1024.0),
+            avg_bpm: Some(self.session.snapshot())
+                .filter(|session| session.samples > 0)
+                .map(|session| session.mean),
+            battery: self.status.battery,
+            // 步数只在变化时推送，超过窗口没有新步数即视为停止
+            cadence_rpm: self.cadence_rpm.map(|(rpm, at)| {
+                if now.duration_since(at) > CADENCE_WINDOW {
+                    0
+                } else {
+                    rpm
+                }
+            }),
+            movement: self.movement.map(|(movement, at)| {
+                if now.duration_since(at) > MOVEMENT_TIMEOUT {
+                    0.0
+                } else {
+                    movement
+                }
+            }),
+        };
+
+        // 与 HeartRate.txt 一样，内容变化时才写
+        if status_enabled() {
+            let session = self.session.snapshot();
+            let has_samples = session.samples > 0;
+            let status = status_file::Status {
+                bpm: heart_rate_u8,
+                percent: OscValues::new(heart_rate_u8, config).percent,
+                connected: true,
+                rssi: self.signal.as_ref().and_then(|signal| signal.rssi),
+                session: status_file::Session {
+                    min: has_samples.then_some(session.min),
+                    max: has_samples.then_some(session.max),
+                    avg: has_samples.then_some(session.mean),
+                    trimp: has_samples.then_some(self.training_load.total),
+                },
+                ..self.status.clone()
+            };
+            if status != self.status {
+                publish_status(&status);
+                self.status = status;
+            }
+        }
+
+        // 插件只变换发往 VRChat 的心率；0（未佩戴）原样发送
+        let osc_hr = if heart_rate_u8 > 0 {
+            let transformed = plugin::transform(heart_rate_u8, &self.history);
+            if self.history.len() == plugin::HISTORY_LEN {
+                self.history.remove(0);
+            }
+            self.history.push(heart_rate_u8);
+            transformed
+        } else {
+            0
+        };
+
+        // 新的真实读数到达，停止断线保持
+        ghost::cancel();
+        ghost::record(osc_hr);
+        self.run_script(heart_rate_u8, &measurement.rr_intervals, true, now);
+        if skip {
+            return;
+        }
+        let sent = match config.send_mode {
+            SendMode::OnNotification => {
+                send_osc(self.socket, self.osc_addr, osc_hr, extras, config)
+            }
+            SendMode::Scheduled => Ok(scheduler::update(osc_hr, extras, config)),
+        };
+        match sent {
+            Ok(vrc_status) => {
+                self.osc_error_shown = false;
+                let v = OscValues::new(osc_hr, config);
+                console_display::heart_rate(
+                    config.console_display,
+                    osc_hr,
+                    v.percent,
+                    template::zone(osc_hr, v.max_hr),
+                    || match &self.signal {
+                        Some(signal) => format!("状态 -> {}  RSSI: {}", vrc_status, signal),
+                        None => format!("状态 -> {}", vrc_status),
+                    },
+                );
+            }
+            Err(e) => {
+                if !self.osc_error_shown {
+                    warn!(
+                        "发送 OSC 数据时出错: {}（将继续重试，恢复前不再重复提示）",
+                        e
+                    );
+                    self.osc_error_shown = true;
+                }
+            }
+        }
+    }
+}
+
+// --- 主应用程序逻辑 ---
+async fn main_loop(
+    config: &Config,
+    osc_addr: SocketAddr,
+    hr_file: &Path,
+    cache_file: &Path,
+) -> Result<()> {
+    // 一次性创建 UDP 套接字并复用（用 send_to 发送）
+    let socket = osc_socket(config, osc_addr)
+        .with_context(|| format!("创建发送到 {} 的 OSC 套接字", osc_addr))?;
+    match config.osc_local_ip {
+        Some(local_ip) => info!(
+            "OSC Socket 已创建（经由本机地址 {}），将发送到 {}",
+            local_ip, osc_addr
+        ),
+        None => info!("OSC Socket 已创建，将发送到 {}", osc_addr),
+    }
+    if config.send_mode == SendMode::Scheduled {
+        scheduler::start(&socket, osc_addr, config);
+        info!("将以 {} Hz 定时发送 OSC 数据", config.send_rate_hz);
+    }
+    if config.osc_proxy_enabled {
+        let listen = SocketAddrV4::new(config.osc_proxy_listen_ip, config.osc_proxy_listen_port);
+        match osc_proxy::start(&socket, listen, osc_addr) {
+            Ok(local) => info!(
+                "OSC 代理已启动：{} 收到的数据包将原样转发到 {}",
+                local, osc_addr
+            ),
+            Err(e) => warn!(
+                "无法启动 OSC 代理 {}（{}），请检查端口是否被占用。",
+                listen, e
+            ),
+        }
+    }
+
+    let primary = async {
+        let mut source: Box<dyn source::HeartRateSource> = match config.source {
+            SourceKind::Pulsoid => {
+                info!("心率来源: Pulsoid");
+                Box::new(pulsoid::PulsoidSource { config })
+            }
+            SourceKind::Ble => {
+                let (manager, central) = acquire_adapter().await?;
+                if config.broadcast_mode {
+                    return broadcast::run(manager, central, &socket, osc_addr, config, hr_file)
+                        .await;
+                }
+                Box::new(BleSource {
+                    manager,
+                    central,
+                    osc_addr,
+                    config,
+                    cache_file,
+                })
+            }
+        };
+        source::run(source.as_mut(), &socket, osc_addr, config, hr_file).await
+    };
+    if !config.serial_fallback_enabled {
+        return primary.await;
+    }
+    // 串口备用源与主心率源并行运行，主心率源长时间没有数据时接替
+    tokio::select! {
+        result = primary => result,
+        never = serial_source::run(&socket, osc_addr, config, hr_file) => match never {},
+    }
+}
+
+/// 本次运行中最近使用的设备（`cache_valid_secs`）：扫描选中的 Peripheral 及选中或最近一次收到心率的时间。
+/// 设备短暂离开又回来时，在有效期内直接重连它，省去 `scan_duration_secs` 的扫描等待。
+#[derive(Debug, Default)]
+struct ScanCache {
+    entry: Option<(Peripheral, Instant)>,
+}
+
+impl ScanCache {
+    fn store(&mut self, device: &Peripheral, now: Instant) {
+        self.entry = Some((device.clone(), now));
+    }
+
+    /// 仍在有效期内的设备；过期时清除。
+    fn take_valid(&mut self, valid: Duration, now: Instant) -> Option<Peripheral> {
+        let (device, stored) = self.entry.take()?;
+        (now.saturating_duration_since(stored) < valid).then_some(device)
+    }
+
+    fn invalidate(&mut self) {
+        self.entry = None;
+    }
+}
+
+/// 蓝牙心率数据源：扫描、连接并接收心率，断开后自动重连。
+struct BleSource<'a> {
+    manager: Manager,
+    central: Adapter,
+    /// 只用于连接成功时的日志
+    osc_addr: SocketAddr,
+    config: &'a Config,
+    cache_file: &'a Path,
+}
+
+impl source::HeartRateSource for BleSource<'_> {
+    fn run<'a>(
+        &'a mut self,
+        events: &'a source::Events,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        Box::pin(connect_loop(
+            self.manager.clone(),
+            self.central.clone(),
+            events,
+            self.osc_addr,
+            self.config,
+            self.cache_file,
+        ))
+    }
+}
+
+/// 处理重置请求：删除设备缓存、忘掉本次运行中记住的设备并清零输出，之后立即重新扫描。
+fn forget_devices(
+    cache_file: &Path,
+    last_device: &mut Option<String>,
+    scan_cache: &mut ScanCache,
+    events: &source::Events,
+) {
+    match reset_last_device(cache_file) {
+        Ok(_) => info!("收到重置请求：已清除设备缓存，立即重新扫描..."),
+        Err(e) => warn!("收到重置请求，但删除设备缓存失败: {}", e),
+    }
+    *last_device = None;
+    scan_cache.invalidate();
+    recorder::event(recorder::Event::Rescan);
+    events.send(source::SourceEvent::Reset);
+}
+
+/// 扫描、连接并接收心率，断开后自动重连，不会返回 Ok。
+async fn connect_loop(
+    mut manager: Manager,
+    mut central: Adapter,
+    events: &source::Events,
+    osc_addr: SocketAddr,
+    config: &Config,
+    cache_file: &Path,
+) -> Result<()> {
+    let mut last_device = load_last_device(cache_file);
+    let selector = device_selector::from_config(config);
+    let mut scan_cache = ScanCache::default();
+    let cache_valid = Duration::from_secs(config.cache_valid_secs);
+    let mut attempts = ConnectionAttempts::new(config.max_connection_attempts_before_adapter_reset);
+
+    loop {
+        // 用于扫描的外部循环；刚才还在收到心率的设备先直接重连，不扫描
+        let cached = scan_cache.take_valid(cache_valid, Instant::now());
+        let mut from_cache = cached.is_some();
+        // 扫描期间同样响应重置请求：放弃本轮扫描，忘掉缓存的设备后重新开始
+        let scanned = {
+            let scan = async {
+                match cached {
+                    Some(device) => {
+                        info!("跳过扫描，直接重连刚才的设备 {}...", device.address());
+                        Ok(device)
+                    }
+                    None => {
+                        find_target_device(
+                            &central,
+                            config,
+                            last_device.as_deref(),
+                            selector.as_ref(),
+                        )
+                        .await
+                    }
+                }
+            };
+            tokio::select! {
+                result = scan => Some(result),
+                () = wait_for_rescan_request() => None,
+            }
+        };
+        let Some(scanned) = scanned else {
+            forget_devices(cache_file, &mut last_device, &mut scan_cache, events);
+            continue;
+        };
+        let device = match scanned {
+            Ok(p) => {
+                if !cache_valid.is_zero() {
+                    scan_cache.store(&p, Instant::now());
+                }
+                p
+            }
+            Err(e) if is_scan_hang(&e) => {
+                info!("蓝牙扫描启动超时，蓝牙栈可能已卡死。");
+                (manager, central) = reset_ble_stack(manager, central).await?;
+                continue;
+            }
+            Err(_) if powered_adapter(&manager).await.is_none() => {
+                // 适配器已关闭/拔出：不再每隔 retry_delay_secs 刷一遍蓝牙错误
+                (manager, central) = wait_for_adapter().await;
+                continue;
+            }
+            Err(e) => {
+                warn!(
+                    "{}。请检查设备是否在附近，电脑蓝牙是否开启。设备是否被其它心率接收设备连接。",
+                    e
+                );
+                info!("将在 {} 秒后重试扫描...", config.retry_delay_secs);
+                tokio::select! {
+                    () = time::sleep(Duration::from_secs(config.retry_delay_secs)) => {}
+                    () = wait_for_rescan_request() => {
+                        forget_devices(cache_file, &mut last_device, &mut scan_cache, events);
+                    }
+                }
+                continue;
+            }
+        };
+
+        // 内部循环：对同一设备重试连接。
+        // 连续 MAX_CONSECUTIVE_FAILURES 次未收到任何心率数据则放弃该设备、重新扫描
+        // （设备可能已关机/走远/更换了随机 MAC 地址）。
+        let mut consecutive_failures: u32 = 0;
+        let mut received_from_device = false;
+        let mut device_info: Option<DeviceInfo> = None;
+        loop {
+            // 连接期间收到重置请求时放弃当前连接（ConnectionGuard 负责断开），立即重新扫描；
+            // 适配器被关闭/拔出时同样立即放弃，不等卡住的蓝牙操作超时
+            let mut monitor = AdapterMonitor::start(&manager, &central);
+            let outcome = tokio::select! {
+                result = handle_device_connection(
+                    &device,
+                    events,
+                    osc_addr,
+                    config,
+                    &mut device_info,
+                ) => Some(result),
+                () = monitor.lost() => Some(Err(AppError::AdapterRemoved)),
+                _ = wait_for_rescan_request() => None,
+            };
+            drop(monitor);
+            let Some(result) = outcome else {
+                forget_devices(cache_file, &mut last_device, &mut scan_cache, events);
+                break;
+            };
+            let adapter_removed = matches!(result, Err(AppError::AdapterRemoved));
+            let received_any = match result {
+                Ok(received) => received,
+                Err(AppError::AdapterRemoved) => {
+                    warn!("{}", AppError::AdapterRemoved);
+                    false
+                }
+                Err(e) => {
+                    error!("处理连接时发生错误: {}", error_chain(&e));
+                    false
+                }
+            };
+            recorder::event(recorder::Event::Disconnected);
+
+            // 断开期间向 VRChat 发送未佩戴状态，并清零 HeartRate.txt；
+            // 启用断线保持时先继续发送最后的心率 ghost_mode_secs 秒，到时由保持任务清零（见 source 模块）
+            events.send(source::SourceEvent::Disconnected);
+
+            if received_any {
+                // 每次选中设备后第一次收到数据时都保存，连同本次读到的设备信息
+                let first_received = !received_from_device;
+                received_from_device = true;
+                if !cache_valid.is_zero() {
+                    scan_cache.store(&device, Instant::now());
+                }
+                let key = device_key(&device);
+                if first_received || last_device.as_deref() != Some(key.as_str()) {
+                    save_last_device(cache_file, &key, device_info.as_ref());
+                    last_device = Some(key);
+                }
+            }
+
+            // 连接中途关闭/拔出适配器：等它恢复后用新的 Manager/Adapter 重新扫描（旧 Peripheral 已失效）
+            if adapter_removed || powered_adapter(&manager).await.is_none() {
+                scan_cache.invalidate();
+                (manager, central) = wait_for_adapter().await;
+                break;
+            }
+
+            // 失败的连接累计过多时重新初始化蓝牙栈（旧 Peripheral 随之失效，需重新扫描）
+            if let Some(failed) = attempts.record(received_any) {
+                warn!("连续 {} 次连接失败，正在重置蓝牙适配器...", failed);
+                scan_cache.invalidate();
+                (manager, central) = reset_ble_stack(manager, central).await?;
+                break;
+            }
+
+            if received_any {
+                consecutive_failures = 0;
+                // 已经从这台设备收到过数据，之后的断线按普通重连处理
+                from_cache = false;
+            } else if from_cache && consecutive_failures == 0 {
+                // 跳过扫描后的第一次直接重连就失败：设备可能已更换地址或不在附近，立即重新扫描
+                info!("直接重连失败，将重新扫描...");
+                scan_cache.invalidate();
+                break;
+            } else {
+                consecutive_failures += 1;
+                if consecutive_failures >= MAX_CONSECUTIVE_FAILURES {
+                    if !received_from_device {
+                        // 从未收到数据的设备不值得跳过扫描再试
+                        scan_cache.invalidate();
+                    }
+                    info!(
+                        "连续 {} 次未能从设备获取心率数据，将重新开始扫描...",
+                        consecutive_failures
+                    );
+                    break;
+                }
+            }
+
+            info!(
+                "连接已断开。将在 {} 秒后尝试重新连接...",
+                config.retry_delay_secs
+            );
+            time::sleep(Duration::from_secs(config.retry_delay_secs)).await;
+        }
+    }
+}
+
+// --- 命令行参数 ---
+
+const USAGE: &str = "\
+用法:
+  HeartRate-For-VRChat                          正常运行（设置见 config.toml）
+  HeartRate-For-VRChat --discover-uuids <MAC>   探测非标准设备的心率服务/特征 UUID
+  HeartRate-For-VRChat --scan-only <名称>        只扫描不连接，每秒显示名称包含该关键字的设备的信号强度（Ctrl-C 停止）
+  HeartRate-For-VRChat --reset-cache            忘记上次使用的设备（last_device.txt），下次重新扫描选择
+  HeartRate-For-VRChat devices [list]           列出设备库（devices.toml）中保存的设备，* 为首选设备
+  HeartRate-For-VRChat devices add --name <别名> --mac <MAC>
+                                                保存设备；别名会显示在日志中，也可以写进 preferred_device_order
+  HeartRate-For-VRChat devices remove <别名>     从设备库删除设备
+  HeartRate-For-VRChat devices use <别名>        设为首选设备：扫描时在附近即优先连接
+  HeartRate-For-VRChat --pair <MAC>             （仅 Linux）先用 bluetoothctl 配对设备，成功后照常连接
+  HeartRate-For-VRChat --osc-test [周期秒数]     不连接蓝牙，每秒发送 60–200 往返扫描的测试心率（默认周期 30 秒）
+  HeartRate-For-VRChat --osc-test-fixed <BPM>   不连接蓝牙，每秒发送固定的测试心率
+  HeartRate-For-VRChat --replay <文件>           不连接蓝牙，按原始采样间隔重放会话记录 CSV 或 JSONL 心率记录（每行 {\"ts\": ..., \"bpm\": ...}）
+  HeartRate-For-VRChat --benchmark [--duration-secs 10] [--rate-hz 100]
+                                                不连接蓝牙，尽快发送 时长×速率 个 OSC Bundle，测量吞吐量、延迟和缓冲区溢出率
+  HeartRate-For-VRChat --config-dump            打印实际生效的配置（TOML），与默认值不同的项标注 # (overridden)
+  HeartRate-For-VRChat --obs-test               连接 OBS WebSocket，把 obs_input_name 文本源设为 TEST（验证 obs_* 配置）
+  HeartRate-For-VRChat --export-session <ID>     把历史库（heartrate.db）中的一个会话导出为 CSV，输出到标准输出
+  HeartRate-For-VRChat --help                   显示本帮助
+
+运行中重置设备缓存并立即重新扫描（无需重启）:
+  Linux/macOS: kill -USR1 <进程 PID>
+  Windows:     另开一个终端执行 HeartRate-For-VRChat --reset-cache";
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Run,
+    Help,
+    /// 删除设备缓存；Windows 上同时通知正在运行的实例重新扫描
+    ResetCache,
+    /// 连接指定 MAC（macOS 上为设备 ID）的设备并试听其所有可通知特征
+    DiscoverUuids(String),
+    /// 只扫描不连接，持续打印名称匹配的设备的 RSSI
+    ScanOnly(String),
+    /// 不使用蓝牙，按测试图案发送 OSC
+    OscTest(osc_test::Pattern),
+    /// 不使用蓝牙，按原始间隔重放心率记录
+    Replay(PathBuf),
+    /// 打印合并默认值后实际生效的配置
+    ConfigDump,
+    /// 把历史库中的会话导出为 CSV
+    ExportSession(i64),
+    /// 连接 OBS 并把文本源设为 TEST，验证 obs_* 配置
+    ObsTest,
+    /// 不使用蓝牙，测量 OSC 编码+发送的吞吐量
+    Benchmark(benchmark::Options),
+    /// （Linux）用 bluetoothctl 配对指定 MAC 的设备，成功后照常运行
+    Pair(String),
+    /// 管理设备库 devices.toml
+    Devices(devices::Action),
+}
+
+fn parse_args(args: &[String]) -> std::result::Result<Command, String> {
+    match args {
+        [] => Ok(Command::Run),
+        [flag] if flag == "--help" || flag == "-h" => Ok(Command::Help),
+        [flag] if flag == "--reset-cache" => Ok(Command::ResetCache),
+        [command, rest @ ..] if command == "devices" => {
+            devices::parse_args(rest).map(Command::Devices)
+        }
+        [flag] if flag == "--config-dump" => Ok(Command::ConfigDump),
+        [flag] if flag == "--obs-test" => Ok(Command::ObsTest),
+        [flag, target] if flag == "--discover-uuids" => Ok(Command::DiscoverUuids(target.clone())),
+        [flag] if flag == "--discover-uuids" => {
+            Err("--discover-uuids 需要指定设备 MAC 地址。".to_string())
+        }
+        [flag, mac] if flag == "--pair" => pair::normalize_mac(mac)
+            .map(Command::Pair)
+            .ok_or_else(|| format!("无效的 MAC 地址: {}（应形如 AA:BB:CC:DD:EE:FF）", mac)),
+        [flag] if flag == "--pair" => Err("--pair 需要指定设备 MAC 地址。".to_string()),
+        [flag, name] if flag == "--scan-only" => Ok(Command::ScanOnly(name.clone())),
+        [flag] if flag == "--scan-only" => {
+            Err("--scan-only 需要指定设备名称（或其中的关键字）。".to_string())
+        }
+        [flag] if flag == "--osc-test" => Ok(Command::OscTest(osc_test::Pattern::Sweep {
+            period_secs: osc_test::DEFAULT_SWEEP_PERIOD_SECS,
+        })),
+        [flag, period] if flag == "--osc-test" => match period.parse::<u64>() {
+            Ok(period_secs) if period_secs >= 2 => {
+                Ok(Command::OscTest(osc_test::Pattern::Sweep { period_secs }))
+            }
+            _ => Err(format!("无效的扫描周期: {}（需为不小于 2 的秒数）", period)),
+        },
+        [flag, bpm] if flag == "--osc-test-fixed" => match bpm.parse::<u8>() {
+            Ok(bpm) => Ok(Command::OscTest(osc_test::Pattern::Fixed(bpm))),
+            Err(_) => Err(format!("无效的心率: {}（需为 0–255 的整数）", bpm)),
+        },
+        [flag] if flag == "--osc-test-fixed" => {
+            Err("--osc-test-fixed 需要指定心率值。".to_string())
+        }
+        [flag, file] if flag == "--replay" => Ok(Command::Replay(PathBuf::from(file))),
+        [flag] if flag == "--replay" => Err("--replay 需要指定心率记录文件。".to_string()),
+        [flag, options @ ..] if flag == "--benchmark" => {
+            benchmark::parse_options(options).map(Command::Benchmark)
+        }
+        [flag, id] if flag == "--export-session" => match id.parse::<i64>() {
+            Ok(id) => Ok(Command::ExportSession(id)),
+            Err(_) => Err(format!("无效的会话 ID: {}", id)),
+        },
+        [flag] if flag == "--export-session" => {
+            Err("--export-session 需要指定会话 ID（sessions 表的 id）。".to_string())
+        }
+        [other, ..] => Err(format!("无法识别的参数: {}", other)),
+    }
+}
+
+/// 长期运行、退出时需要清零状态的命令：正常运行（蓝牙循环）、OSC 测试或重放。
+async fn run_command(
+    command: &Command,
+    config: &Config,
+    osc_addr: SocketAddr,
+    hr_file: &Path,
+    cache_file: &Path,
+) -> Result<()> {
+    if config.osc_feedback_enabled {
+        osc_feedback::start(config.osc_receive_port, config.osc_parameter_prefix());
+    }
+    match command {
+        Command::OscTest(pattern) => osc_test::run(*pattern, config, osc_addr).await,
+        Command::Replay(path) => replay::run(path, config, osc_addr, hr_file).await,
+        _ => main_loop(config, osc_addr, hr_file, cache_file).await,
+    }
+}
+
+/// Unix 上让退出信号与长期运行的命令竞争，确保进程终止前发送清零状态。
+#[cfg(unix)]
+async fn run_application(
+    command: &Command,
+    config: &Config,
+    osc_addr: SocketAddr,
+    hr_file: &Path,
+    cache_file: &Path,
+) -> Result<()> {
+    tokio::select! {
+        result = run_command(command, config, osc_addr, hr_file, cache_file) => result,
+        signal_result = wait_for_exit_signal() => {
+            signal_result?;
+            info!("收到退出信号，正在清理状态...");
+            run_exit_cleanup();
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn run_application(
+    command: &Command,
+    config: &Config,
+    osc_addr: SocketAddr,
+    hr_file: &Path,
+    cache_file: &Path,
+) -> Result<()> {
+    run_command(command, config, osc_addr, hr_file, cache_file).await
+}
+
+/// 出错退出前暂停，避免双击运行时窗口一闪而过看不到错误信息。
+fn pause_before_exit() {
+    println!("按回车键退出...");
+    let mut line = String::new();
+    let _ = io::stdin().read_line(&mut line);
+}
+
+fn print_banner() {
+    println!("HeartRate For VRChat v{}", env!("CARGO_PKG_VERSION"));
+    println!(
+        "1.通过蓝牙连接心率设备（任何标准 GATT 心率服务 0x180D 设备），将心率发送至 VRChat OSC"
+    );
+    println!("2.可选：在 config.toml 中开启 write_heart_rate_file 后，心率会同步写入程序目录下的 HeartRate.txt（供 OBS 等软件使用，默认关闭）");
+    println!("3.连接模式、设备名、OSC 地址等可在程序目录下的 config.toml 中修改");
+    println!("发送的 OSC 参数列表见 README（hr_connected / isHRActive / hr_percent / VRCOSC Normalised / HR）");
+    println!("适配预制件1：https://booth.pm/ja/items/6224828");
+    println!("适配预制件2：https://booth.pm/ja/items/7197938");
+    println!("Author 箱天: 喵喵喵———— ");
+}
+
+/// 启动横幅的最后一行（需要配置）：当前的 OSC 平台预设及实际使用的端口和参数前缀。
+fn print_platform(config: &Config) {
+    println!(
+        "OSC 平台预设：{}（端口 {}，参数前缀 {}）",
+        config.osc_platform,
+        config.osc_port(),
+        config.osc_parameter_prefix()
+    );
+    println!();
+}
+
+// 单线程运行时足矣：本程序每秒只处理一条蓝牙通知，
+// 默认的多线程运行时会按 CPU 核数起 worker 线程，纯属浪费。
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command = match parse_args(&args) {
+        Ok(command) => command,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            return;
+        }
+    };
+    // 配置导出 / 会话导出只输出 TOML / CSV，便于重定向到文件
+    if !matches!(command, Command::ConfigDump | Command::ExportSession(_)) {
+        print_banner();
+    }
+    if command == Command::Help {
+        println!("\n{}", USAGE);
+        return;
+    }
+
+    let dir = exe_dir();
+    let cache_file = dir.join(LAST_DEVICE_FILE);
+    if command == Command::ResetCache {
+        match reset_last_device(&cache_file) {
+            Ok(true) => println!("已删除设备缓存 {}。", cache_file.display()),
+            Ok(false) => println!("没有需要删除的设备缓存。"),
+            Err(e) => eprintln!("删除设备缓存 {} 失败: {}", cache_file.display(), e),
+        }
+        #[cfg(windows)]
+        if notify_running_instance() {
+            println!("已通知正在运行的程序重新扫描。");
+        }
+        return;
+    }
+
+    if let Command::Devices(action) = &command {
+        let devices_file = dir.join(devices::FILE);
+        if let Err(e) = devices::run(action, &devices_file) {
+            eprintln!("读写设备库 {} 失败: {}", devices_file.display(), e);
+        }
+        return;
+    }
+
+    let config = load_config(&dir);
+    let hr_file = heart_rate_file(&dir, &config);
+    if !matches!(command, Command::ConfigDump | Command::ExportSession(_)) {
+        print_platform(&config);
+    }
+
+    if command == Command::ConfigDump {
+        match dump_config(&config) {
+            Ok(text) => print!("{}", text),
+            Err(e) => eprintln!("导出配置失败: {}", e),
+        }
+        return;
+    }
+
+    if let Command::ExportSession(id) = command {
+        let db_file = history_db_file(&dir, &config);
+        match history_db::export_csv(&db_file, id, &mut io::stdout().lock()) {
+            Ok(Some(_)) => {}
+            Ok(None) => eprintln!("历史库 {} 中没有会话 {}。", db_file.display(), id),
+            Err(e) => eprintln!("导出会话失败（{}）: {}", db_file.display(), e),
+        }
+        return;
+    }
+
+    logging::init(if config.debug_log {
+        "debug"
+    } else {
+        &config.log_level
+    });
+
+    // 配对成功后照常运行，扫描时优先选择刚配对的设备
+    let command = match command {
+        Command::Pair(address) => {
+            if !pair::run(&address) {
+                return;
+            }
+            save_last_device(&cache_file, &address, None);
+            Command::Run
+        }
+        // 配置了 replay_file 时正常运行改为重放
+        Command::Run => match &config.replay_file {
+            Some(path) => Command::Replay(dir.join(path)),
+            None => Command::Run,
+        },
+        command => command,
+    };
+
+    if let Command::DiscoverUuids(target) = &command {
+        if let Err(e) = discover::run(&config, target).await {
+            error!("探测失败: {}", e);
+        }
+        return;
+    }
+
+    if let Command::ScanOnly(name) = &command {
+        if let Err(e) = scan_only::run(&config, name).await {
+            error!("扫描失败: {}", e);
+        }
+        return;
+    }
+
+    if command == Command::ObsTest {
+        match obs::test(&config, "TEST").await {
+            Ok(()) => println!(
+                "已将 OBS 文本源 \"{}\" 设为 TEST，配置正确。",
+                config.obs_input_name
+            ),
+            Err(e) => error!("OBS 测试失败: {}", e),
+        }
+        return;
+    }
+
+    let osc_addr = resolve_osc_addr(&config);
+
+    if let Command::Benchmark(options) = command {
+        if let Err(e) = benchmark::run(options, &config, osc_addr) {
+            error!("性能测试失败: {}", e);
+        }
+        return;
+    }
+
+    if config.write_heart_rate_file {
+        match prepare_heart_rate_file(&hr_file, &config) {
+            Ok(()) => info!("心率将写入 {}", hr_file.display()),
+            Err(e) => warn!(
+                "无法写入心率文件 {}（{}），请检查 heart_rate_file_path 所在目录是否存在且可写。",
+                hr_file.display(),
+                e
+            ),
+        }
+    }
+
+    if config.write_split_files {
+        if let Err(e) = prepare_split_files(&hr_file, &config) {
+            warn!(
+                "无法写入单值文件（{}），请检查 {} 是否存在且可写。",
+                e,
+                hr_file.parent().unwrap_or(&dir).display()
+            );
+        }
+    }
+
+    if config.write_status_json {
+        let status_file = status_json_file(&dir, &config);
+        match status_file::init(status_file.clone()) {
+            Ok(()) => info!("状态将写入 {}", status_file.display()),
+            Err(e) => warn!(
+                "无法写入状态文件 {}（{}），请检查 status_json_path 所在目录是否存在且可写。",
+                status_file.display(),
+                e
+            ),
+        }
+    }
+
+    if config.write_session_log {
+        let (log_dir, settings) = session_log_settings(&dir, &config);
+        match session_log::SessionLog::open(&log_dir, settings, chrono::Local::now()) {
+            Ok(log) => {
+                info!("会话记录将写入 {}", log.path().display());
+                recorder::register(Box::new(log));
+            }
+            Err(e) => warn!(
+                "无法创建会话记录（{}），请检查 {} 是否存在且可写。",
+                e,
+                log_dir.display()
+            ),
+        }
+    }
+
+    if config.write_history_db {
+        let db_file = history_db_file(&dir, &config);
+        let commit_interval = Duration::from_secs(config.history_db_commit_secs);
+        match history_db::HistoryDb::open(&db_file, commit_interval) {
+            Ok(db) => {
+                info!("会话历史将写入 {}", db_file.display());
+                recorder::register(Box::new(db));
+            }
+            Err(e) => warn!(
+                "无法打开历史库 {}（{}），请检查 history_db_path 所在目录是否存在且可写。",
+                db_file.display(),
+                e
+            ),
+        }
+    }
+    if config.influx_enabled {
+        match influx::InfluxOutput::start(&config) {
+            Ok(output) => {
+                if config.influx_dry_run {
+                    info!("InfluxDB dry run：line protocol 将打印到控制台，不会发送。");
+                } else {
+                    info!(
+                        "读数将每 {} 秒写入 InfluxDB {}（bucket {}）",
+                        config.influx_flush_secs, config.influx_url, config.influx_bucket
+                    );
+                }
+                recorder::register(Box::new(output));
+            }
+            Err(e) => warn!(
+                "influx_url = \"{}\" 无效（{}），InfluxDB 输出未启用。",
+                config.influx_url, e
+            ),
+        }
+    }
+    if config.webhook_enabled {
+        match webhook::WebhookOutput::start(&config) {
+            Ok(output) => {
+                info!("连接状态和心率阈值事件将发送到 {}", config.webhook_url);
+                recorder::register(Box::new(output));
+            }
+            Err(e) => warn!(
+                "webhook_url = \"{}\" 无效（{}），Webhook 未启用。",
+                config.webhook_url, e
+            ),
+        }
+    }
+    if config.discord_enabled {
+        match discord::start(&config) {
+            Ok(()) => info!("断开、低电量提醒将发送到 Discord"),
+            Err(e) => warn!("discord_webhook_url 无效（{}），Discord 通知未启用。", e),
+        }
+    }
+    if config.twitch_enabled {
+        let output = twitch::TwitchOutput::start(&config);
+        if config.twitch_dry_run {
+            info!("Twitch 播报为试运行模式，消息只打印到控制台");
+        }
+        recorder::register(Box::new(output));
+    }
+    if command == Command::Run {
+        devices::init(&dir.join(devices::FILE), &config.preferred_device_order);
+        summary::start(
+            config.max_heart_rate_for_percent,
+            config
+                .session_summary_log
+                .then(|| dir.join(SESSION_SUMMARY_LOG_FILE)),
+        );
+    }
+    recorder::event(recorder::Event::Start);
+
+    if config.shm_enabled {
+        match shm::init(&config.shm_name) {
+            Ok(()) => info!("心率将写入共享内存 {}", config.shm_name),
+            Err(e) => warn!("无法创建共享内存 {}（{}）。", config.shm_name, e),
+        }
+    }
+
+    if config.registry_output_enabled {
+        match registry_output::init(&config.registry_key_path) {
+            Ok(()) => info!("心率将写入注册表 {}", config.registry_key_path),
+            Err(e) => warn!(
+                "无法打开注册表键 {}（{}），注册表输出未启用。",
+                config.registry_key_path, e
+            ),
+        }
+    }
+
+    if config.tray_enabled && matches!(command, Command::Run | Command::Replay(_)) {
+        // 托盘创建成功后控制台即被隐藏，先打印提示
+        info!("已在系统托盘显示图标，控制台窗口将隐藏（可从托盘菜单重新打开）");
+        if let Err(e) = tray::start(dir.join("config.toml")) {
+            warn!("无法创建托盘图标（{}），将保留控制台窗口。", e);
+        }
+    }
+
+    if config.pipe_enabled {
+        match pipe::start(&config.pipe_name, config.pipe_socket_path.clone()) {
+            Ok(endpoint) => info!("心率将推送到 {}", endpoint),
+            Err(e) => warn!("无法创建本机推送管道 {}（{}）。", config.pipe_name, e),
+        }
+    }
+
+    if config.websocket_enabled {
+        let addr = SocketAddrV4::new(config.websocket_ip, config.websocket_port);
+        match websocket::start(addr).await {
+            Ok(()) => info!("WebSocket 服务已启动: ws://{}", addr),
+            Err(e) => warn!(
+                "无法启动 WebSocket 服务 {}（{}），请检查端口是否被占用。",
+                addr, e
+            ),
+        }
+    }
+
+    if config.resonite_enabled {
+        let addr = SocketAddrV4::new(config.resonite_ip, config.resonite_port);
+        match resonite::start(addr, config.resonite_path.clone(), config.resonite_format).await {
+            Ok(local) => info!(
+                "Resonite WebSocket 服务已启动: ws://{}{}",
+                local, config.resonite_path
+            ),
+            Err(e) => warn!(
+                "无法启动 Resonite WebSocket 服务 {}（{}），请检查端口是否被占用。",
+                addr, e
+            ),
+        }
+    }
+
+    if config.http_enabled {
+        let addr = SocketAddrV4::new(config.http_ip, config.http_port);
+        match http_api::start(
+            addr,
+            config.http_cors_origin.clone(),
+            config.http_history_secs,
+            config.websocket_enabled.then_some(config.websocket_port),
+        )
+        .await
+        {
+            Ok(()) => info!("HTTP 接口已启动: http://{}/api/state", addr),
+            Err(e) => warn!(
+                "无法启动 HTTP 接口 {}（{}），请检查端口是否被占用。",
+                addr, e
+            ),
+        }
+    }
+
+    if config.haptics_enabled {
+        match haptics::start(&config, osc_addr) {
+            Ok(target) => info!(
+                "将随心跳向 {} 发送震动脉冲（{}）",
+                target,
+                config.haptics_osc_addresses.join("、")
+            ),
+            Err(e) => warn!("无法创建心跳震动的 OSC 套接字（{}），心跳震动未启用。", e),
+        }
+    }
+
+    if config.vr_notify_xsoverlay || config.vr_notify_ovr_toolkit {
+        vr_notify::start(&config);
+        let targets: Vec<&str> = [
+            (config.vr_notify_xsoverlay, "XSOverlay"),
+            (config.vr_notify_ovr_toolkit, "OVR Toolkit"),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, name)| enabled.then_some(name))
+        .collect();
+        info!(
+            "断开、低电量和心率报警将通过 {} 在头显内通知",
+            targets.join(" 和 ")
+        );
+    }
+
+    if config.discord_presence_enabled {
+        discord_presence::start(&config);
+        info!("将在 Discord 动态中显示实时心率（需本机 Discord 客户端在运行）");
+    }
+
+    if config.obs_enabled {
+        obs::start(&config, config.heart_rate_file_offline.clone());
+        info!(
+            "将通过 OBS WebSocket（{}:{}）更新文本源 \"{}\"",
+            config.obs_host, config.obs_port, config.obs_input_name
+        );
+    }
+
+    if config.mqtt_enabled {
+        mqtt::start(&config);
+        info!(
+            "将把心率发布到 MQTT 代理 {}:{}（主题 {}）",
+            config.mqtt_host, config.mqtt_port, config.mqtt_topic
+        );
+    }
+
+    if let Some(path) = &config.plugin_path {
+        let path = dir.join(path);
+        match plugin::load(&path) {
+            Ok(()) => info!("已加载心率变换插件 {}", path.display()),
+            Err(e) => warn!(
+                "无法加载心率变换插件 {}（{}），将发送原始心率。",
+                path.display(),
+                e
+            ),
+        }
+    }
+
+    if let Some(path) = &config.script_path {
+        let path = dir.join(path);
+        info!("将在每次读数时调用脚本 {}", path.display());
+        script::start(path, config.osc_parameter_prefix());
+    }
+
+    // 初始化各平台共用的退出清理上下文。
+    let _ = CLEANUP_CTX.set(CleanupCtx {
+        osc_addr,
+        config: config.clone(),
+        hr_file: hr_file.clone(),
+    });
+    #[cfg(windows)]
+    if !register_exit_handler() {
+        warn!("注册退出清理处理器失败（退出时 VRChat 可能残留最后一次心率）。");
+    }
+
+    if command == Command::Run && !start_rescan_listener() {
+        warn!("注册重新扫描请求失败（运行中的 --reset-cache / SIGUSR1 通知将不可用）。");
+    }
+
+    let result = run_application(&command, &config, osc_addr, &hr_file, &cache_file).await;
+    recorder::finish();
+    if let Err(e) = result {
+        error!("发生错误: {}", error_chain(&e));
+        if command == Command::Run {
+            error!("请检查系统是否有蓝牙适配器、蓝牙服务是否已启动。");
+        }
+        pause_before_exit();
+        return;
+    }
+
+    info!("程序已停止。");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_osc::{
+        assert_param_bool, assert_param_float, assert_param_int, decode_bundle, param,
+        TestOscReceiver,
+    };
+    use std::net::SocketAddr;
+
+    #[test]
+    fn config_template_is_valid_and_uses_default_osc_target() {
+        let config: Config = toml::from_str(CONFIG_TEMPLATE).expect("parse config template");
+
+        assert_eq!(config, Config::default());
+        assert_eq!(
+            resolve_osc_addr(&config),
+            SocketAddr::from((Ipv4Addr::LOCALHOST, 9000))
+        );
+    }
+
+    #[test]
+    fn config_default_is_valid() {
+        let config = Config::default();
+        // 默认配置经过 load_config 的同一校验后不应有任何项被调整
+        assert_eq!(validate_config(config.clone()), config);
+
+        assert_eq!(config.osc_ip, "127.0.0.1");
+        assert_eq!(config.osc_platform, OscPlatform::Vrchat);
+        assert_eq!(config.osc_port(), 9000);
+        assert_eq!(config.osc_parameter_prefix(), "/avatar/parameters/");
+        assert_eq!(config.max_heart_rate_for_percent, 200.0);
+        assert_eq!(config.retry_delay_secs, 5);
+        assert_eq!(config.heartbeat_timeout_secs, 15);
+        assert_eq!(config.outputs, OutputToggles::default());
+        assert!(parse_hex(&config.start_command_hex).is_some());
+        assert_eq!(config.clone(), config);
+    }
+
+    #[test]
+    fn parse_error_position_is_reported_as_line_and_column() {
+        let text = "osc_ip = \"127.0.0.1\"\n名称 = 1\nosc_port = ?\n";
+        assert_eq!(line_column(text, 0), (1, 1));
+        assert_eq!(line_column(text, text.find("?").unwrap()), (3, 12));
+        assert_eq!(line_column(text, text.find("= 1").unwrap()), (2, 4));
+
+        let e = toml::from_str::<Config>(text).unwrap_err();
+        assert!(describe_parse_error(text, &e).starts_with("位置: 第 2 行"));
+    }
+
+    #[test]
+    fn broken_config_is_restored_from_backup_or_replaced_by_template() {
+        let dir = env::temp_dir().join(format!("hr-vrc-config-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        let backup = dir.join("config.toml.bak");
+        let broken = dir.join("config.toml.broken");
+
+        fs::write(&path, "retry_delay_secs = 7\n").unwrap();
+        assert_eq!(read_config_file(&path).retry_delay_secs, 7);
+        assert_eq!(
+            fs::read_to_string(&backup).unwrap(),
+            "retry_delay_secs = 7\n"
+        );
+
+        fs::write(&path, "retry_delay_secs = \n").unwrap();
+        assert_eq!(read_config_file(&path).retry_delay_secs, 7);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "retry_delay_secs = 7\n");
+        assert_eq!(
+            fs::read_to_string(&broken).unwrap(),
+            "retry_delay_secs = \n"
+        );
+
+        // 无法另存出错的文件时不改动 config.toml，只在内存中使用备份或默认配置
+        fs::remove_file(&broken).unwrap();
+        fs::create_dir(&broken).unwrap();
+        fs::write(&path, "retry_delay_secs = 3\n?").unwrap();
+        assert_eq!(read_config_file(&path).retry_delay_secs, 7);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "retry_delay_secs = 3\n?"
+        );
+        fs::write(&backup, "[broken").unwrap();
+        assert_eq!(read_config_file(&path), Config::default());
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "retry_delay_secs = 3\n?"
+        );
+        fs::remove_dir(&broken).unwrap();
+
+        fs::write(&path, "retry_delay_secs = \n").unwrap();
+        assert_eq!(read_config_file(&path), Config::default());
+        assert_eq!(fs::read_to_string(&path).unwrap(), CONFIG_TEMPLATE);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn resolve_osc_addr_preserves_valid_remote_ipv4_and_port() {
+        let config = Config {
+            osc_ip: "192.168.1.42".to_string(),
+            osc_port: Some(9123),
+            ..Config::default()
+        };
+
+        assert_eq!(
+            resolve_osc_addr(&config),
+            SocketAddr::from((Ipv4Addr::new(192, 168, 1, 42), 9123))
+        );
+    }
+
+    #[test]
+    fn resolve_osc_addr_accepts_ipv6_with_or_without_brackets() {
+        for osc_ip in ["fd00::42", "[fd00::42]"] {
+            let config = Config {
+                osc_ip: osc_ip.to_string(),
+                osc_port: Some(9000),
+                ..Config::default()
+            };
+            assert_eq!(
+                resolve_osc_addr(&config),
+                SocketAddr::from((Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 0x42), 9000))
+            );
+        }
+    }
+
+    #[test]
+    fn resolve_osc_addr_falls_back_to_localhost_and_preserves_port() {
+        let config = Config {
+            osc_ip: "not-an-ip".to_string(),
+            osc_port: Some(9456),
+            ..Config::default()
+        };
+
+        assert_eq!(
+            resolve_osc_addr(&config),
+            SocketAddr::from((Ipv4Addr::LOCALHOST, 9456))
+        );
+    }
+
+    #[test]
+    fn multicast_group_overrides_osc_ip_and_sets_ttl() {
+        let group = Ipv4Addr::new(239, 0, 0, 42);
+        let config = Config {
+            osc_multicast_group: Some(group),
+            osc_ttl: 4,
+            ..Config::default()
+        };
+        let osc_addr = resolve_osc_addr(&config);
+        assert_eq!(osc_addr, SocketAddr::from((group, 9000)));
+
+        let socket = osc_socket(&config, osc_addr).expect("create multicast socket");
+        assert_eq!(socket.multicast_ttl_v4().expect("read TTL"), 4);
+    }
+
+    #[test]
+    fn osc_bind_addr_uses_configured_local_ip() {
+        let v4_target = SocketAddr::from((Ipv4Addr::LOCALHOST, 9000));
+        let v6_target = SocketAddr::from((Ipv6Addr::LOCALHOST, 9000));
+        assert_eq!(
+            osc_bind_addr(&Config::default(), v4_target),
+            SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))
+        );
+        assert_eq!(
+            osc_bind_addr(&Config::default(), v6_target),
+            SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))
+        );
+
+        let config = Config {
+            osc_local_ip: Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            ..Config::default()
+        };
+        // 地址族与目标不同的 osc_local_ip 被忽略
+        assert_eq!(
+            osc_bind_addr(&config, v6_target),
+            SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))
+        );
+        let socket = UdpSocket::bind(osc_bind_addr(&config, v4_target)).expect("bind loopback");
+        assert_eq!(
+            socket.local_addr().expect("local addr").ip(),
+            IpAddr::V4(Ipv4Addr::LOCALHOST)
+        );
+    }
+
+    fn characteristic(service_uuid: Uuid, uuid: Uuid) -> Characteristic {
+        Characteristic {
+            uuid,
+            service_uuid,
+            properties: CharPropFlags::NOTIFY,
+            descriptors: BTreeSet::new(),
+        }
+    }
+
+    #[test]
+    fn find_hr_characteristic_follows_service_priority() {
+        let garmin = Uuid::from_u128(0x00000001_0000_1000_8000_00805f9b34fb);
+        let characteristics = BTreeSet::from([
+            characteristic(garmin, HEART_RATE_CHAR_UUID),
+            characteristic(HEART_RATE_SERVICE_UUID, HEART_RATE_CHAR_UUID),
+        ]);
+
+        let standard_char = [HEART_RATE_CHAR_UUID];
+
+        let found = find_hr_characteristic(
+            &characteristics,
+            &[HEART_RATE_SERVICE_UUID, garmin],
+            &standard_char,
+        );
+        assert_eq!(found.map(|c| c.service_uuid), Some(HEART_RATE_SERVICE_UUID));
+
+        let found = find_hr_characteristic(
+            &characteristics,
+            &[garmin, HEART_RATE_SERVICE_UUID],
+            &standard_char,
+        );
+        assert_eq!(found.map(|c| c.service_uuid), Some(garmin));
+    }
+
+    #[test]
+    fn device_info_decodes_strings_and_lists_present_fields() {
+        assert_eq!(
+            decode_device_info_string(b"Xiaomi\0\0"),
+            Some("Xiaomi".to_string())
+        );
+        assert_eq!(decode_device_info_string(b" \0"), None);
+
+        let info = DeviceInfo {
+            manufacturer: Some("Xiaomi".to_string()),
+            model: None,
+            firmware: Some("2.1.0".to_string()),
+        };
+        assert_eq!(info.to_string(), "厂商: Xiaomi | 固件: 2.1.0");
+        assert!(DeviceInfo::default().is_empty());
+    }
+
+    #[test]
+    fn hr_source_prefers_notify_then_indicate_then_polling() {
+        assert_eq!(
+            hr_source(CharPropFlags::NOTIFY | CharPropFlags::READ),
+            Some(HrSource::Notify)
+        );
+        assert_eq!(
+            hr_source(CharPropFlags::NOTIFY | CharPropFlags::INDICATE),
+            Some(HrSource::Notify)
+        );
+        assert_eq!(
+            hr_source(CharPropFlags::INDICATE | CharPropFlags::READ),
+            Some(HrSource::Indicate)
+        );
+        assert!(hr_source(CharPropFlags::INDICATE).is_some_and(HrSource::is_subscribed));
+        assert!(!HrSource::Poll.is_subscribed());
+        assert_eq!(hr_source(CharPropFlags::READ), Some(HrSource::Poll));
+        assert_eq!(hr_source(CharPropFlags::WRITE), None);
+    }
+
+    #[test]
+    fn last_device_cache_round_trips_and_resets() {
+        let dir = env::temp_dir().join(format!("hr-vrc-cache-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("create temp dir");
+        let cache_file = dir.join(LAST_DEVICE_FILE);
+
+        assert_eq!(load_last_device(&cache_file), None);
+        save_last_device(&cache_file, "AA:BB:CC:DD:EE:FF", None);
+        assert_eq!(
+            load_last_device(&cache_file).as_deref(),
+            Some("AA:BB:CC:DD:EE:FF")
+        );
+        let info = DeviceInfo {
+            manufacturer: Some("Polar Electro Oy".to_string()),
+            model: None,
+            firmware: Some("5.0.0".to_string()),
+        };
+        save_last_device(&cache_file, "AA:BB:CC:DD:EE:FF", Some(&info));
+        assert_eq!(
+            fs::read_to_string(&cache_file).expect("read cache"),
+            "AA:BB:CC:DD:EE:FF\nmanufacturer=Polar Electro Oy\nfirmware=5.0.0\n"
+        );
+        assert_eq!(
+            load_last_device(&cache_file).as_deref(),
+            Some("AA:BB:CC:DD:EE:FF")
+        );
+        assert!(reset_last_device(&cache_file).expect("reset"));
+        assert!(!reset_last_device(&cache_file).expect("reset again"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn only_start_scan_timeouts_trigger_ble_stack_reset() {
+        assert!(is_scan_hang(&AppError::Timeout {
+            op: "start_scan",
+            secs: 30
+        }));
+        assert!(!is_scan_hang(&AppError::Timeout {
+            op: "connect",
+            secs: 15
+        }));
+        assert!(!is_scan_hang(&AppError::DeviceNotFound));
+        assert!(is_scan_hang(
+            &Err::<(), _>(AppError::Timeout {
+                op: "start_scan",
+                secs: 30
+            })
+            .context("扫描设备")
+            .unwrap_err()
+        ));
+    }
+
+    #[test]
+    fn failed_connection_attempts_trigger_reset_at_limit() {
+        let mut attempts = ConnectionAttempts::new(3);
+        assert_eq!(attempts.record(false), None);
+        assert_eq!(attempts.record(false), None);
+        assert_eq!(attempts.record(true), None, "success resets the count");
+        assert_eq!(attempts.record(false), None);
+        assert_eq!(attempts.record(false), None);
+        assert_eq!(attempts.record(false), Some(3));
+        assert_eq!(attempts.record(false), None, "count restarts after a reset");
+
+        let mut disabled = ConnectionAttempts::new(0);
+        assert!((0..100).all(|_| disabled.record(false).is_none()));
+    }
+
+    #[test]
+    fn error_chain_lists_each_context_and_the_root_cause() {
+        let e = Err::<(), _>(AppError::Timeout {
+            op: "connect",
+            secs: 15,
+        })
+        .context("连接设备 AA:BB")
+        .with_context(|| "处理连接".to_string())
+        .unwrap_err();
+        assert_eq!(
+            error_chain(&e),
+            "处理连接时出错\n  原因: 连接设备 AA:BB时出错\n  原因: connect 超时（15 秒内未完成）。"
+        );
+        assert!(matches!(e.root(), AppError::Timeout { op: "connect", .. }));
+        assert_eq!(error_chain(&AppError::DeviceNotFound), "未能找到目标设备。");
+    }
+
+    #[test]
+    fn parse_args_recognises_flags() {
+        let args = |list: &[&str]| list.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(parse_args(&args(&[])), Ok(Command::Run));
+        assert_eq!(
+            parse_args(&args(&["--discover-uuids", "AA:BB:CC:DD:EE:FF"])),
+            Ok(Command::DiscoverUuids("AA:BB:CC:DD:EE:FF".to_string()))
+        );
+        assert_eq!(
+            parse_args(&args(&["--scan-only", "Polar H10"])),
+            Ok(Command::ScanOnly("Polar H10".to_string()))
+        );
+        assert_eq!(
+            parse_args(&args(&["--reset-cache"])),
+            Ok(Command::ResetCache)
+        );
+        assert_eq!(
+            parse_args(&args(&["--config-dump"])),
+            Ok(Command::ConfigDump)
+        );
+        assert_eq!(parse_args(&args(&["--obs-test"])), Ok(Command::ObsTest));
+        assert_eq!(
+            parse_args(&args(&["--pair", "a0:9e:1a:00:bc:12"])),
+            Ok(Command::Pair("A0:9E:1A:00:BC:12".to_string()))
+        );
+        assert!(parse_args(&args(&["--pair", "a0:9e"])).is_err());
+        assert_eq!(
+            parse_args(&args(&["--osc-test"])),
+            Ok(Command::OscTest(osc_test::Pattern::Sweep {
+                period_secs: 30
+            }))
+        );
+        assert_eq!(
+            parse_args(&args(&["--osc-test", "10"])),
+            Ok(Command::OscTest(osc_test::Pattern::Sweep {
+                period_secs: 10
+            }))
+        );
+        assert_eq!(
+            parse_args(&args(&["--osc-test-fixed", "120"])),
+            Ok(Command::OscTest(osc_test::Pattern::Fixed(120)))
+        );
+        assert_eq!(
+            parse_args(&args(&["devices", "use", "chest"])),
+            Ok(Command::Devices(devices::Action::Use("chest".to_string())))
+        );
+        assert_eq!(
+            parse_args(&args(&["--replay", "session.jsonl"])),
+            Ok(Command::Replay(PathBuf::from("session.jsonl")))
+        );
+        assert_eq!(
+            parse_args(&args(&["--export-session", "3"])),
+            Ok(Command::ExportSession(3))
+        );
+        assert_eq!(
+            parse_args(&args(&["--benchmark"])),
+            Ok(Command::Benchmark(benchmark::Options::default()))
+        );
+        assert_eq!(
+            parse_args(&args(&["--benchmark", "--rate-hz", "1000"])),
+            Ok(Command::Benchmark(benchmark::Options {
+                duration_secs: 10,
+                rate_hz: 1000
+            }))
+        );
+        assert!(parse_args(&args(&["--osc-test", "0"])).is_err());
+        assert!(parse_args(&args(&["--export-session", "latest"])).is_err());
+        assert!(parse_args(&args(&["--osc-test-fixed", "300"])).is_err());
+        assert!(parse_args(&args(&["--osc-test-fixed"])).is_err());
+        assert!(parse_args(&args(&["--replay"])).is_err());
+        assert!(parse_args(&args(&["devices", "forget"])).is_err());
+        assert!(parse_args(&args(&["--discover-uuids"])).is_err());
+        assert!(parse_args(&args(&["--scan-only"])).is_err());
+        assert!(parse_args(&args(&["--bogus"])).is_err());
+    }
+
+    #[test]
+    fn parse_heart_rate_measurement_handles_formats_and_rr_intervals() {
+        assert_eq!(
+            parse_heart_rate_measurement(&[0x00, 72]),
+            Some(HeartRateMeasurement {
+                heart_rate: 72,
+                rr_intervals: Vec::new(),
+                energy_expended: None,
+            })
+        );
+        // 16 位心率 + 能量消耗 + 两个 RR 间期
+        assert_eq!(
+            parse_heart_rate_measurement(&[0x19, 0x2C, 0x01, 0x10, 0x00, 0x00, 0x04, 0x20, 0x03]),
+            Some(HeartRateMeasurement {
+                heart_rate: 300,
+                rr_intervals: vec![1024, 800],
+                energy_expended: Some(16),
+            })
+        );
+        assert_eq!(parse_heart_rate_measurement(&[0x01, 0x48]), None);
+        assert_eq!(parse_heart_rate_measurement(&[]), None);
+    }
+
+    #[test]
+    fn parses_spo2_as_plain_percent_or_plx_sfloat() {
+        assert_eq!(parse_spo2(&[97]), Some(97));
+        assert_eq!(parse_spo2(&[150]), None);
+        // PLX Continuous Measurement：flags + SpO2 (SFLOAT) + 脉率
+        assert_eq!(parse_spo2(&[0x00, 0x61, 0x00, 0x48, 0x00]), Some(97));
+        // 975 × 10^-1
+        assert_eq!(parse_spo2(&[0x00, 0xcf, 0xf3, 0x48, 0x00]), Some(98));
+        // NaN（测量中）
+        assert_eq!(parse_spo2(&[0x00, 0xff, 0x07, 0xff, 0x07]), None);
+        assert_eq!(parse_spo2(&[]), None);
+    }
+
+    #[test]
+    fn periodic_stats_ignores_zero_and_resets_on_flush() {
+        let mut stats = PeriodicStats::default();
+        for bpm in [72, 0, 91, 62] {
+            stats.update(bpm);
+        }
+        let snapshot = stats.flush();
+        assert_eq!((snapshot.min, snapshot.max, snapshot.samples), (62, 91, 3));
+        assert!((snapshot.mean - 75.0).abs() < f32::EPSILON);
+
+        assert_eq!(stats.flush().samples, 0);
+    }
+
+    #[test]
+    fn training_load_follows_banister_formula() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut load = TrainingLoad::default();
+        load.update(100, at(0), 200.0);
+        assert_eq!(load.total, 0.0, "the first reading has no interval yet");
+        for secs in 1..=60 {
+            load.update(100, at(secs), 200.0);
+        }
+        // 1 分钟 × 0.5 × e^0.96
+        assert!((load.total - 0.5 * 0.96_f32.exp()).abs() < 1e-4);
+
+        // 未佩戴的时间与过长的间隔不计入
+        let before = load.total;
+        load.update(0, at(61), 200.0);
+        load.update(100, at(120), 200.0);
+        assert_eq!(load.total, before);
+        load.update(100, at(180), 200.0);
+        assert!((load.total - before - 5.0 / 60.0 * 0.5 * 0.96_f32.exp()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn parse_payload_honours_format_hint() {
+        assert_eq!(
+            parse_payload(&[0x00, 72], PayloadFormat::Standard).map(|m| m.heart_rate),
+            Some(72)
+        );
+        assert_eq!(
+            parse_payload(&[72, 0x00], PayloadFormat::RawU8).map(|m| m.heart_rate),
+            Some(72)
+        );
+        assert_eq!(parse_payload(&[], PayloadFormat::RawU8), None);
+    }
+
+    #[test]
+    fn hr_alarm_sounds_on_entry_and_respects_cooldown() {
+        let config = Config {
+            hr_alarm_high: Some(150),
+            hr_alarm_low: Some(45),
+            alarm_cooldown_secs: 60,
+            ..Config::default()
+        };
+        let start = Instant::now();
+        let mut alarm = HrAlarm::default();
+
+        assert_eq!(alarm.update(120, start, &config), None);
+        assert_eq!(alarm.update(151, start, &config), Some(AlarmKind::High));
+        assert!(alarm.is_active());
+        // 持续高于阈值不重复提示
+        assert_eq!(alarm.update(160, start, &config), None);
+        // 冷却期内回落后再次越线不响，但报警状态照常更新
+        assert_eq!(alarm.update(140, start, &config), None);
+        assert!(!alarm.is_active());
+        let later = start + Duration::from_secs(10);
+        assert_eq!(alarm.update(155, later, &config), None);
+        assert!(alarm.is_active());
+        // 冷却期过后进入低心率报警
+        let much_later = start + Duration::from_secs(120);
+        assert_eq!(alarm.update(40, much_later, &config), Some(AlarmKind::Low));
+        // 0 表示未佩戴，不触发低心率报警
+        assert_eq!(alarm.update(0, much_later, &config), None);
+        assert!(!alarm.is_active());
+    }
+
+    #[test]
+    fn sanitize_device_name_keeps_cjk_and_strips_invisible_characters() {
+        assert_eq!(sanitize_device_name("华为手环 8"), "华为手环 8");
+        assert_eq!(
+            sanitize_device_name("HUAWEI\u{200B} Band\0\n"),
+            "HUAWEI Band"
+        );
+        assert_eq!(sanitize_device_name("\u{FEFF}小米手环"), "小米手环");
+    }
+
+    #[test]
+    fn fit_device_name_truncates_and_pads_by_grapheme() {
+        assert_eq!(fit_device_name("荣耀手环", 6), "荣耀手环  ");
+        assert_eq!(fit_device_name("Xiaomi Smart Band 10", 6), "Xiaomi");
+    }
+
+    #[test]
+    fn configured_destination_receives_normal_and_cleared_osc_state() {
+        let receiver = TestOscReceiver::bind();
+        let receiver_addr = receiver.addr();
+        let sender = UdpSocket::bind("127.0.0.1:0").expect("bind OSC sender");
+        let config = Config {
+            osc_ip: receiver_addr.ip().to_string(),
+            osc_port: Some(receiver_addr.port()),
+            ..Config::default()
+        };
+        let osc_addr = resolve_osc_addr(&config);
+
+        send_osc(&sender, osc_addr, 77, OscExtras::default(), &config)
+            .expect("send normal OSC state");
+        let normal = receiver.recv_bundle();
+        assert_param_int(&normal, "HR", 77);
+        assert_param_bool(&normal, "hr_connected", true);
+
+        clear_state(
+            &sender,
+            osc_addr,
+            &config,
+            Path::new("unused-heart-rate.txt"),
+        );
+        let cleared = receiver.recv_bundle();
+        assert_param_int(&cleared, "HR", 0);
+        assert_param_bool(&cleared, "hr_connected", false);
+    }
+
+    #[test]
+    fn packet_modes_send_one_bundle_or_one_datagram_per_parameter() {
+        let receiver = TestOscReceiver::bind();
+        let sender = UdpSocket::bind("127.0.0.1:0").expect("bind OSC sender");
+        let mut config = Config {
+            osc_ip: receiver.addr().ip().to_string(),
+            osc_port: Some(receiver.addr().port()),
+            ..Config::default()
+        };
+        let osc_addr = resolve_osc_addr(&config);
+        let count = hr_messages(88, OscExtras::default(), &config).len();
+
+        let status =
+            send_osc(&sender, osc_addr, 88, OscExtras::default(), &config).expect("send bundle");
+        assert!(!status.contains("数据报"));
+        let bundle = receiver.recv_bundle();
+        assert_eq!(bundle.content.len(), count);
+        assert_param_int(&bundle, "HR", 88);
+
+        config.osc_packet_mode = OscPacketMode::Messages;
+        let status =
+            send_osc(&sender, osc_addr, 88, OscExtras::default(), &config).expect("send messages");
+        assert!(status.ends_with(&format!("数据报: {}", count)));
+        let messages = receiver.recv_messages(count);
+        assert_eq!(messages.content, bundle.content);
+        assert_param_int(&messages, "HR", 88);
+        assert_param_bool(&messages, "hr_connected", true);
+    }
+
+    #[test]
+    fn parameter_types_change_the_encoded_argument_types() {
+        let extras = OscExtras {
+            alarm: true,
+            ..OscExtras::default()
+        };
+        let alarm_config = Config {
+            hr_alarm_high: Some(150),
+            ..Config::default()
+        };
+        let default = decode_bundle(&encode_hr_bundle(90, extras, &alarm_config).unwrap());
+        assert_param_bool(&default, "hr_connected", true);
+        assert_param_bool(&default, "isHRActive", true);
+        assert_param_bool(&default, "hr_alarm", true);
+        assert_param_int(&default, "HR", 90);
+
+        let config = Config {
+            steady_state_mute: true,
+            hr_alarm_low: Some(40),
+            parameter_types: ParameterTypes {
+                connected: OscBoolType::Int,
+                active: OscBoolType::Float,
+                alarm: OscBoolType::Int,
+                steady: OscBoolType::Float,
+                hr: OscIntType::Float,
+            },
+            ..Config::default()
+        };
+        let typed = decode_bundle(&encode_hr_bundle(90, extras, &config).unwrap());
+        assert_param_int(&typed, "hr_connected", 1);
+        assert_param_float(&typed, "isHRActive", 1.0, 0.0);
+        assert_param_int(&typed, "hr_alarm", 1);
+        assert_param_float(&typed, "hr_steady", 0.0, 0.0);
+        assert_param_float(&typed, "HR", 90.0, 0.0);
+
+        let cleared = decode_bundle(&encode_hr_bundle(0, OscExtras::default(), &config).unwrap());
+        assert_param_int(&cleared, "hr_connected", 0);
+        assert_param_float(&cleared, "isHRActive", 0.0, 0.0);
+        assert_param_float(&cleared, "HR", 0.0, 0.0);
+    }
+
+    #[test]
+    fn custom_parameters_are_appended_after_the_builtin_ones() {
+        let config = Config {
+            custom_osc_params: vec![
+                custom_params::CustomParam::new(
+                    "hr_scaled",
+                    custom_params::OscParamType::Float,
+                    "(bpm - 60) / 140.0",
+                ),
+                custom_params::CustomParam::new(
+                    "hr_sdnn",
+                    custom_params::OscParamType::Int,
+                    "sdnn",
+                ),
+            ],
+            ..Config::default()
+        };
+        let bundle = decode_bundle(&encode_hr_bundle(130, OscExtras::default(), &config).unwrap());
+        assert_param_float(&bundle, "hr_scaled", 0.5, 1e-6);
+        assert!(param(&bundle, "hr_sdnn").is_none(), "sdnn is unavailable");
+        match bundle.content.last() {
+            Some(rosc::OscPacket::Message(message)) => {
+                assert_eq!(message.addr, "/avatar/parameters/hr_scaled")
+            }
+            other => panic!("expected the custom parameter last, got {other:?}"),
+        }
+
+        let extras = OscExtras {
+            sdnn_ms: Some(42.4),
+            ..OscExtras::default()
+        };
+        let bundle = decode_bundle(&encode_hr_bundle(130, extras, &config).unwrap());
+        assert_param_int(&bundle, "hr_sdnn", 42);
+    }
+
+    #[test]
+    fn encoded_bundle_only_carries_stress_and_trimp_when_available() {
+        let config = Config::default();
+        let plain = decode_bundle(&encode_hr_bundle(90, OscExtras::default(), &config).unwrap());
+        assert_param_int(&plain, "HR", 90);
+        assert!(param(&plain, "hr_stress").is_none());
+        assert!(param(&plain, "hr_trimp").is_none());
+
+        let extras = OscExtras {
+            alarm: true,
+            stress: Some(0.5),
+            trimp: Some(0.25),
+            spo2: Some(97),
+            cadence_rpm: Some(172),
+            movement: Some(0.4),
+            ..OscExtras::default()
+        };
+        let full = decode_bundle(&encode_hr_bundle(90, extras, &config).unwrap());
+        // 没配置报警阈值时不发送 hr_alarm
+        assert!(param(&full, "hr_alarm").is_none());
+        let alarm_config = Config {
+            hr_alarm_high: Some(150),
+            ..Config::default()
+        };
+        let alarmed = decode_bundle(&encode_hr_bundle(90, extras, &alarm_config).unwrap());
+        assert_param_bool(&alarmed, "hr_alarm", true);
+        assert_param_float(&full, "hr_stress", 0.5, 1e-6);
+        assert_param_float(&full, "hr_trimp", 0.25, 1e-6);
+        assert_param_int(&full, "hr_spo2", 97);
+        assert_param_float(&full, "hr_spo2_float", 0.97, 1e-6);
+        assert_param_int(&full, "hr_cadence_rpm", 172);
+        assert!(param(&plain, "hr_cadence_rpm").is_none());
+        assert_param_float(&full, "hr_movement", 0.4, 1e-6);
+        assert!(param(&plain, "hr_movement").is_none());
+    }
+
+    #[test]
+    fn cadence_is_step_delta_over_window() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut cadence = CadenceEstimator::default();
+        assert_eq!(cadence.update(1000, at(0)), None);
+        // 2 秒 6 步 = 180 步/分钟
+        assert_eq!(cadence.update(1003, at(1000)), Some(180));
+        assert_eq!(cadence.update(1006, at(2000)), Some(180));
+        // 只看最近 5 秒：窗口外的读数作为起点基准，按实际间隔计算（8 秒 4 步）
+        assert_eq!(cadence.update(1010, at(10_000)), Some(30));
+        // 10 秒 10 步
+        assert_eq!(cadence.update(1016, at(12_000)), Some(60));
+        // 计数回退时重新开始
+        assert_eq!(cadence.update(5, at(13_000)), None);
+        // 上限 255
+        assert_eq!(cadence.update(100, at(14_000)), Some(255));
+    }
+
+    #[test]
+    fn parses_step_count_formats() {
+        assert_eq!(parse_step_count(&[0x10, 0x27, 0x00, 0x00]), Some(10_000));
+        assert_eq!(
+            parse_step_count(&[0x0c, 0x34, 0x12, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
+            Some(0x1234)
+        );
+        assert_eq!(parse_step_count(&[0x0c, 0x34, 0x12]), Some(0x1234));
+        assert_eq!(parse_step_count(&[0x01, 0x02]), None);
+    }
+
+    #[test]
+    fn signal_monitor_warns_once_per_weak_streak() {
+        let config = Config {
+            rssi_warn_floor: -85,
+            rssi_warn_samples: 2,
+            ..Config::default()
+        };
+        let mut signal = SignalMonitor::default();
+        assert_eq!(signal.to_string(), "N/A");
+        assert_eq!(signal.quality(), None);
+
+        assert!(!signal.update(Some(-90), &config));
+        assert!(signal.update(Some(-92), &config));
+        assert!(!signal.update(Some(-95), &config), "only once per streak");
+        assert!(!signal.update(None, &config));
+        assert!(!signal.update(Some(-90), &config), "N/A resets the streak");
+        assert!(signal.update(Some(-90), &config));
 
-        // 信号最强候选
-        if let Some(rssi) = properties.rssi {
-            if strongest_candidate
-                .as_ref()
-                .is_none_or(|(_, best)| rssi > *best)
-            {
-                strongest_candidate = Some((p.clone(), rssi));
-            }
-        }
+        signal.update(Some(-75), &config);
+        assert_eq!(signal.to_string(), "-75 dBm");
+        assert_eq!(signal.quality(), Some(0.5));
+        signal.update(Some(-30), &config);
+        assert_eq!(signal.quality(), Some(1.0));
+        signal.update(Some(-120), &config);
+        assert_eq!(signal.quality(), Some(0.0));
     }
 
-    let chosen_peripheral = match config.selection_mode.as_str() {
-        "name" => {
-            println!(
-                "\n选择模式: 按名称匹配, 关键字: {:?}",
-                config.target_device_names
-            );
-            name_match_candidate
-        }
-        "strongest" => {
-            println!("\n选择模式: 选择信号最强的设备");
-            strongest_candidate.map(|(p, _rssi)| p)
-        }
-        _ => {
-            println!(
-                "\n选择模式: 自动（优先匹配名称 {:?}，无匹配时选择信号最强）",
-                config.target_device_names
-            );
-            name_match_candidate.or(strongest_candidate.map(|(p, _rssi)| p))
-        }
-    };
+    #[test]
+    fn deduper_collapses_identical_bursts_within_window() {
+        let window = Duration::from_millis(200);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut deduper = NotificationDeduper::default();
 
-    // 无论成功与否都停止扫描
-    let _ = central.stop_scan().await;
+        // 唤醒后的突发：5 条相同通知挤在 100 ms 内
+        let accepted: Vec<bool> = [0, 20, 40, 70, 100]
+            .into_iter()
+            .map(|ms| deduper.accept(&[0x00, 72], at(ms), window))
+            .collect();
+        assert_eq!(accepted, [true, false, false, false, false]);
+        assert_eq!(deduper.suppressed, 4);
 
-    match chosen_peripheral {
-        Some(p) => {
-            let props = p.properties().await?.unwrap_or_default();
-            let name = props
-                .local_name
-                .unwrap_or_else(|| "未知设备 Unknown Device".to_string());
-            let filtered_device_name: String =
-                name.chars().filter(|c| c.is_ascii_alphanumeric()).collect();
-            println!("选择设备: {:?} ({})", filtered_device_name, p.address());
-            Ok(p)
-        }
-        None => {
-            println!("\n未找到符合条件的设备。");
-            Err(AppError::DeviceNotFound)
-        }
+        // 内容变化立即处理；窗口外的相同内容（正常的 1 Hz 通知）也照常处理
+        assert!(deduper.accept(&[0x00, 73], at(120), window));
+        assert!(deduper.accept(&[0x00, 73], at(1120), window));
+
+        let mut disabled = NotificationDeduper::default();
+        assert!(disabled.accept(&[0x00, 72], at(0), Duration::ZERO));
+        assert!(disabled.accept(&[0x00, 72], at(0), Duration::ZERO));
     }
-}
 
-/// 处理设备连接的整个生命周期。
-/// 返回 Ok(true) 表示本次连接期间至少收到过一次心率数据；
-/// 断开清理（disconnect）由调用方统一执行。
-async fn handle_device_connection(
-    device: &Peripheral,
-    socket: &UdpSocket,
-    osc_addr: SocketAddrV4,
-    config: &Config,
-    hr_file: &Path,
-) -> Result<bool> {
-    // is_connected 查询失败时视为未连接，直接尝试 connect
-    if !device.is_connected().await.unwrap_or(false) {
-        println!("\n正在连接设备 {}...", device.address());
-        ble_timeout(device.connect()).await?;
+    #[test]
+    fn backlog_keeps_only_latest_heart_rate_and_other_characteristics() {
+        let spo2 = SPO2_CHAR_UUID;
+        let notification = |uuid, value| ValueNotification {
+            uuid,
+            value: vec![0x00, value],
+        };
+        let mut pending: VecDeque<ValueNotification> = [
+            notification(HEART_RATE_CHAR_UUID, 70),
+            notification(spo2, 97),
+            notification(HEART_RATE_CHAR_UUID, 71),
+            notification(HEART_RATE_CHAR_UUID, 72),
+        ]
+        .into();
+        assert_eq!(keep_latest(&mut pending, HEART_RATE_CHAR_UUID), 2);
+        let kept: Vec<u8> = pending.iter().map(|n| n.value[1]).collect();
+        assert_eq!(kept, [97, 72]);
+
+        let mut other_only: VecDeque<ValueNotification> = [notification(spo2, 97)].into();
+        assert_eq!(keep_latest(&mut other_only, HEART_RATE_CHAR_UUID), 0);
+        assert_eq!(other_only.len(), 1);
     }
-    println!("设备连接成功！正在监听心率...");
-    println!("正在向 OSC 地址 {} 发送数据", osc_addr);
 
-    ble_timeout(device.discover_services()).await?;
+    #[test]
+    fn reading_deduper_skips_unchanged_bpm_until_interval_elapses() {
+        let config = Config {
+            dedup_identical_readings: true,
+            dedup_min_interval_ms: 500,
+            ..Config::default()
+        };
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut dedup = ReadingDeduper::default();
 
-    let hr_char = device
-        .characteristics()
-        .into_iter()
-        .find(|c| c.uuid == HEART_RATE_CHAR_UUID)
-        .ok_or(AppError::CharacteristicNotFound)?;
+        let skipped: Vec<bool> = [(0, 72), (200, 72), (400, 72), (500, 72), (600, 73)]
+            .into_iter()
+            .map(|(ms, bpm)| dedup.skip(bpm, at(ms), &config))
+            .collect();
+        assert_eq!(skipped, [false, true, true, false, false]);
+        assert_eq!(dedup.skipped, 2);
 
-    // Notify 和 Indicate 都可以订阅（btleplug 会自动选择正确的 CCCD 值）
-    if !hr_char
-        .properties
-        .intersects(CharPropFlags::NOTIFY | CharPropFlags::INDICATE)
-    {
-        eprintln!("错误：心率特征不支持通知 (Notify/Indicate)。");
-        return Err(AppError::SubscriptionFailed);
+        let mut disabled = ReadingDeduper::default();
+        assert!(!disabled.skip(72, at(0), &Config::default()));
+        assert!(!disabled.skip(72, at(0), &Config::default()));
     }
 
-    ble_timeout(device.subscribe(&hr_char)).await?;
-    let mut notification_stream = device.notifications().await?;
-    println!("已成功订阅心率通知。等待数据...");
+    #[test]
+    fn sink_only_rewrites_heart_rate_file_when_value_changes() {
+        let dir = env::temp_dir().join(format!("hr-vrc-sink-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("create temp dir");
+        let hr_file = dir.join("HeartRate.txt");
+        let receiver = UdpSocket::bind("127.0.0.1:0").expect("bind OSC receiver");
+        let osc_addr = receiver.local_addr().expect("read receiver address");
+        let sender = UdpSocket::bind("127.0.0.1:0").expect("bind OSC sender");
+        let config = Config {
+            write_heart_rate_file: true,
+            stats_interval_secs: 0,
+            ..Config::default()
+        };
+        let mut sink = HeartRateSink::new(&sender, osc_addr, &config, &hr_file);
+        let beat = |heart_rate| HeartRateMeasurement {
+            heart_rate,
+            rr_intervals: Vec::new(),
+            energy_expended: None,
+        };
 
-    let mut received_any = false;
-    // 心率数值变化时才写文件：fs::write 每次都是完整的打开/截断/写/关闭，
-    // 还可能触发杀毒软件实时扫描，是本程序最重的单个动作
-    let mut last_written_hr: Option<u8> = None;
-    // 错误只提示一次，恢复后重置（避免 VRChat 未启动时每秒刷屏）
-    let mut osc_error_shown = false;
-    let mut file_error_shown = false;
+        let flush = || file_writer::flush(Duration::from_secs(5));
 
-    // 使用 `loop` 和 `tokio::time::timeout` 来实现带超时的事件接收
-    loop {
-        match time::timeout(
-            Duration::from_secs(config.heartbeat_timeout_secs),
-            notification_stream.next(),
-        )
-        .await
-        {
-            // Case 1: 超时发生
-            Err(_) => {
-                println!(
-                    "\n未在 {} 秒内收到心率数据，认为连接已断开。",
-                    config.heartbeat_timeout_secs
-                );
-                break;
-            }
-            // Case 2: 成功接收到数据
-            Ok(Some(notification)) => {
-                if notification.uuid == HEART_RATE_CHAR_UUID && notification.value.len() >= 2 {
-                    // 解析 GATT Heart Rate Measurement: flags 位 0 决定 8/16 位格式
-                    let flag = notification.value[0];
-                    let heart_rate: u16 = if (flag & 0x01) == 0 {
-                        notification.value[1] as u16
-                    } else {
-                        if notification.value.len() < 3 {
-                            continue;
-                        }
-                        u16::from_le_bytes([notification.value[1], notification.value[2]])
-                    };
-
-                    received_any = true;
-                    let heart_rate_u8 = heart_rate.min(255) as u8;
-
-                    if config.write_heart_rate_file && last_written_hr != Some(heart_rate_u8) {
-                        match fs::write(hr_file, heart_rate_u8.to_string()) {
-                            Ok(()) => {
-                                last_written_hr = Some(heart_rate_u8);
-                                file_error_shown = false;
-                            }
-                            Err(e) => {
-                                last_written_hr = None;
-                                if !file_error_shown {
-                                    eprintln!(
-                                        "\n写入心率到文件 {} 时出错: {}（恢复前不再重复提示）",
-                                        hr_file.display(),
-                                        e
-                                    );
-                                    file_error_shown = true;
-                                }
-                            }
-                        }
-                    }
+        sink.handle(&beat(72), Instant::now());
+        flush();
+        assert_eq!(fs::read_to_string(&hr_file).unwrap(), "72");
 
-                    match send_osc(socket, osc_addr, heart_rate_u8, config) {
-                        Ok(vrc_status) => {
-                            osc_error_shown = false;
-                            print!("状态 -> {}   \r", vrc_status);
-                            let _ = io::stdout().flush();
-                        }
-                        Err(e) => {
-                            if !osc_error_shown {
-                                eprintln!(
-                                    "\n发送 OSC 数据时出错: {}（将继续重试，恢复前不再重复提示）",
-                                    e
-                                );
-                                osc_error_shown = true;
-                            }
-                        }
-                    }
-                }
-            }
-            // Case 3: 通知流正常关闭 (例如设备主动优雅断连)
-            Ok(None) => {
-                println!("\n通知流已关闭。");
-                break;
-            }
-        }
-    }
+        // 数值未变时不再写文件：删掉文件后不会被重新创建
+        fs::remove_file(&hr_file).unwrap();
+        sink.handle(&beat(72), Instant::now());
+        flush();
+        assert!(!hr_file.exists());
 
-    // 释放订阅；断开连接由调用方统一处理
-    let _ = device.unsubscribe(&hr_char).await;
-    Ok(received_any)
-}
+        sink.handle(&beat(75), Instant::now());
+        flush();
+        assert_eq!(fs::read_to_string(&hr_file).unwrap(), "75");
 
-// --- 主应用程序逻辑 ---
-async fn main_loop(config: &Config, osc_addr: SocketAddrV4, hr_file: &Path) -> Result<()> {
-    let manager = Manager::new().await?;
+        // 清零（空闲）后写入离线内容；恢复后相同的数值也要重新写入
+        sink.clear();
+        flush();
+        assert_eq!(
+            fs::read_to_string(&hr_file).unwrap(),
+            config.heart_rate_file_offline
+        );
+        sink.handle(&beat(75), Instant::now());
+        flush();
+        assert_eq!(fs::read_to_string(&hr_file).unwrap(), "75");
 
-    // 一次性创建 UDP 套接字并复用（用 send_to 发送）
-    let socket = UdpSocket::bind("0.0.0.0:0")?;
-    println!("OSC Socket 已创建，将发送到 {}", osc_addr);
+        let _ = fs::remove_dir_all(&dir);
+    }
 
-    loop {
-        // 用于扫描的外部循环
-        let device = match find_target_device(&manager, config).await {
-            Ok(p) => p,
-            Err(e) => {
-                println!("\n错误: {}\n请检查设备是否在附近，电脑蓝牙是否开启。设备是否被其它心率接收设备连接。", e);
-                println!("将在 {} 秒后重试扫描...", config.retry_delay_secs);
-                time::sleep(Duration::from_secs(config.retry_delay_secs)).await;
-                continue;
-            }
+    #[test]
+    fn steady_state_needs_full_calm_window_and_exits_on_variation() {
+        let config = Config {
+            steady_state_mute: true,
+            ..Config::default()
         };
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut detector = SteadyStateDetector::default();
 
-        // 内部循环：对同一设备重试连接。
-        // 连续 MAX_CONSECUTIVE_FAILURES 次未收到任何心率数据则放弃该设备、重新扫描
-        // （设备可能已关机/走远/更换了随机 MAC 地址）。
-        let mut consecutive_failures: u32 = 0;
-        loop {
-            let received_any =
-                match handle_device_connection(&device, &socket, osc_addr, config, hr_file).await {
-                    Ok(received) => received,
-                    Err(e) => {
-                        eprintln!("\n处理连接时发生错误: {}", e);
-                        false
-                    }
-                };
-
-            // 无论因超时、流关闭还是错误退出，都显式断开连接，
-            // 确保下一轮能重新走完整的 connect/subscribe 流程，
-            // 避免链路残留导致"看似在重连、实际永不重订阅"的死循环。
-            let _ = device.disconnect().await;
-
-            // 断开期间向 VRChat 发送未佩戴状态，并清零 HeartRate.txt
-            clear_state(&socket, osc_addr, config, hr_file);
+        // 窗口未满 5 分钟时不进入静息
+        for secs in 0..300 {
+            let hr = if secs % 2 == 0 { 64 } else { 66 };
+            assert_eq!(detector.update(hr, at(secs), &config), None);
+        }
+        assert_eq!(detector.update(65, at(300), &config), Some(true));
+        assert!(detector.is_active());
+        assert_eq!(detector.update(65, at(301), &config), None);
 
-            if received_any {
-                consecutive_failures = 0;
-            } else {
-                consecutive_failures += 1;
-                if consecutive_failures >= MAX_CONSECUTIVE_FAILURES {
-                    println!(
-                        "\n连续 {} 次未能从设备获取心率数据，将重新开始扫描...",
-                        consecutive_failures
-                    );
-                    break;
-                }
+        // 心率突然变化、标准差超过阈值后立即退出
+        let mut exited = None;
+        for secs in 302..340 {
+            if let Some(state) = detector.update(110, at(secs), &config) {
+                exited = Some(state);
+                break;
             }
-
-            println!(
-                "\n连接已断开。将在 {} 秒后尝试重新连接...",
-                config.retry_delay_secs
-            );
-            time::sleep(Duration::from_secs(config.retry_delay_secs)).await;
         }
-    }
-}
+        assert_eq!(exited, Some(false));
 
-/// Unix 上让退出信号与长期运行的蓝牙循环竞争，确保进程终止前发送清零状态。
-#[cfg(unix)]
-async fn run_application(config: &Config, osc_addr: SocketAddrV4, hr_file: &Path) -> Result<()> {
-    tokio::select! {
-        result = main_loop(config, osc_addr, hr_file) => result,
-        signal_result = wait_for_exit_signal() => {
-            signal_result?;
-            println!("\n收到退出信号，正在清理状态...");
-            run_exit_cleanup();
-            Ok(())
+        // 平均心率不低于 resting_hr_threshold 时不算静息
+        let mut fast = SteadyStateDetector::default();
+        for secs in 0..=300 {
+            fast.update(80, at(secs), &config);
         }
+        assert!(!fast.is_active());
+
+        // 摘下设备（心率 0）重新计时
+        detector.update(0, at(400), &config);
+        assert_eq!(detector.update(65, at(401), &config), None);
     }
-}
 
-#[cfg(not(unix))]
-async fn run_application(config: &Config, osc_addr: SocketAddrV4, hr_file: &Path) -> Result<()> {
-    main_loop(config, osc_addr, hr_file).await
-}
+    #[test]
+    fn steady_state_reports_inactive_over_osc() {
+        let config = Config {
+            steady_state_mute: true,
+            ..Config::default()
+        };
+        let extras = OscExtras {
+            steady: true,
+            ..OscExtras::default()
+        };
+        let bundle = decode_bundle(&encode_hr_bundle(65, extras, &config).unwrap());
+        assert_param_bool(&bundle, "isHRActive", false);
+        assert_param_bool(&bundle, "hr_connected", true);
+        assert_param_bool(&bundle, "hr_steady", true);
 
-/// 出错退出前暂停，避免双击运行时窗口一闪而过看不到错误信息。
-fn pause_before_exit() {
-    println!("按回车键退出...");
-    let mut line = String::new();
-    let _ = io::stdin().read_line(&mut line);
-}
+        // 未开启静息检测时不发送 hr_steady
+        let plain =
+            decode_bundle(&encode_hr_bundle(65, OscExtras::default(), &Config::default()).unwrap());
+        assert!(param(&plain, "hr_steady").is_none());
+    }
 
-// 单线程运行时足矣：本程序每秒只处理一条蓝牙通知，
-// 默认的多线程运行时会按 CPU 核数起 worker 线程，纯属浪费。
-#[tokio::main(flavor = "current_thread")]
-async fn main() {
-    println!("HeartRate For VRChat v{}", env!("CARGO_PKG_VERSION"));
-    println!(
-        "1.通过蓝牙连接心率设备（任何标准 GATT 心率服务 0x180D 设备），将心率发送至 VRChat OSC"
-    );
-    println!("2.可选：在 config.toml 中开启 write_heart_rate_file 后，心率会同步写入程序目录下的 HeartRate.txt（供 OBS 等软件使用，默认关闭）");
-    println!("3.连接模式、设备名、OSC 地址等可在程序目录下的 config.toml 中修改");
-    println!("发送的 OSC 参数列表见 README（hr_connected / isHRActive / hr_percent / VRCOSC Normalised / HR）");
-    println!("适配预制件1：https://booth.pm/ja/items/6224828");
-    println!("适配预制件2：https://booth.pm/ja/items/7197938");
-    println!("Author 箱天: 喵喵喵———— ");
-    println!();
+    #[test]
+    fn parse_hex_accepts_spaced_byte_strings() {
+        assert_eq!(parse_hex("01"), Some(vec![0x01]));
+        assert_eq!(parse_hex("15 01 0a"), Some(vec![0x15, 0x01, 0x0A]));
+        assert_eq!(parse_hex(""), Some(Vec::new()));
+        assert_eq!(parse_hex("1"), None);
+        assert_eq!(parse_hex("zz"), None);
+        assert_eq!(parse_hex("é1"), None);
+    }
 
-    let dir = exe_dir();
-    let config = load_config(&dir);
-    let hr_file = dir.join("HeartRate.txt");
+    #[test]
+    fn idle_tracker_enters_after_zero_streak_and_resumes_on_first_beat() {
+        let config = Config {
+            idle_after_zero_readings: 3,
+            ..Config::default()
+        };
+        let mut tracker = IdleTracker::default();
 
-    let osc_addr = resolve_osc_addr(&config);
+        assert_eq!(tracker.update(0, &config), None);
+        assert_eq!(tracker.update(0, &config), None);
+        assert_eq!(tracker.update(72, &config), None, "streak broken");
+        assert_eq!(tracker.update(0, &config), None);
+        assert_eq!(tracker.update(0, &config), None);
+        assert_eq!(tracker.update(0, &config), Some(true));
+        assert_eq!(tracker.update(0, &config), None, "still idle");
+        assert!(tracker.idle);
+        assert_eq!(tracker.update(68, &config), Some(false));
+        assert!(!tracker.idle);
 
-    // 初始化各平台共用的退出清理上下文。
-    let _ = CLEANUP_CTX.set(CleanupCtx {
-        osc_addr,
-        config: config.clone(),
-        hr_file: hr_file.clone(),
-    });
-    #[cfg(windows)]
-    if !register_exit_handler() {
-        eprintln!("注册退出清理处理器失败（退出时 VRChat 可能残留最后一次心率）。");
+        let disabled = Config {
+            idle_after_zero_readings: 0,
+            ..Config::default()
+        };
+        let mut tracker = IdleTracker::default();
+        for _ in 0..100 {
+            assert_eq!(tracker.update(0, &disabled), None);
+        }
     }
 
-    if let Err(e) = run_application(&config, osc_addr, &hr_file).await {
-        eprintln!("\n发生错误: {}", e);
-        eprintln!("请检查系统是否有蓝牙适配器、蓝牙服务是否已启动。");
-        pause_before_exit();
-        return;
+    #[test]
+    fn auth_key_must_be_sixteen_hex_bytes() {
+        let key = "0x00112233445566778899aabbccddeeff";
+        assert_eq!(
+            auth_key_bytes(key),
+            Some([
+                0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD,
+                0xEE, 0xFF
+            ])
+        );
+        assert_eq!(auth_key_bytes(&key[2..]), auth_key_bytes(key));
+        assert_eq!(auth_key_bytes("0011"), None);
+        assert_eq!(auth_key_bytes("not a key"), None);
     }
 
-    println!("\n程序已停止。");
-}
+    #[test]
+    fn heart_rate_file_is_replaced_without_leaving_temp_file() {
+        let dir = env::temp_dir().join(format!("hr-vrc-atomic-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("create temp dir");
+        let hr_file = dir.join("HeartRate.txt");
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::net::SocketAddr;
+        replace_file(&hr_file, "72").expect("write");
+        replace_file(&hr_file, "128").expect("overwrite");
+        assert_eq!(fs::read_to_string(&hr_file).unwrap(), "128");
+        assert!(!hr_file.with_extension("txt.tmp").exists());
 
-    fn receive_packet(socket: &UdpSocket) -> rosc::OscPacket {
-        let mut buf = [0_u8; 2048];
-        let (len, _) = socket.recv_from(&mut buf).expect("receive OSC packet");
-        let (remaining, packet) = rosc::decoder::decode_udp(&buf[..len]).expect("decode OSC");
-        assert!(remaining.is_empty());
-        packet
+        let _ = fs::remove_dir_all(&dir);
     }
 
-    fn message_args<'a>(packet: &'a rosc::OscPacket, address: &str) -> &'a [rosc::OscType] {
-        let rosc::OscPacket::Bundle(bundle) = packet else {
-            panic!("expected OSC bundle");
+    #[test]
+    fn dump_config_redacts_secrets() {
+        let config = Config {
+            auth_key: Some("key-secret".to_string()),
+            obs_password: Some("obs-secret".to_string()),
+            mqtt_password: Some("mqtt-secret".to_string()),
+            influx_token: Some("influx-secret".to_string()),
+            webhook_url: "https://example.com/hook-secret".to_string(),
+            discord_webhook_url: "https://discord.com/api/webhooks/1/discord-secret".to_string(),
+            twitch_oauth_token: Some("twitch-secret".to_string()),
+            pulsoid_token: Some("pulsoid-secret".to_string()),
+            ..Config::default()
         };
+        let dump = dump_config(&config).expect("config should serialize");
 
-        bundle
-            .content
-            .iter()
-            .find_map(|packet| match packet {
-                rosc::OscPacket::Message(message) if message.addr == address => {
-                    Some(message.args.as_slice())
-                }
-                _ => None,
-            })
-            .unwrap_or_else(|| panic!("missing OSC message {address}"))
+        assert!(!dump.contains("secret"), "secrets leaked: {}", dump);
+        assert!(dump.contains("auth_key = \"***\"  # (overridden)"));
+        assert!(dump.contains("webhook_url = \"***\"  # (overridden)"));
+        // 未设置的项保持原样
+        let unset = dump_config(&Config::default()).expect("config should serialize");
+        assert!(!unset.contains("***"));
     }
 
     #[test]
-    fn config_template_is_valid_and_uses_default_osc_target() {
-        let config: Config = toml::from_str(CONFIG_TEMPLATE).expect("parse config template");
+    fn mark_overridden_flags_changed_keys_and_tables() {
+        let parse = |text: &str| toml::Value::Table(toml::from_str(text).unwrap());
+        let default = parse(
+            "osc_port = 9000\nosc_ip = \"127.0.0.1\"\nhr_char_formats = {}\n\
+             outputs = { hr = true, osc = { bundle = true } }\n\
+             nested = { inner = { port = 9000 } }\nitems = [{ name = \"a\" }]\n",
+        );
+        let text = "osc_port = 9001\nosc_ip = \"127.0.0.1\"\ninline.a.b = 1\n\n\
+                    [hr_char_formats]\nabc = \"raw-u8\"\n\n\
+                    [outputs]\nhr = true\nosc.bundle = false\n\n\
+                    [nested.inner]\nport = 9001\n\n\
+                    [[items]]\nname = \"a\"\n\n[[items]]\nname = \"b\"\n";
+        let current = parse(text);
 
-        assert_eq!(config, Config::default());
         assert_eq!(
-            resolve_osc_addr(&config),
-            SocketAddrV4::new(Ipv4Addr::LOCALHOST, 9000)
+            mark_overridden(text, &current, &default),
+            "osc_port = 9001  # (overridden)\nosc_ip = \"127.0.0.1\"\n\
+             inline.a.b = 1  # (overridden)\n\n\
+             [hr_char_formats]  # (overridden)\nabc = \"raw-u8\"  # (overridden)\n\n\
+             [outputs]  # (overridden)\nhr = true\nosc.bundle = false  # (overridden)\n\n\
+             [nested.inner]  # (overridden)\nport = 9001  # (overridden)\n\n\
+             [[items]]\nname = \"a\"\n\n\
+             [[items]]  # (overridden)\nname = \"b\"  # (overridden)\n"
         );
     }
 
     #[test]
-    fn resolve_osc_addr_preserves_valid_remote_ipv4_and_port() {
+    fn heart_rate_file_defaults_to_exe_dir_and_accepts_absolute_path() {
+        let dir = env::temp_dir().join(format!("hr-vrc-path-test-{}", std::process::id()));
+        assert_eq!(
+            heart_rate_file(&dir, &Config::default()),
+            dir.join("HeartRate.txt")
+        );
+
+        let absolute = env::temp_dir().join("obs").join("hr.txt");
         let config = Config {
-            osc_ip: "192.168.1.42".to_string(),
-            osc_port: 9123,
+            heart_rate_file_path: Some(absolute.clone()),
+            ..Config::default()
+        };
+        assert_eq!(heart_rate_file(&dir, &config), absolute);
+
+        let nested = Config {
+            heart_rate_file_path: Some(PathBuf::from("obs/hr.txt")),
             ..Config::default()
         };
+        let hr_file = heart_rate_file(&dir, &nested);
+        prepare_heart_rate_file(&hr_file, &Config::default()).expect("create parent and write");
+        assert_eq!(fs::read_to_string(&hr_file).unwrap(), "0");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn osc_timetag_uses_ntp_epoch() {
+        let unix_start = osc_timetag(UNIX_EPOCH);
+        assert_eq!(unix_start.seconds, 2_208_988_800);
+        assert_eq!(unix_start.fractional, 0);
+
+        let half = osc_timetag(UNIX_EPOCH + Duration::from_millis(1_500));
+        assert_eq!(half.seconds, 2_208_988_801);
+        assert_eq!(half.fractional, 1 << 31);
+
+        // 2024-01-01T00:00:00Z = Unix 1_704_067_200 = NTP 3_913_056_000
+        let quarter = osc_timetag(UNIX_EPOCH + Duration::new(1_704_067_200, 250_000_000));
+        assert_eq!(quarter.seconds, 3_913_056_000);
+        assert_eq!(quarter.fractional, 1 << 30);
+        // 1 纳秒 ≈ 4.29 个 2^-32 秒单位，向下取整
+        let nano = osc_timetag(UNIX_EPOCH + Duration::new(0, 1));
+        assert_eq!(nano.fractional, 4);
+        let almost = osc_timetag(UNIX_EPOCH + Duration::new(0, 999_999_999));
+        assert_eq!(almost.fractional, u32::MAX - 4);
 
+        // 1970 年之前无法表示，退回 "immediately"
         assert_eq!(
-            resolve_osc_addr(&config),
-            SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 42), 9123)
+            osc_timetag(UNIX_EPOCH - Duration::from_secs(1)),
+            OscTimetag::IMMEDIATELY
+        );
+        assert_eq!(
+            OscTimetag::Immediate.at(SystemTime::now()),
+            rosc::OscTime {
+                seconds: 0,
+                fractional: 1
+            }
+        );
+        assert_eq!(
+            OscTimetag::WallClock.at(UNIX_EPOCH),
+            osc_timetag(UNIX_EPOCH)
         );
     }
 
     #[test]
-    fn resolve_osc_addr_falls_back_to_localhost_and_preserves_port() {
+    fn heart_rate_file_content_uses_format_and_offline_text() {
         let config = Config {
-            osc_ip: "not-an-ip".to_string(),
-            osc_port: 9456,
+            heart_rate_file_format: "❤ {hr:03} {percent}% Z{zone} avg {avg}".to_string(),
+            heart_rate_file_offline: "离线".to_string(),
             ..Config::default()
         };
-
         assert_eq!(
-            resolve_osc_addr(&config),
-            SocketAddrV4::new(Ipv4Addr::LOCALHOST, 9456)
+            heart_rate_file_content(150, &[140, 145], &config),
+            "❤ 150 75% Z3 avg 145"
         );
+        assert_eq!(heart_rate_file_content(0, &[140], &config), "离线");
+        assert_eq!(heart_rate_file_content(72, &[], &Config::default()), "72");
     }
 
     #[test]
-    fn configured_destination_receives_normal_and_cleared_osc_state() {
-        let receiver = UdpSocket::bind("127.0.0.1:0").expect("bind OSC receiver");
-        receiver
-            .set_read_timeout(Some(Duration::from_secs(1)))
-            .expect("set receive timeout");
-        let receiver_addr = match receiver.local_addr().expect("read receiver address") {
-            SocketAddr::V4(addr) => addr,
-            SocketAddr::V6(_) => panic!("expected IPv4 receiver"),
-        };
-        let sender = UdpSocket::bind("127.0.0.1:0").expect("bind OSC sender");
+    fn output_toggles_apply_to_osc_bundle() {
         let config = Config {
-            osc_ip: receiver_addr.ip().to_string(),
-            osc_port: receiver_addr.port(),
+            outputs: OutputToggles {
+                hr: false,
+                zone: true,
+                ..OutputToggles::default()
+            },
             ..Config::default()
         };
-        let osc_addr = resolve_osc_addr(&config);
+        let bundle = decode_bundle(&encode_hr_bundle(150, OscExtras::default(), &config).unwrap());
+        assert!(param(&bundle, "HR").is_none());
+        assert_param_int(&bundle, "hr_zone", 3);
+        assert_param_bool(&bundle, "hr_connected", true);
+    }
 
-        send_osc(&sender, osc_addr, 77, &config).expect("send normal OSC state");
-        let normal_packet = receive_packet(&receiver);
-        assert_eq!(
-            message_args(&normal_packet, "/avatar/parameters/HR"),
-            [rosc::OscType::Int(77)]
-        );
+    #[test]
+    fn platform_preset_is_layered_under_explicit_settings() {
+        let addrs = |config: &Config| -> Vec<String> {
+            decode_bundle(&encode_hr_bundle(120, OscExtras::default(), config).unwrap())
+                .content
+                .into_iter()
+                .map(|packet| match packet {
+                    rosc::OscPacket::Message(message) => message.addr,
+                    rosc::OscPacket::Bundle(_) => panic!("unexpected nested bundle"),
+                })
+                .collect()
+        };
+
+        let cvr: Config = toml::from_str("osc_platform = \"chilloutvr\"").unwrap();
+        assert_eq!(cvr.osc_port(), 9000);
+        let cvr_addrs = addrs(&cvr);
+        assert!(cvr_addrs.contains(&"/avatar/parameter/HR".to_string()));
+        assert!(cvr_addrs
+            .iter()
+            .all(|addr| addr.starts_with("/avatar/parameter/")));
+        assert!(!cvr_addrs.iter().any(|addr| addr.ends_with("isHRActive")));
+        assert!(!cvr_addrs.iter().any(|addr| addr.contains("VRCOSC")));
+
+        let overridden: Config = toml::from_str(
+            "osc_platform = \"chilloutvr\"\nosc_port = 9100\n\
+             osc_parameter_prefix = \"/custom/\"\nosc_compat_parameters = true\n\
+             [outputs]\nhr = false\n",
+        )
+        .unwrap();
         assert_eq!(
-            message_args(&normal_packet, "/avatar/parameters/hr_connected"),
-            [rosc::OscType::Bool(true)]
+            resolve_osc_addr(&overridden),
+            SocketAddr::from((Ipv4Addr::LOCALHOST, 9100))
         );
+        let overridden_addrs = addrs(&overridden);
+        assert!(overridden_addrs.contains(&"/custom/isHRActive".to_string()));
+        assert!(overridden_addrs.contains(&"/custom/hr_percent".to_string()));
+        assert!(!overridden_addrs.contains(&"/custom/HR".to_string()));
 
-        clear_state(
-            &sender,
-            osc_addr,
-            &config,
-            Path::new("unused-heart-rate.txt"),
+        let vrchat = Config::default();
+        assert!(
+            addrs(&vrchat).contains(&"/avatar/parameters/VRCOSC/Heartrate/Normalised".to_string())
         );
-        let cleared_packet = receive_packet(&receiver);
+    }
+
+    #[test]
+    fn split_files_are_written_only_for_enabled_outputs() {
+        let dir = env::temp_dir().join(format!("hr-vrc-split-test-{}", std::process::id()));
+        let hr_file = dir.join("HeartRate.txt");
+        let sender = UdpSocket::bind("127.0.0.1:0").expect("bind OSC sender");
+        let config = Config {
+            write_split_files: true,
+            stats_interval_secs: 0,
+            outputs: OutputToggles {
+                percent: false,
+                ..OutputToggles::default()
+            },
+            ..Config::default()
+        };
+        prepare_split_files(&hr_file, &config).expect("prepare split files");
         assert_eq!(
-            message_args(&cleared_packet, "/avatar/parameters/HR"),
-            [rosc::OscType::Int(0)]
+            fs::read_to_string(dir.join("HRConnected.txt")).unwrap(),
+            "false"
+        );
+
+        let osc_addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 9));
+        let mut sink = HeartRateSink::new(&sender, osc_addr, &config, &hr_file);
+        sink.handle(
+            &HeartRateMeasurement {
+                heart_rate: 100,
+                rr_intervals: Vec::new(),
+                energy_expended: None,
+            },
+            Instant::now(),
         );
+        file_writer::flush(Duration::from_secs(5));
+        assert_eq!(fs::read_to_string(dir.join("HR.txt")).unwrap(), "100");
         assert_eq!(
-            message_args(&cleared_packet, "/avatar/parameters/hr_connected"),
-            [rosc::OscType::Bool(false)]
+            fs::read_to_string(dir.join("HRConnected.txt")).unwrap(),
+            "true"
         );
+        assert!(!dir.join("HRPercent.txt").exists());
+        assert!(!dir.join("HRZone.txt").exists());
+        assert!(!hr_file.exists());
+
+        let _ = fs::remove_dir_all(&dir);
     }
 }
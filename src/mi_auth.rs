@@ -0,0 +1,206 @@
+//! 小米手环认证：Mi Band 2–4 等使用旧版（华米）协议的手环要求客户端先完成认证握手才保持连接/推送心率。
+//!
+//! 握手走私有服务 0xFEE1 下的认证特征：请求随机数 `02 00` → 手环回 `10 02 01` + 16 字节随机数 →
+//! 用每台设备各自的认证密钥（auth_key）做 AES-128-ECB 加密后回写 `03 00` + 密文 →
+//! 手环回 `10 03 01` 表示认证成功。认证密钥与手环绑定，只能从官方 App 的账号数据中取得。
+//!
+//! Xiaomi Smart Band 8 及之后的型号改用小米的新版认证协议，没有上述认证特征，这里不做握手；
+//! 这些型号开启"心率广播"后通过标准心率服务连接，不需要 auth_key。订阅失败或一直没有心率时，
+//! `setup_error` 按设备有无旧版认证特征给出需要 auth_key 或需要开启心率广播的具体错误。
+
+use std::time::Duration;
+
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockEncrypt, KeyInit};
+use aes::{Aes128, Block};
+use btleplug::api::{Peripheral as _, WriteType};
+use btleplug::platform::Peripheral;
+use futures_util::StreamExt;
+use tokio::time;
+use uuid::Uuid;
+
+use crate::{ble_timeout, AppError, Config, Result};
+
+const MI_SERVICE_FEE0_UUID: Uuid = Uuid::from_u128(0x0000fee0_0000_1000_8000_00805f9b34fb);
+const MI_SERVICE_FEE1_UUID: Uuid = Uuid::from_u128(0x0000fee1_0000_1000_8000_00805f9b34fb);
+const AUTH_CHAR_UUID: Uuid = Uuid::from_u128(0x00000009_0000_3512_2118_0009af100700);
+
+/// 广播名称包含这些关键字（不区分大小写）的设备视为小米设备。
+const XIAOMI_NAME_KEYWORDS: [&str; 3] = ["xiaomi", "mi smart band", "redmi"];
+
+const REQUEST_CHALLENGE: [u8; 2] = [0x02, 0x00];
+const SEND_RESPONSE: [u8; 2] = [0x03, 0x00];
+
+/// 按广播名称或服务列表判断是否为小米设备（只对这些设备尝试认证）。
+pub fn is_xiaomi(name: Option<&str>, services: &[Uuid]) -> bool {
+    name.is_some_and(|name| {
+        let name = name.to_lowercase();
+        XIAOMI_NAME_KEYWORDS.iter().any(|k| name.contains(k))
+    }) || services
+        .iter()
+        .any(|s| *s == MI_SERVICE_FEE0_UUID || *s == MI_SERVICE_FEE1_UUID)
+}
+
+/// 从手环的 `10 02 01` 应答中取出 16 字节随机数。
+fn parse_challenge(value: &[u8]) -> Option<[u8; 16]> {
+    match value {
+        [0x10, 0x02, 0x01, challenge @ ..] => challenge.try_into().ok(),
+        _ => None,
+    }
+}
+
+/// 用认证密钥加密随机数，组成回写给手环的数据。
+fn challenge_response(key: &[u8; 16], challenge: &[u8; 16]) -> Vec<u8> {
+    let cipher = Aes128::new(&GenericArray::from(*key));
+    let mut block = Block::from(*challenge);
+    cipher.encrypt_block(&mut block);
+    let mut response = SEND_RESPONSE.to_vec();
+    response.extend_from_slice(&block);
+    response
+}
+
+/// 已发现的服务中是否有旧版认证特征（只有这些手环需要 auth_key）。
+pub fn has_auth_characteristic(device: &Peripheral) -> bool {
+    device
+        .characteristics()
+        .iter()
+        .any(|c| c.uuid == AUTH_CHAR_UUID)
+}
+
+/// 小米设备订阅失败或一直没有心率时的具体原因：有旧版认证特征但未配置 auth_key 时为
+/// `AuthRequired`，没有该特征（Smart Band 8 及之后的型号）时为 `XiaomiBroadcastRequired`；
+/// 已配置密钥并完成握手时为 None，沿用原来的错误。
+pub fn setup_error(has_auth_characteristic: bool, auth_key_configured: bool) -> Option<AppError> {
+    match (has_auth_characteristic, auth_key_configured) {
+        (true, false) => Some(AppError::AuthRequired),
+        (true, true) => None,
+        (false, _) => Some(AppError::XiaomiBroadcastRequired),
+    }
+}
+
+/// 对已发现服务的设备执行认证握手，返回是否进行了握手。
+/// 设备没有旧版认证特征时（较新的型号或无需认证的固件）不握手，返回 `Ok(false)`。
+pub async fn authenticate(device: &Peripheral, key: &[u8; 16], config: &Config) -> Result<bool> {
+    let Some(auth_char) = device
+        .characteristics()
+        .into_iter()
+        .find(|c| c.uuid == AUTH_CHAR_UUID)
+    else {
+        return Ok(false);
+    };
+
+    let mut notifications = device.notifications().await?;
+    ble_timeout(
+        "subscribe",
+        config.service_timeout_secs,
+        device.subscribe(&auth_char),
+    )
+    .await?;
+    let timeout = Duration::from_secs(config.service_timeout_secs);
+
+    let result = async {
+        ble_timeout(
+            "write",
+            config.service_timeout_secs,
+            device.write(&auth_char, &REQUEST_CHALLENGE, WriteType::WithoutResponse),
+        )
+        .await?;
+        let challenge = loop {
+            match time::timeout(timeout, notifications.next()).await {
+                Ok(Some(n)) if n.uuid == AUTH_CHAR_UUID => match parse_challenge(&n.value) {
+                    Some(challenge) => break challenge,
+                    None => return Err(AppError::AuthFailed),
+                },
+                Ok(Some(_)) => {}
+                Ok(None) | Err(_) => return Err(AppError::AuthFailed),
+            }
+        };
+
+        ble_timeout(
+            "write",
+            config.service_timeout_secs,
+            device.write(
+                &auth_char,
+                &challenge_response(key, &challenge),
+                WriteType::WithoutResponse,
+            ),
+        )
+        .await?;
+        loop {
+            match time::timeout(timeout, notifications.next()).await {
+                Ok(Some(n)) if n.uuid == AUTH_CHAR_UUID => {
+                    return if n.value.starts_with(&[0x10, 0x03, 0x01]) {
+                        Ok(())
+                    } else {
+                        Err(AppError::AuthFailed)
+                    };
+                }
+                Ok(Some(_)) => {}
+                Ok(None) | Err(_) => return Err(AppError::AuthFailed),
+            }
+        }
+    }
+    .await;
+
+    let _ = ble_timeout(
+        "unsubscribe",
+        config.service_timeout_secs,
+        device.unsubscribe(&auth_char),
+    )
+    .await;
+    result.map(|()| true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognises_xiaomi_by_name_or_service() {
+        assert!(is_xiaomi(Some("Xiaomi Smart Band 9"), &[]));
+        assert!(is_xiaomi(Some("Mi Smart Band 6"), &[]));
+        assert!(is_xiaomi(None, &[MI_SERVICE_FEE0_UUID]));
+        assert!(!is_xiaomi(Some("HUAWEI WATCH"), &[]));
+        assert!(!is_xiaomi(None, &[]));
+    }
+
+    #[test]
+    fn setup_error_depends_on_the_legacy_auth_characteristic() {
+        assert!(matches!(
+            setup_error(true, false),
+            Some(AppError::AuthRequired)
+        ));
+        assert!(setup_error(true, true).is_none());
+        assert!(matches!(
+            setup_error(false, false),
+            Some(AppError::XiaomiBroadcastRequired)
+        ));
+        assert!(matches!(
+            setup_error(false, true),
+            Some(AppError::XiaomiBroadcastRequired)
+        ));
+    }
+
+    #[test]
+    fn challenge_must_carry_sixteen_bytes() {
+        let mut value = vec![0x10, 0x02, 0x01];
+        value.extend(1..=16);
+        let expected: [u8; 16] = core::array::from_fn(|i| i as u8 + 1);
+        assert_eq!(parse_challenge(&value), Some(expected));
+        assert_eq!(parse_challenge(&value[..10]), None);
+        assert_eq!(parse_challenge(&[0x10, 0x02, 0x04]), None);
+    }
+
+    #[test]
+    fn response_is_the_aes_encrypted_challenge() {
+        // FIPS-197 附录 C.1 的 AES-128 测试向量
+        let key: [u8; 16] = core::array::from_fn(|i| i as u8);
+        let challenge: [u8; 16] = core::array::from_fn(|i| (i as u8) * 0x11);
+        let mut expected = SEND_RESPONSE.to_vec();
+        expected.extend_from_slice(&[
+            0x69, 0xc4, 0xe0, 0xd8, 0x6a, 0x7b, 0x04, 0x30, 0xd8, 0xcd, 0xb7, 0x80, 0x70, 0xb4,
+            0xc5, 0x5a,
+        ]);
+        assert_eq!(challenge_response(&key, &challenge), expected);
+    }
+}
@@ -0,0 +1,247 @@
+//! MQTT 输出（`mqtt_enabled = true`）：作为 MQTT 客户端连接 `mqtt_host:mqtt_port`，
+//! 状态每次变化时发布三个保留（retained）消息，供 Home Assistant 等家庭自动化系统使用：
+//!
+//! - `mqtt_topic`（默认 `heartrate/bpm`）：当前心率，纯文本数字，未佩戴或未连接时为 `0`；
+//! - `mqtt_state_topic`（默认 `heartrate/state`）：与 status.json 字段相同的 JSON（见 status_file 模块）；
+//! - `mqtt_availability_topic`（默认 `heartrate/availability`）：已连接设备时为 `online`，
+//!   设备断开时为 `offline`。该主题同时注册为遗嘱（LWT），程序退出或崩溃、与代理断开时由代理改为 `offline`。
+//!
+//! 只发布、不订阅任何主题。状态经 `publish_status` 写入一个 watch 通道，由后台任务发布，
+//! 心率处理路径上只有一次通道写入，代理不可用时不影响 OSC 发送；来不及发布的中间状态直接跳过。
+//! 与代理断开后按 1、2、4……最长 30 秒的间隔重连，连上后立即重新发布当前状态。
+//! 开启 `mqtt_ha_discovery` 时还会发布 Home Assistant 自动发现消息（见 ha_discovery 模块）。
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rumqttc::{AsyncClient, Event, EventLoop, LastWill, MqttOptions, Packet, QoS, Transport};
+use tokio::sync::watch;
+use tokio::time;
+use tracing::{debug, info, warn};
+
+use crate::status_file::{self, Status};
+use crate::{ha_discovery, Config};
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+/// 客户端请求队列长度：每次状态变化发布 3 条消息（首次连接设备时另加 3 条发现消息），留出余量
+const REQUEST_CAPACITY: usize = 16;
+
+const ONLINE: &str = "online";
+const OFFLINE: &str = "offline";
+
+/// 发布参数（来自配置）。
+struct Topics {
+    bpm: String,
+    state: String,
+    availability: String,
+    qos: QoS,
+}
+
+impl Topics {
+    fn new(config: &Config) -> Self {
+        Topics {
+            bpm: config.mqtt_topic.clone(),
+            state: config.mqtt_state_topic.clone(),
+            availability: config.mqtt_availability_topic.clone(),
+            qos: qos(config.mqtt_qos),
+        }
+    }
+}
+
+/// 配置中的 0 / 1 / 2 对应 MQTT 的三个服务质量等级；其他值已在加载配置时纠正。
+fn qos(level: u8) -> QoS {
+    match level {
+        0 => QoS::AtMostOnce,
+        2 => QoS::ExactlyOnce,
+        _ => QoS::AtLeastOnce,
+    }
+}
+
+fn options(config: &Config, topics: &Topics) -> MqttOptions {
+    let mut options = MqttOptions::new(
+        config.mqtt_client_id.clone(),
+        config.mqtt_host.clone(),
+        config.mqtt_port,
+    );
+    options.set_keep_alive(KEEP_ALIVE);
+    options.set_last_will(LastWill::new(
+        topics.availability.clone(),
+        OFFLINE,
+        topics.qos,
+        true,
+    ));
+    if let Some(username) = &config.mqtt_username {
+        options.set_credentials(
+            username.clone(),
+            config.mqtt_password.clone().unwrap_or_default(),
+        );
+    }
+    if config.mqtt_tls {
+        // 使用系统信任的根证书验证代理
+        options.set_transport(Transport::tls_with_default_config());
+    }
+    options
+}
+
+/// 最新状态；退出时丢弃发送端，后台任务随之结束。
+static LATEST: Mutex<Option<watch::Sender<Status>>> = Mutex::new(None);
+
+/// 启动时调用（需在 tokio 运行时内）：在后台连接代理，之后 `publish` 才会生效。
+pub fn start(config: &Config) {
+    let topics = Topics::new(config);
+    let (client, eventloop) = AsyncClient::new(options(config, &topics), REQUEST_CAPACITY);
+    let publisher = Arc::new(Publisher {
+        client,
+        topics,
+        discovery: ha_discovery::Settings::new(config),
+        announced: Mutex::new(None),
+    });
+    let (sender, receiver) = watch::channel(Status::default());
+    *LATEST.lock().unwrap() = Some(sender);
+    tokio::spawn(run(eventloop, Arc::clone(&publisher), receiver.clone()));
+    tokio::spawn(publish_changes(publisher, receiver));
+}
+
+/// 是否已启用（`start` 已调用）。
+pub fn is_enabled() -> bool {
+    LATEST.lock().unwrap().is_some()
+}
+
+/// 更新最新状态，由后台任务发布；未启用时不做任何事。
+pub fn publish(status: &Status) {
+    if let Some(sender) = LATEST.lock().unwrap().as_ref() {
+        sender.send_replace(status.clone());
+    }
+}
+
+struct Publisher {
+    client: AsyncClient,
+    topics: Topics,
+    /// Home Assistant 自动发现设置，未开启时为 None
+    discovery: Option<ha_discovery::Settings>,
+    /// 本次连接中已发布过发现消息的设备地址
+    announced: Mutex<Option<String>>,
+}
+
+impl Publisher {
+    /// 把一条保留消息排入客户端队列（由事件循环实际发送），队列已满时返回 false。
+    fn enqueue(&self, topic: &str, payload: String) -> bool {
+        match self
+            .client
+            .try_publish(topic, self.topics.qos, true, payload)
+        {
+            Ok(()) => true,
+            Err(e) => {
+                debug!("MQTT 发布队列已满，跳过本次状态: {}", e);
+                false
+            }
+        }
+    }
+
+    /// 排队发布一个状态；设备地址与上次发布发现消息时不同则先发布发现消息。
+    /// 队列已满时放弃本次，下次状态变化或重连时会再发布。
+    fn publish(&self, status: &Status) {
+        if !self.announce(status) {
+            return;
+        }
+        let json = match status_file::to_json_line(status) {
+            Ok(json) => json,
+            Err(e) => {
+                warn!("生成 MQTT 状态消息时出错: {}", e);
+                return;
+            }
+        };
+        let availability = if status.connected { ONLINE } else { OFFLINE };
+        let topics = &self.topics;
+        let _ = self.enqueue(&topics.bpm, status.bpm.to_string())
+            && self.enqueue(&topics.state, json)
+            && self.enqueue(&topics.availability, availability.to_string());
+    }
+
+    /// 按需发布 Home Assistant 发现消息，队列已满时返回 false。
+    fn announce(&self, status: &Status) -> bool {
+        let (Some(discovery), Some(address)) = (&self.discovery, &status.device_address) else {
+            return true;
+        };
+        let mut announced = self.announced.lock().unwrap();
+        if announced.as_ref() == Some(address) {
+            return true;
+        }
+        let messages =
+            match ha_discovery::messages(discovery, address, status.device_name.as_deref()) {
+                Ok(messages) => messages,
+                Err(e) => {
+                    warn!("生成 Home Assistant 发现消息时出错: {}", e);
+                    return true;
+                }
+            };
+        if !messages
+            .into_iter()
+            .all(|(topic, payload)| self.enqueue(&topic, payload))
+        {
+            return false;
+        }
+        *announced = Some(address.clone());
+        true
+    }
+}
+
+/// 驱动事件循环：rumqttc 在 `poll` 中完成连接、重连和实际发送。
+/// `poll` 不宜与其他 future 放在同一个 select! 中被取消，状态变化由 `publish_changes` 另起任务排队。
+async fn run(mut eventloop: EventLoop, publisher: Arc<Publisher>, latest: watch::Receiver<Status>) {
+    let mut backoff = MIN_BACKOFF;
+    let mut warned = false;
+    let mut connected = false;
+    loop {
+        match eventloop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                info!("已连接 MQTT 代理，将发布到 {}。", publisher.topics.bpm);
+                backoff = MIN_BACKOFF;
+                warned = false;
+                connected = true;
+                // 代理可能已因遗嘱把 availability 改为 offline，连上后立即恢复当前状态；
+                // 代理重启后保留消息可能已丢失，发现消息也重新发布
+                publisher.announced.lock().unwrap().take();
+                let status = latest.borrow().clone();
+                publisher.publish(&status);
+            }
+            Ok(_) => {}
+            Err(e) => {
+                if connected {
+                    info!("与 MQTT 代理的连接已断开（{}），将自动重连。", e);
+                    connected = false;
+                } else if !warned {
+                    warn!("无法连接 MQTT 代理: {}，将在后台继续重试。", e);
+                    warned = true;
+                } else {
+                    debug!("重连 MQTT 代理失败: {}", e);
+                }
+                // 下一次 poll 会重新连接
+                time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+}
+
+/// 状态每次变化时排队发布，直到发送端被丢弃（程序退出）。
+async fn publish_changes(publisher: Arc<Publisher>, mut latest: watch::Receiver<Status>) {
+    while latest.changed().await.is_ok() {
+        let status = latest.borrow_and_update().clone();
+        publisher.publish(&status);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn qos_levels_map_to_mqtt_qos() {
+        assert_eq!(qos(0), QoS::AtMostOnce);
+        assert_eq!(qos(1), QoS::AtLeastOnce);
+        assert_eq!(qos(2), QoS::ExactlyOnce);
+    }
+}
@@ -0,0 +1,404 @@
+//! 心跳超时的系统定时器实现（`use_native_watchdog = true`）：Windows 上用可等待定时器
+//! （`SetWaitableTimer`），Linux 上用 `timerfd`，macOS/FreeBSD 上用 kqueue `EVFILT_TIMER`。
+//!
+//! 定时器由一个专用线程阻塞等待，到期后通过 `oneshot` 通知 BLE 任务走超时流程，
+//! 不依赖 tokio 的时间轮按时推进；BLE 任务收到通知仍需要执行器调度到它。
+//! 其他平台上 `NativeWatchdog::new` 返回 `Unsupported`，调用方退回 `tokio::time::timeout`。
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tokio::sync::oneshot;
+use tracing::warn;
+
+/// 等待线程与 `NativeWatchdog` 共享的状态。
+#[derive(Default)]
+struct Shared {
+    /// 当前的截止时间及通知发送端；`disarm` 或重新 `arm` 时丢弃旧的发送端
+    pending: Mutex<Option<(Instant, oneshot::Sender<()>)>>,
+    /// `NativeWatchdog` 已被丢弃，等待线程应退出
+    closed: AtomicBool,
+}
+
+pub struct NativeWatchdog {
+    handle: Arc<sys::Timer>,
+    shared: Arc<Shared>,
+}
+
+impl NativeWatchdog {
+    /// 创建系统定时器及其等待线程。
+    pub fn new() -> io::Result<Self> {
+        let handle = Arc::new(sys::Timer::new()?);
+        let shared = Arc::new(Shared::default());
+        let (timer, state) = (handle.clone(), shared.clone());
+        thread::Builder::new()
+            .name("native-watchdog".to_string())
+            .spawn(move || wait_loop(&timer, &state))?;
+        Ok(NativeWatchdog { handle, shared })
+    }
+
+    /// 在 `duration` 后触发；返回的接收端在到期时收到通知。之前未到期的 `arm` 作废
+    /// （其接收端收到关闭错误）。设置定时器失败时接收端立即以关闭错误结束，按超时处理。
+    pub fn arm(&self, duration: Duration) -> oneshot::Receiver<()> {
+        let (sender, receiver) = oneshot::channel();
+        // 等待线程已退出：丢弃发送端，立即按超时处理
+        if self.shared.closed.load(Ordering::SeqCst) {
+            return receiver;
+        }
+        let mut pending = self.shared.pending.lock().unwrap();
+        if let Err(e) = self.handle.set(duration) {
+            warn!("设置系统定时器失败: {}", e);
+            *pending = None;
+            return receiver;
+        }
+        *pending = Some((Instant::now() + duration, sender));
+        receiver
+    }
+
+    /// 取消尚未到期的 `arm`。
+    pub fn disarm(&self) {
+        let mut pending = self.shared.pending.lock().unwrap();
+        *pending = None;
+        if let Err(e) = self.handle.cancel() {
+            warn!("取消系统定时器失败: {}", e);
+        }
+    }
+}
+
+impl Drop for NativeWatchdog {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::SeqCst);
+        // 让定时器立即到期，唤醒等待线程使其退出；句柄随线程持有的 Arc 一起关闭
+        let _ = self.handle.set(Duration::ZERO);
+    }
+}
+
+fn wait_loop(timer: &sys::Timer, shared: &Shared) {
+    loop {
+        if let Err(e) = timer.wait() {
+            warn!("等待系统定时器失败，心跳超时将立即触发: {}", e);
+            break;
+        }
+        if shared.closed.load(Ordering::SeqCst) {
+            break;
+        }
+        let mut pending = shared.pending.lock().unwrap();
+        let now = Instant::now();
+        match pending.take() {
+            // 定时器与 Instant 的时钟精度不同，可能早到一点：按剩余时间重新设置
+            Some((deadline, sender)) if now < deadline => {
+                if let Err(e) = timer.set(deadline - now) {
+                    warn!("设置系统定时器失败: {}", e);
+                    continue;
+                }
+                *pending = Some((deadline, sender));
+            }
+            Some((_, sender)) => {
+                let _ = sender.send(());
+            }
+            // 已被 disarm，或是上一次 arm 残留的到期
+            None => {}
+        }
+    }
+    // 丢弃发送端：之后的等待按超时处理，而不是永远等下去
+    shared.closed.store(true, Ordering::SeqCst);
+    shared.pending.lock().unwrap().take();
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod sys {
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::time::Duration;
+
+    pub struct Timer(OwnedFd);
+
+    impl Timer {
+        pub fn new() -> io::Result<Self> {
+            // SAFETY: 无指针参数；返回的描述符由 OwnedFd 负责关闭。
+            let fd = unsafe { libc::timerfd_create(libc::CLOCK_MONOTONIC, libc::TFD_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // SAFETY: fd 是刚创建的有效描述符，此后只归 OwnedFd 所有。
+            Ok(Timer(unsafe { OwnedFd::from_raw_fd(fd) }))
+        }
+
+        /// 重新设置为 `duration` 后到期（一次性）。
+        pub fn set(&self, duration: Duration) -> io::Result<()> {
+            // it_value 全 0 表示停止定时器，因此至少 1 纳秒
+            let duration = duration.max(Duration::from_nanos(1));
+            self.settime(libc::timespec {
+                tv_sec: duration.as_secs() as libc::time_t,
+                tv_nsec: duration.subsec_nanos() as libc::c_long,
+            })
+        }
+
+        pub fn cancel(&self) -> io::Result<()> {
+            self.settime(libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            })
+        }
+
+        fn settime(&self, value: libc::timespec) -> io::Result<()> {
+            let spec = libc::itimerspec {
+                it_interval: libc::timespec {
+                    tv_sec: 0,
+                    tv_nsec: 0,
+                },
+                it_value: value,
+            };
+            // SAFETY: spec 在调用期间有效；不需要旧值。
+            let result = unsafe {
+                libc::timerfd_settime(self.0.as_raw_fd(), 0, &spec, std::ptr::null_mut())
+            };
+            if result < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+
+        /// 阻塞直到定时器到期。
+        pub fn wait(&self) -> io::Result<()> {
+            let mut expirations = [0u8; 8];
+            loop {
+                // SAFETY: 缓冲区长度与传入的 count 一致。
+                let n = unsafe {
+                    libc::read(
+                        self.0.as_raw_fd(),
+                        expirations.as_mut_ptr().cast(),
+                        expirations.len(),
+                    )
+                };
+                if n == expirations.len() as isize {
+                    return Ok(());
+                }
+                let err = io::Error::last_os_error();
+                if n < 0 && err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+        }
+    }
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+mod sys {
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::time::Duration;
+
+    /// kqueue 中定时器事件的标识
+    const TIMER_IDENT: usize = 1;
+
+    pub struct Timer(OwnedFd);
+
+    impl Timer {
+        pub fn new() -> io::Result<Self> {
+            // SAFETY: 无参数；返回的描述符由 OwnedFd 负责关闭。
+            let kq = unsafe { libc::kqueue() };
+            if kq < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // SAFETY: kq 是刚创建的有效描述符，此后只归 OwnedFd 所有。
+            Ok(Timer(unsafe { OwnedFd::from_raw_fd(kq) }))
+        }
+
+        /// 重新设置为 `duration` 后到期（一次性，毫秒精度，向上取整）。
+        pub fn set(&self, duration: Duration) -> io::Result<()> {
+            let millis = duration.as_nanos().div_ceil(1_000_000).max(1);
+            self.change(libc::EV_ADD | libc::EV_ONESHOT, millis as i64)
+        }
+
+        pub fn cancel(&self) -> io::Result<()> {
+            match self.change(libc::EV_DELETE, 0) {
+                // 已经到期（EV_ONESHOT 会自动删除）或从未设置
+                Err(e) if e.raw_os_error() == Some(libc::ENOENT) => Ok(()),
+                result => result,
+            }
+        }
+
+        fn change(&self, flags: u16, millis: i64) -> io::Result<()> {
+            // SAFETY: kevent 是纯数据结构，全 0 是合法值；各平台的额外字段保持为 0。
+            let mut event: libc::kevent = unsafe { std::mem::zeroed() };
+            event.ident = TIMER_IDENT as _;
+            event.filter = libc::EVFILT_TIMER as _;
+            event.flags = flags as _;
+            event.data = millis as _;
+            // SAFETY: 只提交一个变更、不接收事件。
+            let result = unsafe {
+                libc::kevent(
+                    self.0.as_raw_fd(),
+                    &event,
+                    1,
+                    std::ptr::null_mut(),
+                    0,
+                    std::ptr::null(),
+                )
+            };
+            if result < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+
+        /// 阻塞直到定时器到期。
+        pub fn wait(&self) -> io::Result<()> {
+            loop {
+                // SAFETY: 同上，全 0 的 kevent 只用作输出缓冲区。
+                let mut event: libc::kevent = unsafe { std::mem::zeroed() };
+                // SAFETY: 不提交变更，最多接收一个事件到 event；不设超时。
+                let n = unsafe {
+                    libc::kevent(
+                        self.0.as_raw_fd(),
+                        std::ptr::null(),
+                        0,
+                        &mut event,
+                        1,
+                        std::ptr::null(),
+                    )
+                };
+                if n == 1 {
+                    return Ok(());
+                }
+                let err = io::Error::last_os_error();
+                if n < 0 && err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                if n < 0 {
+                    return Err(err);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::io;
+    use std::time::Duration;
+
+    use windows_sys::Win32::Foundation::{CloseHandle, HANDLE, WAIT_OBJECT_0};
+    use windows_sys::Win32::System::Threading::{
+        CancelWaitableTimer, CreateWaitableTimerW, SetWaitableTimer, WaitForSingleObject, INFINITE,
+    };
+
+    /// 可等待定时器句柄；HANDLE 是裸指针，存为整数以便跨线程共享
+    pub struct Timer(usize);
+
+    impl Timer {
+        pub fn new() -> io::Result<Self> {
+            // SAFETY: 无安全属性、无名称；同步（自动复位）定时器，句柄在 Drop 中关闭。
+            let handle = unsafe { CreateWaitableTimerW(std::ptr::null(), 0, std::ptr::null()) };
+            if handle.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Timer(handle as usize))
+        }
+
+        /// 重新设置为 `duration` 后到期（一次性）。
+        pub fn set(&self, duration: Duration) -> io::Result<()> {
+            // 负值表示相对时间，单位 100 纳秒；0 会被当作绝对时间，因此至少 1 个单位
+            let due = -((duration.as_nanos() / 100).clamp(1, i64::MAX as u128) as i64);
+            // SAFETY: 句柄有效；due 在调用期间有效；不使用完成例程。
+            let ok =
+                unsafe { SetWaitableTimer(self.0 as HANDLE, &due, 0, None, std::ptr::null(), 0) };
+            if ok == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+
+        pub fn cancel(&self) -> io::Result<()> {
+            // SAFETY: 句柄有效。
+            if unsafe { CancelWaitableTimer(self.0 as HANDLE) } == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+
+        /// 阻塞直到定时器到期。
+        pub fn wait(&self) -> io::Result<()> {
+            // SAFETY: 句柄有效。
+            if unsafe { WaitForSingleObject(self.0 as HANDLE, INFINITE) } != WAIT_OBJECT_0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+    }
+
+    impl Drop for Timer {
+        fn drop(&mut self) {
+            // SAFETY: 句柄有效且只在这里关闭一次。
+            unsafe { CloseHandle(self.0 as HANDLE) };
+        }
+    }
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    windows
+)))]
+mod sys {
+    use std::io;
+    use std::time::Duration;
+
+    pub struct Timer;
+
+    impl Timer {
+        pub fn new() -> io::Result<Self> {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "当前平台没有系统定时器实现",
+            ))
+        }
+
+        pub fn set(&self, _duration: Duration) -> io::Result<()> {
+            unreachable!()
+        }
+
+        pub fn cancel(&self) -> io::Result<()> {
+            unreachable!()
+        }
+
+        pub fn wait(&self) -> io::Result<()> {
+            unreachable!()
+        }
+    }
+}
+
+#[cfg(all(test, any(target_os = "linux", target_os = "macos", windows)))]
+mod tests {
+    use super::*;
+    use tokio::sync::oneshot::error::TryRecvError;
+
+    #[test]
+    fn fires_once_and_disarm_or_rearm_cancels() {
+        let watchdog = NativeWatchdog::new().unwrap();
+
+        let mut fired = watchdog.arm(Duration::from_millis(20));
+        thread::sleep(Duration::from_millis(300));
+        assert_eq!(fired.try_recv(), Ok(()));
+
+        let mut disarmed = watchdog.arm(Duration::from_millis(20));
+        watchdog.disarm();
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(disarmed.try_recv(), Err(TryRecvError::Closed));
+
+        // 重新 arm 会作废上一次，新的截止时间到了才触发
+        let mut replaced = watchdog.arm(Duration::from_secs(60));
+        let mut rearmed = watchdog.arm(Duration::from_millis(20));
+        assert_eq!(replaced.try_recv(), Err(TryRecvError::Closed));
+        thread::sleep(Duration::from_millis(300));
+        assert_eq!(rearmed.try_recv(), Ok(()));
+    }
+}
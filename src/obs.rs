@@ -0,0 +1,483 @@
+//! OBS 文本源输出（`obs_enabled = true`）：作为 obs-websocket v5 客户端连接 OBS
+//! （OBS 菜单"工具 → WebSocket 服务器设置"，默认端口 4455），心率文本变化时对名为 `obs_input_name`
+//! 的文本源调用 `SetInputSettings` 更新其 `text`。文本与 HeartRate.txt 相同
+//! （`heart_rate_file_format` / `heart_rate_file_offline`），不需要浏览器源。
+//!
+//! 连接在后台任务中进行：OBS 未启动或重启时按 1、2、4……最长 30 秒的间隔重连；每秒最多更新两次，
+//! 期间的中间值直接跳过，只发送最新的。连接失败只在第一次记警告，之后降为 debug 日志，
+//! 心率处理路径上只有一次 watch 通道写入，不会影响 OSC 发送。
+
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+use tokio::time;
+use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tracing::{debug, info, warn};
+
+use crate::Config;
+
+/// 两次更新之间的最短间隔。
+const MIN_UPDATE_INTERVAL: Duration = Duration::from_millis(500);
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// 连接、握手及等待请求结果的最长时间。
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// obs-websocket 协议的 RPC 版本。
+const RPC_VERSION: u32 = 1;
+/// 认证失败时 OBS 关闭连接使用的代码（WebSocketCloseCode::AuthenticationFailed）。
+const CLOSE_AUTHENTICATION_FAILED: u16 = 4009;
+
+// 消息类型（WebSocketOpCode）
+const OP_HELLO: u8 = 0;
+const OP_IDENTIFY: u8 = 1;
+const OP_IDENTIFIED: u8 = 2;
+const OP_REQUEST: u8 = 6;
+const OP_REQUEST_RESPONSE: u8 = 7;
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+#[derive(Debug)]
+pub enum Error {
+    WebSocket(Box<tungstenite::Error>),
+    Json(serde_json::Error),
+    Timeout,
+    PasswordRequired,
+    AuthenticationFailed,
+    /// OBS 关闭了连接（附关闭原因）
+    Closed(Option<String>),
+    /// 收到不符合协议的消息
+    Protocol(&'static str),
+    /// OBS 拒绝了请求（附状态码和说明）
+    Rejected(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::WebSocket(e) => write!(f, "{}", e),
+            Error::Json(e) => write!(f, "消息格式错误: {}", e),
+            Error::Timeout => write!(f, "等待 OBS 响应超时"),
+            Error::PasswordRequired => {
+                write!(
+                    f,
+                    "OBS 开启了身份验证，请在 config.toml 中设置 obs_password"
+                )
+            }
+            Error::AuthenticationFailed => write!(f, "obs_password 不正确"),
+            Error::Closed(Some(reason)) => write!(f, "OBS 关闭了连接（{}）", reason),
+            Error::Closed(None) => write!(f, "OBS 关闭了连接"),
+            Error::Protocol(what) => write!(f, "不符合 obs-websocket v5 协议: {}", what),
+            Error::Rejected(reason) => write!(f, "OBS 拒绝了请求（{}）", reason),
+        }
+    }
+}
+
+impl From<tungstenite::Error> for Error {
+    fn from(e: tungstenite::Error) -> Self {
+        Error::WebSocket(Box::new(e))
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
+
+#[derive(Deserialize)]
+struct Op {
+    op: u8,
+}
+
+#[derive(Deserialize)]
+struct HelloMessage {
+    d: Hello,
+}
+
+#[derive(Deserialize)]
+struct Hello {
+    /// OBS 未开启身份验证时没有该字段
+    authentication: Option<Challenge>,
+}
+
+#[derive(Deserialize)]
+struct Challenge {
+    challenge: String,
+    salt: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Identify {
+    rpc_version: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    authentication: Option<String>,
+    /// 不订阅任何事件
+    event_subscriptions: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Request<'a> {
+    request_type: &'static str,
+    request_id: String,
+    request_data: SetInputSettings<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SetInputSettings<'a> {
+    input_name: &'a str,
+    input_settings: TextSettings<'a>,
+}
+
+#[derive(Serialize)]
+struct TextSettings<'a> {
+    text: &'a str,
+}
+
+#[derive(Deserialize)]
+struct ResponseMessage {
+    d: RequestResponse,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RequestResponse {
+    request_status: RequestStatus,
+}
+
+#[derive(Deserialize)]
+struct RequestStatus {
+    result: bool,
+    code: u16,
+    comment: Option<String>,
+}
+
+impl RequestStatus {
+    fn describe(&self) -> String {
+        match &self.comment {
+            Some(comment) => format!("代码 {}: {}", self.code, comment),
+            None => format!("代码 {}", self.code),
+        }
+    }
+}
+
+/// 连接参数（来自配置）。
+struct Settings {
+    url: String,
+    password: Option<String>,
+    input_name: String,
+}
+
+impl Settings {
+    fn new(config: &Config) -> Self {
+        // IPv6 地址在 URL 中需要方括号
+        let host = if config.obs_host.contains(':') && !config.obs_host.starts_with('[') {
+            format!("[{}]", config.obs_host)
+        } else {
+            config.obs_host.clone()
+        };
+        Settings {
+            url: format!("ws://{}:{}", host, config.obs_port),
+            password: config.obs_password.clone(),
+            input_name: config.obs_input_name.clone(),
+        }
+    }
+}
+
+/// 最新的文本；退出时丢弃发送端，后台任务随之结束。
+static LATEST: Mutex<Option<watch::Sender<String>>> = Mutex::new(None);
+
+/// 启动时调用（需在 tokio 运行时内）：在后台连接 OBS，之后 `publish` 才会生效。
+/// `initial` 为连接后首先写入的文本。
+pub fn start(config: &Config, initial: String) {
+    let (sender, receiver) = watch::channel(initial);
+    *LATEST.lock().unwrap() = Some(sender);
+    tokio::spawn(run(Settings::new(config), receiver));
+}
+
+/// 是否已启用（`start` 已调用）。
+pub fn is_enabled() -> bool {
+    LATEST.lock().unwrap().is_some()
+}
+
+/// 更新要写入文本源的内容（与上次相同时不发送）；未启用时不做任何事。
+pub fn publish(text: &str) {
+    let latest = LATEST.lock().unwrap();
+    let Some(sender) = latest.as_ref() else {
+        return;
+    };
+    sender.send_if_modified(|current| {
+        if current == text {
+            return false;
+        }
+        text.clone_into(current);
+        true
+    });
+}
+
+/// `--obs-test`：连接 OBS 并把文本源设为 `text`，等待 OBS 确认。
+pub async fn test(config: &Config, text: &str) -> Result<(), Error> {
+    let settings = Settings::new(config);
+    let mut ws = connect(&settings).await?;
+    send_text(&mut ws, &settings.input_name, text, 1).await?;
+    let status = time::timeout(TIMEOUT, async {
+        loop {
+            if let Some(status) = request_status(&next_text(&mut ws).await?)? {
+                return Ok::<_, Error>(status);
+            }
+        }
+    })
+    .await
+    .map_err(|_| Error::Timeout)??;
+    let _ = time::timeout(TIMEOUT, ws.close(None)).await;
+    if !status.result {
+        return Err(Error::Rejected(status.describe()));
+    }
+    Ok(())
+}
+
+async fn run(settings: Settings, mut latest: watch::Receiver<String>) {
+    let mut backoff = MIN_BACKOFF;
+    let mut warned = false;
+    loop {
+        let mut connected = false;
+        let Err(e) = session(&settings, &mut latest, &mut connected).await else {
+            // 发送端已被丢弃（程序退出）
+            return;
+        };
+        if connected {
+            info!("与 OBS 的连接已断开（{}），将自动重连。", e);
+            backoff = MIN_BACKOFF;
+            warned = false;
+        } else if !warned {
+            warn!(
+                "无法连接 OBS（{}）: {}，将在后台继续重试。",
+                settings.url, e
+            );
+            warned = true;
+        } else {
+            debug!("重连 OBS 失败: {}", e);
+        }
+        time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// 一次连接：先写入当前文本，之后每次变化时写入（限速），直到连接出错或发送端被丢弃。
+async fn session(
+    settings: &Settings,
+    latest: &mut watch::Receiver<String>,
+    connected: &mut bool,
+) -> Result<(), Error> {
+    let mut ws = connect(settings).await?;
+    *connected = true;
+    info!("已连接 OBS，将更新文本源 \"{}\"。", settings.input_name);
+
+    let mut request_id = 0u64;
+    // 本次连接是否已报告过请求被拒绝（如文本源不存在），避免每次更新都刷屏
+    let mut rejected = false;
+    let mut last_sent: Option<Instant> = None;
+    loop {
+        if let Some(sent) = last_sent {
+            time::sleep(MIN_UPDATE_INTERVAL.saturating_sub(sent.elapsed())).await;
+        }
+        let text = latest.borrow_and_update().clone();
+        request_id += 1;
+        send_text(&mut ws, &settings.input_name, &text, request_id).await?;
+        last_sent = Some(Instant::now());
+
+        // 等待下一次变化，期间读取请求结果
+        loop {
+            tokio::select! {
+                changed = latest.changed() => {
+                    if changed.is_err() {
+                        let _ = time::timeout(TIMEOUT, ws.close(None)).await;
+                        return Ok(());
+                    }
+                    break;
+                }
+                incoming = next_text(&mut ws) => {
+                    let Some(status) = request_status(&incoming?)? else {
+                        continue;
+                    };
+                    if !status.result && !rejected {
+                        warn!(
+                            "OBS 拒绝更新文本源 \"{}\"（{}），请检查 obs_input_name 是否与 OBS 中的来源名称一致。",
+                            settings.input_name,
+                            status.describe()
+                        );
+                        rejected = true;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 建立连接并完成 Hello / Identify 握手。
+async fn connect(settings: &Settings) -> Result<Socket, Error> {
+    let (mut ws, _) = time::timeout(
+        TIMEOUT,
+        tokio_tungstenite::connect_async(settings.url.as_str()),
+    )
+    .await
+    .map_err(|_| Error::Timeout)??;
+
+    let hello = time::timeout(TIMEOUT, next_text(&mut ws))
+        .await
+        .map_err(|_| Error::Timeout)??;
+    if serde_json::from_str::<Op>(&hello)?.op != OP_HELLO {
+        return Err(Error::Protocol("第一条消息不是 Hello"));
+    }
+    let hello: HelloMessage = serde_json::from_str(&hello)?;
+    let authentication = match (hello.d.authentication, &settings.password) {
+        (Some(challenge), Some(password)) => Some(authentication(
+            password,
+            &challenge.salt,
+            &challenge.challenge,
+        )),
+        (Some(_), None) => return Err(Error::PasswordRequired),
+        (None, _) => None,
+    };
+    let identify = message(
+        OP_IDENTIFY,
+        &Identify {
+            rpc_version: RPC_VERSION,
+            authentication,
+            event_subscriptions: 0,
+        },
+    )?;
+    ws.send(Message::Text(identify)).await?;
+
+    // 认证失败时 OBS 直接关闭连接（next_text 返回 AuthenticationFailed）
+    let identified = time::timeout(TIMEOUT, next_text(&mut ws))
+        .await
+        .map_err(|_| Error::Timeout)??;
+    if serde_json::from_str::<Op>(&identified)?.op != OP_IDENTIFIED {
+        return Err(Error::Protocol("握手后的消息不是 Identified"));
+    }
+    Ok(ws)
+}
+
+/// obs-websocket v5 的认证字符串：
+/// base64(sha256(base64(sha256(password + salt)) + challenge))。
+fn authentication(password: &str, salt: &str, challenge: &str) -> String {
+    let mut secret = Sha256::new();
+    secret.update(password);
+    secret.update(salt);
+    let secret = STANDARD.encode(secret.finalize());
+    let mut response = Sha256::new();
+    response.update(secret);
+    response.update(challenge);
+    STANDARD.encode(response.finalize())
+}
+
+fn message(op: u8, d: &impl Serialize) -> Result<String, Error> {
+    Ok(format!(
+        "{{\"op\":{},\"d\":{}}}",
+        op,
+        serde_json::to_string(d)?
+    ))
+}
+
+async fn send_text(
+    ws: &mut Socket,
+    input_name: &str,
+    text: &str,
+    request_id: u64,
+) -> Result<(), Error> {
+    let request = message(
+        OP_REQUEST,
+        &Request {
+            request_type: "SetInputSettings",
+            request_id: request_id.to_string(),
+            request_data: SetInputSettings {
+                input_name,
+                input_settings: TextSettings { text },
+            },
+        },
+    )?;
+    time::timeout(TIMEOUT, ws.send(Message::Text(request)))
+        .await
+        .map_err(|_| Error::Timeout)??;
+    Ok(())
+}
+
+/// 下一条文本消息；连接关闭或出错时返回错误（Ping 由库自动回复）。
+async fn next_text(ws: &mut Socket) -> Result<String, Error> {
+    loop {
+        match ws.next().await {
+            Some(Ok(Message::Text(text))) => return Ok(text),
+            Some(Ok(Message::Close(None))) => return Err(Error::Closed(None)),
+            Some(Ok(Message::Close(Some(frame)))) => {
+                let code = u16::from(frame.code);
+                if code == CLOSE_AUTHENTICATION_FAILED {
+                    return Err(Error::AuthenticationFailed);
+                }
+                return Err(Error::Closed(Some(if frame.reason.is_empty() {
+                    format!("代码 {}", code)
+                } else {
+                    format!("代码 {}: {}", code, frame.reason)
+                })));
+            }
+            Some(Ok(_)) => {}
+            Some(Err(e)) => return Err(e.into()),
+            None => return Err(Error::Closed(None)),
+        }
+    }
+}
+
+/// 若是请求结果（RequestResponse）则返回其状态，其他消息返回 `None`。
+fn request_status(text: &str) -> Result<Option<RequestStatus>, Error> {
+    if serde_json::from_str::<Op>(text)?.op != OP_REQUEST_RESPONSE {
+        return Ok(None);
+    }
+    let response: ResponseMessage = serde_json::from_str(text)?;
+    Ok(Some(response.d.request_status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn authentication_matches_protocol_example() {
+        // obs-websocket 协议文档中的示例
+        assert_eq!(
+            authentication(
+                "supersecretpassword",
+                "lM1GncleQOaCu9lT1yeUZhFYnqhsLLP1G5lAGo3ixaI=",
+                "+IxH4CnCiqpX1rM9scsNynZzbOe4KhDeYcTNS3PDaeY="
+            ),
+            "1Ct943GAT+6YQUUX47Ia/ncufilbe6+oD6lY+5kaCu4="
+        );
+    }
+
+    #[test]
+    fn url_brackets_ipv6_hosts() {
+        let config = |host: &str| Config {
+            obs_host: host.to_string(),
+            ..Config::default()
+        };
+        assert_eq!(
+            Settings::new(&config("127.0.0.1")).url,
+            "ws://127.0.0.1:4455"
+        );
+        assert_eq!(Settings::new(&config("::1")).url, "ws://[::1]:4455");
+        assert_eq!(
+            Settings::new(&config("obs-pc.local")).url,
+            "ws://obs-pc.local:4455"
+        );
+    }
+}
@@ -0,0 +1,231 @@
+//! OSC 回传（`osc_feedback_enabled = true`）：监听 VRChat 从 `osc_receive_port`（默认 9001）
+//! 发回的 `HR` 参数（地址前缀与发送时相同，默认 `/avatar/parameters/HR`），与最近一次发送的心率比对，测量往返延迟。
+//!
+//! VRChat 只在参数值变化时回传，因此只有心率数值变化的那次发送会记录发送时间；
+//! 当前 Avatar 没有 `HR` 参数时不会收到回传，也就没有延迟数据。
+//!
+//! 收到 `/avatar/change`（切换了 avatar）时通知定时发送重新发送全部参数。
+
+use std::net::{Ipv4Addr, SocketAddrV4, UdpSocket};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::Instant;
+
+use tracing::{info, warn};
+
+const AVATAR_CHANGE_ADDRESS: &str = "/avatar/change";
+
+#[derive(Debug, Default)]
+struct Tracker {
+    /// 最近一次发送的 HR 值
+    last_sent: Option<i32>,
+    /// 已发送、尚未收到回传的 HR 值及其发送时间
+    pending: Option<(i32, Instant)>,
+    /// 最近一次测得的往返延迟（毫秒）
+    rtt_ms: Option<u32>,
+}
+
+impl Tracker {
+    fn record_sent(&mut self, hr: i32, now: Instant) {
+        if self.last_sent != Some(hr) {
+            self.last_sent = Some(hr);
+            self.pending = Some((hr, now));
+        }
+    }
+
+    fn record_received(&mut self, hr: i32, now: Instant) {
+        if let Some((sent, at)) = self.pending {
+            if sent == hr {
+                self.pending = None;
+                self.rtt_ms = Some(now.duration_since(at).as_millis().min(u32::MAX as u128) as u32);
+            }
+        }
+    }
+}
+
+static TRACKER: OnceLock<Mutex<Tracker>> = OnceLock::new();
+
+/// 启动接收线程，`prefix` 为参数地址前缀（见 `Config::osc_parameter_prefix`）；
+/// 端口被占用（例如其他 OSC 工具也在监听）时提示并返回 false。
+pub fn start(port: u16, prefix: &str) -> bool {
+    let socket = match UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port)) {
+        Ok(socket) => socket,
+        Err(e) => {
+            warn!(
+                "无法监听 OSC 回传端口 {}（{}），往返延迟将不可用。",
+                port, e
+            );
+            return false;
+        }
+    };
+    if TRACKER.set(Mutex::new(Tracker::default())).is_err() {
+        return true;
+    }
+    info!("正在监听 VRChat 的 OSC 回传（端口 {}）", port);
+
+    let hr_address = format!("{}HR", prefix);
+    thread::spawn(move || {
+        let mut buf = [0_u8; rosc::decoder::MTU];
+        loop {
+            let Ok((len, _)) = socket.recv_from(&mut buf) else {
+                continue;
+            };
+            let Ok((_, packet)) = rosc::decoder::decode_udp(&buf[..len]) else {
+                continue;
+            };
+            if is_avatar_change(&packet) {
+                crate::scheduler::resync();
+            }
+            let now = Instant::now();
+            let mut values = Vec::new();
+            collect_hr_values(&packet, &hr_address, &mut values);
+            if let Some(tracker) = TRACKER.get() {
+                let mut tracker = tracker.lock().unwrap_or_else(|e| e.into_inner());
+                for hr in values {
+                    tracker.record_received(hr, now);
+                }
+            }
+        }
+    });
+    true
+}
+
+/// 记录一次成功发送的 HR 值（未启用回传时不做任何事）。
+pub fn record_sent(hr: i32, now: Instant) {
+    if let Some(tracker) = TRACKER.get() {
+        tracker
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .record_sent(hr, now);
+    }
+}
+
+/// 最近一次测得的往返延迟（毫秒）；未启用或尚未收到回传时为 None。
+pub fn rtt_ms() -> Option<u32> {
+    TRACKER
+        .get()
+        .and_then(|tracker| tracker.lock().unwrap_or_else(|e| e.into_inner()).rtt_ms)
+}
+
+/// 数据包（含嵌套 Bundle）中是否有 `/avatar/change`。
+fn is_avatar_change(packet: &rosc::OscPacket) -> bool {
+    match packet {
+        rosc::OscPacket::Message(message) => message.addr == AVATAR_CHANGE_ADDRESS,
+        rosc::OscPacket::Bundle(bundle) => bundle.content.iter().any(is_avatar_change),
+    }
+}
+
+/// 取出数据包（含嵌套 Bundle）中所有地址为 `hr_address` 的值；
+/// `[parameter_types] hr = "float"` 时回传的是浮点数，取整后比较。
+fn collect_hr_values(packet: &rosc::OscPacket, hr_address: &str, out: &mut Vec<i32>) {
+    match packet {
+        rosc::OscPacket::Message(message) if message.addr == hr_address => {
+            match message.args.first() {
+                Some(rosc::OscType::Int(hr)) => out.push(*hr),
+                Some(rosc::OscType::Float(hr)) => out.push(hr.round() as i32),
+                _ => {}
+            }
+        }
+        rosc::OscPacket::Message(_) => {}
+        rosc::OscPacket::Bundle(bundle) => {
+            for packet in &bundle.content {
+                collect_hr_values(packet, hr_address, out);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const HR_ADDRESS: &str = "/avatar/parameters/HR";
+
+    fn message(addr: &str, arg: rosc::OscType) -> rosc::OscPacket {
+        rosc::OscPacket::Message(rosc::OscMessage {
+            addr: addr.to_string(),
+            args: vec![arg],
+        })
+    }
+
+    #[test]
+    fn collects_hr_from_messages_and_nested_bundles() {
+        let packet = rosc::OscPacket::Bundle(rosc::OscBundle {
+            timetag: rosc::OscTime {
+                seconds: 0,
+                fractional: 1,
+            },
+            content: vec![
+                message(HR_ADDRESS, rosc::OscType::Int(72)),
+                message("/avatar/parameters/hr_percent", rosc::OscType::Float(0.4)),
+                rosc::OscPacket::Bundle(rosc::OscBundle {
+                    timetag: rosc::OscTime {
+                        seconds: 0,
+                        fractional: 1,
+                    },
+                    content: vec![message(HR_ADDRESS, rosc::OscType::Int(73))],
+                }),
+                message(HR_ADDRESS, rosc::OscType::Float(74.0)),
+            ],
+        });
+        let mut values = Vec::new();
+        collect_hr_values(&packet, HR_ADDRESS, &mut values);
+        assert_eq!(values, [72, 73, 74]);
+
+        // 平台预设或 osc_parameter_prefix 改变了前缀时按发送时的地址匹配
+        let mut values = Vec::new();
+        collect_hr_values(&packet, "/custom/HR", &mut values);
+        assert!(values.is_empty());
+        collect_hr_values(
+            &message("/custom/HR", rosc::OscType::Int(80)),
+            "/custom/HR",
+            &mut values,
+        );
+        assert_eq!(values, [80]);
+    }
+
+    #[test]
+    fn detects_avatar_change() {
+        let change = message(
+            AVATAR_CHANGE_ADDRESS,
+            rosc::OscType::String("avtr_0".to_string()),
+        );
+        assert!(is_avatar_change(&change));
+        assert!(is_avatar_change(&rosc::OscPacket::Bundle(
+            rosc::OscBundle {
+                timetag: rosc::OscTime {
+                    seconds: 0,
+                    fractional: 1,
+                },
+                content: vec![change],
+            }
+        )));
+        assert!(!is_avatar_change(&message(
+            HR_ADDRESS,
+            rosc::OscType::Int(72)
+        )));
+    }
+
+    #[test]
+    fn rtt_is_measured_from_first_send_of_a_changed_value() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut tracker = Tracker::default();
+
+        tracker.record_sent(72, at(0));
+        // 数值不变的重复发送不会刷新发送时间
+        tracker.record_sent(72, at(1000));
+        tracker.record_received(71, at(1010));
+        assert_eq!(tracker.rtt_ms, None);
+        tracker.record_received(72, at(1040));
+        assert_eq!(tracker.rtt_ms, Some(1040));
+
+        tracker.record_sent(75, at(2000));
+        tracker.record_received(75, at(2035));
+        assert_eq!(tracker.rtt_ms, Some(35));
+        // 同一值的重复回传不再计算
+        tracker.record_received(75, at(3000));
+        assert_eq!(tracker.rtt_ms, Some(35));
+    }
+}
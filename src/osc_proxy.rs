@@ -0,0 +1,111 @@
+//! OSC 代理（`osc_proxy_enabled = true`）：本程序挡在 VRChat 前面，其他 OSC 工具改为发往
+//! `osc_proxy_listen_ip:osc_proxy_listen_port`（默认 `127.0.0.1:9010`），收到的每个数据包原样转发到
+//! OSC 目标（`osc_ip:osc_port`），心率 Bundle 也从同一个套接字发出，VRChat 只看到一个发送方。
+//!
+//! 转发不解码：格式错误的数据包也照原样转发，由接收端决定如何处理。接收和发送共用一个
+//! 固定大小的缓冲区，每个数据包不做额外分配。
+
+use std::io;
+use std::net::{SocketAddr, SocketAddrV4, UdpSocket};
+use std::thread;
+
+use tracing::{debug, warn};
+
+/// UDP 数据包的最大长度。
+const MAX_PACKET: usize = 65_536;
+
+/// 启动转发线程：监听 `listen`，把收到的数据包经 `socket`（心率 Bundle 使用的套接字）发往 `target`。
+/// 返回实际监听的地址。
+pub fn start(
+    socket: &UdpSocket,
+    listen: SocketAddrV4,
+    target: SocketAddr,
+) -> io::Result<SocketAddr> {
+    if SocketAddr::V4(listen) == target {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "监听地址与 OSC 目标相同，数据包会被转发给自己",
+        ));
+    }
+    let listener = UdpSocket::bind(listen)?;
+    let local_addr = listener.local_addr()?;
+    let sender = socket.try_clone()?;
+    thread::Builder::new()
+        .name("osc-proxy".to_string())
+        .spawn(move || forward(&listener, &sender, target))?;
+    Ok(local_addr)
+}
+
+/// 转发循环；监听套接字出现无法恢复的错误时结束。
+fn forward(listener: &UdpSocket, sender: &UdpSocket, target: SocketAddr) {
+    let mut buf = vec![0_u8; MAX_PACKET];
+    let mut warned = false;
+    loop {
+        let len = match listener.recv_from(&mut buf) {
+            Ok((len, _)) => len,
+            // Windows 上之前的发送遇到 ICMP 端口不可达时，下一次接收会返回 WSAECONNRESET，忽略即可
+            Err(e) if e.kind() == io::ErrorKind::ConnectionReset => continue,
+            Err(e) => {
+                warn!("OSC 代理接收数据时出错（{}），已停止转发。", e);
+                return;
+            }
+        };
+        match sender.send_to(&buf[..len], target) {
+            Ok(_) => warned = false,
+            // 目标端口无人监听（VRChat 未启动），与 send_raw_osc 相同视为已发送
+            Err(e) if e.kind() == io::ErrorKind::ConnectionReset => {}
+            Err(e) if !warned => {
+                warn!("OSC 代理转发到 {} 失败（{}），恢复前不再提示。", target, e);
+                warned = true;
+            }
+            Err(e) => debug!("OSC 代理转发失败: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    #[test]
+    fn forwards_packets_unchanged_through_the_shared_socket() {
+        let target = UdpSocket::bind("127.0.0.1:0").expect("bind target");
+        target
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let target_addr = target.local_addr().unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").expect("bind sender");
+        let proxy = start(
+            &socket,
+            SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0),
+            target_addr,
+        )
+        .expect("start proxy");
+
+        let tool = UdpSocket::bind("127.0.0.1:0").expect("bind tool");
+        let mut buf = [0_u8; 256];
+        let packets: [&[u8]; 2] = [
+            b"/avatar/parameters/Other\0\0\0\0,f\0\0\x3f\x80\0\0",
+            b"\xffnot an OSC packet",
+        ];
+        for packet in packets {
+            tool.send_to(packet, proxy).unwrap();
+            let (len, from) = target.recv_from(&mut buf).expect("forwarded packet");
+            assert_eq!(&buf[..len], packet);
+            assert_eq!(
+                from,
+                socket.local_addr().unwrap(),
+                "sent from the shared socket"
+            );
+        }
+
+        assert!(start(
+            &socket,
+            SocketAddrV4::new(Ipv4Addr::LOCALHOST, 9000),
+            SocketAddr::from((Ipv4Addr::LOCALHOST, 9000)),
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,83 @@
+//! `--osc-test` / `--osc-test-fixed <BPM>`：不使用蓝牙，按测试图案每秒发送一次 OSC，
+//! 用于调试 avatar 的参数绑定。发送走与正常运行相同的 `send_osc`，Ctrl-C 退出时同样发送清零状态。
+
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use tokio::time;
+use tracing::{info, warn};
+
+use crate::{osc_feedback, osc_socket, send_osc, Config, OscExtras, Result, ResultExt};
+
+/// 扫描图案的心率范围。
+const SWEEP_MIN_BPM: u8 = 60;
+const SWEEP_MAX_BPM: u8 = 200;
+
+/// 未指定周期时的扫描周期（秒）。
+pub const DEFAULT_SWEEP_PERIOD_SECS: u64 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pattern {
+    /// 在一个周期内从 60 升到 200 再回到 60
+    Sweep { period_secs: u64 },
+    /// 持续发送固定心率
+    Fixed(u8),
+}
+
+/// 三角波：周期起点为 60，半周期处为 200。
+fn sweep_bpm(elapsed: Duration, period_secs: u64) -> u8 {
+    let phase = (elapsed.as_secs_f64() / period_secs as f64).fract();
+    let level = 1.0 - (2.0 * phase - 1.0).abs();
+    let span = f64::from(SWEEP_MAX_BPM - SWEEP_MIN_BPM);
+    SWEEP_MIN_BPM + (level * span).round() as u8
+}
+
+/// 每秒发送一次测试心率，直到进程被 Ctrl-C 终止。
+pub async fn run(pattern: Pattern, config: &Config, osc_addr: SocketAddr) -> Result<()> {
+    let socket = osc_socket(config, osc_addr).context("创建 OSC 套接字")?;
+    match pattern {
+        Pattern::Sweep { period_secs } => info!(
+            "OSC 测试：心率在 {}–{} 之间往返扫描（周期 {} 秒），发送到 {}，按 Ctrl-C 退出。",
+            SWEEP_MIN_BPM, SWEEP_MAX_BPM, period_secs, osc_addr
+        ),
+        Pattern::Fixed(bpm) => info!(
+            "OSC 测试：持续发送心率 {} 到 {}，按 Ctrl-C 退出。",
+            bpm, osc_addr
+        ),
+    }
+
+    let start = Instant::now();
+    let mut ticker = time::interval(Duration::from_secs(1));
+    loop {
+        ticker.tick().await;
+        let bpm = match pattern {
+            Pattern::Sweep { period_secs } => sweep_bpm(start.elapsed(), period_secs),
+            Pattern::Fixed(bpm) => bpm,
+        };
+        let extras = OscExtras {
+            rtt_ms: osc_feedback::rtt_ms(),
+            ..OscExtras::default()
+        };
+        match send_osc(&socket, osc_addr, bpm, extras, config) {
+            Ok(status) => println!("状态 -> {}", status),
+            Err(e) => warn!("发送 OSC 数据时出错: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sweep_rises_to_max_and_returns() {
+        let at = |secs| Duration::from_secs(secs);
+        assert_eq!(sweep_bpm(at(0), 30), 60);
+        assert_eq!(sweep_bpm(at(15), 30), 200);
+        assert_eq!(sweep_bpm(at(30), 30), 60);
+        assert_eq!(sweep_bpm(at(45), 30), 200);
+        let rising = sweep_bpm(at(5), 30);
+        assert!(rising > 60 && rising < 200);
+        assert_eq!(rising, sweep_bpm(at(25), 30));
+    }
+}
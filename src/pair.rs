@@ -0,0 +1,192 @@
+//! `--pair <MAC>`（仅 Linux）：btleplug 不处理交互式配对，需要配对的设备（如部分手环）
+//! 首次使用前必须先在 bluetoothctl 中手动配对。这里代为执行 `bluetoothctl pair <MAC>`，
+//! 等待其输出 "Pairing successful"（最多 `TIMEOUT`），成功后照常连接；失败时打印手动配对的步骤。
+//! Windows 和 macOS 在连接时由系统弹窗处理配对，不需要此命令。
+
+/// 规范化 MAC 地址（`aa:bb:cc:dd:ee:ff` → `AA:BB:CC:DD:EE:FF`，与设备缓存的格式一致）；格式不对时返回 None。
+pub fn normalize_mac(mac: &str) -> Option<String> {
+    let parts: Vec<&str> = mac.split(':').collect();
+    let valid = parts.len() == 6
+        && parts
+            .iter()
+            .all(|part| part.len() == 2 && part.chars().all(|c| c.is_ascii_hexdigit()));
+    valid.then(|| mac.to_ascii_uppercase())
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::io::{self, BufRead, BufReader};
+    use std::process::{Command, Stdio};
+    use std::sync::mpsc::{self, RecvTimeoutError};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use tracing::{debug, error, info};
+
+    /// 等待配对结果的最长时间（含在设备上确认的时间）。
+    const TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// 执行配对，返回是否成功（成功后应继续连接）。
+    pub fn run(mac: &str) -> bool {
+        info!(
+            "正在通过 bluetoothctl 配对 {}（最多等待 {} 秒），如设备要求确认请在设备上操作...",
+            mac,
+            TIMEOUT.as_secs()
+        );
+        match pair(mac) {
+            Ok(()) => {
+                info!("配对成功。");
+                true
+            }
+            Err(e) => {
+                error!("配对失败: {}", e);
+                print_recovery(mac);
+                false
+            }
+        }
+    }
+
+    fn pair(mac: &str) -> Result<(), String> {
+        let mut child = Command::new("bluetoothctl")
+            .args(["pair", mac])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound => "找不到 bluetoothctl，请先安装 bluez".to_string(),
+                _ => format!("无法运行 bluetoothctl: {}", e),
+            })?;
+        let stdout = child.stdout.take().expect("stdout 已设为管道");
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let deadline = Instant::now() + TIMEOUT;
+        let result = loop {
+            match lines.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(line) => {
+                    let line = strip_ansi(&line);
+                    debug!("bluetoothctl: {}", line);
+                    if let Some(outcome) = outcome(&line) {
+                        break outcome;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    break Err(format!("{} 秒内没有配对成功", TIMEOUT.as_secs()))
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    break Err("bluetoothctl 已退出，但没有报告配对成功".to_string())
+                }
+            }
+        };
+        // 超时或已得到结果时 bluetoothctl 可能仍在等待代理应答，直接结束
+        let _ = child.kill();
+        let _ = child.wait();
+        result
+    }
+
+    /// 判断 bluetoothctl 的一行输出是否给出了配对结果。
+    fn outcome(line: &str) -> Option<Result<(), String>> {
+        if line.contains("Pairing successful") || line.contains("org.bluez.Error.AlreadyExists") {
+            return Some(Ok(()));
+        }
+        if line.contains("Failed to pair") || line.contains("not available") {
+            return Some(Err(line.trim().to_string()));
+        }
+        None
+    }
+
+    /// 去掉 bluetoothctl 输出中的颜色控制序列（`ESC [ ... 字母`）。
+    fn strip_ansi(line: &str) -> String {
+        let mut text = String::with_capacity(line.len());
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            if c == '\u{1b}' {
+                for c in chars.by_ref() {
+                    if c.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            } else {
+                text.push(c);
+            }
+        }
+        text
+    }
+
+    fn print_recovery(mac: &str) {
+        println!(
+            "\
+请按以下步骤手动配对后再运行程序：
+  1. 佩戴好心率设备并让它进入可配对状态（部分设备需长按按键或在手机 App 中断开连接）
+  2. 确认蓝牙服务在运行：systemctl status bluetooth
+  3. 运行 bluetoothctl，依次输入：
+       power on
+       agent on
+       default-agent
+       scan on        （看到 {mac} 出现后）
+       scan off
+       pair {mac}     （如提示确认或输入 PIN，按提示操作）
+       trust {mac}
+  4. 提示 AlreadyExists 但仍无法连接时，先执行 remove {mac} 再从第 3 步重试
+  5. 提示权限不足时，把当前用户加入 bluetooth 组（sudo usermod -aG bluetooth $USER）并重新登录"
+        );
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn bluetoothctl_output_decides_outcome() {
+            let line =
+                strip_ansi("\u{1b}[0;94m[CHG]\u{1b}[0m Device AA:BB:CC:DD:EE:FF Paired: yes");
+            assert_eq!(line, "[CHG] Device AA:BB:CC:DD:EE:FF Paired: yes");
+            assert_eq!(outcome(&line), None);
+            assert_eq!(outcome("Pairing successful"), Some(Ok(())));
+            assert_eq!(
+                outcome("Failed to pair: org.bluez.Error.AlreadyExists"),
+                Some(Ok(()))
+            );
+            assert_eq!(
+                outcome("Device AA:BB:CC:DD:EE:FF not available"),
+                Some(Err("Device AA:BB:CC:DD:EE:FF not available".to_string()))
+            );
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    /// 其他平台由系统处理配对，打印说明后返回 false。
+    pub fn run(_mac: &str) -> bool {
+        println!(
+            "--pair 仅适用于 Linux：Windows 和 macOS 会在连接时由系统处理配对，直接运行程序即可。"
+        );
+        false
+    }
+}
+
+pub use imp::run;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mac_is_validated_and_uppercased() {
+        assert_eq!(
+            normalize_mac("a0:9e:1a:00:bc:12").as_deref(),
+            Some("A0:9E:1A:00:BC:12")
+        );
+        assert_eq!(normalize_mac("A0:9E:1A:00:BC"), None);
+        assert_eq!(normalize_mac("A0-9E-1A-00-BC-12"), None);
+        assert_eq!(normalize_mac("G0:9E:1A:00:BC:12"), None);
+    }
+}
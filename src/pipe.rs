@@ -0,0 +1,255 @@
+//! 本机推送输出（`pipe_enabled = true`）：Windows 上创建命名管道 `\\.\pipe\<pipe_name>`，
+//! 其他平台创建 Unix 域套接字（默认 `<临时目录>/<pipe_name>.sock`，可用 `pipe_socket_path` 指定），
+//! 每次发送 OSC 时向所有已连接的客户端写一行 JSON：
+//!
+//! ```json
+//! {"bpm":87,"connected":true,"timestamp_ms":1760000000000}
+//! ```
+//!
+//! `bpm` 与 OSC `HR` 相同，`connected` 与 OSC `hr_connected` 相同。客户端连接后立即收到最近一行。
+//! 写入是非阻塞的：客户端来不及读、缓冲区已满时丢弃这次更新而不是等待，卡住的读取方不会拖慢 OSC 发送。
+
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tracing::{debug, info, warn};
+
+#[cfg(unix)]
+type Stream = tokio::net::UnixStream;
+#[cfg(windows)]
+type Stream = tokio::net::windows::named_pipe::NamedPipeServer;
+
+/// 非阻塞写入：缓冲区已满时返回 `WouldBlock`，不等待。
+trait TryWrite {
+    fn try_write(&self, buf: &[u8]) -> io::Result<usize>;
+}
+
+impl TryWrite for Stream {
+    fn try_write(&self, buf: &[u8]) -> io::Result<usize> {
+        Stream::try_write(self, buf)
+    }
+}
+
+struct Client<S> {
+    stream: S,
+    /// 上一行没写完的部分；写完之前新的更新直接丢弃，保证每行完整
+    pending: Vec<u8>,
+}
+
+impl<S: TryWrite> Client<S> {
+    fn new(stream: S) -> Self {
+        Client {
+            stream,
+            pending: Vec::new(),
+        }
+    }
+
+    /// 发送一行，返回是否已接收（没有因为客户端读得慢而被丢弃）；返回错误表示客户端已断开。
+    fn offer(&mut self, line: &[u8]) -> io::Result<bool> {
+        self.flush()?;
+        if !self.pending.is_empty() {
+            return Ok(false);
+        }
+        self.pending.extend_from_slice(line);
+        self.flush()?;
+        Ok(true)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        while !self.pending.is_empty() {
+            match self.stream.try_write(&self.pending) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.pending.drain(..n);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct Update {
+    bpm: u8,
+    connected: bool,
+    timestamp_ms: u64,
+}
+
+struct Clients {
+    clients: Vec<Client<Stream>>,
+    /// 最近一行，新客户端连接后先收到它
+    last_line: Vec<u8>,
+}
+
+static CLIENTS: Mutex<Option<Clients>> = Mutex::new(None);
+
+/// 启动时调用（需在 tokio 运行时内）：创建管道/套接字并在后台接受连接，成功后 `publish` 才会生效。
+/// 返回实际的管道名或套接字路径。
+pub fn start(name: &str, socket_path: Option<PathBuf>) -> io::Result<String> {
+    let endpoint = listen(name, socket_path)?;
+    *CLIENTS.lock().unwrap() = Some(Clients {
+        clients: Vec::new(),
+        last_line: Vec::new(),
+    });
+    Ok(endpoint)
+}
+
+/// 向所有客户端推送一次更新；未启用时不做任何事。
+pub fn publish(bpm: u8, connected: bool) {
+    let mut clients = CLIENTS.lock().unwrap();
+    let Some(clients) = clients.as_mut() else {
+        return;
+    };
+    let update = Update {
+        bpm,
+        connected,
+        timestamp_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64),
+    };
+    let Ok(mut line) = serde_json::to_vec(&update) else {
+        return;
+    };
+    line.push(b'\n');
+    clients
+        .clients
+        .retain_mut(|client| match client.offer(&line) {
+            Ok(true) => true,
+            Ok(false) => {
+                debug!("本机推送客户端读取过慢，已丢弃一次更新");
+                true
+            }
+            Err(e) => {
+                info!("本机推送客户端已断开（{}）", e);
+                false
+            }
+        });
+    clients.last_line = line;
+}
+
+fn accepted(stream: Stream) {
+    let mut clients = CLIENTS.lock().unwrap();
+    let Some(clients) = clients.as_mut() else {
+        return;
+    };
+    let mut client = Client::new(stream);
+    if !clients.last_line.is_empty() && client.offer(&clients.last_line).is_err() {
+        return;
+    }
+    info!("本机推送客户端已连接");
+    clients.clients.push(client);
+}
+
+#[cfg(unix)]
+fn listen(name: &str, socket_path: Option<PathBuf>) -> io::Result<String> {
+    use tokio::net::UnixListener;
+
+    let path = socket_path.unwrap_or_else(|| std::env::temp_dir().join(format!("{}.sock", name)));
+    // 上次异常退出残留的套接字文件会导致 bind 失败
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)?;
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => accepted(stream),
+                Err(e) => {
+                    warn!("接受本机推送连接失败: {}", e);
+                    return;
+                }
+            }
+        }
+    });
+    Ok(path.display().to_string())
+}
+
+#[cfg(windows)]
+fn listen(name: &str, _socket_path: Option<PathBuf>) -> io::Result<String> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    let pipe_name = format!(r"\\.\pipe\{}", name);
+    // 第一个实例用来确认管道名没有被其他程序占用
+    let mut server = ServerOptions::new()
+        .first_pipe_instance(true)
+        .create(&pipe_name)?;
+    let name = pipe_name.clone();
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = server.connect().await {
+                warn!("接受本机推送连接失败: {}", e);
+                return;
+            }
+            // 先创建下一个实例再移交已连接的实例，避免两次连接之间管道不存在
+            let next = match ServerOptions::new().create(&name) {
+                Ok(next) => next,
+                Err(e) => {
+                    warn!("创建命名管道实例失败: {}", e);
+                    accepted(server);
+                    return;
+                }
+            };
+            accepted(std::mem::replace(&mut server, next));
+        }
+    });
+    Ok(pipe_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// 每次最多接受 `capacity` 字节的假客户端；`closed` 时写入报错。
+    struct FakeStream {
+        received: RefCell<Vec<u8>>,
+        capacity: RefCell<usize>,
+        closed: bool,
+    }
+
+    impl TryWrite for FakeStream {
+        fn try_write(&self, buf: &[u8]) -> io::Result<usize> {
+            if self.closed {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            let mut capacity = self.capacity.borrow_mut();
+            if *capacity == 0 {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            let n = buf.len().min(*capacity);
+            *capacity -= n;
+            self.received.borrow_mut().extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn slow_client_drops_updates_but_never_splits_lines() {
+        let mut client = Client::new(FakeStream {
+            received: RefCell::new(Vec::new()),
+            capacity: RefCell::new(4),
+            closed: false,
+        });
+        // 只写进去一部分，剩下的留到下次
+        assert!(client.offer(b"{\"bpm\":80}\n").unwrap());
+        // 缓冲区仍然满：这次更新被丢弃
+        assert!(!client.offer(b"{\"bpm\":81}\n").unwrap());
+
+        *client.stream.capacity.borrow_mut() = 100;
+        assert!(client.offer(b"{\"bpm\":82}\n").unwrap());
+        assert_eq!(
+            client.stream.received.borrow().as_slice(),
+            b"{\"bpm\":80}\n{\"bpm\":82}\n"
+        );
+
+        let mut closed = Client::new(FakeStream {
+            received: RefCell::new(Vec::new()),
+            capacity: RefCell::new(100),
+            closed: true,
+        });
+        assert!(closed.offer(b"{}\n").is_err());
+    }
+}
@@ -0,0 +1,73 @@
+//! 心率变换插件（`plugin_path`）：在发送 OSC 之前用用户提供的动态库（.dll / .so / .dylib）变换心率，
+//! 便于自定义平滑或心率区间算法而无需修改本程序。
+//!
+//! 插件需导出 C ABI 函数：
+//!
+//! ```c
+//! uint8_t hr_transform(uint8_t bpm, const uint8_t *history, size_t history_len);
+//! ```
+//!
+//! `history` 指向最近的原始心率（按时间先后，最后一个是本次读数之前的值），只在调用期间有效，
+//! 插件不得保存该指针或写入其中。示例见 `examples/identity_plugin/`。
+//!
+//! # 安全边界
+//!
+//! 加载动态库本身就会执行库的初始化代码，而本程序无法验证导出符号的真实签名：
+//! 签名不符、插件内部 panic 跨越 FFI 边界、访问越界或在多线程下不安全，都会导致未定义行为，
+//! 后果可能是崩溃或内存损坏。Rust 的安全保证止于 `hr_transform` 调用处——只加载你信任的插件。
+
+use std::path::Path;
+use std::sync::OnceLock;
+
+use libloading::Library;
+
+use crate::Result;
+
+/// 插件导出的心率变换函数签名。
+pub type HrTransform = unsafe extern "C" fn(bpm: u8, history: *const u8, history_len: usize) -> u8;
+
+/// 传给插件的历史心率个数上限。
+pub const HISTORY_LEN: usize = 60;
+
+struct Plugin {
+    /// 必须与 `transform` 同生命周期：库被卸载后函数指针即失效
+    _library: Library,
+    transform: HrTransform,
+}
+
+static PLUGIN: OnceLock<Plugin> = OnceLock::new();
+
+/// 加载插件并查找 `hr_transform` 符号；进程内只加载一次，此后一直保留到退出。
+pub fn load(path: &Path) -> Result<()> {
+    // SAFETY: 加载任意动态库无法被验证为安全，见模块文档中的安全边界说明；
+    // 符号按文档约定的签名取出，签名是否真的匹配由插件作者保证。
+    let plugin = unsafe {
+        let library = Library::new(path)?;
+        let transform: HrTransform = *library.get::<HrTransform>(b"hr_transform\0")?;
+        Plugin {
+            _library: library,
+            transform,
+        }
+    };
+    let _ = PLUGIN.set(plugin);
+    Ok(())
+}
+
+/// 用已加载的插件变换心率；未加载插件时原样返回。
+pub fn transform(bpm: u8, history: &[u8]) -> u8 {
+    match PLUGIN.get() {
+        // SAFETY: history 在调用期间有效且不会被修改；插件须遵守模块文档中的约定。
+        Some(plugin) => unsafe { (plugin.transform)(bpm, history.as_ptr(), history.len()) },
+        None => bpm,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passes_heart_rate_through_without_plugin() {
+        assert_eq!(transform(72, &[70, 71]), 72);
+    }
+}
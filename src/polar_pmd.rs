@@ -0,0 +1,195 @@
+//! Polar 加速度数据（`polar_acc_enabled = true`）：Polar OH1 等光学心率传感器除心率外还通过
+//! 私有的 PMD（Polar Measurement Data）服务提供加速度计数据。连接后向 PMD 控制点写入
+//! "开始 ACC 测量"命令（25 Hz、16 位、±8 g），数据特征随后推送加速度帧。
+//!
+//! 每帧：1 字节测量类型（0x02 = ACC）、8 字节时间戳、1 字节帧类型（0x01 = 未压缩的 16 位），
+//! 其后为若干个 x/y/z 各 2 字节小端的样本，单位 mG。每帧取各样本合加速度去掉重力（1 g）后的平均值，
+//! 按 `polar_acc_max_g` 归一化为 0–1，作为 `/avatar/parameters/hr_movement` 随下一次心率发送——
+//! 刚开始运动、心率还没上来时 avatar 就能做出反应。其他帧类型（压缩帧）忽略。
+
+use btleplug::api::{CharPropFlags, Characteristic, Peripheral as _, WriteType};
+use btleplug::platform::Peripheral;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::{ble_timeout, subscribe_optional, Config};
+
+const PMD_CONTROL_UUID: Uuid = Uuid::from_u128(0xfb005c81_02e7_f387_1cad_8acd2d8df0c8);
+const PMD_DATA_UUID: Uuid = Uuid::from_u128(0xfb005c82_02e7_f387_1cad_8acd2d8df0c8);
+
+/// PMD 的测量类型：加速度
+const MEASUREMENT_ACC: u8 = 0x02;
+/// 帧类型：未压缩，每轴 16 位
+const FRAME_TYPE_16_BIT: u8 = 0x01;
+/// 测量类型与时间戳的长度，其后是帧类型
+const HEADER_LEN: usize = 9;
+
+/// 开始 ACC 测量：操作码 0x02、类型 ACC，设置项依次为采样率 25 Hz、分辨率 16 位、量程 8 g
+/// （每项为 设置类型、个数 1、2 字节小端值）。
+const START_ACC: [u8; 14] = [
+    0x02, 0x02, 0x00, 0x01, 0x19, 0x00, 0x01, 0x01, 0x10, 0x00, 0x02, 0x01, 0x08, 0x00,
+];
+
+/// 控制点应答的第一个字节
+const CONTROL_POINT_RESPONSE: u8 = 0xf0;
+
+/// 已订阅的 PMD 特征（断开时由连接守卫退订）。
+pub struct Pmd {
+    pub control: Characteristic,
+    pub data: Characteristic,
+}
+
+/// 订阅 PMD 控制点与数据特征并请求 ACC 数据；设备不支持或请求失败时只提示，退订已订阅的特征并返回 None。
+pub async fn start(device: &Peripheral, config: &Config) -> Option<Pmd> {
+    let Some(control) = device
+        .characteristics()
+        .into_iter()
+        .find(|c| c.uuid == PMD_CONTROL_UUID && c.properties.contains(CharPropFlags::INDICATE))
+    else {
+        warn!("设备没有 Polar PMD 服务，将不发送 hr_movement。");
+        return None;
+    };
+    // 控制点的应答以指示发送，须先订阅
+    if let Err(e) = ble_timeout(
+        "subscribe",
+        config.service_timeout_secs,
+        device.subscribe(&control),
+    )
+    .await
+    {
+        warn!("订阅 Polar PMD 控制点失败: {}，将不发送 hr_movement。", e);
+        return None;
+    }
+    let Some(data) = subscribe_optional(
+        device,
+        config,
+        PMD_DATA_UUID,
+        "Polar PMD 数据特征",
+        "hr_movement",
+    )
+    .await
+    else {
+        unsubscribe(device, config, &control).await;
+        return None;
+    };
+    match ble_timeout(
+        "write",
+        config.service_timeout_secs,
+        device.write(&control, &START_ACC, WriteType::WithResponse),
+    )
+    .await
+    {
+        Ok(()) => {
+            info!("已请求 Polar 加速度数据（25 Hz）。");
+            Some(Pmd { control, data })
+        }
+        Err(e) => {
+            warn!("请求 Polar 加速度数据失败: {}，将不发送 hr_movement。", e);
+            unsubscribe(device, config, &data).await;
+            unsubscribe(device, config, &control).await;
+            None
+        }
+    }
+}
+
+/// 启动失败时退订已订阅的 PMD 特征（返回 None 时连接守卫不知道这些订阅）。
+async fn unsubscribe(device: &Peripheral, config: &Config, characteristic: &Characteristic) {
+    let _ = ble_timeout(
+        "unsubscribe",
+        config.service_timeout_secs,
+        device.unsubscribe(characteristic),
+    )
+    .await;
+}
+
+/// 检查控制点应答（`F0 操作码 类型 状态 …`），请求被拒绝时提示。
+pub fn check_response(value: &[u8]) {
+    match value {
+        [CONTROL_POINT_RESPONSE, 0x02, MEASUREMENT_ACC, 0x00, ..] => {
+            debug!("设备已开始发送加速度数据")
+        }
+        [CONTROL_POINT_RESPONSE, 0x02, MEASUREMENT_ACC, status, ..] => warn!(
+            "设备拒绝了加速度数据请求（错误码 {}），将不发送 hr_movement。",
+            status
+        ),
+        _ => {}
+    }
+}
+
+/// 解析一帧未压缩的 16 位加速度数据，返回各样本的 x/y/z（mG）；其他测量类型或帧类型为 None。
+fn parse_acc_frame(value: &[u8]) -> Option<Vec<[i16; 3]>> {
+    if value.len() <= HEADER_LEN
+        || value[0] != MEASUREMENT_ACC
+        || value[HEADER_LEN] != FRAME_TYPE_16_BIT
+    {
+        return None;
+    }
+    let samples = &value[HEADER_LEN + 1..];
+    let axis = |bytes: &[u8]| i16::from_le_bytes([bytes[0], bytes[1]]);
+    Some(
+        samples
+            .chunks_exact(6)
+            .map(|s| [axis(&s[0..2]), axis(&s[2..4]), axis(&s[4..6])])
+            .collect(),
+    )
+}
+
+/// 一帧样本的运动强度：合加速度与 1 g 之差的平均值除以 `max_g`，限制在 0–1。
+fn movement(samples: &[[i16; 3]], max_g: f32) -> Option<f32> {
+    if samples.is_empty() {
+        return None;
+    }
+    let total: f32 = samples
+        .iter()
+        .map(|&[x, y, z]| {
+            let (x, y, z) = (f32::from(x), f32::from(y), f32::from(z));
+            ((x * x + y * y + z * z).sqrt() / 1000.0 - 1.0).abs()
+        })
+        .sum();
+    Some((total / samples.len() as f32 / max_g).min(1.0))
+}
+
+/// 数据特征的一次通知换算为 hr_movement；不是可解析的加速度帧时为 None。
+pub fn parse_movement(value: &[u8], config: &Config) -> Option<f32> {
+    movement(&parse_acc_frame(value)?, config.polar_acc_max_g)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(frame_type: u8, samples: &[[i16; 3]]) -> Vec<u8> {
+        let mut value = vec![MEASUREMENT_ACC];
+        value.extend_from_slice(&123_456_789_u64.to_le_bytes());
+        value.push(frame_type);
+        for sample in samples {
+            for axis in sample {
+                value.extend_from_slice(&axis.to_le_bytes());
+            }
+        }
+        value
+    }
+
+    #[test]
+    fn parses_uncompressed_16_bit_frames() {
+        let samples = [[0, 0, 1000], [-12, 980, -40]];
+        assert_eq!(
+            parse_acc_frame(&frame(FRAME_TYPE_16_BIT, &samples)),
+            Some(samples.to_vec())
+        );
+        // 压缩帧、其他测量类型和过短的数据都忽略
+        assert_eq!(parse_acc_frame(&frame(0x80, &samples)), None);
+        assert_eq!(parse_acc_frame(&[0x00; 16]), None);
+        assert_eq!(parse_acc_frame(&[MEASUREMENT_ACC, 0x00]), None);
+    }
+
+    #[test]
+    fn movement_excludes_gravity_and_is_normalised() {
+        // 静止：只有重力
+        assert_eq!(movement(&[[0, 0, 1000], [0, -1000, 0]], 2.0), Some(0.0));
+        // 合加速度 2 g 与 0 g（自由落体）都比重力多或少 1 g
+        assert_eq!(movement(&[[0, 0, 2000], [0, 0, 0]], 2.0), Some(0.5));
+        assert_eq!(movement(&[[0, 0, 8000]], 2.0), Some(1.0));
+        assert_eq!(movement(&[], 2.0), None);
+    }
+}
@@ -0,0 +1,217 @@
+//! Pulsoid 心率源（`source = "pulsoid"`）：连接 Pulsoid 的实时 WebSocket 接口，
+//! 接收已经同步到 Pulsoid 的心率（Apple Watch、WearOS 等本程序无法直接通过蓝牙连接的设备），
+//! 与蓝牙读数一样送往 OSC、文件、统计等各个输出。
+//!
+//! 令牌（`pulsoid_token`）在 Pulsoid 网站"设置 → 令牌"中创建，需要 `data:heart_rate:read` 权限。
+//! 连接断开或失败时按 1、2、4……最长 30 秒的间隔重连，失败只在第一次记警告。
+//! 超过 `heartbeat_timeout_secs` 没有收到心率视为设备断开（断线保持、清零与蓝牙相同），
+//! 之后再收到心率时重新视为已连接，不必重建 WebSocket 连接。
+
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+use futures_util::StreamExt;
+use serde::Deserialize;
+use tokio::net::TcpStream;
+use tokio::time;
+use tokio_rustls::client::TlsStream;
+use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_tungstenite::WebSocketStream;
+use tracing::{debug, info, warn};
+
+use crate::source::{Events, HeartRateSample, HeartRateSource, SourceDevice, SourceEvent};
+use crate::{http_client, recorder, Config, HeartRateMeasurement, Result};
+
+const HOST: &str = "dev.pulsoid.net";
+const PATH: &str = "/api/v1/data/real_time";
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// 建立连接（TCP、TLS 与 WebSocket 握手）的最长时间。
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+type Socket = WebSocketStream<TlsStream<TcpStream>>;
+
+#[derive(Debug)]
+enum Error {
+    Io(io::Error),
+    WebSocket(tungstenite::Error),
+    Timeout,
+    /// Pulsoid 关闭了连接（附关闭原因）
+    Closed(Option<String>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::WebSocket(e) => write!(f, "{}", e),
+            Error::Timeout => write!(f, "连接超时"),
+            Error::Closed(Some(reason)) => write!(f, "Pulsoid 关闭了连接（{}）", reason),
+            Error::Closed(None) => write!(f, "Pulsoid 关闭了连接"),
+        }
+    }
+}
+
+/// 实时接口的一条消息：`{"measured_at": 1625310655000, "data": {"heart_rate": 72}}`。
+#[derive(Deserialize)]
+struct Frame {
+    data: FrameData,
+}
+
+#[derive(Deserialize)]
+struct FrameData {
+    heart_rate: u16,
+}
+
+/// 解析一条心率消息；格式不符时为 None。
+fn parse_frame(text: &str) -> Option<u16> {
+    serde_json::from_str::<Frame>(text)
+        .ok()
+        .map(|frame| frame.data.heart_rate)
+}
+
+/// Pulsoid 心率数据源。
+pub struct PulsoidSource<'a> {
+    pub config: &'a Config,
+}
+
+impl HeartRateSource for PulsoidSource<'_> {
+    fn run<'a>(&'a mut self, events: &'a Events) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        Box::pin(connect_loop(self.config, events))
+    }
+}
+
+/// 连接并接收心率，断开后按退避间隔重连，不会返回。
+async fn connect_loop(config: &Config, events: &Events) -> Result<()> {
+    // 加载配置时已确认设置了令牌
+    let token = config.pulsoid_token.as_deref().unwrap_or_default();
+    let mut backoff = MIN_BACKOFF;
+    let mut warned = false;
+    loop {
+        match connect(token).await {
+            Ok(socket) => {
+                info!("已连接到 Pulsoid，等待心率数据…");
+                backoff = MIN_BACKOFF;
+                warned = false;
+                let e = receive(socket, config, events).await;
+                warn!(
+                    "与 Pulsoid 的连接已断开（{}），{} 秒后重连。",
+                    e,
+                    backoff.as_secs()
+                );
+            }
+            Err(e) if !warned => {
+                warned = true;
+                warn!(
+                    "无法连接到 Pulsoid（{}），请检查网络和 pulsoid_token，将在后台持续重试。",
+                    e
+                );
+            }
+            Err(e) => debug!("连接 Pulsoid 失败: {}", e),
+        }
+        time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+async fn connect(token: &str) -> std::result::Result<Socket, Error> {
+    let url = format!(
+        "wss://{}{}?access_token={}&response_mode=json",
+        HOST,
+        PATH,
+        http_client::percent_encode(token)
+    );
+    let handshake = async {
+        let stream = http_client::connect_tls(&format!("{}:443", HOST), HOST)
+            .await
+            .map_err(Error::Io)?;
+        let (socket, _) = tokio_tungstenite::client_async(url, stream)
+            .await
+            .map_err(Error::WebSocket)?;
+        Ok(socket)
+    };
+    time::timeout(CONNECT_TIMEOUT, handshake)
+        .await
+        .map_err(|_| Error::Timeout)?
+}
+
+/// 接收心率直到连接断开，返回断开原因。
+async fn receive(mut socket: Socket, config: &Config, events: &Events) -> Error {
+    let timeout = Duration::from_secs(config.heartbeat_timeout_secs);
+    let mut connected = false;
+    // 超时从最近一次收到心率算起：Ping 和无法解析的消息不会推迟判定断开
+    let mut last_heart_rate = time::Instant::now();
+    let error = loop {
+        let message = match time::timeout_at(last_heart_rate + timeout, socket.next()).await {
+            Ok(Some(Ok(message))) => message,
+            Ok(Some(Err(e))) => break Error::WebSocket(e),
+            Ok(None) => break Error::Closed(None),
+            Err(_) => {
+                last_heart_rate = time::Instant::now();
+                if connected {
+                    connected = false;
+                    warn!(
+                        "超过 {} 秒没有收到 Pulsoid 的心率数据，视为设备已断开。",
+                        config.heartbeat_timeout_secs
+                    );
+                    recorder::event(recorder::Event::Disconnected);
+                    events.send(SourceEvent::Disconnected);
+                }
+                continue;
+            }
+        };
+        let text = match message {
+            Message::Text(text) => text,
+            Message::Close(frame) => {
+                break Error::Closed(frame.map(|frame| frame.reason.to_string()))
+            }
+            // Ping 由 tungstenite 自动回复
+            _ => continue,
+        };
+        let Some(heart_rate) = parse_frame(&text) else {
+            debug!("忽略无法解析的 Pulsoid 消息: {}", text);
+            continue;
+        };
+        last_heart_rate = time::Instant::now();
+        if !connected {
+            connected = true;
+            info!("开始接收 Pulsoid 心率数据");
+            recorder::event(recorder::Event::Connected("pulsoid".to_string()));
+            events.send(SourceEvent::Connected(SourceDevice {
+                name: Some("Pulsoid".to_string()),
+                ..SourceDevice::default()
+            }));
+        }
+        events.send(SourceEvent::Sample(HeartRateSample {
+            measurement: HeartRateMeasurement {
+                heart_rate,
+                rr_intervals: Vec::new(),
+                energy_expended: None,
+            },
+            timestamp: Instant::now(),
+        }));
+    };
+    if connected {
+        recorder::event(recorder::Event::Disconnected);
+        events.send(SourceEvent::Disconnected);
+    }
+    error
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_heart_rate_frames() {
+        assert_eq!(
+            parse_frame(r#"{"measured_at":1625310655000,"data":{"heart_rate":72}}"#),
+            Some(72)
+        );
+        assert_eq!(parse_frame(r#"{"data":{}}"#), None);
+        assert_eq!(parse_frame("not json"), None);
+    }
+}
@@ -0,0 +1,112 @@
+//! 读数记录输出（CSV 会话文件、SQLite 历史库）的公共接口。
+//!
+//! 各输出实现 `Recorder`，启动时按配置 `register`；接收循环只调用本模块的 `reading` / `event`，
+//! 不关心启用了哪些输出。没有注册任何输出时这些调用什么也不做。
+
+use std::fmt;
+use std::sync::{Mutex, MutexGuard};
+
+use chrono::{DateTime, Local, SecondsFormat};
+
+/// 一条心率读数。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reading<'a> {
+    pub bpm: u8,
+    /// RR 间期，单位 1/1024 秒（规范原始值）
+    pub rr_intervals: &'a [u16],
+    pub rssi: Option<i16>,
+    /// 设备上报的累计能量消耗（kJ）
+    pub energy_kj: Option<u16>,
+}
+
+/// 解释读数空档的连接事件。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// 程序开始记录
+    Start,
+    /// 已连接设备（地址）
+    Connected(String),
+    Disconnected,
+    /// 连续读数为 0，进入空闲模式
+    Idle,
+    /// 退出空闲模式
+    Active,
+    /// 收到重置请求，放弃当前连接重新扫描
+    Rescan,
+    /// 程序退出
+    Stop,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Start => write!(f, "start"),
+            Event::Connected(device) => write!(f, "connected {}", device),
+            Event::Disconnected => write!(f, "disconnected"),
+            Event::Idle => write!(f, "idle"),
+            Event::Active => write!(f, "active"),
+            Event::Rescan => write!(f, "rescan"),
+            Event::Stop => write!(f, "stop"),
+        }
+    }
+}
+
+pub trait Recorder: Send {
+    fn reading(&mut self, at: DateTime<Local>, reading: &Reading);
+
+    fn event(&mut self, at: DateTime<Local>, event: &Event);
+
+    /// 退出时调用（在 `Event::Stop` 之后）：写入缓存的数据并关闭。
+    fn finish(&mut self);
+}
+
+static RECORDERS: Mutex<Vec<Box<dyn Recorder>>> = Mutex::new(Vec::new());
+
+/// 退出清理（Windows 上在控制台事件处理例程内）也会调用：某个输出持锁时 panic 导致锁中毒后
+/// 仍取回列表，继续分发并关闭其余输出，不让 panic 越过 FFI 边界。
+fn recorders() -> MutexGuard<'static, Vec<Box<dyn Recorder>>> {
+    RECORDERS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// 启动时注册一个输出。
+pub fn register(recorder: Box<dyn Recorder>) {
+    recorders().push(recorder);
+}
+
+/// 把读数分发给所有输出。
+pub fn reading(reading: &Reading) {
+    let now = Local::now();
+    for recorder in recorders().iter_mut() {
+        recorder.reading(now, reading);
+    }
+}
+
+/// 把事件分发给所有输出。
+pub fn event(event: Event) {
+    let now = Local::now();
+    for recorder in recorders().iter_mut() {
+        recorder.event(now, &event);
+    }
+}
+
+/// 退出时调用：记录 `Event::Stop` 后关闭并移除所有输出；可重复调用。
+pub fn finish() {
+    event(Event::Stop);
+    for mut recorder in recorders().drain(..) {
+        recorder.finish();
+    }
+}
+
+/// 输出中使用的时间格式（RFC 3339，毫秒精度，带本地时区）。
+pub fn timestamp(at: DateTime<Local>) -> String {
+    at.to_rfc3339_opts(SecondsFormat::Millis, false)
+}
+
+/// RR 间期换算为毫秒，多个用 `;` 分隔；没有时为空字符串。
+pub fn rr_ms(rr_intervals: &[u16]) -> String {
+    rr_intervals
+        .iter()
+        .map(|rr| (u32::from(*rr) * 1000 / 1024).to_string())
+        .collect::<Vec<_>>()
+        .join(";")
+}
@@ -0,0 +1,127 @@
+//! 注册表输出（`registry_output_enabled = true`）：每次发送 OSC 时把心率写入 `registry_key_path`
+//! 指定的 DWORD 值，供 AutoHotkey（`RegRead`）等脚本实时读取。未佩戴/断开时为 0。
+//!
+//! 路径的最后一段是值名称，前面是键（不存在时自动创建）：默认的 `HKCU\Software\HeartRateVRC\BPM`
+//! 即 `HKEY_CURRENT_USER\Software\HeartRateVRC` 下的值 `BPM`。根键支持 `HKCU` / `HKEY_CURRENT_USER`
+//! 和 `HKLM` / `HKEY_LOCAL_MACHINE`（后者需要管理员权限）。
+//!
+//! 仅 Windows，且需以 `--features registry-output` 编译；其他构建中该配置会被忽略并在启动时警告。
+
+/// 当前构建是否支持注册表输出。
+pub const SUPPORTED: bool = cfg!(all(windows, feature = "registry-output"));
+
+/// 根键。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hive {
+    CurrentUser,
+    LocalMachine,
+}
+
+/// 拆分 `根键\键\值名称`，返回根键、键路径和值名称。
+pub fn parse_path(path: &str) -> Result<(Hive, &str, &str), String> {
+    let path = path.trim_matches('\\');
+    let (root, rest) = path
+        .split_once('\\')
+        .ok_or_else(|| "应形如 HKCU\\Software\\HeartRateVRC\\BPM".to_string())?;
+    let hive = match root.to_ascii_uppercase().as_str() {
+        "HKCU" | "HKEY_CURRENT_USER" => Hive::CurrentUser,
+        "HKLM" | "HKEY_LOCAL_MACHINE" => Hive::LocalMachine,
+        _ => return Err(format!("不支持的根键 {}，应为 HKCU 或 HKLM", root)),
+    };
+    let (key, value) = rest
+        .rsplit_once('\\')
+        .ok_or_else(|| "缺少键路径，值不能直接写在根键下".to_string())?;
+    if key.is_empty() || value.is_empty() {
+        return Err("键路径和值名称都不能为空".to_string());
+    }
+    Ok((hive, key, value))
+}
+
+#[cfg(all(windows, feature = "registry-output"))]
+mod imp {
+    use std::sync::Mutex;
+
+    use tracing::{debug, info, warn};
+    use winreg::enums::{HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE};
+    use winreg::RegKey;
+
+    use super::{parse_path, Hive};
+
+    struct Value {
+        key: RegKey,
+        name: String,
+        /// 上次写入失败，已警告
+        failing: bool,
+    }
+
+    static VALUE: Mutex<Option<Value>> = Mutex::new(None);
+
+    /// 启动时调用：打开（必要时创建）键，成功后 `write` 才会生效。
+    pub fn init(path: &str) -> Result<(), String> {
+        let (hive, key, name) = parse_path(path)?;
+        let root = RegKey::predef(match hive {
+            Hive::CurrentUser => HKEY_CURRENT_USER,
+            Hive::LocalMachine => HKEY_LOCAL_MACHINE,
+        });
+        let (key, _) = root.create_subkey(key).map_err(|e| e.to_string())?;
+        *VALUE.lock().unwrap() = Some(Value {
+            key,
+            name: name.to_string(),
+            failing: false,
+        });
+        Ok(())
+    }
+
+    /// 写入一次心率；未启用时不做任何事。
+    pub fn write(bpm: u8) {
+        let mut value = VALUE.lock().unwrap();
+        let Some(value) = value.as_mut() else {
+            return;
+        };
+        match value.key.set_value(&value.name, &u32::from(bpm)) {
+            Ok(()) => {
+                if value.failing {
+                    info!("已恢复写入注册表。");
+                    value.failing = false;
+                }
+            }
+            Err(e) if !value.failing => {
+                warn!("写入注册表值 {} 失败: {}", value.name, e);
+                value.failing = true;
+            }
+            Err(e) => debug!("写入注册表值 {} 失败: {}", value.name, e),
+        }
+    }
+}
+
+#[cfg(not(all(windows, feature = "registry-output")))]
+mod imp {
+    /// 不支持的构建中启动校验已关闭该功能，不会调用到这里。
+    pub fn init(_path: &str) -> Result<(), String> {
+        Err("当前构建不支持注册表输出".to_string())
+    }
+
+    pub fn write(_bpm: u8) {}
+}
+
+pub use imp::{init, write};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_splits_into_hive_key_and_value() {
+        assert_eq!(
+            parse_path(r"HKCU\Software\HeartRateVRC\BPM"),
+            Ok((Hive::CurrentUser, r"Software\HeartRateVRC", "BPM"))
+        );
+        assert_eq!(
+            parse_path(r"HKEY_LOCAL_MACHINE\SOFTWARE\HR\Value\"),
+            Ok((Hive::LocalMachine, r"SOFTWARE\HR", "Value"))
+        );
+        assert!(parse_path(r"HKCU\BPM").is_err());
+        assert!(parse_path(r"HKCR\Software\HR\BPM").is_err());
+        assert!(parse_path("BPM").is_err());
+    }
+}
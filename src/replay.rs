@@ -0,0 +1,223 @@
+//! `--replay <文件>`（或 `replay_file`）：不使用蓝牙，按原始采样间隔重放心率记录，
+//! 用于可重复地测试 avatar 动画。发送走与正常运行相同的 `send_osc`，放完后发送清零状态并退出。
+//!
+//! 支持两种格式，逐行判断：
+//!
+//! - 会话记录 CSV（`write_session_log` 写出的 `sessions/session-*.csv`，见 session_log 模块）：
+//!   取 `timestamp` 与 `bpm` 两列，表头和事件行（`bpm` 为空）跳过；
+//! - JSONL，每行一条记录，如 `{"ts": "2024-05-01T20:15:03.250+08:00", "bpm": 82}`：`ts` 为 RFC 3339 时间
+//!   或 Unix 秒数（可带小数），`bpm` 为 0–255 的心率，其他字段忽略。适合手工编写或由其他工具转换的测试数据。
+//!
+//! 空行跳过，无法解析的行警告后跳过。相邻记录之间等待两者时间戳之差；时间戳倒退时立即发送下一条。
+
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::DateTime;
+use serde::Deserialize;
+use tokio::time;
+use tracing::{info, warn};
+
+use crate::{
+    clear_state, osc_feedback, osc_socket, send_osc, Config, OscExtras, Result, ResultExt,
+};
+
+#[derive(Debug, Deserialize)]
+struct Record {
+    ts: Timestamp,
+    bpm: u8,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Timestamp {
+    /// Unix 秒数
+    Seconds(f64),
+    /// RFC 3339 时间
+    Rfc3339(String),
+}
+
+impl Timestamp {
+    /// 换算为 Unix 毫秒；无法解析时为 `None`。
+    fn millis(&self) -> Option<i64> {
+        match self {
+            Timestamp::Seconds(secs) if secs.is_finite() => Some((secs * 1000.0).round() as i64),
+            Timestamp::Seconds(_) => None,
+            Timestamp::Rfc3339(text) => DateTime::parse_from_rfc3339(text)
+                .ok()
+                .map(|at| at.timestamp_millis()),
+        }
+    }
+}
+
+/// 一条待重放的读数。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Sample {
+    /// Unix 毫秒
+    at_ms: i64,
+    bpm: u8,
+}
+
+/// 解析一行记录；空行、CSV 表头与事件行为 `Ok(None)`。
+fn parse_line(line: &str) -> std::result::Result<Option<Sample>, String> {
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if !line.starts_with('{') {
+        return parse_csv_line(line);
+    }
+    let record: Record = serde_json::from_str(line).map_err(|e| e.to_string())?;
+    let at_ms = record
+        .ts
+        .millis()
+        .ok_or_else(|| format!("无效的时间戳 {:?}", record.ts))?;
+    Ok(Some(Sample {
+        at_ms,
+        bpm: record.bpm,
+    }))
+}
+
+/// 解析会话记录 CSV 的一行（`timestamp,bpm,rr_ms,connected,rssi,event`）。
+fn parse_csv_line(line: &str) -> std::result::Result<Option<Sample>, String> {
+    let mut fields = line.split(',');
+    let (Some(ts), Some(bpm)) = (fields.next(), fields.next()) else {
+        return Err("不是 JSON 记录，也不是会话记录 CSV".to_string());
+    };
+    // 表头与连接、断开等事件行没有心率
+    if ts == "timestamp" || bpm.is_empty() {
+        return Ok(None);
+    }
+    let at_ms = DateTime::parse_from_rfc3339(ts)
+        .map_err(|_| format!("无效的时间戳 {:?}", ts))?
+        .timestamp_millis();
+    let bpm = bpm.parse().map_err(|_| format!("无效的心率 {:?}", bpm))?;
+    Ok(Some(Sample { at_ms, bpm }))
+}
+
+/// 解析整个文件，跳过无法解析的行（逐行警告）。
+fn parse_samples(text: &str) -> Vec<Sample> {
+    let mut samples = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        match parse_line(line) {
+            Ok(Some(sample)) => samples.push(sample),
+            Ok(None) => {}
+            Err(e) => warn!("重放文件第 {} 行无法解析（{}），已跳过。", index + 1, e),
+        }
+    }
+    samples
+}
+
+/// 发送 `next` 前需要等待的时间；时间戳倒退时不等待。
+fn delay(previous: Sample, next: Sample) -> Duration {
+    Duration::from_millis(u64::try_from(next.at_ms - previous.at_ms).unwrap_or(0))
+}
+
+/// 按记录的时间间隔依次发送文件中的心率，放完后发送清零状态并返回。
+pub async fn run(path: &Path, config: &Config, osc_addr: SocketAddr, hr_file: &Path) -> Result<()> {
+    let text =
+        fs::read_to_string(path).with_context(|| format!("读取重放文件 {}", path.display()))?;
+    let samples = parse_samples(&text);
+    let (Some(first), Some(last)) = (samples.first(), samples.last()) else {
+        warn!("重放文件 {} 中没有可用的记录。", path.display());
+        return Ok(());
+    };
+    let socket = osc_socket(config, osc_addr).context("创建 OSC 套接字")?;
+    info!(
+        "重放 {}：共 {} 条记录，时长约 {} 秒，发送到 {}，按 Ctrl-C 退出。",
+        path.display(),
+        samples.len(),
+        delay(*first, *last).as_secs(),
+        osc_addr
+    );
+
+    let mut previous = *first;
+    for &sample in &samples {
+        time::sleep(delay(previous, sample)).await;
+        previous = sample;
+        let extras = OscExtras {
+            rtt_ms: osc_feedback::rtt_ms(),
+            ..OscExtras::default()
+        };
+        match send_osc(&socket, osc_addr, sample.bpm, extras, config) {
+            Ok(status) => println!("状态 -> {}", status),
+            Err(e) => warn!("发送 OSC 数据时出错: {}", e),
+        }
+    }
+    clear_state(&socket, osc_addr, config, hr_file);
+    info!("重放完成。");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rfc3339_and_unix_timestamps() {
+        let text = concat!(
+            "{\"ts\": \"2024-05-01T20:15:03.250+08:00\", \"bpm\": 82}\n",
+            "\n",
+            "{\"ts\": \"2024-05-01T12:15:04Z\", \"bpm\": 85, \"rr_ms\": [705]}\n",
+            "not json\n",
+            "{\"ts\": 1714565705.5, \"bpm\": 90}\n",
+            "{\"ts\": \"yesterday\", \"bpm\": 91}\n",
+        );
+        let samples = parse_samples(text);
+        assert_eq!(
+            samples,
+            vec![
+                Sample {
+                    at_ms: 1_714_565_703_250,
+                    bpm: 82
+                },
+                Sample {
+                    at_ms: 1_714_565_704_000,
+                    bpm: 85
+                },
+                Sample {
+                    at_ms: 1_714_565_705_500,
+                    bpm: 90
+                },
+            ]
+        );
+        assert_eq!(parse_line("  "), Ok(None));
+        assert!(parse_line("{\"ts\": 1, \"bpm\": 300}").is_err());
+    }
+
+    #[test]
+    fn parses_session_log_csv() {
+        let text = concat!(
+            "timestamp,bpm,rr_ms,connected,rssi,event\n",
+            "2026-10-16T21:30:00.000+08:00,,,false,,connected: AA:BB\n",
+            "2026-10-16T21:30:01.250+08:00,87,690;702,true,-67,\n",
+            "2026-10-16T21:30:02.250+08:00,88,,true,,\n",
+            "2026-10-16T21:30:03.000+08:00,abc,,true,,\n",
+            "2026-10-16T21:35:12.004+08:00,,,false,,disconnected\n",
+        );
+        assert_eq!(
+            parse_samples(text),
+            vec![
+                Sample {
+                    at_ms: 1_792_157_401_250,
+                    bpm: 87
+                },
+                Sample {
+                    at_ms: 1_792_157_402_250,
+                    bpm: 88
+                },
+            ]
+        );
+        assert!(parse_line("just some text").is_err());
+    }
+
+    #[test]
+    fn delay_follows_timestamps_and_never_goes_negative() {
+        let at = |at_ms| Sample { at_ms, bpm: 80 };
+        assert_eq!(delay(at(1_000), at(2_250)), Duration::from_millis(1_250));
+        assert_eq!(delay(at(1_000), at(1_000)), Duration::ZERO);
+        assert_eq!(delay(at(2_000), at(1_000)), Duration::ZERO);
+    }
+}
@@ -0,0 +1,161 @@
+//! Resonite 输出（`resonite_enabled = true`）：Resonite 不读取 OSC avatar 参数，而是用 WebSocket 客户端组件接收数据。
+//! 这里在 `ws://resonite_ip:resonite_port/resonite_path`（默认 `ws://127.0.0.1:8767/heartrate`）上监听，
+//! 与 OSC 输出同时运行，在 VRChat 和 Resonite 之间切换不需要重启程序。
+//!
+//! 每条消息是一个文本帧，新客户端连接后立即收到当前状态，之后心率或连接状态每次变化时推送一条。
+//! 格式由 `resonite_format` 决定：
+//!
+//! - `"text"`（默认）：只有心率数字，如 `87`；未连接或未佩戴时为 `0`。常见的 Resonite 心率物品
+//!   直接把收到的字符串解析为整数即可使用；
+//! - `"json"`：`{"bpm":87,"connected":true}`，需要区分"未佩戴"和"未连接"时使用。
+//!
+//! 推送逻辑与 websocket 模块相同（只保留最新一条，发送过慢的客户端被断开），其他路径的握手返回 404。
+
+use std::io;
+use std::net::{SocketAddr, SocketAddrV4};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tracing::warn;
+
+use crate::websocket;
+
+/// 消息格式。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResoniteFormat {
+    /// 只有心率数字
+    #[default]
+    Text,
+    /// `{"bpm":87,"connected":true}`
+    Json,
+}
+
+#[derive(Serialize)]
+struct JsonMessage {
+    bpm: u8,
+    connected: bool,
+}
+
+impl ResoniteFormat {
+    fn message(self, bpm: u8, connected: bool) -> serde_json::Result<String> {
+        match self {
+            ResoniteFormat::Text => Ok(if connected { bpm } else { 0 }.to_string()),
+            ResoniteFormat::Json => serde_json::to_string(&JsonMessage { bpm, connected }),
+        }
+    }
+}
+
+/// 最新消息及格式；退出时丢弃发送端，所有连接随之关闭。
+static LATEST: Mutex<Option<(watch::Sender<String>, ResoniteFormat)>> = Mutex::new(None);
+
+/// 启动时调用：开始监听并在后台接受 `path` 上的连接，成功后 `publish` 才会生效。返回实际监听的地址。
+pub async fn start(
+    addr: SocketAddrV4,
+    path: String,
+    format: ResoniteFormat,
+) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr).await?;
+    let local_addr = listener.local_addr()?;
+    let initial = format.message(0, false).map_err(io::Error::other)?;
+    let (sender, receiver) = watch::channel(initial);
+    *LATEST.lock().unwrap() = Some((sender, format));
+    websocket::spawn_server(listener, receiver, Some(path));
+    Ok(local_addr)
+}
+
+/// 是否已启用（`start` 成功且尚未关闭）。
+pub fn is_enabled() -> bool {
+    LATEST.lock().unwrap().is_some()
+}
+
+/// 更新心率与连接状态；内容没有变化时不推送，未启用时不做任何事。
+pub fn publish(bpm: u8, connected: bool) {
+    let latest = LATEST.lock().unwrap();
+    let Some((sender, format)) = latest.as_ref() else {
+        return;
+    };
+    match format.message(bpm, connected) {
+        Ok(message) => {
+            sender.send_if_modified(|current| {
+                let changed = *current != message;
+                if changed {
+                    *current = message;
+                }
+                changed
+            });
+        }
+        Err(e) => warn!("生成 Resonite 消息时出错: {}", e),
+    }
+}
+
+/// 退出时调用：停止接受连接并关闭现有连接。
+pub fn shutdown() {
+    LATEST.lock().unwrap().take();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    use futures_util::stream::Stream;
+    use futures_util::StreamExt;
+    use tokio_tungstenite::tungstenite::{self, Message};
+
+    async fn next_text<S>(ws: &mut S) -> String
+    where
+        S: Stream<Item = tungstenite::Result<Message>> + Unpin,
+    {
+        match ws.next().await {
+            Some(Ok(Message::Text(text))) => text,
+            other => panic!("expected a text frame, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn formats_carry_bpm_and_connection() {
+        assert_eq!(ResoniteFormat::Text.message(87, true).unwrap(), "87");
+        assert_eq!(ResoniteFormat::Text.message(87, false).unwrap(), "0");
+        assert_eq!(
+            ResoniteFormat::Json.message(87, true).unwrap(),
+            r#"{"bpm":87,"connected":true}"#
+        );
+        assert_eq!(
+            ResoniteFormat::Json.message(0, false).unwrap(),
+            r#"{"bpm":0,"connected":false}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn websocket_clients_receive_heart_rate_on_configured_path() {
+        let addr = start(
+            SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0),
+            "/heartrate".to_string(),
+            ResoniteFormat::Text,
+        )
+        .await
+        .expect("start Resonite server");
+
+        let url = format!("ws://{}/heartrate", addr);
+        let (mut ws, _) = tokio_tungstenite::connect_async(url.as_str())
+            .await
+            .expect("connect to Resonite endpoint");
+        assert_eq!(next_text(&mut ws).await, "0", "current state on connect");
+        publish(87, true);
+        assert_eq!(next_text(&mut ws).await, "87");
+        publish(0, false);
+        assert_eq!(next_text(&mut ws).await, "0");
+
+        let wrong_path = format!("ws://{}/other", addr);
+        assert!(
+            tokio_tungstenite::connect_async(wrong_path.as_str())
+                .await
+                .is_err(),
+            "other paths are rejected"
+        );
+        shutdown();
+    }
+}
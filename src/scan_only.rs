@@ -0,0 +1,97 @@
+//! `--scan-only <名称>`：只扫描不连接，每秒打印名称包含该关键字的设备的 RSSI，
+//! 用于调整蓝牙适配器/天线位置、排查距离导致的断线。Ctrl-C 停止。
+
+use std::time::Duration;
+
+use btleplug::api::{Central, Peripheral as _, ScanFilter};
+use btleplug::platform::Adapter;
+use chrono::Local;
+use tokio::time;
+
+use crate::{acquire_adapter, ble_timeout, rssi_quality, sanitize_device_name, Config, Result};
+
+/// 信号条的格数。
+const BAR_WIDTH: usize = 10;
+
+/// 开始扫描并持续打印，直到 Ctrl-C。
+pub async fn run(config: &Config, name: &str) -> Result<()> {
+    let (_manager, central) = acquire_adapter().await?;
+    // 不按服务过滤：只看信号，设备是否广播 0x180D 无关紧要
+    ble_timeout(
+        "start_scan",
+        config.scan_timeout_secs,
+        central.start_scan(ScanFilter::default()),
+    )
+    .await?;
+    println!(
+        "正在扫描（不连接），每秒显示名称包含 \"{}\" 的设备的信号强度，按 Ctrl-C 停止。",
+        name
+    );
+    let result = tokio::select! {
+        result = scan_only_loop(&central, name) => result,
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    };
+    let _ = central.stop_scan().await;
+    result
+}
+
+/// 每秒读取一次已发现的设备，打印名称匹配的设备的当前 RSSI；不会自行结束。
+pub async fn scan_only_loop(adapter: &Adapter, name: &str) -> Result<()> {
+    let mut interval = time::interval(Duration::from_secs(1));
+    // 只在从"找到"变为"找不到"时提示一次，避免刷屏
+    let mut was_found = true;
+    loop {
+        interval.tick().await;
+        let mut found = false;
+        for p in adapter.peripherals().await? {
+            let Ok(Some(props)) = p.properties().await else {
+                continue;
+            };
+            let Some(device_name) = props.local_name.as_deref().map(sanitize_device_name) else {
+                continue;
+            };
+            if !device_name.contains(name) {
+                continue;
+            }
+            found = true;
+            println!(
+                "[{}] {} | MAC: {} | {}",
+                Local::now().format("%H:%M:%S"),
+                device_name,
+                p.address(),
+                format_rssi(props.rssi)
+            );
+        }
+        if !found && was_found {
+            println!("暂未发现名称包含 \"{}\" 的设备，继续扫描...", name);
+        }
+        was_found = found;
+    }
+}
+
+/// RSSI 及信号条，例如 `信号强度: -67 dBm ███████░░░`；平台未提供 RSSI 时显示 N/A。
+fn format_rssi(rssi: Option<i16>) -> String {
+    let Some(rssi) = rssi else {
+        return "信号强度: N/A".to_string();
+    };
+    let filled = (rssi_quality(rssi) * BAR_WIDTH as f32).round() as usize;
+    format!(
+        "信号强度: {} dBm {}{}",
+        rssi,
+        "█".repeat(filled),
+        "░".repeat(BAR_WIDTH - filled)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rssi_bar_spans_quality_range() {
+        assert_eq!(format_rssi(Some(-75)), "信号强度: -75 dBm █████░░░░░");
+        assert_eq!(format_rssi(Some(-40)), "信号强度: -40 dBm ██████████");
+        assert_eq!(format_rssi(Some(-110)), "信号强度: -110 dBm ░░░░░░░░░░");
+        assert_eq!(format_rssi(None), "信号强度: N/A");
+    }
+}
@@ -0,0 +1,318 @@
+//! 定时发送（`send_mode = "scheduled"`，默认）：收到读数时只更新最新状态，由独立任务以
+//! `send_rate_hz`（默认 4 Hz）的固定频率发送 OSC，与设备的通知频率无关——
+//! 0.2 Hz 通知的手环也能让 avatar 上的数值平滑变化，4 Hz 的胸带也不会多占带宽。
+//!
+//! 每次发送时整数、布尔等参数只在数值变化时发送；浮点参数向最新读数的数值平滑靠近
+//! （时间常数 `SMOOTHING_SECS`），每次都发送。每隔 `RESYNC_INTERVAL` 以及 VRChat 回传
+//! `/avatar/change`（需开启 osc_feedback_enabled）时重新发送一次全部参数，
+//! 切换 avatar 或重启 VRChat 后数值不变的参数也能恢复。断开或清零时立即停止定时发送，
+//! 由 `clear_state` 发送的清零数据生效，收到新读数后重新开始。
+//!
+//! `send_mode = "on-notification"` 恢复旧行为：每收到一次通知立即发送一次完整数据。
+//! 重放（`--replay`）与断线保持始终按各自的节奏直接发送；断线保持开始时暂停定时发送，
+//! 避免两边交替发送不同的数值。
+
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use rosc::{OscMessage, OscPacket, OscType};
+use tokio::task::JoinHandle;
+use tokio::time::{self, MissedTickBehavior};
+use tracing::debug;
+
+use crate::{
+    hr_messages, osc_feedback, osc_status, publish_local_outputs, send_osc_messages, Config,
+    OscExtras, OscValues,
+};
+
+/// 浮点参数平滑的时间常数（秒）：约这么久后走完与新数值差距的 63%。
+const SMOOTHING_SECS: f32 = 1.0;
+/// 重新发送全部参数（包括未变化的整数、布尔参数）的间隔。
+const RESYNC_INTERVAL: Duration = Duration::from_secs(5);
+
+/// 定时发送的状态。
+#[derive(Debug)]
+struct Scheduler {
+    /// 最近一次读数（已经过心率变换插件）；None 表示已清零，暂停发送
+    latest: Option<(u8, OscExtras)>,
+    /// 各地址最近一次发送的值
+    sent: Vec<(String, OscType)>,
+    /// 上次全部重新发送的时间
+    last_resync: Option<Instant>,
+}
+
+/// 定时发送的状态及发送任务。模块级函数操作全局实例 `SCHEDULE`，测试可以使用各自的实例。
+#[derive(Debug)]
+pub struct Schedule {
+    state: Mutex<Scheduler>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+pub static SCHEDULE: Schedule = Schedule::new();
+
+impl Scheduler {
+    /// 距上次全部重新发送已超过 `RESYNC_INTERVAL` 时忘记已发送的值，本次发送全部参数。
+    fn resync_if_due(&mut self, now: Instant) {
+        if self
+            .last_resync
+            .is_none_or(|at| now.duration_since(at) >= RESYNC_INTERVAL)
+        {
+            self.last_resync = Some(now);
+            self.sent.clear();
+        }
+    }
+
+    /// 由最新数值生成本次要发送的消息：非浮点参数只在变化时发送，
+    /// 浮点参数从上次发送的值向目标值靠近 `alpha`（0–1）的比例后发送。
+    fn tick(&mut self, messages: Vec<OscPacket>, alpha: f32) -> Vec<OscMessage> {
+        let mut out = Vec::new();
+        for packet in messages {
+            let OscPacket::Message(mut message) = packet else {
+                continue;
+            };
+            let previous = self.sent.iter_mut().find(|(addr, _)| *addr == message.addr);
+            if let (Some(OscType::Float(target)), Some((_, OscType::Float(last)))) =
+                (message.args.first_mut(), previous.as_ref())
+            {
+                let smoothed = *last + (*target - *last) * alpha;
+                // 足够接近后直接取目标值，避免无限逼近
+                if (smoothed - *target).abs() >= 0.001 {
+                    *target = smoothed;
+                }
+            }
+            let value = match message.args.first() {
+                Some(value) => value.clone(),
+                None => continue,
+            };
+            match previous {
+                Some((_, last)) => {
+                    if *last == value && !matches!(value, OscType::Float(_)) {
+                        continue;
+                    }
+                    *last = value;
+                }
+                None => self.sent.push((message.addr.clone(), value)),
+            }
+            out.push(message);
+        }
+        out
+    }
+}
+
+/// 每次发送时浮点参数靠近目标值的比例。
+fn smoothing_alpha(interval: Duration) -> f32 {
+    1.0 - (-interval.as_secs_f32() / SMOOTHING_SECS).exp()
+}
+
+/// 发送间隔；send_rate_hz 已在加载配置时限制在合理范围内。
+fn interval(config: &Config) -> Duration {
+    Duration::from_secs_f32(1.0 / config.send_rate_hz)
+}
+
+impl Schedule {
+    pub const fn new() -> Self {
+        Schedule {
+            state: Mutex::new(Scheduler {
+                latest: None,
+                sent: Vec::new(),
+                last_resync: None,
+            }),
+            task: Mutex::new(None),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, Scheduler> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// 启动定时发送任务（重复调用时替换旧任务）。
+    pub fn start(&'static self, socket: &UdpSocket, osc_addr: SocketAddr, config: &Config) {
+        let Ok(socket) = socket.try_clone() else {
+            return;
+        };
+        let config = config.clone();
+        let task = tokio::spawn(async move {
+            let period = interval(&config);
+            let hr_addr = format!("{}HR", config.osc_parameter_prefix());
+            let alpha = smoothing_alpha(period);
+            let mut ticker = time::interval(period);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                let messages = {
+                    let mut state = self.state();
+                    let Some((heart_rate, extras)) = state.latest else {
+                        continue;
+                    };
+                    let now = Instant::now();
+                    state.resync_if_due(now);
+                    let messages = state.tick(hr_messages(heart_rate, extras, &config), alpha);
+                    // 只在本次确实发送了 HR 时记录回传比对用的发送时间
+                    if messages.iter().any(|message| message.addr == hr_addr) {
+                        let v = OscValues::new(heart_rate, &config);
+                        osc_feedback::record_sent(i32::from(v.hr_for_int), now);
+                    }
+                    messages
+                };
+                if messages.is_empty() {
+                    continue;
+                }
+                if let Err(e) = send_osc_messages(&socket, osc_addr, messages, &config) {
+                    debug!("定时发送 OSC 数据时出错: {}", e);
+                }
+            }
+        });
+        if let Some(old) = self
+            .task
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .replace(task)
+        {
+            old.abort();
+        }
+    }
+
+    /// 收到读数时调用：更新最新状态并写入本机输出，返回状态行描述（实际发送由定时任务完成）。
+    pub fn update(&self, heart_rate: u8, extras: OscExtras, config: &Config) -> String {
+        self.state().latest = Some((heart_rate, extras));
+        let v = publish_local_outputs(heart_rate, config);
+        format!(
+            "{}  定时发送: {} Hz",
+            osc_status(heart_rate, &v, extras),
+            config.send_rate_hz
+        )
+    }
+
+    /// 断开、清零或开始断线保持时调用：暂停发送并忘记已发送的值，恢复后第一次发送完整数据。
+    pub fn clear(&self) {
+        let mut state = self.state();
+        state.latest = None;
+        state.sent.clear();
+    }
+
+    /// 切换 avatar 时调用：下一次发送全部参数。
+    pub fn resync(&self) {
+        self.state().sent.clear();
+    }
+}
+
+/// 启动全局的定时发送任务（见 `Schedule::start`）。
+pub fn start(socket: &UdpSocket, osc_addr: SocketAddr, config: &Config) {
+    SCHEDULE.start(socket, osc_addr, config);
+}
+
+/// 收到读数时调用（见 `Schedule::update`）。
+pub fn update(heart_rate: u8, extras: OscExtras, config: &Config) -> String {
+    SCHEDULE.update(heart_rate, extras, config)
+}
+
+/// 断开或清零时调用（见 `Schedule::clear`）。
+pub fn clear() {
+    SCHEDULE.clear();
+}
+
+/// 切换 avatar 时调用：下一次发送全部参数。
+pub fn resync() {
+    SCHEDULE.resync();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(addr: &str, value: OscType) -> OscPacket {
+        OscPacket::Message(OscMessage {
+            addr: addr.to_string(),
+            args: vec![value],
+        })
+    }
+
+    fn values(messages: &[OscMessage]) -> Vec<(&str, OscType)> {
+        messages
+            .iter()
+            .map(|message| (message.addr.as_str(), message.args[0].clone()))
+            .collect()
+    }
+
+    #[test]
+    fn ints_and_bools_are_sent_on_change_and_floats_are_smoothed() {
+        let mut scheduler = Scheduler {
+            latest: None,
+            sent: Vec::new(),
+            last_resync: None,
+        };
+        let reading = |hr: i32, percent: f32| {
+            vec![
+                message("/hr", OscType::Int(hr)),
+                message("/active", OscType::Bool(true)),
+                message("/percent", OscType::Float(percent)),
+            ]
+        };
+
+        // 第一次全部发送，浮点数直接取目标值
+        let first = scheduler.tick(reading(60, 0.5), 0.5);
+        assert_eq!(
+            values(&first),
+            vec![
+                ("/hr", OscType::Int(60)),
+                ("/active", OscType::Bool(true)),
+                ("/percent", OscType::Float(0.5)),
+            ]
+        );
+
+        // 数值不变：只有浮点参数继续发送
+        let same = scheduler.tick(reading(60, 0.5), 0.5);
+        assert_eq!(values(&same), vec![("/percent", OscType::Float(0.5))]);
+
+        // 新读数：整数立即跟上，浮点数逐步靠近
+        let changed = scheduler.tick(reading(80, 1.0), 0.5);
+        assert_eq!(
+            values(&changed),
+            vec![
+                ("/hr", OscType::Int(80)),
+                ("/percent", OscType::Float(0.75))
+            ]
+        );
+        let next = scheduler.tick(reading(80, 1.0), 0.5);
+        assert_eq!(values(&next), vec![("/percent", OscType::Float(0.875))]);
+        for _ in 0..20 {
+            scheduler.tick(reading(80, 1.0), 0.5);
+        }
+        let settled = scheduler.tick(reading(80, 1.0), 0.5);
+        assert_eq!(values(&settled), vec![("/percent", OscType::Float(1.0))]);
+    }
+
+    #[test]
+    fn unchanged_ints_and_bools_are_resent_periodically() {
+        let mut scheduler = Scheduler {
+            latest: None,
+            sent: Vec::new(),
+            last_resync: None,
+        };
+        let reading = || {
+            vec![
+                message("/hr", OscType::Int(60)),
+                message("/active", OscType::Bool(true)),
+            ]
+        };
+        let start = Instant::now();
+        scheduler.resync_if_due(start);
+        assert_eq!(scheduler.tick(reading(), 0.5).len(), 2);
+
+        scheduler.resync_if_due(start + Duration::from_secs(1));
+        assert!(scheduler.tick(reading(), 0.5).is_empty());
+
+        // 切换 avatar 后的新 avatar 在下一次全部重新发送时收到未变化的参数
+        scheduler.resync_if_due(start + RESYNC_INTERVAL);
+        assert_eq!(scheduler.tick(reading(), 0.5).len(), 2);
+        assert!(scheduler.tick(reading(), 0.5).is_empty());
+    }
+
+    #[test]
+    fn smoothing_follows_the_send_interval() {
+        let alpha = smoothing_alpha(Duration::from_millis(250));
+        assert!((alpha - 0.221).abs() < 0.001, "alpha = {}", alpha);
+        assert!(smoothing_alpha(Duration::from_secs(10)) > 0.99);
+    }
+}
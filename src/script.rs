@@ -0,0 +1,411 @@
+//! 自定义输出脚本（`script_path`）：每次收到心率读数时调用 Rhai 脚本中的 `on_reading(ctx)`，
+//! 脚本可以据此发送 OSC 参数、写文件或打印日志，无需重新编译即可实现"持续高心率时触发动画"之类的逻辑。
+//! 示例见 `examples/scripts/sustained_high_hr.rhai`。
+//!
+//! `ctx` 是一个对象映射：
+//!
+//! - `hr`：心率（整数，未佩戴时为 0）；
+//! - `rr`：本次通知中的 RR 间期数组（毫秒，浮点数，可能为空）；
+//! - `connected`：是否已连接（断开、清零、断线保持结束和退出时以 `false` 和 `hr = 0` 调用一次）；
+//! - `elapsed`：本次连接已持续的秒数（浮点数）；
+//! - `session`：本次连接的统计 `#{min, max, avg, samples}`，还没有样本时均为 0。
+//!
+//! 脚本中的 `this` 是跨读数保留的状态映射（重新加载脚本后清空），可用 `this.x = ...` 保存任意数据。
+//! 可调用的函数：
+//!
+//! - `osc_int(name, 整数)` / `osc_float(name, 数值)` / `osc_bool(name, 布尔)`：本次读数之后发送一条 OSC 消息，
+//!   `name` 不以 `/` 开头时加上参数地址前缀；
+//! - `write_file(path, text)`：写入文本文件（后台写入，相对路径相对于脚本所在目录）；
+//! - `log(text)` 与 `print(text)`：打印到日志。
+//!
+//! 脚本文件修改后在下一次读数时自动重新加载。编译或运行出错时记录警告并停用脚本，
+//! 直到文件再次修改；单次调用的运算步数有上限，死循环也不会卡住蓝牙接收循环。
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use rhai::{Array, CallFnOptions, Dynamic, Engine, Map, Scope, AST, FLOAT, INT};
+use rosc::{OscMessage, OscType};
+use tracing::{info, warn};
+
+use crate::file_writer;
+
+/// 单次调用 `on_reading` 允许的运算步数。
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// 本次连接的心率统计。
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Session {
+    pub min: u8,
+    pub max: u8,
+    pub avg: f32,
+    pub samples: u32,
+}
+
+/// 传给 `on_reading` 的一次读数。
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Reading {
+    pub hr: u8,
+    pub rr_ms: Vec<f32>,
+    pub connected: bool,
+    pub elapsed_secs: f64,
+    pub session: Session,
+}
+
+impl Reading {
+    fn to_dynamic(&self) -> Dynamic {
+        let mut session = Map::new();
+        session.insert("min".into(), Dynamic::from(INT::from(self.session.min)));
+        session.insert("max".into(), Dynamic::from(INT::from(self.session.max)));
+        session.insert("avg".into(), Dynamic::from(FLOAT::from(self.session.avg)));
+        session.insert(
+            "samples".into(),
+            Dynamic::from(INT::from(self.session.samples)),
+        );
+        let rr: Array = self
+            .rr_ms
+            .iter()
+            .map(|&rr| Dynamic::from(FLOAT::from(rr)))
+            .collect();
+
+        let mut ctx = Map::new();
+        ctx.insert("hr".into(), Dynamic::from(INT::from(self.hr)));
+        ctx.insert("rr".into(), Dynamic::from_array(rr));
+        ctx.insert("connected".into(), Dynamic::from(self.connected));
+        ctx.insert("elapsed".into(), Dynamic::from(self.elapsed_secs));
+        ctx.insert("session".into(), Dynamic::from_map(session));
+        Dynamic::from_map(ctx)
+    }
+}
+
+/// 已编译的脚本及其状态。
+struct Script {
+    engine: Engine,
+    ast: AST,
+    /// 脚本中的 `this`
+    state: Dynamic,
+    /// `osc_*` 函数在本次调用中排队的消息
+    pending: Arc<Mutex<Vec<OscMessage>>>,
+}
+
+impl Script {
+    /// 编译脚本并注册 API；语法错误或没有 `on_reading(ctx)` 时返回错误说明。
+    fn load(path: &Path, prefix: &str) -> Result<Script, String> {
+        let source = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let dir = path.parent().unwrap_or(Path::new(".")).to_path_buf();
+        let pending = Arc::new(Mutex::new(Vec::new()));
+
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.on_print(|text| info!("[脚本] {}", text));
+        engine.register_fn("log", |text: &str| info!("[脚本] {}", text));
+        engine.register_fn("write_file", move |path: &str, text: &str| {
+            file_writer::submit(dir.join(path), text.to_string());
+        });
+        let emit = |pending: &Arc<Mutex<Vec<OscMessage>>>| {
+            let pending = Arc::clone(pending);
+            let prefix = prefix.to_string();
+            move |name: &str, arg: OscType| {
+                let addr = if name.starts_with('/') {
+                    name.to_string()
+                } else {
+                    format!("{}{}", prefix, name)
+                };
+                pending.lock().unwrap().push(OscMessage {
+                    addr,
+                    args: vec![arg],
+                });
+            }
+        };
+        let send = emit(&pending);
+        engine.register_fn("osc_int", move |name: &str, value: INT| {
+            send(
+                name,
+                OscType::Int(value.clamp(i32::MIN.into(), i32::MAX.into()) as i32),
+            );
+        });
+        let send = emit(&pending);
+        engine.register_fn("osc_float", move |name: &str, value: FLOAT| {
+            send(name, OscType::Float(value as f32));
+        });
+        // 允许 osc_float("x", 1) 这样传入整数
+        let send = emit(&pending);
+        engine.register_fn("osc_float", move |name: &str, value: INT| {
+            send(name, OscType::Float(value as f32));
+        });
+        let send = emit(&pending);
+        engine.register_fn("osc_bool", move |name: &str, value: bool| {
+            send(name, OscType::Bool(value));
+        });
+
+        let ast = engine.compile(source).map_err(|e| e.to_string())?;
+        if !ast
+            .iter_functions()
+            .any(|f| f.name == "on_reading" && f.params.len() == 1)
+        {
+            return Err("脚本中没有定义 on_reading(ctx) 函数".to_string());
+        }
+        Ok(Script {
+            engine,
+            ast,
+            state: Dynamic::from_map(Map::new()),
+            pending,
+        })
+    }
+
+    /// 调用 `on_reading`，返回脚本排队的 OSC 消息。
+    fn call(&mut self, reading: &Reading) -> Result<Vec<OscMessage>, String> {
+        self.pending.lock().unwrap().clear();
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut self.state);
+        // 返回值不使用，脚本通过 osc_* 函数输出
+        let _ = self
+            .engine
+            .call_fn_with_options::<Dynamic>(
+                options,
+                &mut Scope::new(),
+                &self.ast,
+                "on_reading",
+                (reading.to_dynamic(),),
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(std::mem::take(&mut *self.pending.lock().unwrap()))
+    }
+}
+
+/// 脚本文件及其加载状态。
+struct Watched {
+    path: PathBuf,
+    prefix: String,
+    /// 最近一次尝试加载时文件的修改时间
+    modified: Option<SystemTime>,
+    /// 加载失败或运行出错后为 None，直到文件再次修改
+    script: Option<Script>,
+    /// 最近一次交给脚本的读数
+    last: Option<Reading>,
+}
+
+impl Watched {
+    fn reading(&mut self, reading: &Reading) -> Vec<OscMessage> {
+        self.last = Some(reading.clone());
+        let modified = fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if modified != self.modified {
+            self.modified = modified;
+            let reload = self.script.is_some();
+            self.script = match Script::load(&self.path, &self.prefix) {
+                Ok(script) => {
+                    info!(
+                        "已{}脚本 {}",
+                        if reload { "重新加载" } else { "加载" },
+                        self.path.display()
+                    );
+                    Some(script)
+                }
+                Err(e) => {
+                    warn!(
+                        "无法加载脚本 {}（{}），修改文件后将重新尝试。",
+                        self.path.display(),
+                        e
+                    );
+                    None
+                }
+            };
+        }
+        let Some(script) = &mut self.script else {
+            return Vec::new();
+        };
+        match script.call(reading) {
+            Ok(messages) => messages,
+            Err(e) => {
+                warn!(
+                    "脚本 {} 运行出错，已停用（{}），修改文件后将重新加载。",
+                    self.path.display(),
+                    e
+                );
+                self.script = None;
+                Vec::new()
+            }
+        }
+    }
+
+    /// 上一次读数为已连接时以 `connected = false`、`hr = 0` 调用一次，
+    /// 会话统计与已连接时长沿用上一次读数；已经通知过或还没有读数时不调用。
+    fn disconnected(&mut self) -> Vec<OscMessage> {
+        match &self.last {
+            Some(last) if last.connected => {
+                let reading = Reading {
+                    hr: 0,
+                    rr_ms: Vec::new(),
+                    connected: false,
+                    ..last.clone()
+                };
+                self.reading(&reading)
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+static SCRIPT: Mutex<Option<Watched>> = Mutex::new(None);
+
+/// 启用脚本；文件在第一次读数时加载。
+pub fn start(path: PathBuf, prefix: &str) {
+    *SCRIPT.lock().unwrap() = Some(Watched {
+        path,
+        prefix: prefix.to_string(),
+        modified: None,
+        script: None,
+        last: None,
+    });
+}
+
+/// 把读数交给脚本，返回脚本要发送的 OSC 消息；未启用或脚本已停用时为空。
+pub fn reading(reading: &Reading) -> Vec<OscMessage> {
+    match SCRIPT.lock().unwrap().as_mut() {
+        Some(watched) => watched.reading(reading),
+        None => Vec::new(),
+    }
+}
+
+/// 断开或清零时调用，让脚本以 `connected = false` 收到一次读数，返回脚本要发送的 OSC 消息。
+pub fn disconnected() -> Vec<OscMessage> {
+    match SCRIPT.lock().unwrap().as_mut() {
+        Some(watched) => watched.disconnected(),
+        None => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PREFIX: &str = "/avatar/parameters/";
+
+    fn at(hr: u8, elapsed_secs: f64) -> Reading {
+        Reading {
+            hr,
+            rr_ms: vec![60_000.0 / f32::from(hr.max(1))],
+            connected: true,
+            elapsed_secs,
+            session: Session {
+                min: 60,
+                max: 180,
+                avg: 120.0,
+                samples: 10,
+            },
+        }
+    }
+
+    fn sustained_high(value: bool) -> Vec<OscMessage> {
+        vec![OscMessage {
+            addr: "/avatar/parameters/HRSustainedHigh".to_string(),
+            args: vec![OscType::Bool(value)],
+        }]
+    }
+
+    #[test]
+    fn example_script_triggers_after_sustained_high_heart_rate() {
+        let path = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/examples/scripts/sustained_high_hr.rhai"
+        ));
+        let mut script = Script::load(path, PREFIX).expect("example script should compile");
+        let mut call = |reading| script.call(&reading).expect("example script should run");
+
+        assert!(call(at(160, 0.0)).is_empty());
+        assert!(call(at(165, 29.0)).is_empty());
+        assert_eq!(call(at(170, 30.0)), sustained_high(true));
+        // 触发后只发送一次
+        assert!(call(at(170, 45.0)).is_empty());
+        assert_eq!(call(at(140, 46.0)), sustained_high(false));
+        // 回落后重新计时
+        assert!(call(at(160, 50.0)).is_empty());
+        assert!(call(at(160, 70.0)).is_empty());
+        assert_eq!(call(at(160, 80.0)), sustained_high(true));
+        let disconnected = Reading {
+            connected: false,
+            ..at(0, 81.0)
+        };
+        assert_eq!(call(disconnected), sustained_high(false));
+    }
+
+    #[test]
+    fn script_errors_disable_the_script_until_the_file_changes() {
+        let path = std::env::temp_dir().join(format!("hr_script_test_{}.rhai", std::process::id()));
+        fs::write(
+            &path,
+            r#"fn on_reading(ctx) { osc_int("/hr", ctx.hr); if ctx.hr > 200 { throw "too high"; } }"#,
+        )
+        .unwrap();
+        let mut watched = Watched {
+            path: path.clone(),
+            prefix: PREFIX.to_string(),
+            modified: None,
+            script: None,
+            last: None,
+        };
+
+        let sent = watched.reading(&at(80, 0.0));
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].args, vec![OscType::Int(80)]);
+        assert!(watched.reading(&at(220, 1.0)).is_empty());
+        assert!(watched.script.is_none());
+        // 停用后不再调用
+        assert!(watched.reading(&at(80, 2.0)).is_empty());
+
+        // 文件修改后重新加载；语法错误同样只停用脚本
+        fs::write(&path, "fn on_reading(ctx) { osc_int(").unwrap();
+        watched.modified = None;
+        assert!(watched.reading(&at(80, 3.0)).is_empty());
+        assert!(watched.script.is_none());
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn disconnect_is_reported_once_with_connected_false() {
+        let path = std::env::temp_dir().join(format!(
+            "hr_script_disconnect_test_{}.rhai",
+            std::process::id()
+        ));
+        fs::write(
+            &path,
+            r#"fn on_reading(ctx) { osc_bool("/connected", ctx.connected); osc_int("/hr", ctx.hr); }"#,
+        )
+        .unwrap();
+        let mut watched = Watched {
+            path: path.clone(),
+            prefix: PREFIX.to_string(),
+            modified: None,
+            script: None,
+            last: None,
+        };
+        let args = |messages: Vec<OscMessage>| -> Vec<OscType> {
+            messages.into_iter().flat_map(|m| m.args).collect()
+        };
+
+        // 还没有读数时不调用
+        assert!(watched.disconnected().is_empty());
+        assert_eq!(
+            args(watched.reading(&at(90, 0.0))),
+            [OscType::Bool(true), OscType::Int(90)]
+        );
+        assert_eq!(
+            args(watched.disconnected()),
+            [OscType::Bool(false), OscType::Int(0)]
+        );
+        assert_eq!(
+            watched.last.as_ref().map(|last| last.session),
+            Some(at(90, 0.0).session)
+        );
+        // 断开后的再次清零（例如断线保持结束后退出）不重复调用
+        assert!(watched.disconnected().is_empty());
+
+        let _ = fs::remove_file(&path);
+    }
+}
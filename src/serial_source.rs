@@ -0,0 +1,183 @@
+//! 串口备用心率源（`serial_fallback_enabled = true`）：蓝牙连续 `serial_fallback_timeout_secs` 秒
+//! 没有收到心率数据时，打开 `serial_port`（`serial_baud`，默认 9600）读取 USB CDC / 串口输出的心率，
+//! 例如医用血氧仪每秒输出的 `BPM=72\r\n`。读数走与蓝牙相同的 `HeartRateSink`（OSC、文件及其他输出）。
+//!
+//! 蓝牙在后台照常扫描/重连，重新收到心率后立即关闭串口、回到蓝牙。串口打不开或中途拔出时
+//! 每隔 `retry_delay_secs` 秒重试，只在第一次失败时记警告。
+
+use std::convert::Infallible;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::time;
+use tokio_serial::SerialPortBuilderExt;
+use tracing::{debug, info, warn};
+
+use crate::{clear_state, Config, HeartRateMeasurement, HeartRateSink, Result};
+
+/// 检查蓝牙是否恢复的间隔。
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 最近一次从蓝牙收到心率的时间；None 表示本次运行尚未收到。
+static LAST_BLE_READING: Mutex<Option<Instant>> = Mutex::new(None);
+/// 是否正在使用串口数据。
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// 蓝牙连接/广播每收到一条心率时调用。
+pub fn ble_reading(now: Instant) {
+    *LAST_BLE_READING.lock().unwrap() = Some(now);
+}
+
+/// 是否正在使用串口数据；此时蓝牙断开不应清零输出。
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// 蓝牙已经多久没有心率数据（本次运行尚未收到时从 `since` 起算）。
+fn ble_silence(since: Instant, now: Instant) -> Duration {
+    let last = LAST_BLE_READING.lock().unwrap().unwrap_or(since);
+    now.saturating_duration_since(last)
+}
+
+/// 从一行串口输出中解析心率：`BPM=72`（不区分大小写，允许首尾空白和 `\r`）。
+fn parse_line(line: &str) -> Option<u16> {
+    let (key, value) = line.trim().split_once('=')?;
+    if !key.trim().eq_ignore_ascii_case("BPM") {
+        return None;
+    }
+    value.trim().parse().ok()
+}
+
+/// 与蓝牙主循环并行运行：蓝牙静默超时后改用串口，蓝牙恢复后停止。不会返回。
+pub async fn run(
+    socket: &UdpSocket,
+    osc_addr: SocketAddr,
+    config: &Config,
+    hr_file: &Path,
+) -> Infallible {
+    let timeout = Duration::from_secs(config.serial_fallback_timeout_secs);
+    let start = Instant::now();
+    let mut warned = false;
+    loop {
+        // 等待蓝牙静默超过阈值
+        loop {
+            let silence = ble_silence(start, Instant::now());
+            if silence >= timeout {
+                break;
+            }
+            time::sleep((timeout - silence).max(CHECK_INTERVAL)).await;
+        }
+
+        match session(socket, osc_addr, config, hr_file, start, &mut warned).await {
+            Ok(()) => info!("蓝牙已恢复接收心率，停止使用串口 {}。", config.serial_port),
+            Err(e) => {
+                if ACTIVE.swap(false, Ordering::Relaxed) {
+                    warn!(
+                        "串口 {} 读取失败（{}），将自动重试。",
+                        config.serial_port, e
+                    );
+                    clear_state(socket, osc_addr, config, hr_file);
+                    warned = true;
+                } else if !warned {
+                    warn!(
+                        "蓝牙 {} 秒没有心率数据，但无法打开串口 {}（{}），将每隔 {} 秒重试。",
+                        config.serial_fallback_timeout_secs,
+                        config.serial_port,
+                        e,
+                        config.retry_delay_secs
+                    );
+                    warned = true;
+                } else {
+                    debug!("打开串口 {} 失败: {}", config.serial_port, e);
+                }
+                time::sleep(Duration::from_secs(config.retry_delay_secs)).await;
+            }
+        }
+        ACTIVE.store(false, Ordering::Relaxed);
+    }
+}
+
+/// 打开串口并转发心率，直到蓝牙恢复（Ok）或串口出错/超时无数据（Err）。
+async fn session(
+    socket: &UdpSocket,
+    osc_addr: SocketAddr,
+    config: &Config,
+    hr_file: &Path,
+    start: Instant,
+    warned: &mut bool,
+) -> Result<()> {
+    let port = tokio_serial::new(config.serial_port.as_str(), config.serial_baud)
+        .open_native_async()
+        .map_err(io::Error::from)?;
+    let mut lines = BufReader::new(port).lines();
+    info!(
+        "蓝牙 {} 秒没有心率数据，改用串口 {}（{} baud）。",
+        config.serial_fallback_timeout_secs, config.serial_port, config.serial_baud
+    );
+    *warned = false;
+
+    let mut sink = HeartRateSink::new(socket, osc_addr, config, hr_file);
+    sink.status.device_name = Some(format!("串口 {}", config.serial_port));
+    let heartbeat = Duration::from_secs(config.heartbeat_timeout_secs);
+    let mut last_beat = Instant::now();
+    let mut check = time::interval(CHECK_INTERVAL);
+    loop {
+        tokio::select! {
+            line = time::timeout(heartbeat.saturating_sub(last_beat.elapsed()), lines.next_line()) => {
+                let line = match line {
+                    Ok(Ok(Some(line))) => line,
+                    Ok(Ok(None)) => {
+                        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+                    }
+                    Ok(Err(e)) => return Err(e.into()),
+                    Err(_) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            format!("{} 秒内没有心率数据", config.heartbeat_timeout_secs),
+                        )
+                        .into());
+                    }
+                };
+                let Some(heart_rate) = parse_line(&line) else {
+                    debug!("忽略无法识别的串口输出: {:?}", line);
+                    continue;
+                };
+                let now = Instant::now();
+                last_beat = now;
+                ACTIVE.store(true, Ordering::Relaxed);
+                sink.handle(
+                    &HeartRateMeasurement {
+                        heart_rate,
+                        rr_intervals: Vec::new(),
+                        energy_expended: None,
+                    },
+                    now,
+                );
+            }
+            _ = check.tick() => {
+                if ble_silence(start, Instant::now()) < CHECK_INTERVAL * 2 {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_line_reads_bpm_key() {
+        assert_eq!(parse_line("BPM=72\r"), Some(72));
+        assert_eq!(parse_line("  bpm = 130 "), Some(130));
+        assert_eq!(parse_line("SPO2=98"), None);
+        assert_eq!(parse_line("BPM="), None);
+        assert_eq!(parse_line("hello"), None);
+    }
+}
@@ -0,0 +1,224 @@
+//! CSV 会话记录（`write_session_log = true`）：每条心率读数追加一行，供事后分析。
+//!
+//! 文件位于 `session_log_dir`（默认程序目录下的 `sessions`），文件名带开始时间，
+//! 如 `session-20261016-213000.csv`。每行格式：
+//!
+//! ```text
+//! timestamp,bpm,rr_ms,connected,rssi,event
+//! 2026-10-16T21:30:01.250+08:00,87,690;702,true,-67,
+//! 2026-10-16T21:35:12.004+08:00,,,false,,disconnected
+//! ```
+//!
+//! - `rr_ms`：本次通知携带的 RR 间期（毫秒，多个用 `;` 分隔），设备不提供时为空
+//! - `rssi`：未开启 RSSI 轮询或平台不提供时为空
+//! - `event`：读数行为空；连接、断开、进入空闲等事件单独记一行，解释数据中的空档
+//!
+//! 写入经过缓冲，每 `session_log_flush_secs` 秒才落盘一次；超过 `session_log_rotate_mb`
+//! 或 `session_log_rotate_mins` 时换新文件（0 = 不按该条件轮换）。正常退出时落盘并关闭。
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use tracing::{info, warn};
+
+use crate::recorder::{self, Event, Reading, Recorder};
+
+const HEADER: &str = "timestamp,bpm,rr_ms,connected,rssi,event\n";
+
+/// 落盘与轮换设置（来自 Config）。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    pub flush_interval: Duration,
+    /// 单个文件的最大字节数，0 = 不限
+    pub max_bytes: u64,
+    /// 单个文件覆盖的最长时间，None = 不限
+    pub max_age: Option<Duration>,
+}
+
+#[derive(Debug)]
+pub struct SessionLog {
+    dir: PathBuf,
+    settings: Settings,
+    writer: BufWriter<File>,
+    path: PathBuf,
+    opened: Instant,
+    bytes: u64,
+    last_flush: Instant,
+    error_shown: bool,
+}
+
+impl SessionLog {
+    /// 创建目录和第一个文件，提前暴露目录不可写的问题。
+    pub fn open(dir: &Path, settings: Settings, now: DateTime<Local>) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(file_name(now));
+        let mut writer = BufWriter::new(File::options().create(true).append(true).open(&path)?);
+        writer.write_all(HEADER.as_bytes())?;
+        let opened = Instant::now();
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            settings,
+            writer,
+            path,
+            opened,
+            bytes: HEADER.len() as u64,
+            last_flush: opened,
+            error_shown: false,
+        })
+    }
+
+    fn append(&mut self, row: &str) {
+        if let Err(e) = self.try_append(row) {
+            if !self.error_shown {
+                warn!(
+                    "写入会话记录 {} 时出错: {}（恢复前不再重复提示）",
+                    self.path.display(),
+                    e
+                );
+                self.error_shown = true;
+            }
+        } else {
+            self.error_shown = false;
+        }
+    }
+
+    fn try_append(&mut self, row: &str) -> io::Result<()> {
+        if self.should_rotate() {
+            self.rotate()?;
+        }
+        self.writer.write_all(row.as_bytes())?;
+        self.bytes += row.len() as u64;
+        if self.last_flush.elapsed() >= self.settings.flush_interval {
+            self.writer.flush()?;
+            self.last_flush = Instant::now();
+        }
+        Ok(())
+    }
+
+    fn should_rotate(&self) -> bool {
+        (self.settings.max_bytes > 0 && self.bytes >= self.settings.max_bytes)
+            || self
+                .settings
+                .max_age
+                .is_some_and(|age| self.opened.elapsed() >= age)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        let next = Self::open(&self.dir, self.settings, Local::now())?;
+        info!("会话记录已轮换到 {}", next.path.display());
+        *self = next;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Recorder for SessionLog {
+    fn reading(&mut self, at: DateTime<Local>, reading: &Reading) {
+        self.append(&reading_row(at, reading));
+    }
+
+    fn event(&mut self, at: DateTime<Local>, event: &Event) {
+        self.append(&event_row(at, event));
+    }
+
+    fn finish(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+fn file_name(now: DateTime<Local>) -> String {
+    format!("session-{}.csv", now.format("%Y%m%d-%H%M%S"))
+}
+
+fn reading_row(at: DateTime<Local>, reading: &Reading) -> String {
+    format!(
+        "{},{},{},true,{},\n",
+        recorder::timestamp(at),
+        reading.bpm,
+        recorder::rr_ms(reading.rr_intervals),
+        reading
+            .rssi
+            .map(|rssi| rssi.to_string())
+            .unwrap_or_default()
+    )
+}
+
+fn event_row(at: DateTime<Local>, event: &Event) -> String {
+    format!(
+        "{},,,false,,{}\n",
+        recorder::timestamp(at),
+        event.to_string().replace(',', ";")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::env;
+
+    fn at(secs: i64) -> DateTime<Local> {
+        Local.timestamp_opt(secs, 0).unwrap()
+    }
+
+    fn bpm(bpm: u8) -> Reading<'static> {
+        Reading {
+            bpm,
+            rr_intervals: &[],
+            rssi: None,
+            energy_kj: None,
+        }
+    }
+
+    #[test]
+    fn rows_have_one_column_per_header_field() {
+        let columns = HEADER.trim_end().split(',').count();
+        let full = Reading {
+            bpm: 87,
+            rr_intervals: &[707, 719],
+            rssi: Some(-67),
+            energy_kj: None,
+        };
+        let reading = reading_row(at(0), &full);
+        assert!(reading.ends_with(",87,690;702,true,-67,\n"));
+        assert_eq!(reading.trim_end().split(',').count(), columns);
+        assert!(reading_row(at(0), &bpm(0)).ends_with(",0,,true,,\n"));
+        let event = event_row(at(0), &Event::Connected("A0:9E, Polar".to_string()));
+        assert!(event.ends_with(",,,false,,connected A0:9E; Polar\n"));
+        assert_eq!(event.trim_end().split(',').count(), columns);
+    }
+
+    #[test]
+    fn rotates_to_a_new_file_once_the_size_limit_is_reached() {
+        let dir = env::temp_dir().join(format!("hr-vrc-session-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let settings = Settings {
+            flush_interval: Duration::ZERO,
+            max_bytes: 100,
+            max_age: None,
+        };
+        let mut log = SessionLog::open(&dir, settings, at(0)).unwrap();
+        let first = log.path.clone();
+        log.reading(at(1), &bpm(80));
+        assert!(!log.should_rotate());
+        log.reading(at(2), &bpm(81));
+        assert!(log.should_rotate());
+        log.reading(at(3), &bpm(82));
+        assert_ne!(log.path, first);
+        log.finish();
+
+        let old = fs::read_to_string(&first).unwrap();
+        assert!(old.starts_with(HEADER));
+        assert_eq!(old.lines().count(), 3);
+        let new = fs::read_to_string(&log.path).unwrap();
+        assert_eq!(new.lines().count(), 2);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,79 @@
+//! 共享内存输出（`shm_enabled = true`）：每次发送 OSC 时同步写入名为 `shm_name` 的共享内存段，
+//! 供 TouchDesigner、Processing 等本机工具低延迟读取。
+//!
+//! 段大小 16 字节，布局固定（小端）：
+//!
+//! | 偏移 | 类型 | 含义 |
+//! |---|---|---|
+//! | 0 | u8 | 心率（与 OSC `HR` 相同，未佩戴/断开时为 0） |
+//! | 1 | u8 | 是否活跃（与 OSC `isHRActive` 相同，1 / 0） |
+//! | 2–3 | — | 保留，为 0 |
+//! | 4–7 | u32 | 序号，每次写入加 1；读取方据此判断数据是否更新 |
+//! | 8–15 | — | 保留，为 0 |
+
+use std::ptr;
+use std::sync::Mutex;
+
+use shared_memory::{Shmem, ShmemConf, ShmemError};
+
+const SIZE: usize = 16;
+const SEQUENCE_OFFSET: usize = 4;
+
+/// 共享内存映射；只在 `SEGMENT` 的互斥锁内访问。
+struct Segment {
+    shmem: Shmem,
+    sequence: u32,
+}
+
+// SAFETY: Shmem 只持有映射的地址和系统句柄，不绑定线程；所有访问都经过 SEGMENT 的互斥锁。
+unsafe impl Send for Segment {}
+
+static SEGMENT: Mutex<Option<Segment>> = Mutex::new(None);
+
+/// 启动时调用：创建共享内存段（已存在时打开，例如上次异常退出后残留），成功后 `write` 才会生效。
+pub fn init(name: &str) -> Result<(), ShmemError> {
+    let shmem = match ShmemConf::new().size(SIZE).os_id(name).create() {
+        Ok(shmem) => shmem,
+        Err(ShmemError::MappingIdExists) => ShmemConf::new().os_id(name).open()?,
+        Err(e) => return Err(e),
+    };
+    *SEGMENT.lock().unwrap() = Some(Segment { shmem, sequence: 0 });
+    Ok(())
+}
+
+/// 写入一次数据；未启用时不做任何事。
+pub fn write(bpm: u8, active: bool) {
+    let mut segment = SEGMENT.lock().unwrap();
+    let Some(segment) = segment.as_mut() else {
+        return;
+    };
+    if segment.shmem.len() < SIZE {
+        return;
+    }
+    segment.sequence = segment.sequence.wrapping_add(1);
+    let data = encode(bpm, active, segment.sequence);
+    // SAFETY: 映射至少 SIZE 字节（上面已检查），本进程只在持锁时写入。
+    unsafe { ptr::copy_nonoverlapping(data.as_ptr(), segment.shmem.as_ptr(), SIZE) };
+}
+
+fn encode(bpm: u8, active: bool, sequence: u32) -> [u8; SIZE] {
+    let mut data = [0; SIZE];
+    data[0] = bpm;
+    data[1] = u8::from(active);
+    data[SEQUENCE_OFFSET..SEQUENCE_OFFSET + 4].copy_from_slice(&sequence.to_le_bytes());
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout_is_bpm_active_then_little_endian_sequence() {
+        assert_eq!(
+            encode(87, true, 0x0102_0304),
+            [87, 1, 0, 0, 4, 3, 2, 1, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(encode(0, false, 1)[..2], [0, 0]);
+    }
+}
@@ -0,0 +1,290 @@
+//! 心率数据源抽象：数据源（蓝牙连接循环、Pulsoid、测试用的固定心率源）只负责产生读数和连接状态事件，
+//! 由 `run` 把事件按顺序交给 `HeartRateSink` 送往 OSC、文件等各个输出。
+//! 断开时的断线保持与清零也在这里统一处理，数据源不必持有 OSC 套接字。
+//!
+//! 事件经无界通道传递，`run` 与数据源在同一任务中交替运行，事件按发送顺序立即处理；
+//! 数据源返回后剩余的事件仍会处理完。广播模式（一次监听多台设备）与串口备用源仍直接使用 `HeartRateSink`。
+
+use std::future::Future;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::Path;
+use std::pin::Pin;
+use std::time::Instant;
+
+use tokio::sync::mpsc;
+use tracing::{warn, Span};
+
+use crate::{
+    clear_state, ghost, serial_source, status_enabled, Config, HeartRateMeasurement, HeartRateSink,
+    Result, SignalMonitor,
+};
+
+/// 一次心率读数及其接收时间。
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeartRateSample {
+    pub measurement: HeartRateMeasurement,
+    pub timestamp: Instant,
+}
+
+/// 连接成功、开始接收时数据源提供的设备信息。
+#[derive(Debug, Clone, Default)]
+pub struct SourceDevice {
+    pub name: Option<String>,
+    pub address: Option<String>,
+    /// 连接时读到的电量
+    pub battery: Option<u8>,
+    /// 设备信息服务中的厂商、型号与固件版本
+    pub manufacturer: Option<String>,
+    pub model: Option<String>,
+    pub firmware: Option<String>,
+    /// 数据源会定期发送 `SourceEvent::Rssi`
+    pub rssi: bool,
+    /// 处理本次连接的事件时进入的日志 span（带设备地址等字段）
+    pub span: Option<Span>,
+}
+
+/// 数据源产生的事件。
+#[derive(Debug)]
+pub enum SourceEvent {
+    /// 已连接并开始接收，之后的读数属于这次连接
+    Connected(SourceDevice),
+    Sample(HeartRateSample),
+    /// 最近读取的信号强度（读取失败时为 None）
+    Rssi(Option<i16>),
+    /// 血氧饱和度，随下一次心率一起发送
+    Spo2(u8),
+    /// 累计步数及收到的时间
+    Steps(u32, Instant),
+    /// 归一化运动强度 0–1 及收到的时间，随下一次心率一起发送
+    Movement(f32, Instant),
+    /// 连接期间进入空闲模式（设备未佩戴），清零输出
+    Idle,
+    /// 连接已断开：启用断线保持时继续发送最后的心率，否则立即清零
+    Disconnected,
+    /// 放弃当前设备重新扫描（重置请求）：立即清零，不做断线保持
+    Reset,
+}
+
+/// 数据源的事件发送端。
+pub struct Events(mpsc::UnboundedSender<SourceEvent>);
+
+impl Events {
+    pub fn send(&self, event: SourceEvent) {
+        // 接收端与数据源同生命周期，发送不会失败
+        let _ = self.0.send(event);
+    }
+}
+
+/// 心率数据源。
+pub trait HeartRateSource {
+    /// 运行数据源，把读数和连接状态写入 `events`；只在出错或数据源自身结束时返回。
+    fn run<'a>(&'a mut self, events: &'a Events) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>>;
+}
+
+/// 把事件应用到当前连接的 `HeartRateSink` 上。
+struct Pipeline<'a> {
+    socket: &'a UdpSocket,
+    osc_addr: SocketAddr,
+    config: &'a Config,
+    hr_file: &'a Path,
+    sink: Option<HeartRateSink<'a>>,
+    span: Option<Span>,
+}
+
+impl<'a> Pipeline<'a> {
+    fn apply(&mut self, event: SourceEvent) {
+        // 克隆一份再进入，事件本身可以替换 self.span
+        let span = self.span.clone().unwrap_or_else(Span::none);
+        let _entered = span.enter();
+        match event {
+            SourceEvent::Connected(device) => {
+                let mut sink =
+                    HeartRateSink::new(self.socket, self.osc_addr, self.config, self.hr_file);
+                if device.rssi {
+                    sink.signal = Some(SignalMonitor::default());
+                }
+                if status_enabled() {
+                    sink.status.device_name = device.name;
+                    sink.status.device_address = device.address;
+                    sink.status.device_manufacturer = device.manufacturer;
+                    sink.status.device_model = device.model;
+                    sink.status.device_firmware = device.firmware;
+                }
+                sink.status.battery = device.battery;
+                self.sink = Some(sink);
+                self.span = device.span;
+            }
+            SourceEvent::Sample(sample) => {
+                if let Some(sink) = &mut self.sink {
+                    sink.handle(&sample.measurement, sample.timestamp);
+                }
+            }
+            SourceEvent::Rssi(rssi) => {
+                let signal = self.sink.as_mut().and_then(|sink| sink.signal.as_mut());
+                if let Some(signal) = signal {
+                    if signal.update(rssi, self.config) {
+                        warn!(
+                            "信号持续偏弱（{}，已连续 {} 次低于 {} dBm），连接可能即将断开，请让设备靠近蓝牙适配器。",
+                            signal, self.config.rssi_warn_samples, self.config.rssi_warn_floor
+                        );
+                    }
+                }
+            }
+            SourceEvent::Spo2(spo2) => {
+                if let Some(sink) = &mut self.sink {
+                    sink.spo2 = Some(spo2);
+                }
+            }
+            SourceEvent::Steps(steps, at) => {
+                if let Some(sink) = &mut self.sink {
+                    sink.steps(steps, at);
+                }
+            }
+            SourceEvent::Movement(movement, at) => {
+                if let Some(sink) = &mut self.sink {
+                    sink.movement = Some((movement, at));
+                }
+            }
+            SourceEvent::Idle => {
+                if let Some(sink) = &mut self.sink {
+                    sink.clear();
+                }
+            }
+            SourceEvent::Disconnected => {
+                self.sink = None;
+                self.span = None;
+                // 串口备用源正在提供数据时不清零
+                if !serial_source::is_active()
+                    && !ghost::start(
+                        self.socket,
+                        self.osc_addr,
+                        self.config,
+                        self.hr_file.to_path_buf(),
+                    )
+                {
+                    clear_state(self.socket, self.osc_addr, self.config, self.hr_file);
+                }
+            }
+            SourceEvent::Reset => {
+                self.sink = None;
+                self.span = None;
+                ghost::cancel();
+                clear_state(self.socket, self.osc_addr, self.config, self.hr_file);
+            }
+        }
+    }
+}
+
+/// 运行数据源，并把它的事件送往各个输出，直到数据源返回。
+pub async fn run(
+    source: &mut dyn HeartRateSource,
+    socket: &UdpSocket,
+    osc_addr: SocketAddr,
+    config: &Config,
+    hr_file: &Path,
+) -> Result<()> {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let mut pipeline = Pipeline {
+        socket,
+        osc_addr,
+        config,
+        hr_file,
+        sink: None,
+        span: None,
+    };
+    let events = Events(sender);
+    let mut produce = source.run(&events);
+    let result = loop {
+        tokio::select! {
+            biased;
+            Some(event) = receiver.recv() => pipeline.apply(event),
+            result = &mut produce => break result,
+        }
+    };
+    // 数据源返回前发送的事件（例如最后的断开）仍要处理
+    while let Ok(event) = receiver.try_recv() {
+        pipeline.apply(event);
+    }
+    result
+}
+
+/// 测试用数据源：以固定间隔发送 `count` 次相同的心率，然后断开并结束。
+#[cfg(test)]
+pub struct ConstantSource {
+    pub bpm: u16,
+    pub count: usize,
+    pub interval: std::time::Duration,
+}
+
+#[cfg(test)]
+impl HeartRateSource for ConstantSource {
+    fn run<'a>(&'a mut self, events: &'a Events) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        Box::pin(async move {
+            events.send(SourceEvent::Connected(SourceDevice {
+                name: Some("ConstantSource".to_string()),
+                ..SourceDevice::default()
+            }));
+            for _ in 0..self.count {
+                events.send(SourceEvent::Sample(HeartRateSample {
+                    measurement: HeartRateMeasurement {
+                        heart_rate: self.bpm,
+                        rr_intervals: Vec::new(),
+                        energy_expended: None,
+                    },
+                    timestamp: Instant::now(),
+                }));
+                tokio::time::sleep(self.interval).await;
+            }
+            events.send(SourceEvent::Disconnected);
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::test_osc::{assert_param_bool, assert_param_int, TestOscReceiver};
+    use crate::{file_writer, SendMode};
+
+    #[tokio::test]
+    async fn constant_source_drives_osc_and_heart_rate_file() {
+        let dir = std::env::temp_dir().join(format!("hr-vrc-source-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("create temp dir");
+        let hr_file = dir.join("HeartRate.txt");
+        let receiver = TestOscReceiver::bind();
+        let sender = UdpSocket::bind("127.0.0.1:0").expect("bind OSC sender");
+        let config = Config {
+            write_heart_rate_file: true,
+            heart_rate_file_offline: "offline".to_string(),
+            stats_interval_secs: 0,
+            ghost_mode_secs: 0,
+            send_mode: SendMode::OnNotification,
+            ..Config::default()
+        };
+        let mut source = ConstantSource {
+            bpm: 88,
+            count: 1,
+            interval: Duration::from_millis(10),
+        };
+
+        run(&mut source, &sender, receiver.addr(), &config, &hr_file)
+            .await
+            .expect("constant source finishes");
+
+        let reading = receiver.recv_bundle();
+        assert_param_int(&reading, "HR", 88);
+        assert_param_bool(&reading, "hr_connected", true);
+        // 数据源结束时的断开立即清零（断线保持已关闭）
+        let cleared = receiver.recv_bundle();
+        assert_param_int(&cleared, "HR", 0);
+        assert_param_bool(&cleared, "hr_connected", false);
+        file_writer::flush(Duration::from_secs(5));
+        assert_eq!(fs::read_to_string(&hr_file).unwrap(), "offline");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
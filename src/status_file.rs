@@ -0,0 +1,203 @@
+//! JSON 状态文件（`write_status_json = true`）：心率输出每次变化时原子替换 `status.json`，
+//! 供需要结构化数据的 overlay 等工具读取。`rssi` 和 `session` 几乎每次读数都会变，
+//! 只有它们变化时两次写入至少间隔 `VOLATILE_WRITE_INTERVAL`，避免按通知频率重写文件。
+//!
+//! 格式（字段名与含义保持稳定，以后只追加字段；暂时没有的数据一律为 `null`，不会省略字段）：
+//!
+//! ```json
+//! {
+//!   "bpm": 87,                      // 当前心率，未佩戴或未连接时为 0
+//!   "percent": 0.435,               // 与 OSC hr_percent 相同，0–1
+//!   "connected": true,              // 是否已连接设备（广播模式为已锁定广播者）
+//!   "device_name": "Polar H10",     // 设备广播名
+//!   "device_address": "A0:9E:..",   // 蓝牙地址
+//!   "battery": 80,                  // 电量百分比，设备没有电池服务时为 null
+//!   "rssi": -67,                    // 信号强度（dBm），rssi_poll_secs = 0 或平台不提供时为 null
+//!   "session": { "min": 62, "max": 141, "avg": 88.4, "trimp": 41.7 },  // 本次连接的统计（trimp 为训练负荷原始值），尚无读数时各项为 null
+//!   "device_manufacturer": "Polar Electro Oy",  // 设备信息服务（0x180A）中的厂商、型号与固件版本，
+//!   "device_model": "Polar H10",                // 设备未提供的项为 null
+//!   "device_firmware": "5.0.0",
+//!   "timestamp_ms": 1760000000000   // 写入时间，Unix 毫秒
+//! }
+//! ```
+
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{fs, io};
+
+use serde::Serialize;
+use tracing::warn;
+
+use crate::{file_writer, replace_file};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Status {
+    pub bpm: u8,
+    pub percent: f32,
+    pub connected: bool,
+    pub device_name: Option<String>,
+    pub device_address: Option<String>,
+    pub battery: Option<u8>,
+    pub rssi: Option<i16>,
+    pub session: Session,
+    pub device_manufacturer: Option<String>,
+    pub device_model: Option<String>,
+    pub device_firmware: Option<String>,
+    /// 由 `write` 填写，调用方无需设置
+    pub timestamp_ms: u64,
+}
+
+/// 本次连接的心率统计（不含 0）。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Session {
+    pub min: Option<u8>,
+    pub max: Option<u8>,
+    pub avg: Option<f32>,
+    /// 累计训练负荷（Banister TRIMP），未按 max_session_trimp 归一化
+    pub trimp: Option<f32>,
+}
+
+/// 只有 `rssi` 或 `session` 变化时，两次写入的最小间隔。
+const VOLATILE_WRITE_INTERVAL: Duration = Duration::from_secs(5);
+
+static PATH: OnceLock<PathBuf> = OnceLock::new();
+/// 最近一次提交写入的状态及时间
+static LAST_WRITTEN: Mutex<Option<(Status, Instant)>> = Mutex::new(None);
+
+/// 启动时调用：创建所在目录并写入未连接状态，成功后 `write` 才会生效。
+pub fn init(path: PathBuf) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    write_to(&path, &Status::default())?;
+    let _ = PATH.set(path);
+    Ok(())
+}
+
+/// 是否已启用（`init` 成功）。
+pub fn is_enabled() -> bool {
+    PATH.get().is_some()
+}
+
+/// 盖上当前时间戳后交给写入线程（见 file_writer）；未启用时不做任何事。
+/// 与上次写入相比只有 `rssi` 或 `session` 变化、且间隔不足 `VOLATILE_WRITE_INTERVAL` 时跳过。
+pub fn write(status: &Status) {
+    let Some(path) = PATH.get() else {
+        return;
+    };
+    let now = Instant::now();
+    {
+        let mut last = LAST_WRITTEN.lock().unwrap_or_else(|e| e.into_inner());
+        if !should_write(last.as_ref(), status, now) {
+            return;
+        }
+        *last = Some((status.clone(), now));
+    }
+    match to_json(status, SystemTime::now()) {
+        Ok(json) => file_writer::submit(path.clone(), json),
+        Err(e) => warn!("生成状态文件内容时出错: {}", e),
+    }
+}
+
+fn should_write(last: Option<&(Status, Instant)>, status: &Status, now: Instant) -> bool {
+    let Some((written, at)) = last else {
+        return true;
+    };
+    let volatile_only = Status {
+        rssi: written.rssi,
+        session: written.session,
+        ..status.clone()
+    } == *written;
+    !volatile_only || now.duration_since(*at) >= VOLATILE_WRITE_INTERVAL
+}
+
+fn write_to(path: &Path, status: &Status) -> io::Result<()> {
+    let json = to_json(status, SystemTime::now()).map_err(io::Error::other)?;
+    replace_file(path, &json)
+}
+
+fn to_json(status: &Status, now: SystemTime) -> serde_json::Result<String> {
+    serde_json::to_string_pretty(&stamped(status, now))
+}
+
+/// 单行 JSON（WebSocket 消息用），字段与 status.json 相同。
+pub fn to_json_line(status: &Status) -> serde_json::Result<String> {
+    serde_json::to_string(&stamped(status, SystemTime::now()))
+}
+
+fn stamped(status: &Status, now: SystemTime) -> Status {
+    Status {
+        timestamp_ms: now
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64),
+        ..status.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn missing_data_serializes_as_null() {
+        let status = Status {
+            bpm: 87,
+            connected: true,
+            device_address: Some("A0:9E:1A:00:00:01".to_string()),
+            session: Session {
+                min: Some(80),
+                max: Some(90),
+                avg: Some(85.0),
+                trimp: None,
+            },
+            ..Status::default()
+        };
+        let json = to_json(&status, UNIX_EPOCH + Duration::from_millis(1_500)).unwrap();
+        assert!(json.contains("\"bpm\": 87"));
+        assert!(json.contains("\"device_name\": null"));
+        assert!(json.contains("\"battery\": null"));
+        assert!(json.contains("\"rssi\": null"));
+        assert!(json.contains("\"min\": 80"));
+        assert!(json.contains("\"trimp\": null"));
+        assert!(json.contains("\"device_firmware\": null"));
+        assert!(json.contains("\"timestamp_ms\": 1500"));
+    }
+
+    #[test]
+    fn rssi_and_session_changes_alone_are_rate_limited() {
+        let start = Instant::now();
+        let written = Status {
+            bpm: 80,
+            connected: true,
+            rssi: Some(-60),
+            ..Status::default()
+        };
+        let last = (written.clone(), start);
+        let soon = start + Duration::from_secs(1);
+
+        assert!(should_write(None, &written, start));
+        let noisy = Status {
+            rssi: Some(-61),
+            session: Session {
+                avg: Some(80.5),
+                ..Session::default()
+            },
+            ..written.clone()
+        };
+        assert!(!should_write(Some(&last), &noisy, soon));
+        assert!(should_write(
+            Some(&last),
+            &noisy,
+            start + VOLATILE_WRITE_INTERVAL
+        ));
+        // 心率、连接状态等其他字段变化时立即写入
+        let beat = Status {
+            bpm: 81,
+            ..noisy.clone()
+        };
+        assert!(should_write(Some(&last), &beat, soon));
+        assert!(should_write(Some(&last), &Status::default(), soon));
+    }
+}
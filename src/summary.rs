@@ -0,0 +1,258 @@
+//! 退出时打印的本次运行总结：运行时长、连接/断开时长、最低/平均/最高心率、各心率区间的时长、
+//! 重连次数，以及设备提供能量消耗字段时的卡路里。
+//!
+//! 统计覆盖整个运行（跨越多次断线重连），通过 recorder 接收读数和连接事件；
+//! `session_summary_log = true` 时同时追加到程序目录下的 `sessions.log`。
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use tracing::warn;
+
+use crate::recorder::{self, Event, Reading, Recorder};
+use crate::{auto_gain, template};
+
+/// 相邻两次读数间隔超过该值（断线、空闲等）时，这段时间不计入心率区间。
+const MAX_READING_GAP: Duration = Duration::from_secs(10);
+
+/// 1 千卡 = 4.184 千焦。
+const KJ_PER_KCAL: f32 = 4.184;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionStats {
+    started: Instant,
+    /// max_heart_rate_for_percent（0 为自动）
+    max_hr: f32,
+    connected_since: Option<Instant>,
+    connected: Duration,
+    connections: u32,
+    min: u8,
+    max: u8,
+    sum: u64,
+    samples: u64,
+    zone_time: [Duration; 6],
+    /// 上一次非 0 读数的时间和区间
+    last_zone: Option<(Instant, u8)>,
+    /// 设备上报的累计能量消耗（kJ）在本次运行中的增量
+    energy_kj: u32,
+    /// 本次连接中上一次上报的累计值；设备会在重新佩戴等情况下把它清零
+    last_energy: Option<u16>,
+    energy_seen: bool,
+}
+
+impl SessionStats {
+    pub fn new(now: Instant, max_hr: f32) -> Self {
+        SessionStats {
+            started: now,
+            max_hr,
+            connected_since: None,
+            connected: Duration::ZERO,
+            connections: 0,
+            min: u8::MAX,
+            max: 0,
+            sum: 0,
+            samples: 0,
+            zone_time: [Duration::ZERO; 6],
+            last_zone: None,
+            energy_kj: 0,
+            last_energy: None,
+            energy_seen: false,
+        }
+    }
+
+    fn connected(&mut self, now: Instant) {
+        if self.connected_since.is_none() {
+            self.connected_since = Some(now);
+            self.connections += 1;
+        }
+    }
+
+    fn disconnected(&mut self, now: Instant) {
+        if let Some(since) = self.connected_since.take() {
+            self.connected += now.duration_since(since);
+        }
+        self.last_zone = None;
+        self.last_energy = None;
+    }
+
+    fn reading(&mut self, now: Instant, bpm: u8, energy_kj: Option<u16>) {
+        // 广播模式没有连接事件，收到读数即视为已连接
+        self.connected(now);
+        if let Some(energy) = energy_kj {
+            self.energy_seen = true;
+            // 只累计增量：第一次读到的值包含本次运行之前的消耗
+            match self.last_energy {
+                Some(last) if energy >= last => self.energy_kj += u32::from(energy - last),
+                Some(_) => self.energy_kj += u32::from(energy),
+                None => {}
+            }
+            self.last_energy = Some(energy);
+        }
+        if bpm == 0 {
+            self.last_zone = None;
+            return;
+        }
+        if let Some((at, zone)) = self.last_zone {
+            let gap = now.duration_since(at);
+            if gap <= MAX_READING_GAP {
+                self.zone_time[usize::from(zone)] += gap;
+            }
+        }
+        self.last_zone = Some((now, template::zone(bpm, auto_gain::resolve(self.max_hr))));
+        self.min = self.min.min(bpm);
+        self.max = self.max.max(bpm);
+        self.sum += u64::from(bpm);
+        self.samples += 1;
+    }
+
+    /// 格式化的总结（多行，不含末尾换行）。
+    pub fn summary(&self, now: Instant) -> String {
+        let total = now.duration_since(self.started);
+        let connected = self.connected
+            + self
+                .connected_since
+                .map_or(Duration::ZERO, |since| now.duration_since(since));
+        let mut lines = vec![
+            "===== 本次运行总结 =====".to_string(),
+            format!("运行时长: {}", format_duration(total)),
+            format!(
+                "已连接: {}  未连接: {}  重连次数: {}",
+                format_duration(connected),
+                format_duration(total.saturating_sub(connected)),
+                self.connections.saturating_sub(1)
+            ),
+        ];
+        if self.samples == 0 {
+            lines.push("心率: 无读数".to_string());
+        } else {
+            lines.push(format!(
+                "心率: 最低 {}  平均 {:.0}  最高 {} BPM",
+                self.min,
+                self.sum as f64 / self.samples as f64,
+                self.max
+            ));
+            let zones: Vec<String> = self
+                .zone_time
+                .iter()
+                .enumerate()
+                .map(|(zone, time)| format!("{} {}", zone, format_duration(*time)))
+                .collect();
+            lines.push(format!("心率区间: {}", zones.join("  ")));
+        }
+        if self.energy_seen {
+            lines.push(format!(
+                "能量消耗: 约 {:.0} kcal（设备上报）",
+                self.energy_kj as f32 / KJ_PER_KCAL
+            ));
+        }
+        lines.join("\n")
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+static STATS: Mutex<Option<SessionStats>> = Mutex::new(None);
+static LOG_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// 把读数和事件转交给全局统计。
+struct Tracker;
+
+impl Recorder for Tracker {
+    fn reading(&mut self, _at: DateTime<Local>, reading: &Reading) {
+        if let Some(stats) = STATS.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            stats.reading(Instant::now(), reading.bpm, reading.energy_kj);
+        }
+    }
+
+    fn event(&mut self, _at: DateTime<Local>, event: &Event) {
+        let mut stats = STATS.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(stats) = stats.as_mut() else {
+            return;
+        };
+        match event {
+            Event::Connected(_) => stats.connected(Instant::now()),
+            Event::Disconnected | Event::Rescan | Event::Stop => stats.disconnected(Instant::now()),
+            Event::Start | Event::Idle | Event::Active => {}
+        }
+    }
+
+    fn finish(&mut self) {}
+}
+
+/// 开始统计；`log_path` 不为 None 时退出时把总结追加到该文件。
+pub fn start(max_hr: f32, log_path: Option<PathBuf>) {
+    *STATS.lock().unwrap_or_else(|e| e.into_inner()) =
+        Some(SessionStats::new(Instant::now(), max_hr));
+    *LOG_PATH.lock().unwrap_or_else(|e| e.into_inner()) = log_path;
+    recorder::register(Box::new(Tracker));
+}
+
+/// 退出时调用（在 `recorder::finish` 之后）：返回总结并按需追加到日志文件；未开始统计时返回 None。
+pub fn finish() -> Option<String> {
+    let stats = STATS.lock().unwrap_or_else(|e| e.into_inner()).take()?;
+    let summary = stats.summary(Instant::now());
+    if let Some(path) = LOG_PATH
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_deref()
+    {
+        if let Err(e) = append_log(path, &summary) {
+            warn!("写入 {} 失败: {}", path.display(), e);
+        }
+    }
+    Some(summary)
+}
+
+fn append_log(path: &Path, summary: &str) -> io::Result<()> {
+    let mut file = File::options().create(true).append(true).open(path)?;
+    writeln!(
+        file,
+        "[{}]\n{}\n",
+        Local::now().format("%Y-%m-%d %H:%M:%S"),
+        summary
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_span_reconnects_and_only_count_energy_deltas() {
+        let t0 = Instant::now();
+        let at = |secs| t0 + Duration::from_secs(secs);
+        let mut stats = SessionStats::new(t0, 200.0);
+
+        stats.connected(at(10));
+        stats.reading(at(10), 90, Some(500));
+        stats.reading(at(11), 110, Some(504));
+        // 设备清零了累计值
+        stats.reading(at(12), 0, Some(3));
+        stats.disconnected(at(20));
+
+        stats.connected(at(30));
+        // 重连后第一次读到的累计值不计入
+        stats.reading(at(30), 130, Some(100));
+        stats.reading(at(32), 130, Some(110));
+
+        assert_eq!(stats.connections, 2);
+        assert_eq!((stats.min, stats.max, stats.samples), (90, 130, 4));
+        assert_eq!(stats.energy_kj, 4 + 3 + 10);
+        // 90 (45%) → 区间 0 的 1 秒，130 (65%) → 区间 2 的 2 秒
+        assert_eq!(stats.zone_time[0], Duration::from_secs(1));
+        assert_eq!(stats.zone_time[2], Duration::from_secs(2));
+
+        let summary = stats.summary(at(40));
+        assert!(summary.contains("运行时长: 0:00:40"));
+        assert!(summary.contains("已连接: 0:00:20  未连接: 0:00:20  重连次数: 1"));
+        assert!(summary.contains("最低 90  平均 115  最高 130"));
+        assert!(summary.contains("约 4 kcal"));
+    }
+}
@@ -0,0 +1,147 @@
+//! 文本模板（`heart_rate_file_format` 等）：把 `{hr}`、`{percent}`、`{zone}`、`{avg}` 替换为当前数值。
+//!
+//! 占位符可带宽度：`{hr:03}` 不足 3 位时补零，`{hr:3}` 补空格；`{{` 和 `}}` 输出花括号本身。
+//! 未知占位符原样保留，便于发现拼写错误。
+//!
+//! 通知类消息（Discord、Twitch）的占位符各不相同，用更简单的 [`fill`] 按名称替换。
+
+/// 模板可用的数值。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Values {
+    /// 当前心率
+    pub hr: u8,
+    /// 心率占 `max_heart_rate_for_percent` 的百分比（0–100，四舍五入）
+    pub percent: u8,
+    /// 心率区间 0–5，见 [`zone`]
+    pub zone: u8,
+    /// 最近若干次读数的平均心率（四舍五入）
+    pub avg: u8,
+}
+
+/// 按最大心率百分比划分的五区间：低于 50% 为 0，50–60% 为 1，……，90% 及以上为 5。
+pub fn zone(heart_rate: u8, max_hr: f32) -> u8 {
+    let percent = f32::from(heart_rate) / max_hr * 100.0;
+    if percent < 50.0 {
+        0
+    } else {
+        (((percent - 50.0) / 10.0) as u8 + 1).min(5)
+    }
+}
+
+pub fn render(template: &str, values: &Values) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(pos) = rest.find(['{', '}']) {
+        out.push_str(&rest[..pos]);
+        let tail = &rest[pos..];
+        if tail.starts_with("{{") || tail.starts_with("}}") {
+            out.push_str(&tail[..1]);
+            rest = &tail[2..];
+            continue;
+        }
+        let placeholder = tail
+            .strip_prefix('{')
+            .and_then(|inner| inner.find('}').map(|end| &inner[..end]));
+        match placeholder.and_then(|p| render_placeholder(p, values)) {
+            Some(text) => {
+                out.push_str(&text);
+                // 占位符长度 + 两个花括号
+                rest = &tail[placeholder.map_or(0, str::len) + 2..];
+            }
+            None => {
+                out.push_str(&tail[..1]);
+                rest = &tail[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// 把模板中的 `{名称}` 替换为对应的值，未知占位符原样保留（不支持宽度和转义）。
+pub fn fill(template: &str, values: &[(&str, &str)]) -> String {
+    values
+        .iter()
+        .fold(template.to_string(), |text, (name, value)| {
+            text.replace(&format!("{{{}}}", name), value)
+        })
+}
+
+/// 渲染单个占位符（不含花括号）；名称或宽度无效时返回 None。
+fn render_placeholder(placeholder: &str, values: &Values) -> Option<String> {
+    let (name, spec) = match placeholder.split_once(':') {
+        Some((name, spec)) => (name, Some(spec)),
+        None => (placeholder, None),
+    };
+    let value = match name {
+        "hr" => values.hr,
+        "percent" => values.percent,
+        "zone" => values.zone,
+        "avg" => values.avg,
+        _ => return None,
+    };
+    match spec {
+        None => Some(value.to_string()),
+        Some(spec) => {
+            let width: usize = spec.parse().ok()?;
+            Some(if spec.starts_with('0') {
+                format!("{:0width$}", value, width = width)
+            } else {
+                format!("{:width$}", value, width = width)
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALUES: Values = Values {
+        hr: 87,
+        percent: 44,
+        zone: 0,
+        avg: 85,
+    };
+
+    #[test]
+    fn renders_placeholders_and_padding() {
+        assert_eq!(render("{hr}", &VALUES), "87");
+        assert_eq!(render("{hr} BPM", &VALUES), "87 BPM");
+        assert_eq!(render("❤ {hr}", &VALUES), "❤ 87");
+        assert_eq!(render("{hr:03}", &VALUES), "087");
+        assert_eq!(render("[{hr:4}]", &VALUES), "[  87]");
+        assert_eq!(
+            render("{hr} ({percent}%) Z{zone} avg {avg}", &VALUES),
+            "87 (44%) Z0 avg 85"
+        );
+    }
+
+    #[test]
+    fn keeps_escapes_and_unknown_placeholders() {
+        assert_eq!(render("{{hr}} = {hr}", &VALUES), "{hr} = 87");
+        assert_eq!(render("{bpm} {hr:x} {hr", &VALUES), "{bpm} {hr:x} {hr");
+        assert_eq!(render("}", &VALUES), "}");
+    }
+
+    #[test]
+    fn fill_replaces_named_values_only() {
+        assert_eq!(
+            fill(
+                "{device} 电量 {battery}% {unknown}",
+                &[("device", "A0:9E"), ("battery", "15")]
+            ),
+            "A0:9E 电量 15% {unknown}"
+        );
+    }
+
+    #[test]
+    fn zones_follow_percent_of_max() {
+        assert_eq!(zone(99, 200.0), 0);
+        assert_eq!(zone(100, 200.0), 1);
+        assert_eq!(zone(139, 200.0), 2);
+        assert_eq!(zone(170, 200.0), 4);
+        assert_eq!(zone(180, 200.0), 5);
+        assert_eq!(zone(250, 200.0), 5);
+    }
+}
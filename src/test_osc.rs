@@ -0,0 +1,129 @@
+//! 测试用的 OSC 接收端：绑定本机随机端口，接收并解码程序发出的 Bundle，按参数名断言取值。
+//!
+//! 参数名不含 `/avatar/parameters/` 前缀，如 `HR`、`hr_connected`、`VRCOSC/Heartrate/Normalised`。
+
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Duration;
+
+use rosc::{OscBundle, OscPacket, OscType};
+
+const PARAMETER_PREFIX: &str = "/avatar/parameters/";
+const RECV_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+pub struct TestOscReceiver {
+    socket: UdpSocket,
+}
+
+impl TestOscReceiver {
+    /// 绑定 127.0.0.1 上的随机端口。
+    pub fn bind() -> Self {
+        let socket = UdpSocket::bind("127.0.0.1:0").expect("bind OSC receiver");
+        socket
+            .set_read_timeout(Some(RECV_TIMEOUT))
+            .expect("set receive timeout");
+        TestOscReceiver { socket }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.socket.local_addr().expect("read receiver address")
+    }
+
+    /// 接收并解码一个 Bundle（最多等待 1 秒）。
+    pub fn recv_bundle(&self) -> OscBundle {
+        let mut buf = [0_u8; 2048];
+        let (len, _) = self.socket.recv_from(&mut buf).expect("receive OSC packet");
+        decode_bundle(&buf[..len])
+    }
+
+    /// 不等待：已有数据包时接收并解码一个 Bundle，否则返回 None。
+    pub fn try_recv_bundle(&self) -> Option<OscBundle> {
+        let mut buf = [0_u8; 2048];
+        self.socket.set_nonblocking(true).expect("set non-blocking");
+        let received = self.socket.recv_from(&mut buf);
+        self.socket.set_nonblocking(false).expect("set blocking");
+        match received {
+            Ok((len, _)) => Some(decode_bundle(&buf[..len])),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => None,
+            Err(e) => panic!("receive OSC packet: {e}"),
+        }
+    }
+
+    /// 接收 `count` 个单独发送的消息（`osc_packet_mode = "messages"`），合并为一个 Bundle 以便按参数名断言。
+    pub fn recv_messages(&self, count: usize) -> OscBundle {
+        let mut buf = [0_u8; 2048];
+        let content = (0..count)
+            .map(|_| {
+                let (len, _) = self.socket.recv_from(&mut buf).expect("receive OSC packet");
+                let (remaining, packet) =
+                    rosc::decoder::decode_udp(&buf[..len]).expect("decode OSC");
+                assert!(remaining.is_empty(), "trailing bytes after OSC packet");
+                match packet {
+                    OscPacket::Message(message) => OscPacket::Message(message),
+                    OscPacket::Bundle(_) => panic!("expected a plain OSC message, got a bundle"),
+                }
+            })
+            .collect();
+        OscBundle {
+            timetag: rosc::OscTime {
+                seconds: 0,
+                fractional: 1,
+            },
+            content,
+        }
+    }
+}
+
+/// 解码 `encode_hr_bundle` 等生成的数据，必须恰好是一个 Bundle。
+pub fn decode_bundle(data: &[u8]) -> OscBundle {
+    let (remaining, packet) = rosc::decoder::decode_udp(data).expect("decode OSC");
+    assert!(remaining.is_empty(), "trailing bytes after OSC packet");
+    match packet {
+        OscPacket::Bundle(bundle) => bundle,
+        OscPacket::Message(message) => panic!("expected OSC bundle, got message {}", message.addr),
+    }
+}
+
+/// 参数的全部实参；Bundle 中没有该参数时为 None。
+pub fn param<'a>(bundle: &'a OscBundle, name: &str) -> Option<&'a [OscType]> {
+    bundle.content.iter().find_map(|packet| match packet {
+        OscPacket::Message(message)
+            if message.addr.strip_prefix(PARAMETER_PREFIX) == Some(name) =>
+        {
+            Some(message.args.as_slice())
+        }
+        _ => None,
+    })
+}
+
+fn single<'a>(bundle: &'a OscBundle, name: &str) -> &'a OscType {
+    match param(bundle, name) {
+        Some([value]) => value,
+        Some(args) => panic!("OSC parameter {name} should carry one value, got {args:?}"),
+        None => panic!("missing OSC parameter {name}"),
+    }
+}
+
+pub fn assert_param_bool(bundle: &OscBundle, name: &str, expected: bool) {
+    match single(bundle, name) {
+        OscType::Bool(value) => assert_eq!(*value, expected, "OSC parameter {name}"),
+        other => panic!("OSC parameter {name} should be a bool, got {other:?}"),
+    }
+}
+
+pub fn assert_param_int(bundle: &OscBundle, name: &str, expected: i32) {
+    match single(bundle, name) {
+        OscType::Int(value) => assert_eq!(*value, expected, "OSC parameter {name}"),
+        other => panic!("OSC parameter {name} should be an int, got {other:?}"),
+    }
+}
+
+pub fn assert_param_float(bundle: &OscBundle, name: &str, expected: f32, epsilon: f32) {
+    match single(bundle, name) {
+        OscType::Float(value) => assert!(
+            (value - expected).abs() <= epsilon,
+            "OSC parameter {name} = {value}, expected {expected} ± {epsilon}"
+        ),
+        other => panic!("OSC parameter {name} should be a float, got {other:?}"),
+    }
+}
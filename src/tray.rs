@@ -0,0 +1,163 @@
+//! 托盘图标（`tray_enabled = true`）：在系统托盘显示图标，提示文字为当前心率，控制台窗口隐藏，
+//! 程序在后台运行。菜单项：
+//!
+//! - 打开控制台：重新分配一个控制台窗口显示之后的日志（关闭该窗口会退出程序）；
+//! - 复制心率：把当前心率复制到剪贴板；
+//! - 断开连接：放弃当前连接、清除设备缓存并重新扫描（与 `--reset-cache` 通知运行中的实例相同）；
+//! - 设置…：用记事本打开 config.toml，修改后重启生效；
+//! - 退出：清零心率后退出。
+//!
+//! 托盘在独立线程中运行，心率经 `std::sync::mpsc` 通道从发送 OSC 的异步任务传给它。仅 Windows；
+//! 其他平台上该配置会被忽略并在启动时警告。
+
+/// 当前平台是否支持托盘图标。
+pub const SUPPORTED: bool = cfg!(windows);
+
+/// 托盘图标的提示文字。
+#[cfg_attr(not(windows), allow(dead_code))]
+pub fn tooltip(bpm: u8) -> String {
+    if bpm > 0 {
+        format!("HeartRate-For-VRChat\n心率: {} BPM", bpm)
+    } else {
+        "HeartRate-For-VRChat\n未连接".to_string()
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::io::Write;
+    use std::os::windows::process::CommandExt;
+    use std::path::PathBuf;
+    use std::process::{Command, Stdio};
+    use std::sync::atomic::{AtomicU8, Ordering};
+    use std::sync::{mpsc, Arc, OnceLock};
+    use std::thread;
+
+    use tracing::warn;
+    use tray_item::{IconSource, TIError, TrayItem};
+    use windows_sys::Win32::System::Console::{AllocConsole, FreeConsole, GetConsoleWindow};
+    use windows_sys::Win32::UI::WindowsAndMessaging::{LoadIconW, IDI_APPLICATION};
+
+    use super::tooltip;
+
+    /// 不为 clip.exe / notepad.exe 创建控制台窗口
+    const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+
+    static SENDER: OnceLock<mpsc::Sender<u8>> = OnceLock::new();
+
+    /// 创建托盘图标并隐藏控制台窗口；`config_path` 供"设置…"打开。
+    pub fn start(config_path: PathBuf) -> Result<(), String> {
+        let (sender, updates) = mpsc::channel();
+        let (ready, result) = mpsc::channel();
+        thread::spawn(move || match create(config_path) {
+            Ok((mut tray, latest)) => {
+                let _ = ready.send(Ok(()));
+                let mut shown = None;
+                for bpm in updates {
+                    latest.store(bpm, Ordering::Relaxed);
+                    if shown != Some(bpm) {
+                        shown = Some(bpm);
+                        if let Err(e) = tray.inner_mut().set_tooltip(&tooltip(bpm)) {
+                            warn!("更新托盘提示失败: {}", e);
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                let _ = ready.send(Err(e.to_string()));
+            }
+        });
+        result
+            .recv()
+            .map_err(|_| "托盘线程意外退出".to_string())??;
+        let _ = SENDER.set(sender);
+        // SAFETY: 只是与当前控制台分离，之后的日志输出会被丢弃，直到"打开控制台"重新分配
+        unsafe { FreeConsole() };
+        Ok(())
+    }
+
+    fn create(config_path: PathBuf) -> Result<(TrayItem, Arc<AtomicU8>), TIError> {
+        // SAFETY: 加载系统自带的应用程序图标，句柄由系统管理，无需释放
+        let icon = unsafe { LoadIconW(std::ptr::null_mut(), IDI_APPLICATION) };
+        let mut tray = TrayItem::new(&tooltip(0), IconSource::RawIcon(icon as _))?;
+        let latest = Arc::new(AtomicU8::new(0));
+
+        tray.add_menu_item("打开控制台", || {
+            // SAFETY: 已有控制台时不重复分配
+            unsafe {
+                if GetConsoleWindow().is_null() {
+                    AllocConsole();
+                }
+            }
+        })?;
+        let copied = Arc::clone(&latest);
+        tray.add_menu_item("复制心率", move || {
+            copy_to_clipboard(&copied.load(Ordering::Relaxed).to_string());
+        })?;
+        tray.add_menu_item("断开连接", crate::request_rescan)?;
+        tray.add_menu_item("设置…", move || {
+            if let Err(e) = Command::new("notepad.exe")
+                .arg(&config_path)
+                .creation_flags(CREATE_NO_WINDOW)
+                .spawn()
+            {
+                warn!("无法打开 {}: {}", config_path.display(), e);
+            }
+        })?;
+        tray.inner_mut().add_separator()?;
+        tray.add_menu_item("退出", || {
+            crate::run_exit_cleanup();
+            std::process::exit(0);
+        })?;
+        Ok((tray, latest))
+    }
+
+    /// 经系统自带的 clip.exe 写入剪贴板。
+    fn copy_to_clipboard(text: &str) {
+        let child = Command::new("clip.exe")
+            .stdin(Stdio::piped())
+            .creation_flags(CREATE_NO_WINDOW)
+            .spawn();
+        let result = child.and_then(|mut child| {
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(text.as_bytes())?;
+            }
+            child.wait().map(|_| ())
+        });
+        if let Err(e) = result {
+            warn!("复制心率到剪贴板失败: {}", e);
+        }
+    }
+
+    /// 把最新心率交给托盘线程；未启用托盘时不做任何事。
+    pub fn publish(bpm: u8) {
+        if let Some(sender) = SENDER.get() {
+            let _ = sender.send(bpm);
+        }
+    }
+}
+
+#[cfg(not(windows))]
+mod imp {
+    use std::path::PathBuf;
+
+    /// 不支持的平台上启动校验已关闭该功能，不会调用到这里。
+    pub fn start(_config_path: PathBuf) -> Result<(), String> {
+        Err("当前平台不支持托盘图标".to_string())
+    }
+
+    pub fn publish(_bpm: u8) {}
+}
+
+pub use imp::{publish, start};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tooltip_shows_the_current_heart_rate() {
+        assert_eq!(tooltip(82), "HeartRate-For-VRChat\n心率: 82 BPM");
+        assert_eq!(tooltip(0), "HeartRate-For-VRChat\n未连接");
+    }
+}
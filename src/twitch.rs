@@ -0,0 +1,438 @@
+//! Twitch 聊天播报（`twitch_enabled = true`）：以 `twitch_username` 登录 Twitch IRC（TLS），
+//! 心率向上越过 `twitch_milestones` 中的某个阈值时在 `twitch_channel` 发送一条消息，
+//! 适合直播恐怖游戏时让观众看到心跳加速的瞬间；`twitch_periodic_secs` 大于 0 时还会定时播报当前心率。
+//!
+//! - 同时越过多个阈值（如从 110 跳到 155）只播报最高的一个；每个阈值播报后 `twitch_milestone_cooldown_secs`
+//!   秒内不再播报，心率在阈值附近波动不会刷屏；
+//! - 刚连接设备时的第一条读数只作为基准，不算越过；断开、未佩戴后重新开始；
+//! - 消息正文由模板生成：`twitch_milestone_message` 可用 `{threshold}`、`{bpm}`，
+//!   `twitch_periodic_message` 可用 `{bpm}`。
+//!
+//! 心率处理路径上只判断阈值并写入通道；连接、登录、发送都在后台任务中进行，断线后按指数退避自动重连，
+//! 断线期间排队超过 `STALE_AFTER` 的消息不再发送。`twitch_dry_run = true` 时不连接，只把将要发送的消息打印到控制台。
+
+use std::collections::HashMap;
+use std::io;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc;
+use tokio::time;
+use tracing::{debug, info, warn};
+
+use crate::http_client;
+use crate::recorder::{Event, Reading, Recorder};
+use crate::template::fill;
+use crate::Config;
+
+const SERVER: &str = "irc.chat.twitch.tv:6697";
+const SERVER_NAME: &str = "irc.chat.twitch.tv";
+/// 连接并完成 TLS 握手的最长时间。
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// 服务器约每 5 分钟发送一次 PING，超过该时间没有收到任何消息视为连接已断。
+const IDLE_TIMEOUT: Duration = Duration::from_secs(6 * 60);
+/// 重连等待时间：从最短开始，每次失败翻倍，直到最长。
+const RECONNECT_MIN: Duration = Duration::from_secs(5);
+const RECONNECT_MAX: Duration = Duration::from_secs(5 * 60);
+/// 两条消息之间的最短间隔（普通账号每 30 秒最多 20 条）。
+const MIN_INTERVAL: Duration = Duration::from_secs(2);
+/// 排队超过该时间的消息不再发送（播报的已不是"当前"心率）。
+const STALE_AFTER: Duration = Duration::from_secs(60);
+/// 等待发送的消息数上限，超过时丢弃新消息。
+const QUEUE_CAPACITY: usize = 8;
+
+/// 心率向上越过阈值的检测，每个阈值单独冷却。
+#[derive(Debug)]
+struct Milestones {
+    /// 升序、去重、不含 0
+    thresholds: Vec<u8>,
+    cooldown: Duration,
+    previous: Option<u8>,
+    announced: HashMap<u8, Instant>,
+}
+
+impl Milestones {
+    fn new(thresholds: &[u8], cooldown: Duration) -> Self {
+        let mut thresholds: Vec<u8> = thresholds.iter().copied().filter(|&t| t > 0).collect();
+        thresholds.sort_unstable();
+        thresholds.dedup();
+        Milestones {
+            thresholds,
+            cooldown,
+            previous: None,
+            announced: HashMap::new(),
+        }
+    }
+
+    /// 记录一次读数，返回本次向上越过、且不在冷却中的最高阈值。
+    fn update(&mut self, bpm: u8, now: Instant) -> Option<u8> {
+        if bpm == 0 {
+            self.previous = None;
+            return None;
+        }
+        let previous = self.previous.replace(bpm)?;
+        let threshold = self.thresholds.iter().rev().copied().find(|&t| {
+            previous < t
+                && bpm >= t
+                && self
+                    .announced
+                    .get(&t)
+                    .is_none_or(|at| now.duration_since(*at) >= self.cooldown)
+        })?;
+        self.announced.insert(threshold, now);
+        Some(threshold)
+    }
+
+    /// 断开或重新开始时调用：下一条读数重新作为基准。
+    fn reset(&mut self) {
+        self.previous = None;
+    }
+}
+
+/// 一条待发送的聊天消息。
+#[derive(Debug)]
+struct Message {
+    text: String,
+    queued: Instant,
+}
+
+/// 登录信息。
+#[derive(Debug, Clone)]
+struct Login {
+    channel: String,
+    username: String,
+    token: String,
+}
+
+impl Login {
+    fn new(config: &Config) -> Self {
+        let token = config.twitch_oauth_token.as_deref().unwrap_or_default();
+        Login {
+            channel: config
+                .twitch_channel
+                .trim_start_matches('#')
+                .to_ascii_lowercase(),
+            username: config.twitch_username.to_ascii_lowercase(),
+            token: if token.starts_with("oauth:") {
+                token.to_string()
+            } else {
+                format!("oauth:{}", token)
+            },
+        }
+    }
+}
+
+/// 注册到 recorder 的输出：判断阈值并把消息交给后台任务。
+#[derive(Debug)]
+pub struct TwitchOutput {
+    milestones: Milestones,
+    milestone_message: String,
+    periodic: Option<Duration>,
+    periodic_message: String,
+    next_periodic: Option<Instant>,
+    sender: mpsc::Sender<Message>,
+}
+
+impl TwitchOutput {
+    /// 启动后台任务（需在 tokio 运行时内）。
+    pub fn start(config: &Config) -> Self {
+        let (sender, messages) = mpsc::channel(QUEUE_CAPACITY);
+        let login = Login::new(config);
+        if config.twitch_dry_run {
+            tokio::spawn(print_messages(messages, login.channel));
+        } else {
+            tokio::spawn(run(login, messages));
+        }
+        TwitchOutput {
+            milestones: Milestones::new(
+                &config.twitch_milestones,
+                Duration::from_secs(config.twitch_milestone_cooldown_secs),
+            ),
+            milestone_message: config.twitch_milestone_message.clone(),
+            periodic: (config.twitch_periodic_secs > 0)
+                .then(|| Duration::from_secs(config.twitch_periodic_secs)),
+            periodic_message: config.twitch_periodic_message.clone(),
+            next_periodic: None,
+            sender,
+        }
+    }
+
+    fn queue(&self, text: String) {
+        let message = Message {
+            text,
+            queued: Instant::now(),
+        };
+        if self.sender.try_send(message).is_err() {
+            debug!("Twitch 发送队列已满，丢弃消息");
+        }
+    }
+}
+
+impl Recorder for TwitchOutput {
+    fn reading(&mut self, _at: DateTime<Local>, reading: &Reading) {
+        let now = Instant::now();
+        let bpm = reading.bpm.to_string();
+        if let Some(threshold) = self.milestones.update(reading.bpm, now) {
+            self.queue(fill(
+                &self.milestone_message,
+                &[("threshold", &threshold.to_string()), ("bpm", &bpm)],
+            ));
+        }
+        let Some(interval) = self.periodic else {
+            return;
+        };
+        if reading.bpm == 0 {
+            return;
+        }
+        match self.next_periodic {
+            Some(next) if now >= next => {
+                self.queue(fill(&self.periodic_message, &[("bpm", &bpm)]));
+                self.next_periodic = Some(now + interval);
+            }
+            Some(_) => {}
+            None => self.next_periodic = Some(now + interval),
+        }
+    }
+
+    fn event(&mut self, _at: DateTime<Local>, event: &Event) {
+        match event {
+            Event::Connected(_) | Event::Disconnected | Event::Rescan | Event::Stop => {
+                self.milestones.reset();
+                self.next_periodic = None;
+            }
+            Event::Start | Event::Idle | Event::Active => {}
+        }
+    }
+
+    fn finish(&mut self) {}
+}
+
+/// dry run：只打印将要发送的消息。
+async fn print_messages(mut messages: mpsc::Receiver<Message>, channel: String) {
+    while let Some(message) = messages.recv().await {
+        println!("[Twitch 试运行] #{}: {}", channel, message.text);
+    }
+}
+
+/// 保持连接并发送消息，断线后自动重连，直到发送端被丢弃。
+async fn run(login: Login, mut messages: mpsc::Receiver<Message>) {
+    let mut delay = RECONNECT_MIN;
+    loop {
+        match session(&login, &mut messages, &mut delay).await {
+            Ok(()) => return,
+            Err(e) => warn!("Twitch 聊天连接中断: {}，{} 秒后重连。", e, delay.as_secs()),
+        }
+        time::sleep(delay).await;
+        delay = (delay * 2).min(RECONNECT_MAX);
+    }
+}
+
+/// 服务器消息中需要处理的几种。
+#[derive(Debug, PartialEq)]
+enum Incoming<'a> {
+    Ping(&'a str),
+    /// 001：登录成功
+    Welcome,
+    LoginFailed,
+    /// 服务器即将重启，要求客户端重连
+    Reconnect,
+}
+
+fn parse_incoming(line: &str) -> Option<Incoming<'_>> {
+    let line = line.trim_end();
+    if let Some(payload) = line.strip_prefix("PING ") {
+        return Some(Incoming::Ping(payload));
+    }
+    let mut parts = line.split(' ');
+    parts.next().filter(|prefix| prefix.starts_with(':'))?;
+    match parts.next()? {
+        "001" => Some(Incoming::Welcome),
+        "RECONNECT" => Some(Incoming::Reconnect),
+        "NOTICE"
+            if line.contains("Login authentication failed")
+                || line.contains("Improperly formatted auth") =>
+        {
+            Some(Incoming::LoginFailed)
+        }
+        _ => None,
+    }
+}
+
+/// 一行 PRIVMSG；消息中的换行换成空格，避免被当作另一条命令。
+fn privmsg(channel: &str, text: &str) -> String {
+    format!(
+        "PRIVMSG #{} :{}\r\n",
+        channel,
+        text.replace(['\r', '\n'], " ")
+    )
+}
+
+/// 一次连接：登录、加入频道后转发消息。发送端被丢弃时返回 Ok，连接出错时返回 Err。
+async fn session(
+    login: &Login,
+    messages: &mut mpsc::Receiver<Message>,
+    delay: &mut Duration,
+) -> io::Result<()> {
+    let stream = time::timeout(
+        CONNECT_TIMEOUT,
+        http_client::connect_tls(SERVER, SERVER_NAME),
+    )
+    .await
+    .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+    let mut stream = BufReader::new(stream);
+    stream
+        .write_all(format!("PASS {}\r\nNICK {}\r\n", login.token, login.username).as_bytes())
+        .await?;
+
+    // 登录成功前消息留在通道中
+    let mut line = String::new();
+    loop {
+        check_read(time::timeout(IDLE_TIMEOUT, stream.read_line(&mut line)).await)?;
+        let welcome = respond(&mut stream, &line, login).await?;
+        line.clear();
+        if welcome {
+            break;
+        }
+    }
+    *delay = RECONNECT_MIN;
+
+    let mut last_sent: Option<Instant> = None;
+    loop {
+        tokio::select! {
+            read = time::timeout(IDLE_TIMEOUT, stream.read_line(&mut line)) => {
+                check_read(read)?;
+                respond(&mut stream, &line, login).await?;
+                // read_line 被取消时已读到的部分留在 line 中，读完整行后才清空
+                line.clear();
+            }
+            message = messages.recv() => {
+                let Some(message) = message else {
+                    let _ = stream.write_all(b"QUIT\r\n").await;
+                    return Ok(());
+                };
+                if message.queued.elapsed() > STALE_AFTER {
+                    debug!("丢弃排队过久的 Twitch 消息: {}", message.text);
+                    continue;
+                }
+                if let Some(last) = last_sent {
+                    time::sleep(MIN_INTERVAL.saturating_sub(last.elapsed())).await;
+                }
+                stream.write_all(privmsg(&login.channel, &message.text).as_bytes()).await?;
+                last_sent = Some(Instant::now());
+                debug!("已发送 Twitch 消息: {}", message.text);
+            }
+        }
+    }
+}
+
+fn check_read(read: Result<io::Result<usize>, time::error::Elapsed>) -> io::Result<()> {
+    match read.map_err(|_| io::Error::other("长时间没有收到服务器消息"))?? {
+        0 => Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "服务器关闭了连接",
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// 处理一行服务器消息，返回是否为登录成功。
+async fn respond<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    line: &str,
+    login: &Login,
+) -> io::Result<bool> {
+    match parse_incoming(line) {
+        Some(Incoming::Ping(payload)) => {
+            stream
+                .write_all(format!("PONG {}\r\n", payload).as_bytes())
+                .await?;
+        }
+        Some(Incoming::Welcome) => {
+            stream
+                .write_all(format!("JOIN #{}\r\n", login.channel).as_bytes())
+                .await?;
+            info!("已登录 Twitch 聊天，心率播报将发送到 #{}", login.channel);
+            return Ok(true);
+        }
+        Some(Incoming::LoginFailed) => {
+            return Err(io::Error::other(
+                "登录失败，请检查 twitch_username 和 twitch_oauth_token",
+            ));
+        }
+        Some(Incoming::Reconnect) => return Err(io::Error::other("服务器要求重新连接")),
+        None => {}
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn milestones_announce_highest_upward_crossing_with_cooldown() {
+        let t0 = Instant::now();
+        let secs = |s| t0 + Duration::from_secs(s);
+        let mut milestones = Milestones::new(&[150, 0, 120, 150], Duration::from_secs(600));
+        assert_eq!(milestones.thresholds, [120, 150]);
+
+        // 第一条读数只作为基准
+        assert_eq!(milestones.update(130, secs(0)), None);
+        assert_eq!(milestones.update(110, secs(1)), None);
+        assert_eq!(milestones.update(155, secs(2)), Some(150));
+        // 150 冷却中，回落后再越过只播报 120
+        assert_eq!(milestones.update(100, secs(3)), None);
+        assert_eq!(milestones.update(151, secs(4)), Some(120));
+        assert_eq!(milestones.update(119, secs(5)), None);
+        assert_eq!(milestones.update(125, secs(6)), None);
+        // 冷却结束
+        assert_eq!(milestones.update(140, secs(700)), None);
+        assert_eq!(milestones.update(150, secs(701)), Some(150));
+        // 未佩戴后重新开始
+        assert_eq!(milestones.update(0, secs(800)), None);
+        assert_eq!(milestones.update(130, secs(1500)), None);
+    }
+
+    #[test]
+    fn server_lines_are_recognised() {
+        assert_eq!(
+            parse_incoming("PING :tmi.twitch.tv\r\n"),
+            Some(Incoming::Ping(":tmi.twitch.tv"))
+        );
+        assert_eq!(
+            parse_incoming(":tmi.twitch.tv 001 hrbot :Welcome, GLHF!\r\n"),
+            Some(Incoming::Welcome)
+        );
+        assert_eq!(
+            parse_incoming(":tmi.twitch.tv NOTICE * :Login authentication failed\r\n"),
+            Some(Incoming::LoginFailed)
+        );
+        assert_eq!(
+            parse_incoming(":tmi.twitch.tv RECONNECT\r\n"),
+            Some(Incoming::Reconnect)
+        );
+        assert_eq!(
+            parse_incoming(":hrbot!hrbot@hrbot.tmi.twitch.tv JOIN #streamer\r\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn login_and_messages_are_normalised() {
+        let config = Config {
+            twitch_channel: "#Streamer".to_string(),
+            twitch_username: "HRBot".to_string(),
+            twitch_oauth_token: Some("abc123".to_string()),
+            ..Config::default()
+        };
+        let login = Login::new(&config);
+        assert_eq!(login.channel, "streamer");
+        assert_eq!(login.username, "hrbot");
+        assert_eq!(login.token, "oauth:abc123");
+        assert_eq!(
+            privmsg("streamer", "心率 150\n!ban"),
+            "PRIVMSG #streamer :心率 150 !ban\r\n"
+        );
+    }
+}
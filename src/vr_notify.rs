@@ -0,0 +1,328 @@
+//! 头显内通知（`vr_notify_xsoverlay` / `vr_notify_ovr_toolkit`）：在 VR 中弹出提示，
+//! 不必等别人提醒才发现心率停住了：
+//!
+//! - 设备断开（附断开前的心率）与断开后重新连接（附设备地址），`vr_notify_on_disconnect` / `vr_notify_on_reconnect`；
+//! - 连接时读到的电量低于 `vr_notify_battery_threshold`%（附设备名），`vr_notify_on_low_battery`；
+//! - 心率报警（`hr_alarm_high` / `hr_alarm_low`，附当前心率，与提示音同时触发），`vr_notify_on_alarm`。
+//!
+//! XSOverlay 通过其 UDP 通知接口（JSON 发往 `127.0.0.1:42069`），OVR Toolkit 通过其 WebSocket 接口
+//! （`ws://127.0.0.1:11450/api`，每条通知单独连接）。两者可同时开启。
+//! 通知经后台任务发送，心率处理路径上只有一次通道写入；覆盖层未运行导致发送失败时只警告一次，
+//! 之后静默（恢复发送后再次失败会重新警告）。UDP 发往未监听的端口通常不会报错，XSOverlay 未运行时通知直接丢失。
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use futures_util::SinkExt;
+use serde::Serialize;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::time;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, warn};
+
+use crate::recorder::{self, Event, Reading, Recorder};
+use crate::{AlarmKind, Config};
+
+const XSOVERLAY_ADDR: &str = "127.0.0.1:42069";
+const OVR_TOOLKIT_URL: &str = "ws://127.0.0.1:11450/api";
+/// 通知在头显中停留的时间（XSOverlay）。
+const TOAST_SECS: f32 = 4.0;
+/// 连接 OVR Toolkit 并发送的最长时间。
+const SEND_TIMEOUT: Duration = Duration::from_secs(2);
+/// 等待发送的通知数上限，超过时丢弃新通知。
+const QUEUE_CAPACITY: usize = 16;
+
+/// 一条头显通知。
+#[derive(Debug, Clone, PartialEq)]
+struct Toast {
+    title: &'static str,
+    content: String,
+}
+
+/// XSOverlay 的通知格式（messageType 1 = 通知弹窗）。
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct XsOverlayMessage<'a> {
+    message_type: u8,
+    index: u8,
+    timeout: f32,
+    height: f32,
+    opacity: f32,
+    volume: f32,
+    audio_path: &'a str,
+    title: &'a str,
+    content: &'a str,
+    use_base64_icon: bool,
+    icon: &'a str,
+    source_app: &'a str,
+}
+
+/// OVR Toolkit 的请求：`json` 是再次序列化为字符串的通知内容。
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OvrToolkitMessage<'a> {
+    message_type: &'a str,
+    json: String,
+}
+
+#[derive(Serialize)]
+struct OvrToolkitNotification<'a> {
+    title: &'a str,
+    body: &'a str,
+}
+
+impl Toast {
+    fn xsoverlay_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(&XsOverlayMessage {
+            message_type: 1,
+            index: 0,
+            timeout: TOAST_SECS,
+            height: 175.0,
+            opacity: 1.0,
+            volume: 0.7,
+            audio_path: "default",
+            title: self.title,
+            content: &self.content,
+            use_base64_icon: false,
+            icon: "default",
+            source_app: env!("CARGO_PKG_NAME"),
+        })
+    }
+
+    fn ovr_toolkit_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(&OvrToolkitMessage {
+            message_type: "SendNotification",
+            json: serde_json::to_string(&OvrToolkitNotification {
+                title: self.title,
+                body: &self.content,
+            })?,
+        })
+    }
+}
+
+/// 通知目标与各事件开关（来自配置）。
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    xsoverlay: bool,
+    ovr_toolkit: bool,
+    on_disconnect: bool,
+    on_reconnect: bool,
+    on_low_battery: bool,
+    on_alarm: bool,
+    battery_threshold: u8,
+}
+
+struct Shared {
+    sender: mpsc::Sender<Toast>,
+    settings: Settings,
+}
+
+static SHARED: Mutex<Option<Shared>> = Mutex::new(None);
+
+/// 启动时调用（需在 tokio 运行时内）：启动发送任务并接收连接事件。
+pub fn start(config: &Config) {
+    let settings = Settings {
+        xsoverlay: config.vr_notify_xsoverlay,
+        ovr_toolkit: config.vr_notify_ovr_toolkit,
+        on_disconnect: config.vr_notify_on_disconnect,
+        on_reconnect: config.vr_notify_on_reconnect,
+        on_low_battery: config.vr_notify_on_low_battery,
+        on_alarm: config.vr_notify_on_alarm,
+        battery_threshold: config.vr_notify_battery_threshold,
+    };
+    let (sender, toasts) = mpsc::channel(QUEUE_CAPACITY);
+    tokio::spawn(deliver(toasts, settings));
+    *SHARED.lock().unwrap() = Some(Shared { sender, settings });
+    recorder::register(Box::new(Tracker::default()));
+}
+
+/// 是否已启用（`start` 已调用）。
+pub fn is_enabled() -> bool {
+    SHARED.lock().unwrap().is_some()
+}
+
+/// 按事件开关决定是否发送。
+fn notify(enabled: impl FnOnce(&Settings) -> bool, toast: impl FnOnce() -> Toast) {
+    if let Some(shared) = SHARED.lock().unwrap().as_ref() {
+        if enabled(&shared.settings) && shared.sender.try_send(toast()).is_err() {
+            debug!("头显通知队列已满，丢弃通知");
+        }
+    }
+}
+
+/// 连接时读到设备电量后调用；`device` 为设备名（没有时为地址）。
+pub fn battery(device: &str, level: u8) {
+    notify(
+        |settings| settings.on_low_battery && level < settings.battery_threshold,
+        || Toast {
+            title: "心率带电量低",
+            content: format!("{} 剩余电量 {}%", device, level),
+        },
+    );
+}
+
+/// 心率报警触发（与提示音同时）时调用。
+pub fn alarm(kind: AlarmKind, bpm: u8) {
+    notify(
+        |settings| settings.on_alarm,
+        || Toast {
+            title: match kind {
+                AlarmKind::High => "心率过高",
+                AlarmKind::Low => "心率过低",
+            },
+            content: format!("当前心率 {} BPM", bpm),
+        },
+    );
+}
+
+/// 断开时的通知内容。
+fn disconnected_toast(last_bpm: Option<u8>) -> Toast {
+    Toast {
+        title: "心率带已断开",
+        content: match last_bpm {
+            Some(bpm) => format!("断开前心率 {} BPM，正在尝试重新连接", bpm),
+            None => "正在尝试重新连接".to_string(),
+        },
+    }
+}
+
+/// 注册到 recorder：从连接事件中得出断开与重新连接。
+#[derive(Debug, Default)]
+struct Tracker {
+    /// 当前连接的设备地址，未连接时为 None
+    device: Option<String>,
+    /// 本次运行中断开过，下次连接视为重新连接
+    disconnected: bool,
+    last_bpm: Option<u8>,
+}
+
+impl Recorder for Tracker {
+    fn reading(&mut self, _at: DateTime<Local>, reading: &Reading) {
+        if reading.bpm > 0 {
+            self.last_bpm = Some(reading.bpm);
+        }
+    }
+
+    fn event(&mut self, _at: DateTime<Local>, event: &Event) {
+        match event {
+            Event::Connected(device) => {
+                if self.disconnected {
+                    notify(
+                        |settings| settings.on_reconnect,
+                        || Toast {
+                            title: "心率带已重新连接",
+                            content: device.clone(),
+                        },
+                    );
+                }
+                self.device = Some(device.clone());
+                self.disconnected = false;
+                self.last_bpm = None;
+            }
+            Event::Disconnected | Event::Rescan => {
+                // 只在连接中断开时提醒，重复的断开事件和启动时的扫描不提醒
+                if self.device.take().is_some() {
+                    let last_bpm = self.last_bpm;
+                    notify(
+                        |settings| settings.on_disconnect,
+                        || disconnected_toast(last_bpm),
+                    );
+                    self.disconnected = true;
+                }
+            }
+            Event::Start | Event::Idle | Event::Active | Event::Stop => {}
+        }
+    }
+
+    // 退出时不再提醒断开
+    fn finish(&mut self) {}
+}
+
+/// 每个目标的失败状态：连续失败只警告一次。
+#[derive(Debug, Default)]
+struct Failing {
+    warned: bool,
+}
+
+impl Failing {
+    fn record(&mut self, target: &str, result: Result<(), String>) {
+        match result {
+            Ok(()) => self.warned = false,
+            Err(e) if !self.warned => {
+                warn!(
+                    "无法发送头显通知到 {}（{}），请确认其已在运行；之后的失败不再提示。",
+                    target, e
+                );
+                self.warned = true;
+            }
+            Err(e) => debug!("发送头显通知到 {} 失败: {}", target, e),
+        }
+    }
+}
+
+/// 逐条发送通知，直到发送端被丢弃（程序退出）。
+async fn deliver(mut toasts: mpsc::Receiver<Toast>, settings: Settings) {
+    let mut xsoverlay = Failing::default();
+    let mut ovr_toolkit = Failing::default();
+    while let Some(toast) = toasts.recv().await {
+        if settings.xsoverlay {
+            xsoverlay.record("XSOverlay", send_xsoverlay(&toast).await);
+        }
+        if settings.ovr_toolkit {
+            ovr_toolkit.record("OVR Toolkit", send_ovr_toolkit(&toast).await);
+        }
+    }
+}
+
+async fn send_xsoverlay(toast: &Toast) -> Result<(), String> {
+    let message = toast.xsoverlay_json().map_err(|e| e.to_string())?;
+    let socket = UdpSocket::bind("127.0.0.1:0")
+        .await
+        .map_err(|e| e.to_string())?;
+    socket
+        .send_to(message.as_bytes(), XSOVERLAY_ADDR)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+async fn send_ovr_toolkit(toast: &Toast) -> Result<(), String> {
+    let message = toast.ovr_toolkit_json().map_err(|e| e.to_string())?;
+    time::timeout(SEND_TIMEOUT, async {
+        let (mut ws, _) = tokio_tungstenite::connect_async(OVR_TOOLKIT_URL)
+            .await
+            .map_err(|e| e.to_string())?;
+        ws.send(Message::Text(message))
+            .await
+            .map_err(|e| e.to_string())?;
+        let _ = ws.close(None).await;
+        Ok(())
+    })
+    .await
+    .map_err(|_| "超时".to_string())?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toasts_serialize_for_both_overlays() {
+        let toast = disconnected_toast(Some(96));
+        assert_eq!(toast.content, "断开前心率 96 BPM，正在尝试重新连接");
+
+        let xs = toast.xsoverlay_json().unwrap();
+        assert!(xs.contains("\"messageType\":1"));
+        assert!(xs.contains("\"title\":\"心率带已断开\""));
+        assert!(xs.contains("\"content\":\"断开前心率 96 BPM，正在尝试重新连接\""));
+        assert!(xs.contains("\"useBase64Icon\":false"));
+
+        let ovr = toast.ovr_toolkit_json().unwrap();
+        assert!(ovr.starts_with("{\"messageType\":\"SendNotification\",\"json\":\"{"));
+        assert!(ovr.contains("\\\"body\\\":\\\"断开前心率 96 BPM"));
+
+        assert_eq!(disconnected_toast(None).content, "正在尝试重新连接");
+    }
+}
@@ -0,0 +1,465 @@
+//! Webhook 通知（`webhook_enabled = true`）：发生下列事件时向 `webhook_url` POST 一个 JSON，
+//! 用来触发外部自动化（Home Assistant、Node-RED、IFTTT 等）：
+//!
+//! ```json
+//! {"event":"hr_above_threshold","bpm":152,"device":"A0:9E:1A:00:BC:12","timestamp":"2025-10-09T21:30:00.123+08:00"}
+//! ```
+//!
+//! - `device_connected` / `device_disconnected`：设备连接、断开（含收到重置请求）。连接状态需保持
+//!   `webhook_debounce_secs` 秒才发送，期间又恢复原状的不发送，频繁断连不会刷屏；
+//! - `hr_above_threshold`：心率升到 `webhook_hr_high` 及以上，降到 `webhook_hr_high_exit` 及以下后才会再次触发；
+//! - `hr_below_threshold`：心率降到 `webhook_hr_low` 及以下，升到 `webhook_hr_low_exit` 及以上后才会再次触发。
+//!
+//! 每种事件可用 `webhook_on_*` 单独关闭。`bpm` 为事件发生时（断开时为断开前）的心率，未知时为 null；
+//! `device` 为设备地址，广播模式下为 null。心率为 0（未佩戴）不参与阈值判断。
+//!
+//! 事件经有界通道交给后台任务，心率处理路径上不会等待网络；每个请求独立发送，
+//! 超时或失败时最多重试 `DELIVERY_ATTEMPTS` 次，仍失败则放弃并记警告。支持 `http://` 和 `https://`（见 http_client 模块）。
+
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio::time;
+use tracing::{debug, warn};
+
+use crate::http_client::{self, Post, Target};
+use crate::recorder::{self, Event, Reading, Recorder};
+use crate::Config;
+
+/// 未设置退出阈值时与进入阈值的差（BPM）。
+const DEFAULT_HYSTERESIS: u8 = 5;
+/// 每个事件最多尝试发送的次数及两次之间的等待。
+const DELIVERY_ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+/// 等待后台任务处理的事件数上限，超过时丢弃新事件。
+const QUEUE_CAPACITY: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    DeviceConnected,
+    DeviceDisconnected,
+    HrAboveThreshold,
+    HrBelowThreshold,
+}
+
+impl Kind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::DeviceConnected => "device_connected",
+            Kind::DeviceDisconnected => "device_disconnected",
+            Kind::HrAboveThreshold => "hr_above_threshold",
+            Kind::HrBelowThreshold => "hr_below_threshold",
+        }
+    }
+
+    /// 连接状态事件返回是否已连接，阈值事件返回 None。
+    fn connected(self) -> Option<bool> {
+        match self {
+            Kind::DeviceConnected => Some(true),
+            Kind::DeviceDisconnected => Some(false),
+            Kind::HrAboveThreshold | Kind::HrBelowThreshold => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Notification {
+    kind: Kind,
+    bpm: Option<u8>,
+    device: Option<String>,
+    at: DateTime<Local>,
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    event: &'static str,
+    bpm: Option<u8>,
+    device: Option<&'a str>,
+    timestamp: String,
+}
+
+impl Notification {
+    fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(&Payload {
+            event: self.kind.as_str(),
+            bpm: self.bpm,
+            device: self.device.as_deref(),
+            timestamp: recorder::timestamp(self.at),
+        })
+    }
+}
+
+/// 一个方向的阈值：达到 `enter` 时触发，回到 `exit` 后才重新计数。
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Threshold {
+    enter: u8,
+    exit: u8,
+}
+
+/// 带滞回的心率阈值状态。
+#[derive(Debug, Default)]
+struct Thresholds {
+    high: Option<Threshold>,
+    low: Option<Threshold>,
+    above: bool,
+    below: bool,
+}
+
+impl Thresholds {
+    fn new(config: &Config) -> Self {
+        Thresholds {
+            high: config.webhook_hr_high.map(|enter| Threshold {
+                enter,
+                exit: config
+                    .webhook_hr_high_exit
+                    .unwrap_or(enter.saturating_sub(DEFAULT_HYSTERESIS)),
+            }),
+            low: config.webhook_hr_low.map(|enter| Threshold {
+                enter,
+                exit: config
+                    .webhook_hr_low_exit
+                    .unwrap_or(enter.saturating_add(DEFAULT_HYSTERESIS)),
+            }),
+            above: false,
+            below: false,
+        }
+    }
+
+    /// 用新读数更新状态，返回本次新进入的阈值事件。
+    fn update(&mut self, bpm: u8) -> Option<Kind> {
+        if bpm == 0 {
+            return None;
+        }
+        if let Some(high) = self.high {
+            if self.above && bpm <= high.exit {
+                self.above = false;
+            } else if !self.above && bpm >= high.enter {
+                self.above = true;
+                return Some(Kind::HrAboveThreshold);
+            }
+        }
+        if let Some(low) = self.low {
+            if self.below && bpm >= low.exit {
+                self.below = false;
+            } else if !self.below && bpm <= low.enter {
+                self.below = true;
+                return Some(Kind::HrBelowThreshold);
+            }
+        }
+        None
+    }
+
+    /// 断开后重新计数，下次连接时超出阈值会再次触发。
+    fn reset(&mut self) {
+        self.above = false;
+        self.below = false;
+    }
+}
+
+/// 连接状态去抖：状态变化保持 `delay` 后才发送，期间恢复为已发送的状态则取消。
+#[derive(Debug)]
+struct Debouncer {
+    delay: Duration,
+    /// 最近一次发送的连接状态
+    sent: Option<bool>,
+    pending: Option<(Notification, Instant)>,
+}
+
+impl Debouncer {
+    fn new(delay: Duration) -> Self {
+        Debouncer {
+            delay,
+            sent: None,
+            pending: None,
+        }
+    }
+
+    /// 收到一个连接状态事件；去抖时间为 0 时直接返回需要发送的事件。
+    fn offer(&mut self, notification: Notification, now: Instant) -> Option<Notification> {
+        let connected = notification.kind.connected();
+        if connected == self.sent {
+            self.pending = None;
+            return None;
+        }
+        self.pending = Some((notification, now + self.delay));
+        self.due(now)
+    }
+
+    /// 已保持足够久、需要发送的事件。
+    fn due(&mut self, now: Instant) -> Option<Notification> {
+        match &self.pending {
+            Some((_, deadline)) if *deadline <= now => {
+                let (notification, _) = self.pending.take()?;
+                self.sent = notification.kind.connected();
+                Some(notification)
+            }
+            _ => None,
+        }
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.pending.as_ref().map(|(_, deadline)| *deadline)
+    }
+}
+
+/// 各事件是否发送（`webhook_on_*`）。
+#[derive(Debug, Clone, Copy)]
+struct Flags {
+    on_connect: bool,
+    on_disconnect: bool,
+    on_hr_high: bool,
+    on_hr_low: bool,
+}
+
+impl Flags {
+    fn enabled(self, kind: Kind) -> bool {
+        match kind {
+            Kind::DeviceConnected => self.on_connect,
+            Kind::DeviceDisconnected => self.on_disconnect,
+            Kind::HrAboveThreshold => self.on_hr_high,
+            Kind::HrBelowThreshold => self.on_hr_low,
+        }
+    }
+}
+
+/// 注册到 recorder 的输出：判断事件并交给后台任务发送。
+#[derive(Debug)]
+pub struct WebhookOutput {
+    flags: Flags,
+    thresholds: Thresholds,
+    device: Option<String>,
+    last_bpm: Option<u8>,
+    sender: mpsc::Sender<Notification>,
+}
+
+impl WebhookOutput {
+    /// 启动后台发送任务（需在 tokio 运行时内）。地址无效时返回说明。
+    pub fn start(config: &Config) -> Result<Self, String> {
+        let target = http_client::parse_url(&config.webhook_url)?;
+        let flags = Flags {
+            on_connect: config.webhook_on_connect,
+            on_disconnect: config.webhook_on_disconnect,
+            on_hr_high: config.webhook_on_hr_high,
+            on_hr_low: config.webhook_on_hr_low,
+        };
+        let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(run(
+            receiver,
+            target,
+            Duration::from_secs(config.webhook_debounce_secs),
+            flags,
+        ));
+        Ok(WebhookOutput {
+            flags,
+            thresholds: Thresholds::new(config),
+            device: None,
+            last_bpm: None,
+            sender,
+        })
+    }
+
+    fn notify(&self, kind: Kind, at: DateTime<Local>) {
+        // 连接状态事件即使不发送也要交给去抖，以便判断之后的状态变化
+        if kind.connected().is_none() && !self.flags.enabled(kind) {
+            return;
+        }
+        let notification = Notification {
+            kind,
+            bpm: self.last_bpm,
+            device: self.device.clone(),
+            at,
+        };
+        if self.sender.try_send(notification).is_err() {
+            debug!("Webhook 队列已满，丢弃事件 {}", kind.as_str());
+        }
+    }
+}
+
+impl Recorder for WebhookOutput {
+    fn reading(&mut self, at: DateTime<Local>, reading: &Reading) {
+        if reading.bpm > 0 {
+            self.last_bpm = Some(reading.bpm);
+        }
+        if let Some(kind) = self.thresholds.update(reading.bpm) {
+            self.notify(kind, at);
+        }
+    }
+
+    fn event(&mut self, at: DateTime<Local>, event: &Event) {
+        match event {
+            Event::Connected(device) => {
+                self.device = Some(device.clone());
+                self.last_bpm = None;
+                self.thresholds.reset();
+                self.notify(Kind::DeviceConnected, at);
+            }
+            Event::Disconnected | Event::Rescan => {
+                self.notify(Kind::DeviceDisconnected, at);
+                self.thresholds.reset();
+            }
+            Event::Start | Event::Idle | Event::Active | Event::Stop => {}
+        }
+    }
+
+    // 退出时尚在去抖或重试中的事件直接放弃，不拖慢退出
+    fn finish(&mut self) {}
+}
+
+/// 接收事件：阈值事件立即发送，连接状态事件去抖后发送，直到发送端被丢弃（程序退出）。
+async fn run(
+    mut receiver: mpsc::Receiver<Notification>,
+    target: Target,
+    debounce: Duration,
+    flags: Flags,
+) {
+    let mut debouncer = Debouncer::new(debounce);
+    loop {
+        let ready = match debouncer.deadline() {
+            Some(deadline) => tokio::select! {
+                notification = receiver.recv() => match notification {
+                    Some(notification) => accept(&mut debouncer, notification),
+                    None => return,
+                },
+                () = time::sleep_until(deadline.into()) => debouncer.due(Instant::now()),
+            },
+            None => match receiver.recv().await {
+                Some(notification) => accept(&mut debouncer, notification),
+                None => return,
+            },
+        };
+        if let Some(notification) = ready.filter(|n| flags.enabled(n.kind)) {
+            tokio::spawn(deliver(target.clone(), notification));
+        }
+    }
+}
+
+fn accept(debouncer: &mut Debouncer, notification: Notification) -> Option<Notification> {
+    match notification.kind.connected() {
+        Some(_) => debouncer.offer(notification, Instant::now()),
+        None => Some(notification),
+    }
+}
+
+/// 发送一个事件，失败时重试；每次尝试都受 `http_client::IO_TIMEOUT` 限制。
+async fn deliver(target: Target, notification: Notification) {
+    let body = match notification.to_json() {
+        Ok(body) => body,
+        Err(e) => {
+            warn!("生成 Webhook 内容时出错: {}", e);
+            return;
+        }
+    };
+    let post = Post {
+        target: &target,
+        content_type: "application/json",
+        headers: &[],
+        body: &body,
+    };
+    for attempt in 1..=DELIVERY_ATTEMPTS {
+        match post.send().await {
+            Ok(()) => {
+                debug!("已发送 Webhook 事件 {}", notification.kind.as_str());
+                return;
+            }
+            Err(e) if attempt < DELIVERY_ATTEMPTS => {
+                debug!(
+                    "发送 Webhook 事件 {} 失败（第 {} 次）: {}",
+                    notification.kind.as_str(),
+                    attempt,
+                    e
+                );
+                time::sleep(RETRY_DELAY).await;
+            }
+            Err(e) => warn!(
+                "发送 Webhook 事件 {} 失败（已尝试 {} 次）: {}",
+                notification.kind.as_str(),
+                DELIVERY_ATTEMPTS,
+                e
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification(kind: Kind) -> Notification {
+        Notification {
+            kind,
+            bpm: Some(80),
+            device: Some("A0:9E:1A:00:BC:12".to_string()),
+            at: Local::now(),
+        }
+    }
+
+    #[test]
+    fn thresholds_use_hysteresis() {
+        let config = Config {
+            webhook_hr_high: Some(150),
+            webhook_hr_high_exit: Some(140),
+            webhook_hr_low: Some(50),
+            ..Config::default()
+        };
+        let mut thresholds = Thresholds::new(&config);
+        let events: Vec<Option<Kind>> = [120, 150, 160, 145, 155, 140, 151, 0, 50, 54, 49, 55, 50]
+            .into_iter()
+            .map(|bpm| thresholds.update(bpm))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                None,
+                Some(Kind::HrAboveThreshold),
+                None,
+                None,
+                None,
+                None,
+                Some(Kind::HrAboveThreshold),
+                None,
+                Some(Kind::HrBelowThreshold),
+                None,
+                None,
+                None,
+                Some(Kind::HrBelowThreshold),
+            ]
+        );
+    }
+
+    #[test]
+    fn debouncer_drops_flapping_connection_changes() {
+        let start = Instant::now();
+        let secs = |s| start + Duration::from_secs(s);
+        let mut debouncer = Debouncer::new(Duration::from_secs(10));
+
+        assert_eq!(
+            debouncer.offer(notification(Kind::DeviceConnected), secs(0)),
+            None
+        );
+        assert_eq!(debouncer.due(secs(9)), None);
+        assert_eq!(
+            debouncer.due(secs(10)).map(|n| n.kind),
+            Some(Kind::DeviceConnected)
+        );
+
+        // 断开后 3 秒又连上：不发送任何事件
+        debouncer.offer(notification(Kind::DeviceDisconnected), secs(20));
+        debouncer.offer(notification(Kind::DeviceConnected), secs(23));
+        assert_eq!(debouncer.deadline(), None);
+        assert_eq!(debouncer.due(secs(40)), None);
+
+        // 断开并保持：去抖时间到后发送
+        debouncer.offer(notification(Kind::DeviceDisconnected), secs(50));
+        assert_eq!(
+            debouncer.due(secs(60)).map(|n| n.kind),
+            Some(Kind::DeviceDisconnected)
+        );
+
+        let mut immediate = Debouncer::new(Duration::ZERO);
+        assert!(immediate
+            .offer(notification(Kind::DeviceConnected), secs(0))
+            .is_some());
+    }
+}
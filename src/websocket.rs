@@ -0,0 +1,171 @@
+//! WebSocket 推送（`websocket_enabled = true`）：在 `websocket_ip:websocket_port`（默认 127.0.0.1:8765）
+//! 上监听，状态每次变化时向所有客户端发送一条 JSON 文本消息，字段与 status.json 相同
+//! （bpm、percent、connected、设备名/地址、电量、RSSI、本次连接统计，见 status_file 模块）。
+//! 新客户端连接后立即收到当前状态。供 OBS 浏览器源、网页仪表盘等使用，例如：
+//!
+//! ```js
+//! new WebSocket("ws://127.0.0.1:8765").onmessage = (e) => console.log(JSON.parse(e.data).bpm);
+//! ```
+//!
+//! 最新状态保存在一个 watch 通道中：每个客户端只会拿到最新的一条，来不及发送的中间状态直接跳过，
+//! 不会为读得慢的客户端无限缓存；一条消息在 `SEND_TIMEOUT` 内发不出去的客户端会被断开。
+//! 这套推送逻辑也由 resonite 模块复用（`spawn_server`），只是消息格式不同、限定了握手路径。
+
+use std::io;
+use std::net::{SocketAddr, SocketAddrV4};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::time;
+use tokio_tungstenite::tungstenite::handshake::server::{
+    Callback, ErrorResponse, Request, Response,
+};
+use tokio_tungstenite::tungstenite::http::{self, StatusCode};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, info, warn};
+
+use crate::status_file::{self, Status};
+
+/// 握手或发送一条消息的最长等待时间，超时的客户端被断开。
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 最新状态（JSON）；退出时丢弃发送端，所有连接随之关闭。
+static LATEST: Mutex<Option<watch::Sender<String>>> = Mutex::new(None);
+
+/// 启动时调用：开始监听并在后台接受连接，成功后 `publish` 才会生效。
+pub async fn start(addr: SocketAddrV4) -> io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    let initial = status_file::to_json_line(&Status::default()).map_err(io::Error::other)?;
+    let (sender, receiver) = watch::channel(initial);
+    *LATEST.lock().unwrap() = Some(sender);
+    spawn_server(listener, receiver, None);
+    Ok(())
+}
+
+/// 在后台接受 `listener` 上的连接，向每个客户端推送 `latest` 中的最新消息，发送端被丢弃时全部关闭。
+/// `path` 不为 None 时只接受该路径的握手，其他路径返回 404。
+pub fn spawn_server(listener: TcpListener, latest: watch::Receiver<String>, path: Option<String>) {
+    tokio::spawn(accept_loop(listener, latest, path));
+}
+
+/// 是否已启用（`start` 成功且尚未关闭）。
+pub fn is_enabled() -> bool {
+    LATEST.lock().unwrap().is_some()
+}
+
+/// 更新最新状态并通知所有客户端；未启用时不做任何事。
+pub fn publish(status: &Status) {
+    let latest = LATEST.lock().unwrap();
+    let Some(sender) = latest.as_ref() else {
+        return;
+    };
+    match status_file::to_json_line(status) {
+        Ok(json) => {
+            sender.send_replace(json);
+        }
+        Err(e) => warn!("生成 WebSocket 消息时出错: {}", e),
+    }
+}
+
+/// 退出时调用：停止接受连接并关闭现有连接。
+pub fn shutdown() {
+    LATEST.lock().unwrap().take();
+}
+
+async fn accept_loop(listener: TcpListener, latest: watch::Receiver<String>, path: Option<String>) {
+    let mut closed = latest.clone();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, peer)) => {
+                    tokio::spawn(serve(stream, peer, latest.clone(), path.clone()));
+                }
+                Err(e) => {
+                    warn!("接受 WebSocket 连接失败: {}", e);
+                    time::sleep(Duration::from_secs(1)).await;
+                }
+            },
+            // 状态更新不关心，只等发送端被丢弃
+            changed = closed.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+async fn serve(
+    stream: TcpStream,
+    peer: SocketAddr,
+    mut latest: watch::Receiver<String>,
+    path: Option<String>,
+) {
+    let handshake = tokio_tungstenite::accept_hdr_async(stream, CheckPath(path));
+    let mut ws = match time::timeout(SEND_TIMEOUT, handshake).await {
+        Ok(Ok(ws)) => ws,
+        Ok(Err(e)) => {
+            debug!("WebSocket 握手失败（{}）: {}", peer, e);
+            return;
+        }
+        Err(_) => {
+            debug!("WebSocket 握手超时（{}）", peer);
+            return;
+        }
+    };
+    info!("WebSocket 客户端已连接: {}", peer);
+
+    // 连接后先发送当前状态
+    let mut message = latest.borrow_and_update().clone();
+    loop {
+        match time::timeout(SEND_TIMEOUT, ws.send(Message::Text(message))).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                info!("WebSocket 客户端已断开: {}（{}）", peer, e);
+                return;
+            }
+            Err(_) => {
+                info!("WebSocket 客户端 {} 接收过慢，已断开", peer);
+                return;
+            }
+        }
+        // 等待下一次状态变化，期间处理客户端发来的消息（Ping 由库自动回复）
+        message = loop {
+            tokio::select! {
+                changed = latest.changed() => {
+                    if changed.is_err() {
+                        let _ = time::timeout(SEND_TIMEOUT, ws.close(None)).await;
+                        return;
+                    }
+                    break latest.borrow_and_update().clone();
+                }
+                incoming = ws.next() => match incoming {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => {
+                        info!("WebSocket 客户端已断开: {}", peer);
+                        return;
+                    }
+                    Some(Ok(_)) => {}
+                },
+            }
+        };
+    }
+}
+
+/// 握手回调：设置了路径时拒绝其他路径的握手。
+struct CheckPath(Option<String>);
+
+impl Callback for CheckPath {
+    fn on_request(self, request: &Request, response: Response) -> Result<Response, ErrorResponse> {
+        match &self.0 {
+            Some(path) if request.uri().path() != path => {
+                let mut response = http::Response::new(None);
+                *response.status_mut() = StatusCode::NOT_FOUND;
+                Err(response)
+            }
+            _ => Ok(response),
+        }
+    }
+}